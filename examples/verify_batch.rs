@@ -0,0 +1,118 @@
+//Sequential against batch verification of p2c proofs.
+//
+//usage: cargo run --release --example verify_batch -- PARAMS_DIR [N] [THREADS]
+//
+//The proofs are made on a ProverPool of THREADS workers, one per core by default.
+
+extern crate rand;
+extern crate zktx;
+
+use rand::{thread_rng, Rng};
+
+use std::env;
+use std::sync::Arc;
+use std::time::Instant;
+
+use zktx::p2c::{p2c_info_with, p2c_verify_batch_with, p2c_verify_with, P2cVerifyArgs};
+use zktx::*;
+
+fn main() {
+    let args: Vec<String> = env::args().collect();
+    if args.len() < 2 {
+        eprintln!("usage: verify_batch PARAMS_DIR [N] [THREADS]");
+        return;
+    }
+    let n: usize = args
+        .get(2)
+        .map_or(100, |n| n.parse().expect("N is a number"));
+    let threads: usize = args
+        .get(3)
+        .map_or(0, |n| n.parse().expect("THREADS is a number"));
+    let params = Arc::new(ParamSet::from_dir(&args[1]).unwrap());
+    let pool = ProverPool::new(params.clone(), threads).unwrap();
+
+    let rng = &mut thread_rng();
+    let mut bytes = [0u8; 32];
+    rng.fill_bytes(&mut bytes);
+    let sk: SpendingKey = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>()
+        .parse()
+        .unwrap();
+    let to = sk
+        .incoming_viewing_key()
+        .unwrap()
+        .payment_address()
+        .unwrap();
+
+    let start = Instant::now();
+    let mut pending = Vec::with_capacity(n);
+    for nonce in 0..n as u64 {
+        let rh = random_u644(rng);
+        let rcm = [rng.gen(), rng.gen()];
+        let enc_random = random_u644(rng);
+        let sk = sk.to_string();
+        pending.push(pool.spawn(move |params| {
+            p2c_info_with(
+                params,
+                rh,
+                rcm,
+                [1000, 0],
+                [10, 0],
+                0,
+                [0, 0],
+                [0, 0],
+                nonce,
+                to.addr.to_string(),
+                to.enc_key.to_string(),
+                sk,
+                enc_random,
+                vec![],
+            )
+        }));
+    }
+    let mut proofs: Vec<P2cVerifyArgs> = Vec::with_capacity(n);
+    for (nonce, p) in pending.into_iter().enumerate() {
+        let (proof, hb, coin, delt_ba, enc) = p.wait().unwrap();
+        proofs.push((
+            hb,
+            coin,
+            delt_ba,
+            enc,
+            to.addr.to_string(),
+            0,
+            [0, 0],
+            nonce as u64,
+            proof,
+        ));
+    }
+    println!(
+        "proved {} on {} threads in {:?}",
+        n,
+        pool.threads(),
+        start.elapsed()
+    );
+
+    let start = Instant::now();
+    for p in proofs.iter().cloned() {
+        assert!(p2c_verify_with(&params, p.0, p.1, p.2, p.3, p.4, p.5, p.6, p.7, p.8).unwrap());
+    }
+    println!("sequential: {:?}", start.elapsed());
+
+    let start = Instant::now();
+    assert_eq!(
+        p2c_verify_batch_with(&params, proofs.clone()).unwrap(),
+        None
+    );
+    println!("batch: {:?}", start.elapsed());
+
+    //a wrong coin in the middle is located
+    if n > 1 {
+        let bad = n / 2;
+        proofs[bad].1 = proofs[bad - 1].1.clone();
+        let start = Instant::now();
+        assert_eq!(p2c_verify_batch_with(&params, proofs).unwrap(), Some(bad));
+        println!("batch locating proof {}: {:?}", bad, start.elapsed());
+    }
+}
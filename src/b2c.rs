@@ -1,15 +1,19 @@
 use bellman::groth16::*;
+use bellman::Error as SynthesisError;
 use bellman::*;
 use pairing::bls12_381::{Bls12, Fr, FrRepr};
 use pairing::*;
-use rand::thread_rng;
 
 use jubjub::*;
 
 use base::*;
+use common_verify::batch::verify_batch;
+use common_verify::fixed_base::*;
+use common_verify::note::{encrypt_note, payment_coin};
 use convert::*;
-
-use std::fs::File;
+use error::Error;
+use header::{generate_params, write_params};
+use params::{default_params, ParamSet};
 
 struct B2Ccircuit<'a> {
     generators: &'a [(Vec<Fr>, Vec<Fr>)],
@@ -19,12 +23,18 @@ struct B2Ccircuit<'a> {
     rcm: Assignment<Fr>,
     //value
     va: Assignment<Fr>,
+    //asset
+    asset: Assignment<Fr>,
     //addr
     addr: (Assignment<Fr>, Assignment<Fr>),
+    //enc_key of the receiver
+    enc_key: (Assignment<Fr>, Assignment<Fr>),
     //random number,
     random: Assignment<Fr>,
     //addr_sk
     addr_sk: Vec<Assignment<bool>>,
+    //hash of the note ciphertext
+    ct_hash: Assignment<Fr>,
     //result
     res: &'a mut Vec<FrRepr>,
 }
@@ -40,9 +50,12 @@ impl<'a> B2Ccircuit<'a> {
             j,
             rcm: Assignment::unknown(),
             va: Assignment::unknown(),
+            asset: Assignment::unknown(),
             addr: (Assignment::unknown(), Assignment::unknown()),
+            enc_key: (Assignment::unknown(), Assignment::unknown()),
             random: Assignment::unknown(),
             addr_sk: (0..ADSK).map(|_| Assignment::unknown()).collect(),
+            ct_hash: Assignment::unknown(),
             res,
         }
     }
@@ -52,9 +65,12 @@ impl<'a> B2Ccircuit<'a> {
         j: &'a JubJub,
         rcm: Fr,
         va: Fr,
+        asset: Fr,
         addr: (Fr, Fr),
+        enc_key: (Fr, Fr),
         random: Fr,
         addr_sk: Vec<bool>,
+        ct_hash: Fr,
         res: &'a mut Vec<FrRepr>,
     ) -> B2Ccircuit<'a> {
         assert_eq!(res.len(), 0);
@@ -63,9 +79,12 @@ impl<'a> B2Ccircuit<'a> {
             j,
             rcm: Assignment::known(rcm),
             va: Assignment::known(va),
+            asset: Assignment::known(asset),
             addr: (Assignment::known(addr.0), Assignment::known(addr.1)),
+            enc_key: (Assignment::known(enc_key.0), Assignment::known(enc_key.1)),
             random: Assignment::known(random),
             addr_sk: addr_sk.iter().map(|&b| Assignment::known(b)).collect(),
+            ct_hash: Assignment::known(ct_hash),
             res,
         }
     }
@@ -78,21 +97,31 @@ struct B2CcircuitInput {
     coin: Num<Bls12>,
     //rP
     rp: (Num<Bls12>, Num<Bls12>),
-    //enc
-    enc: Num<Bls12>,
+    //e1 and e2, the note encrypted in the circuit
+    e: (Num<Bls12>, Num<Bls12>),
+    //hash of rP and the note ciphertext
+    ct_hash: Num<Bls12>,
     //addr
     addr: (Num<Bls12>, Num<Bls12>),
+    //asset
+    asset: Num<Bls12>,
 }
 
 impl<'a> Input<Bls12> for B2CcircuitInput {
-    fn synthesize<CS: PublicConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), Error> {
+    fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
         let coin_input = cs.alloc_input(|| Ok(*self.coin.getvalue().get()?))?;
         let va_input = cs.alloc_input(|| Ok(*self.va.getvalue().get()?))?;
         let rpx_input = cs.alloc_input(|| Ok(*self.rp.0.getvalue().get()?))?;
         let rpy_input = cs.alloc_input(|| Ok(*self.rp.1.getvalue().get()?))?;
-        let enc_input = cs.alloc_input(|| Ok(*self.enc.getvalue().get()?))?;
+        let e1_input = cs.alloc_input(|| Ok(*self.e.0.getvalue().get()?))?;
+        let e2_input = cs.alloc_input(|| Ok(*self.e.1.getvalue().get()?))?;
+        let ct_hash_input = cs.alloc_input(|| Ok(*self.ct_hash.getvalue().get()?))?;
         let addrx_input = cs.alloc_input(|| Ok(*self.addr.0.getvalue().get()?))?;
         let addry_input = cs.alloc_input(|| Ok(*self.addr.1.getvalue().get()?))?;
+        let asset_input = cs.alloc_input(|| Ok(*self.asset.getvalue().get()?))?;
 
         cs.enforce(
             LinearCombination::zero() + self.coin.getvar(),
@@ -115,9 +144,19 @@ impl<'a> Input<Bls12> for B2CcircuitInput {
             LinearCombination::zero() + rpy_input,
         );
         cs.enforce(
-            LinearCombination::zero() + self.enc.getvar(),
+            LinearCombination::zero() + self.e.0.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + e1_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.e.1.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + e2_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.ct_hash.getvar(),
             LinearCombination::zero() + CS::one(),
-            LinearCombination::zero() + enc_input,
+            LinearCombination::zero() + ct_hash_input,
         );
         cs.enforce(
             LinearCombination::zero() + self.addr.0.getvar(),
@@ -129,6 +168,11 @@ impl<'a> Input<Bls12> for B2CcircuitInput {
             LinearCombination::zero() + CS::one(),
             LinearCombination::zero() + addry_input,
         );
+        cs.enforce(
+            LinearCombination::zero() + self.asset.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + asset_input,
+        );
 
         Ok(())
     }
@@ -137,72 +181,61 @@ impl<'a> Input<Bls12> for B2CcircuitInput {
 impl<'a> Circuit<Bls12> for B2Ccircuit<'a> {
     type InputMap = B2CcircuitInput;
 
-    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<Self::InputMap, Error> {
-        let rcm_num = Num::new(cs, self.rcm)?;
-        let mut rcm = rcm_num.unpack_sized(cs, RCMBIT)?;
-        let random_num = Num::new(cs, self.random)?;
-        let random = random_num.unpack_sized(cs, 256)?;
-        let addr_x_num = Num::new(cs, self.addr.0)?;
-        let addr_x_bit = addr_x_num.unpack_sized(cs, PHOUT)?;
-        let addr_y_num = Num::new(cs, self.addr.1)?;
-
+    fn synthesize<CS: ConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<Self::InputMap, SynthesisError> {
+        let rcm = Num::new(cs, self.rcm)?;
+        let random = Num::new(cs, self.random)?;
+        let addr_x = Num::new(cs, self.addr.0)?;
+        let enc_key = (Num::new(cs, self.enc_key.0)?, Num::new(cs, self.enc_key.1)?);
         let va = Num::new(cs, self.va)?;
-        let bit_va = va.unpack_sized(cs, VBIT)?;
-        assert_eq!(bit_va.len(), VBIT);
-
-        //prepare table
-        let p1 = Point::enc_point_table(256, 1, cs)?;
+        let asset = Num::new(cs, self.asset)?;
 
-        //coin = PH(addr|value|rcm)
-        let vin = {
-            for b in bit_va.iter() {
-                rcm.push(*b);
-            }
-            for b in addr_x_bit.iter() {
-                rcm.push(*b);
-            }
-            rcm
-        };
-        assert_eq!(vin.len(), PHIN);
-        let coin = pedersen_hash(cs, &vin, self.generators, self.j)?;
+        //coin = PH(PH(rcm|value|pa)|asset), pa = PH(addr.x|enc_key.y)
+        let coin = payment_coin(
+            cs,
+            &rcm,
+            &va,
+            &addr_x,
+            &enc_key.1,
+            &asset,
+            self.generators,
+            self.j,
+        )?;
         if let Ok(x) = coin.getvalue().get() {
             self.res.push(x.into_repr());
         }
 
-        //Enc
-        let message = {
-            let b128 = Num::new(
-                cs,
-                Assignment::known(Fr::from_repr(FrRepr::from_serial([0, 0, 1, 0])).unwrap()),
-            )?;
-            va.mul(cs, &b128)?.add(cs, &rcm_num)
-        }?;
-        let qtable = Point::point_mul_table((&addr_x_num, &addr_y_num), 256, cs)?;
-        let rp = Point::multiply(&p1, &random, cs)?;
-        let rq = Point::multiply(&qtable, &random, cs)?;
+        //(rP, e1, e2), the opening of the coin encrypted to enc_key
+        let (rp, e1, e2) = encrypt_note(
+            cs,
+            &random,
+            &enc_key,
+            &va,
+            &rcm,
+            &asset,
+            self.generators,
+            self.j,
+        )?;
         if let (Ok(x), Ok(y)) = (rp.0.getvalue().get(), rp.1.getvalue().get()) {
             self.res.push(x.into_repr());
             self.res.push(y.into_repr());
         }
-        let key = rq.0;
-        let enc = key.add(cs, &message)?;
-        if let Ok(x) = enc.getvalue().get() {
-            self.res.push(x.into_repr());
-        }
+        //the memo is encrypted outside the circuit and bound through ct_hash
+        let ct_hash = Num::new(cs, self.ct_hash)?;
 
-        let mut addr_sk = Vec::with_capacity(ADSK);
-        for b in self.addr_sk.iter() {
-            addr_sk.push(Bit::alloc(cs, *b)?);
-        }
-        let p1 = Point::enc_point_table(ADSK, 1, cs)?;
-        let addr = Point::multiply(&p1, &addr_sk, cs)?;
+        let addr_sk = alloc_addr_sk(cs, &self.addr_sk)?;
+        let addr = address(cs, &addr_sk, ADSK, self.j)?;
 
         Ok(B2CcircuitInput {
             va,
             coin,
             rp,
-            enc,
+            e: (e1, e2),
+            ct_hash,
             addr,
+            asset,
         })
     }
 }
@@ -210,100 +243,165 @@ impl<'a> Circuit<Bls12> for B2Ccircuit<'a> {
 pub fn b2c_info(
     rcm: [u64; 2],
     va: [u64; 2],
+    asset: u64,
+    addr: String,
+    enc_key: String,
+    addr_sk: String,
+    enc_random: [u64; 4],
+    memo: Vec<u8>,
+) -> Result<(String, String, String), Error> {
+    b2c_info_with(
+        &*default_params()?,
+        rcm,
+        va,
+        asset,
+        addr,
+        enc_key,
+        addr_sk,
+        enc_random,
+        memo,
+    )
+}
+
+pub fn b2c_info_with(
+    params: &ParamSet,
+    rcm: [u64; 2],
+    va: [u64; 2],
+    asset: u64,
     addr: String,
+    enc_key: String,
     addr_sk: String,
     enc_random: [u64; 4],
+    memo: Vec<u8>,
 ) -> Result<(String, String, String), Error> {
-    let addr = str2point(addr);
-    let addr_sk = str2sk(addr_sk);
-    let rng = &mut thread_rng();
+    let addr = point2fr(str2point(addr)?)?;
+    let addr_sk = str2sk(addr_sk)?;
+    let enc = encrypt(va, rcm, asset, &memo, enc_random, enc_key.clone())?;
+    let enc_key = point2fr(str2point(enc_key)?)?;
     let j = JubJub::new();
     let mut res: Vec<FrRepr> = vec![];
-    let proof = create_random_proof::<Bls12, _, _, _>(
-        B2Ccircuit::new(
-            &ph_generator(),
-            &j,
-            Fr::from_repr(FrRepr([rcm[0], rcm[1], 0, 0])).unwrap(),
-            Fr::from_repr(FrRepr([va[0], va[1], 0, 0])).unwrap(),
-            (
-                Fr::from_repr(FrRepr(addr.0)).unwrap(),
-                Fr::from_repr(FrRepr(addr.1)).unwrap(),
+    let proof = params
+        .prove(
+            ParamKind::B2c,
+            B2Ccircuit::new(
+                params.generators(),
+                &j,
+                u6442fr([rcm[0], rcm[1], 0, 0])?,
+                u6442fr([va[0], va[1], 0, 0])?,
+                u6442fr([asset, 0, 0, 0])?,
+                addr,
+                enc_key,
+                Fr::from_serial(enc_random),
+                addr_sk,
+                ct_hash(&str2enc(enc.clone())?)?,
+                &mut res,
             ),
-            Fr::from_serial(enc_random),
-            addr_sk,
-            &mut res,
-        ),
-        b2c_param()?,
-        rng,
-    )?
-    .serial();
+        )?
+        .serial();
     let coin = res[0].serial();
-    let enc = (res[1].serial(), res[2].serial(), res[3].serial());
-    Ok((proof2str(proof), u6442str(coin), enc2str(enc)))
+    Ok((proof2str(proof), u6442str(coin), enc))
 }
 
 pub fn b2c_verify(
     va: [u64; 2],
+    asset: u64,
     coin: String,
     enc: String,
     address: String,
     proof: String,
 ) -> Result<bool, Error> {
-    let coin = str2u644(coin);
-    let enc = str2enc(enc);
-    let address = str2point(address);
-    let proof = str2proof(proof);
-    verify_proof(&b2c_vk()?, &Proof::from_serial(proof), |cs| {
-        let coin = Fr::from_repr(FrRepr::from_serial(coin)).unwrap();
-        let va = Fr::from_repr(FrRepr([va[0], va[1], 0, 0])).unwrap();
-        let rpx = Fr::from_repr(FrRepr::from_serial(enc.0)).unwrap();
-        let rpy = Fr::from_repr(FrRepr::from_serial(enc.1)).unwrap();
-        let enc = Fr::from_repr(FrRepr::from_serial(enc.2)).unwrap();
-        let addrx = Fr::from_repr(FrRepr::from_serial(address.0)).unwrap();
-        let addry = Fr::from_repr(FrRepr::from_serial(address.1)).unwrap();
-        Ok(B2CcircuitInput {
-            coin: Num::new(cs, Assignment::known(coin))?,
-            va: Num::new(cs, Assignment::known(va))?,
-            rp: (
-                Num::new(cs, Assignment::known(rpx))?,
-                Num::new(cs, Assignment::known(rpy))?,
-            ),
-            enc: Num::new(cs, Assignment::known(enc))?,
-            addr: (
-                Num::new(cs, Assignment::known(addrx))?,
-                Num::new(cs, Assignment::known(addry))?,
-            ),
-        })
-    })
+    b2c_verify_with(&*default_params()?, va, asset, coin, enc, address, proof)
 }
 
-pub(crate) fn gen_b2c_param() {
-    let b2c_param_path = b2c_param_path();
-    let b2c_param_path = b2c_param_path.to_str().unwrap();
+pub fn b2c_verify_with(
+    params: &ParamSet,
+    va: [u64; 2],
+    asset: u64,
+    coin: String,
+    enc: String,
+    address: String,
+    proof: String,
+) -> Result<bool, Error> {
+    let coin = u6442fr(str2u644(coin)?)?;
+    let va = u6442fr([va[0], va[1], 0, 0])?;
+    let asset = u6442fr([asset, 0, 0, 0])?;
+    let ((rpx, rpy), e1, e2, ct_hash) = note_inputs(&str2enc(enc)?)?;
+    let (addrx, addry) = point2fr(str2point(address)?)?;
+    let proof = str2proof(proof)?;
+    let ok = verify_proof(
+        &*params.vk(ParamKind::B2c)?,
+        &Proof::from_serial(proof),
+        |cs| {
+            Ok(B2CcircuitInput {
+                coin: Num::new(cs, Assignment::known(coin))?,
+                va: Num::new(cs, Assignment::known(va))?,
+                rp: (
+                    Num::new(cs, Assignment::known(rpx))?,
+                    Num::new(cs, Assignment::known(rpy))?,
+                ),
+                e: (
+                    Num::new(cs, Assignment::known(e1))?,
+                    Num::new(cs, Assignment::known(e2))?,
+                ),
+                ct_hash: Num::new(cs, Assignment::known(ct_hash))?,
+                addr: (
+                    Num::new(cs, Assignment::known(addrx))?,
+                    Num::new(cs, Assignment::known(addry))?,
+                ),
+                asset: Num::new(cs, Assignment::known(asset))?,
+            })
+        },
+    )?;
+    Ok(ok)
+}
 
-    let rng = &mut thread_rng();
-    let params = generate_random_parameters::<Bls12, _, _>(
-        B2Ccircuit::blank(&ph_generator(), &JubJub::new(), &mut vec![]),
-        rng,
-    )
-    .unwrap();
-    params
-        .write(&mut File::create(b2c_param_path).unwrap())
-        .unwrap();
+//public inputs in the order B2CcircuitInput allocates them
+fn b2c_public_inputs(
+    va: [u64; 2],
+    asset: u64,
+    coin: String,
+    enc: String,
+    address: String,
+) -> Result<Vec<Fr>, Error> {
+    let coin = u6442fr(str2u644(coin)?)?;
+    let va = u6442fr([va[0], va[1], 0, 0])?;
+    let asset = u6442fr([asset, 0, 0, 0])?;
+    let ((rpx, rpy), e1, e2, ct_hash) = note_inputs(&str2enc(enc)?)?;
+    let (addrx, addry) = point2fr(str2point(address)?)?;
+    Ok(vec![
+        coin, va, rpx, rpy, e1, e2, ct_hash, addrx, addry, asset,
+    ])
 }
 
-fn b2c_param() -> Result<ProverStream, Error> {
-    let b2c_param_path = b2c_param_path();
-    let b2c_param_path = b2c_param_path.to_str().unwrap();
-    let params = ProverStream::new(b2c_param_path).unwrap();
-    Ok(params)
+//(va, asset, coin, enc, address, proof) as taken by b2c_verify
+pub type B2cVerifyArgs = ([u64; 2], u64, String, String, String, String);
+
+pub fn b2c_verify_batch(proofs: Vec<B2cVerifyArgs>) -> Result<Option<usize>, Error> {
+    b2c_verify_batch_with(&*default_params()?, proofs)
 }
 
-fn b2c_vk() -> Result<(PreparedVerifyingKey<Bls12>), Error> {
+//index of the first proof that does not verify, None if all do
+pub fn b2c_verify_batch_with(
+    params: &ParamSet,
+    proofs: Vec<B2cVerifyArgs>,
+) -> Result<Option<usize>, Error> {
+    let mut batch = Vec::with_capacity(proofs.len());
+    for (va, asset, coin, enc, address, proof) in proofs.into_iter() {
+        batch.push((
+            Proof::from_serial(str2proof(proof)?),
+            b2c_public_inputs(va, asset, coin, enc, address)?,
+        ));
+    }
+    verify_batch(&*params.verifying_key(ParamKind::B2c)?, &batch)
+}
+
+pub(crate) fn gen_b2c_param() -> Result<(), Error> {
     let b2c_param_path = b2c_param_path();
-    let b2c_param_path = b2c_param_path.to_str().unwrap();
-    let mut params = ProverStream::new(b2c_param_path)?;
-    let vk2 = params.get_vk(8)?;
-    let vk = prepare_verifying_key(&vk2);
-    Ok(vk)
+
+    let (params, constraints) = generate_params(B2Ccircuit::blank(
+        &ph_generator()?,
+        &JubJub::new(),
+        &mut vec![],
+    ))?;
+    write_params(ParamKind::B2c, &params, constraints, &b2c_param_path)
 }
@@ -0,0 +1,238 @@
+//Encrypted balances: ba*G + rh*P2 stored by the contract per asset, G = asset_generator(asset)
+//being the value generator of the asset, and the wallet-side tracker of the opening (ba, rh).
+//
+//Sends subtract and receipts add a va*G + rcm*P2 commitment, so the blinding
+//moves by rcm each time. The tracker keeps rh modulo the JubJub group order,
+//which is below the Fr modulus and 2^RHBIT, so it is always a valid p2c rh.
+
+use std::fmt;
+use std::str::FromStr;
+
+use base::{ecc_add, ecc_sub, v_p1_add_rh_p2, value_commitment};
+use convert::{u1282value, value2u128};
+use error::Error;
+use types::ValueCommitment;
+
+//order of the JubJub group, 8 times the prime subgroup order, little-endian limbs
+const GROUP_ORDER: [u64; 4] = [
+    0x84b872f6b7b965b8,
+    0x3341049e66408416,
+    0x3339d80809a1d805,
+    0x73eda753299d7d48,
+];
+
+fn less_than(a: [u64; 4], b: [u64; 4]) -> bool {
+    for i in (0..4).rev() {
+        if a[i] != b[i] {
+            return a[i] < b[i];
+        }
+    }
+    false
+}
+
+fn wrapping_add(a: [u64; 4], b: [u64; 4]) -> [u64; 4] {
+    let mut res = [0u64; 4];
+    let mut carry = 0u128;
+    for i in 0..4 {
+        let sum = a[i] as u128 + b[i] as u128 + carry;
+        res[i] = sum as u64;
+        carry = sum >> 64;
+    }
+    res
+}
+
+fn wrapping_sub(a: [u64; 4], b: [u64; 4]) -> [u64; 4] {
+    let mut res = [0u64; 4];
+    let mut borrow = 0u64;
+    for i in 0..4 {
+        let (diff, b1) = a[i].overflowing_sub(b[i]);
+        let (diff, b2) = diff.overflowing_sub(borrow);
+        res[i] = diff;
+        borrow = (b1 || b2) as u64;
+    }
+    res
+}
+
+fn reduce(mut a: [u64; 4]) -> [u64; 4] {
+    while !less_than(a, GROUP_ORDER) {
+        a = wrapping_sub(a, GROUP_ORDER);
+    }
+    a
+}
+
+//a and b reduced, the sum stays below 2^256
+fn add_mod(a: [u64; 4], b: [u64; 4]) -> [u64; 4] {
+    reduce(wrapping_add(a, b))
+}
+
+fn sub_mod(a: [u64; 4], b: [u64; 4]) -> [u64; 4] {
+    if less_than(a, b) {
+        wrapping_add(wrapping_sub(a, b), GROUP_ORDER)
+    } else {
+        wrapping_sub(a, b)
+    }
+}
+
+//ba*G+rh*P2
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct EncryptedBalance(ValueCommitment);
+
+impl EncryptedBalance {
+    pub fn commit(ba: [u64; 2], asset: u64, rh: [u64; 4]) -> Result<EncryptedBalance, Error> {
+        Ok(EncryptedBalance(value_commitment(ba, asset, rh).parse()?))
+    }
+
+    pub fn commitment(&self) -> ValueCommitment {
+        self.0
+    }
+
+    //receipt of delta = va*G+rcm*P2, of the same asset
+    pub fn add_commitment(&self, delta: &ValueCommitment) -> Result<EncryptedBalance, Error> {
+        Ok(EncryptedBalance(
+            ecc_add(self.0.to_string(), delta.to_string())?.parse()?,
+        ))
+    }
+
+    //send of delta = va*G+rcm*P2, of the same asset
+    pub fn sub_commitment(&self, delta: &ValueCommitment) -> Result<EncryptedBalance, Error> {
+        Ok(EncryptedBalance(
+            ecc_sub(self.0.to_string(), delta.to_string())?.parse()?,
+        ))
+    }
+
+    //add r*P2, the opening's rh moving by r
+    pub fn rerandomise(&self, r: [u64; 4]) -> Result<EncryptedBalance, Error> {
+        self.add_commitment(&v_p1_add_rh_p2([0, 0], r).parse()?)
+    }
+
+    pub fn open(&self, ba: [u64; 2], asset: u64, rh: [u64; 4]) -> Result<bool, Error> {
+        Ok(EncryptedBalance::commit(ba, asset, rh)? == *self)
+    }
+}
+
+impl From<ValueCommitment> for EncryptedBalance {
+    fn from(commitment: ValueCommitment) -> EncryptedBalance {
+        EncryptedBalance(commitment)
+    }
+}
+
+impl FromStr for EncryptedBalance {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<EncryptedBalance, Error> {
+        Ok(EncryptedBalance(s.parse()?))
+    }
+}
+
+impl fmt::Display for EncryptedBalance {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+//opening of an account's EncryptedBalance, updated alongside every send and receipt
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct BalanceTracker {
+    ba: [u64; 2],
+    asset: u64,
+    rh: [u64; 4],
+}
+
+impl BalanceTracker {
+    pub fn new(ba: [u64; 2], asset: u64, rh: [u64; 4]) -> BalanceTracker {
+        BalanceTracker {
+            ba,
+            asset,
+            rh: reduce(rh),
+        }
+    }
+
+    pub fn ba(&self) -> [u64; 2] {
+        self.ba
+    }
+
+    pub fn asset(&self) -> u64 {
+        self.asset
+    }
+
+    //the rh of the next p2c proof
+    pub fn rh(&self) -> [u64; 4] {
+        self.rh
+    }
+
+    pub fn commitment(&self) -> Result<EncryptedBalance, Error> {
+        EncryptedBalance::commit(self.ba, self.asset, self.rh)
+    }
+
+    pub fn opens(&self, balance: &EncryptedBalance) -> Result<bool, Error> {
+        balance.open(self.ba, self.asset, self.rh)
+    }
+
+    //after a p2c of va with delt_ba = va*G+rcm*P2
+    pub fn send(&mut self, va: [u64; 2], rcm: [u64; 2]) -> Result<(), Error> {
+        let ba = match value2u128(self.ba).checked_sub(value2u128(va)) {
+            Some(ba) => ba,
+            None => return Err(Error::BalanceOutOfRange),
+        };
+        self.ba = u1282value(ba);
+        self.rh = sub_mod(self.rh, [rcm[0], rcm[1], 0, 0]);
+        Ok(())
+    }
+
+    //after a c2p of va with delt_ba = va*G+rcm*P2
+    pub fn receive(&mut self, va: [u64; 2], rcm: [u64; 2]) -> Result<(), Error> {
+        let ba = match value2u128(self.ba).checked_add(value2u128(va)) {
+            Some(ba) => ba,
+            None => return Err(Error::BalanceOutOfRange),
+        };
+        self.ba = u1282value(ba);
+        self.rh = add_mod(self.rh, [rcm[0], rcm[1], 0, 0]);
+        Ok(())
+    }
+
+    pub fn rerandomise(&mut self, r: [u64; 4]) {
+        self.rh = add_mod(self.rh, reduce(r));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tracker_follows_balance() {
+        let mut tracker = BalanceTracker::new([100, 0], 3, [7, 0, 0, 0]);
+        let mut balance = tracker.commitment().unwrap();
+
+        //rh - rcm wraps below zero
+        let delta: ValueCommitment = value_commitment([30, 0], 3, [9, 1, 0, 0]).parse().unwrap();
+        balance = balance.sub_commitment(&delta).unwrap();
+        tracker.send([30, 0], [9, 1]).unwrap();
+        assert!(tracker.opens(&balance).unwrap());
+
+        let delta: ValueCommitment = value_commitment([5, 0], 3, [3, 0, 0, 0]).parse().unwrap();
+        balance = balance.add_commitment(&delta).unwrap();
+        tracker.receive([5, 0], [3, 0]).unwrap();
+        assert!(tracker.opens(&balance).unwrap());
+        assert_eq!(tracker.ba(), [75, 0]);
+
+        balance = balance.rerandomise([1, 2, 3, 4]).unwrap();
+        assert!(!tracker.opens(&balance).unwrap());
+        tracker.rerandomise([1, 2, 3, 4]);
+        assert!(tracker.opens(&balance).unwrap());
+    }
+
+    #[test]
+    fn assets_do_not_mix() {
+        let tracker = BalanceTracker::new([100, 0], 1, [7, 0, 0, 0]);
+        let other = BalanceTracker::new([100, 0], 2, [7, 0, 0, 0]);
+        assert!(!tracker.opens(&other.commitment().unwrap()).unwrap());
+    }
+
+    #[test]
+    fn rejects_overdraft() {
+        let mut tracker = BalanceTracker::new([10, 0], 0, [0; 4]);
+        assert!(tracker.send([11, 0], [0, 0]).is_err());
+        assert_eq!(tracker.ba(), [10, 0]);
+    }
+}
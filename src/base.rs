@@ -1,6 +1,8 @@
-use pairing::bls12_381::{Fr, FrRepr};
+use blake2_rfc::blake2b::blake2b;
+use blake2_rfc::blake2s::blake2s;
+use chacha20_poly1305_aead;
+use pairing::bls12_381::Fr;
 use pairing::{Field, PrimeField};
-use rand::{SeedableRng, XorShiftRng};
 use std::fs::File;
 use std::io::prelude::*;
 use std::path::Path;
@@ -9,6 +11,11 @@ use std::sync::Mutex;
 
 use jubjub::*;
 
+use error::Error;
+use generators::{asset_generator, p1, p2, ph_windows};
+use header::{write_atomic, FileHeader};
+use params::clear_default_params;
+
 pub const VBIT: usize = 128;
 pub const RHBIT: usize = 256;
 pub const RCMBIT: usize = 128;
@@ -16,18 +23,35 @@ pub const PHOUT: usize = 256;
 pub const PHIN: usize = 512;
 pub const ADSK: usize = 256;
 pub const TREEDEPTH: usize = 60;
+pub const C2CIN: usize = 2;
+pub const C2COUT: usize = 2;
+pub const MEMO_SIZE: usize = 512;
+pub const ASSETBIT: usize = 64;
+//"Zktx_nk" read little-endian, the low limb of what nk hashes addr_sk with
+pub const NK_TAG: u64 = 0x006b_6e5f_7874_6b5a;
+//"Zktx_ivk" read little-endian, what ivk hashes nk with
+pub const IVK_TAG: u64 = 0x6b76_695f_7874_6b5a;
+//memo length | memo padded to MEMO_SIZE
+const NOTE_PLAINTEXT: usize = 2 + MEMO_SIZE;
+const NOTE_TAG: usize = 16;
+//e1 | e2 | encrypted memo | tag, see common_verify::note
+pub const NOTE_CIPHERTEXT: usize = 64 + NOTE_PLAINTEXT + NOTE_TAG;
 
 lazy_static! {
     pub static ref PARAMPATH: Mutex<String> = Mutex::new("PARAMS".to_string());
 }
 
+//compatibility shim, picks the ParamSet used by the functions not taking one
 pub fn set_param_path(path: &str) {
     *PARAMPATH.lock().unwrap() = path.to_string();
+    clear_default_params();
 }
 
+pub(crate) const GENERATORS: &'static str = "generators";
+
 pub(crate) fn generator_path() -> PathBuf {
     let param_path = PARAMPATH.lock().unwrap().to_owned();
-    Path::new(&param_path).join("generators")
+    Path::new(&param_path).join(GENERATORS)
 }
 
 pub(crate) fn c2b_param_path() -> PathBuf {
@@ -50,48 +74,162 @@ pub(crate) fn c2p_param_path() -> PathBuf {
     Path::new(&param_path).join("c2pparams")
 }
 
+pub(crate) fn c2c_param_path() -> PathBuf {
+    let param_path = PARAMPATH.lock().unwrap().to_owned();
+    Path::new(&param_path).join("c2cparams")
+}
+
 pub(crate) fn range_param_path() -> PathBuf {
     let param_path = PARAMPATH.lock().unwrap().to_owned();
     Path::new(&param_path).join("rangeparams")
 }
 
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub enum ParamKind {
+    B2c,
+    P2c,
+    C2p,
+    C2b,
+    C2c,
+    Range,
+}
+
+pub const PARAM_KINDS: [ParamKind; 6] = [
+    ParamKind::B2c,
+    ParamKind::P2c,
+    ParamKind::C2p,
+    ParamKind::C2b,
+    ParamKind::C2c,
+    ParamKind::Range,
+];
+
+impl ParamKind {
+    pub fn file_name(&self) -> &'static str {
+        match *self {
+            ParamKind::B2c => "b2cparams",
+            ParamKind::P2c => "p2cparams",
+            ParamKind::C2p => "c2pparams",
+            ParamKind::C2b => "c2bparams",
+            ParamKind::C2c => "c2cparams",
+            ParamKind::Range => "rangeparams",
+        }
+    }
+
+    //number of public inputs, counting the constant one
+    pub fn inputs(&self) -> usize {
+        match *self {
+            ParamKind::B2c => 11,
+            ParamKind::P2c => 18,
+            ParamKind::C2p => 9,
+            ParamKind::C2b => 8,
+            //root, nullifiers, coins and (rpx, rpy, e1, e2, ct_hash) per created coin
+            ParamKind::C2c => 2 + C2CIN + C2COUT * 6,
+            ParamKind::Range => 5,
+        }
+    }
+
+    pub fn path(&self) -> PathBuf {
+        match *self {
+            ParamKind::B2c => b2c_param_path(),
+            ParamKind::P2c => p2c_param_path(),
+            ParamKind::C2p => c2p_param_path(),
+            ParamKind::C2b => c2b_param_path(),
+            ParamKind::C2c => c2c_param_path(),
+            ParamKind::Range => range_param_path(),
+        }
+    }
+}
+
 use super::convert::*;
 
-pub(crate) fn gen_ph_generator() {
-    let generator_path = generator_path();
-    let generator_path = generator_path.to_str().unwrap();
+pub(crate) fn existing_param_path(path: PathBuf) -> Result<String, Error> {
+    if !path.exists() {
+        return Err(Error::MissingParamFile(path));
+    }
+    Ok(path.to_string_lossy().into_owned())
+}
 
-    const SEED: [u32; 4] = [0x5dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654];
-    let mut generator_rng = XorShiftRng::from_seed(SEED);
-    let generators = generate_constant_table(&mut generator_rng, &JubJub::new());
-    drop(generator_rng);
+pub(crate) fn check_path_len(path: usize, loc: usize) -> Result<(), Error> {
+    for &len in [path, loc].iter() {
+        if len != TREEDEPTH {
+            return Err(Error::WrongLength {
+                expected: TREEDEPTH,
+                found: len,
+            });
+        }
+    }
+    Ok(())
+}
 
-    let mut writer = File::create(generator_path).unwrap();
+//prover side guard, a proof never commits to more fee than the caller allows
+pub(crate) fn check_fee(fee: [u64; 2], max_fee: [u64; 2]) -> Result<(), Error> {
+    if (fee[1], fee[0]) > (max_fee[1], max_fee[0]) {
+        return Err(Error::FeeTooHigh);
+    }
+    Ok(())
+}
+
+pub(crate) fn gen_ph_generator() -> Result<(), Error> {
+    let generator_path = generator_path();
 
+    let generators = ph_windows(&JubJub::new());
+
+    let mut bytes = Vec::with_capacity(GENERATOR_BYTES);
     for tup in generators.iter() {
         match tup {
             &(ref frxs, ref frys) => {
                 for x in frxs.iter() {
                     for unit in x.serial().iter() {
-                        writer.write_all(&u64to8(*unit)).unwrap();
+                        bytes.extend_from_slice(&u64to8(*unit));
                     }
                 }
                 for y in frys.iter() {
                     for unit in y.serial().iter() {
-                        writer.write_all(&u64to8(*unit)).unwrap();
+                        bytes.extend_from_slice(&u64to8(*unit));
                     }
                 }
             }
         }
     }
+
+    write_atomic(&generator_path, |writer| {
+        FileHeader::new(None, 0, &bytes).write(writer)?;
+        writer.write_all(&bytes)?;
+        Ok(())
+    })
 }
 
-pub(crate) fn ph_generator() -> Vec<(Vec<Fr>, Vec<Fr>)> {
+pub(crate) fn ph_generator() -> Result<Vec<(Vec<Fr>, Vec<Fr>)>, Error> {
     let generator_path = generator_path();
-    let generator_path = generator_path.to_str().unwrap();
+    existing_param_path(generator_path.clone())?;
+    read_ph_generator(&mut File::open(&generator_path)?, &generator_path)
+}
 
-    let mut reader = File::open(generator_path).unwrap();
+//128 windows of 16 x and 16 y coordinates
+const GENERATOR_BYTES: usize = 128 * 32 * 32;
+
+//header and contents of a generators file, path only names it in errors
+pub(crate) fn read_ph_generator<R: Read>(
+    reader: &mut R,
+    path: &Path,
+) -> Result<Vec<(Vec<Fr>, Vec<Fr>)>, Error> {
+    let header = FileHeader::read(reader, path)?;
+    header.expect_circuit(None, path)?;
+    let mut bytes = Vec::with_capacity(GENERATOR_BYTES);
+    reader.read_to_end(&mut bytes)?;
+    header.check(&mut &bytes[..], path)?;
+    if bytes.len() != GENERATOR_BYTES {
+        return Err(Error::BadParamFile(
+            path.to_path_buf(),
+            format!(
+                "{} bytes of generators, expected {}",
+                bytes.len(),
+                GENERATOR_BYTES
+            ),
+        ));
+    }
 
+    let mut reader = &bytes[..];
     let mut serial = vec![];
     for _ in 0..128 {
         let mut xs = vec![];
@@ -100,7 +238,7 @@ pub(crate) fn ph_generator() -> Vec<(Vec<Fr>, Vec<Fr>)> {
             let mut nums: [u64; 4] = [0; 4];
             for i in 0..4 {
                 let mut num: [u8; 8] = [0; 8];
-                reader.read(&mut num).unwrap();
+                reader.read_exact(&mut num)?;
                 nums[i] = u8to64(num);
             }
             xs.push(Fr::from_serial(nums));
@@ -109,46 +247,53 @@ pub(crate) fn ph_generator() -> Vec<(Vec<Fr>, Vec<Fr>)> {
             let mut nums: [u64; 4] = [0; 4];
             for i in 0..4 {
                 let mut num: [u8; 8] = [0; 8];
-                reader.read(&mut num).unwrap();
+                reader.read_exact(&mut num)?;
                 nums[i] = u8to64(num);
             }
             ys.push(Fr::from_serial(nums));
         }
         serial.push((xs, ys));
     }
-    serial
+    Ok(serial)
 }
 
-pub fn address(addr_sk: String) -> String {
-    let addr_sk = str2sk(addr_sk);
-    assert_eq!(addr_sk.len(), ADSK);
-    let mut rng = XorShiftRng::from_seed([0x5dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]); //TODO:choose the seed
-    let j = JubJub::new();
-    let (mut xp, mut yp) = Point::rand(&mut rng, &j).coordinate();
-    let mut x0 = Fr::zero();
-    let mut y0 = Fr::one();
-
-    for i in 0..addr_sk.len() {
-        if addr_sk[i] {
-            let res = point_add(&x0, &y0, &xp, &yp, &j);
-            x0 = res.0;
-            y0 = res.1;
-        }
-        if i != addr_sk.len() - 1 {
-            let res = point_double(xp, yp, &j);
-            xp = res.0;
-            yp = res.1;
-        }
+pub(crate) fn point2fr(point: ([u64; 4], [u64; 4])) -> Result<(Fr, Fr), Error> {
+    let x = u6442fr(point.0)?;
+    let y = u6442fr(point.1)?;
+
+    //-x^2 + y^2 = 1 + d*x^2*y^2
+    let mut xx = x;
+    xx.square();
+    let mut yy = y;
+    yy.square();
+    let mut lhs = yy;
+    lhs.sub_assign(&xx);
+    let mut rhs = JubJub::new().d;
+    rhs.mul_assign(&xx);
+    rhs.mul_assign(&yy);
+    rhs.add_assign(&Fr::one());
+    if lhs != rhs {
+        return Err(Error::NotOnCurve);
     }
 
-    point2str((x0.into_repr().serial(), y0.into_repr().serial()))
+    Ok((x, y))
 }
 
-fn point_double(x: Fr, y: Fr, j: &JubJub) -> (Fr, Fr) {
+pub fn address(addr_sk: String) -> Result<String, Error> {
+    let addr_sk = str2sk(addr_sk)?;
+    let (x0, y0) = point_mul(p1(), addr_sk);
+
+    Ok(point2str((
+        x0.into_repr().serial(),
+        y0.into_repr().serial(),
+    )))
+}
+
+pub(crate) fn point_double(x: Fr, y: Fr, j: &JubJub) -> (Fr, Fr) {
     point_add(&x, &y, &x, &y, j)
 }
 
-fn point_add(x0: &Fr, y0: &Fr, xp: &Fr, yp: &Fr, j: &JubJub) -> (Fr, Fr) {
+pub(crate) fn point_add(x0: &Fr, y0: &Fr, xp: &Fr, yp: &Fr, j: &JubJub) -> (Fr, Fr) {
     let mut y1y2 = y0.clone();
     y1y2.mul_assign(yp);
     let mut x1x2 = x0.clone();
@@ -183,110 +328,60 @@ fn point_add(x0: &Fr, y0: &Fr, xp: &Fr, yp: &Fr, j: &JubJub) -> (Fr, Fr) {
     (x.clone(), y.clone())
 }
 
-pub fn ecc_add(point1: String, point2: String) -> String {
-    let point1 = str2point(point1);
-    let point2 = str2point(point2);
-    let (xfr, yfr) = point_add(
-        &Fr::from_repr(FrRepr::from_serial(point1.0)).unwrap(),
-        &Fr::from_repr(FrRepr::from_serial(point1.1)).unwrap(),
-        &Fr::from_repr(FrRepr::from_serial(point2.0)).unwrap(),
-        &Fr::from_repr(FrRepr::from_serial(point2.1)).unwrap(),
-        &JubJub::new(),
-    );
+pub fn ecc_add(point1: String, point2: String) -> Result<String, Error> {
+    let point1 = point2fr(str2point(point1)?)?;
+    let point2 = point2fr(str2point(point2)?)?;
+    let (xfr, yfr) = point_add(&point1.0, &point1.1, &point2.0, &point2.1, &JubJub::new());
     let x = xfr.into_repr().serial();
     let y = yfr.into_repr().serial();
-    point2str((x, y))
+    Ok(point2str((x, y)))
 }
 
-pub fn ecc_sub(point1: String, point2: String) -> String {
-    let point1 = str2point(point1);
-    let point2 = str2point(point2);
-    let mut temp = Fr::from_repr(FrRepr::from_serial(point2.0)).unwrap();
+pub fn ecc_sub(point1: String, point2: String) -> Result<String, Error> {
+    let point1 = point2fr(str2point(point1)?)?;
+    let point2 = point2fr(str2point(point2)?)?;
+    let mut temp = point2.0;
     temp.negate();
-    let (xfr, yfr) = point_add(
-        &Fr::from_repr(FrRepr::from_serial(point1.0)).unwrap(),
-        &Fr::from_repr(FrRepr::from_serial(point1.1)).unwrap(),
-        &temp,
-        &Fr::from_repr(FrRepr::from_serial(point2.1)).unwrap(),
-        &JubJub::new(),
-    );
+    let (xfr, yfr) = point_add(&point1.0, &point1.1, &temp, &point2.1, &JubJub::new());
     let x = xfr.into_repr().serial();
     let y = yfr.into_repr().serial();
-    point2str((x, y))
+    Ok(point2str((x, y)))
 }
 
 pub fn v_p1_add_r_p2(v: [u64; 2], r: [u64; 2]) -> String {
-    let v = {
-        let mut vec = Vec::with_capacity(128);
-        let mut num = v[0];
-        for _ in 0..64 {
-            vec.push(num & 1 == 1);
-            num >>= 1;
-        }
-        let mut num = v[1];
-        for _ in 0..64 {
-            vec.push(num & 1 == 1);
-            num >>= 1;
-        }
-        vec
-    };
-    let r = {
-        let mut vec = Vec::with_capacity(128);
-        let mut num = r[0];
-        for _ in 0..64 {
-            vec.push(num & 1 == 1);
-            num >>= 1;
-        }
-        let mut num = r[1];
-        for _ in 0..64 {
-            vec.push(num & 1 == 1);
-            num >>= 1;
-        }
-        vec
-    };
+    v_p1_add_rh_p2(v, [r[0], r[1], 0, 0])
+}
+
+//v*P1+rh*P2 with a 256 bit blinding, as the encrypted balances
+pub fn v_p1_add_rh_p2(v: [u64; 2], rh: [u64; 4]) -> String {
+    value_commitment(v, 0, rh)
+}
 
-    let mut rng = XorShiftRng::from_seed([0x5dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]); //TODO:choose the seed
+//v*G+rh*P2 for the value generator G of the asset, P1 for asset 0
+pub fn value_commitment(v: [u64; 2], asset: u64, rh: [u64; 4]) -> String {
     let j = JubJub::new();
 
-    let (mut xp, mut yp) = Point::rand(&mut rng, &j).coordinate();
-    let mut x0 = Fr::zero();
-    let mut y0 = Fr::one();
+    let (x0, y0) = point_mul(asset_generator(asset), u64s2bits(&v));
+    let (x1, y1) = point_mul(p2(), u64s2bits(&rh));
+    let (x0, y0) = point_add(&x0, &y0, &x1, &y1, &j);
 
-    for i in 0..v.len() {
-        if v[i] {
-            let res = point_add(&x0, &y0, &xp, &yp, &j);
-            x0 = res.0;
-            y0 = res.1;
-        }
-        if i != v.len() - 1 {
-            let res = point_double(xp, yp, &j);
-            xp = res.0;
-            yp = res.1;
-        }
-    }
+    point2str((x0.into_repr().serial(), y0.into_repr().serial()))
+}
 
-    let (mut xp, mut yp) = Point::rand(&mut rng, &j).coordinate();
-    for i in 0..r.len() {
-        if r[i] {
-            let res = point_add(&x0, &y0, &xp, &yp, &j);
-            x0 = res.0;
-            y0 = res.1;
-        }
-        if i != r.len() - 1 {
-            let res = point_double(xp, yp, &j);
-            xp = res.0;
-            yp = res.1;
+pub(crate) fn u64s2bits(nums: &[u64]) -> Vec<bool> {
+    let mut v = Vec::with_capacity(nums.len() * 64);
+    for num in nums.iter() {
+        let mut num = *num;
+        for _ in 0..64 {
+            v.push(num & 1 == 1);
+            num >>= 1;
         }
     }
-
-    point2str((x0.into_repr().serial(), y0.into_repr().serial()))
+    v
 }
 
-fn point_mul(point: ([u64; 4], [u64; 4]), num: Vec<bool>) -> (Fr, Fr) {
-    let (mut xp, mut yp) = (
-        Fr::from_repr(FrRepr::from_serial(point.0)).unwrap(),
-        Fr::from_repr(FrRepr::from_serial(point.1)).unwrap(),
-    );
+pub(crate) fn point_mul(point: (Fr, Fr), num: Vec<bool>) -> (Fr, Fr) {
+    let (mut xp, mut yp) = point;
     let mut x0 = Fr::zero();
     let mut y0 = Fr::one();
     let j = JubJub::new();
@@ -307,77 +402,274 @@ fn point_mul(point: ([u64; 4], [u64; 4]), num: Vec<bool>) -> (Fr, Fr) {
     (x0, y0)
 }
 
-pub fn encrypt(message: [u64; 4], random: [u64; 4], address: String) -> String {
-    let address = str2point(address);
-    let random = Fr::from_serial(random).into_repr().serial();
-    let random = {
-        let mut v = vec![];
-        for i in 0..4 {
-            let mut num = random[i];
-            for _ in 0..64 {
-                v.push(num & 1 == 1);
-                num >>= 1;
-            }
-        }
-        v
-    };
-    let rq = point_mul(address, random.clone());
-    let mut enc = Fr::from_repr(FrRepr::from_serial(message)).unwrap();
-    enc.add_assign(&rq.0);
+fn u6442bytes(num: [u64; 4], bytes: &mut Vec<u8>) {
+    for i in 0..4 {
+        bytes.extend_from_slice(&u64to8(num[i]));
+    }
+}
 
-    let mut rng = XorShiftRng::from_seed([0x5dbe6259, 0x8d313d76, 0x3237db17, 0xe5bc0654]); //TODO:choose the seed
-    let j = JubJub::new();
-    let (mut xp, mut yp) = Point::rand(&mut rng, &j).coordinate();
-    let mut x0 = Fr::zero();
-    let mut y0 = Fr::one();
-    for i in 0..random.len() {
-        if random[i] {
-            let res = point_add(&x0, &y0, &xp, &yp, &j);
-            x0 = res.0;
-            y0 = res.1;
-        }
-        if i != random.len() - 1 {
-            let res = point_double(xp, yp, &j);
-            xp = res.0;
-            yp = res.1;
-        }
+//ChaCha20-Poly1305 key of a memo, from the shared point S = r*enc_key = ivk*rP
+fn note_key(sy: Fr, rp: ([u64; 4], [u64; 4])) -> Vec<u8> {
+    let mut input = b"Zktx_kdf".to_vec();
+    u6442bytes(sy.into_repr().serial(), &mut input);
+    u6442bytes(rp.0, &mut input);
+    u6442bytes(rp.1, &mut input);
+    blake2b(32, &[], &input).as_bytes().to_vec()
+}
+
+//every note has its own key, so a fixed nonce is never reused
+const NOTE_NONCE: [u8; 12] = [0; 12];
+
+//(PH(S.y|rP.y), PH(rP.y|S.y)), the pads of e1 and e2
+fn note_pads(sy: Fr, rpy: Fr) -> Result<(Fr, Fr), Error> {
+    let sy = u64s2bits(&sy.into_repr().serial());
+    let rpy = u64s2bits(&rpy.into_repr().serial());
+    let mut v = sy.clone();
+    v.extend(rpy.iter());
+    let pad1 = u6442fr(super::pedersen_hash(&v)?)?;
+    let mut v = rpy;
+    v.extend(sy.iter());
+    let pad2 = u6442fr(super::pedersen_hash(&v)?)?;
+    Ok((pad1, pad2))
+}
+
+//public input binding a proof to the memo ciphertext; 248 bits so it is always canonical.
+//
+//e1 and e2 carry the coin's opening and are proven in the circuit, the memo is
+//not: a proof fixes the ciphertext it was made with, so the memo cannot be
+//swapped afterwards, but it may not decrypt.
+pub(crate) fn ct_hash(enc: &(([u64; 4], [u64; 4]), Vec<u8>)) -> Result<Fr, Error> {
+    let mut input = b"Zktx_cth".to_vec();
+    u6442bytes((enc.0).0, &mut input);
+    u6442bytes((enc.0).1, &mut input);
+    input.extend_from_slice(&enc.1);
+    let mut digest = [0u8; 32];
+    digest[..31].copy_from_slice(&blake2s(32, &[], &input).as_bytes()[..31]);
+    let mut num = [0u64; 4];
+    for i in 0..4 {
+        num[i] = u8sto64(&digest[i * 8..(i + 1) * 8]);
     }
+    u6442fr(num)
+}
 
-    enc2str((
-        x0.into_repr().serial(),
-        y0.into_repr().serial(),
-        enc.into_repr().serial(),
+//(rP, e1, e2, ct_hash), the public inputs of a note in b2c, p2c and c2c
+pub(crate) fn note_inputs(
+    enc: &(([u64; 4], [u64; 4]), Vec<u8>),
+) -> Result<((Fr, Fr), Fr, Fr, Fr), Error> {
+    let ct = &enc.1;
+    let mut e = [[0u64; 4]; 2];
+    for i in 0..8 {
+        e[i / 4][i % 4] = u8sto64(&ct[i * 8..(i + 1) * 8]);
+    }
+    Ok((
+        point2fr(enc.0)?,
+        u6442fr(e[0])?,
+        u6442fr(e[1])?,
+        ct_hash(enc)?,
     ))
 }
 
-pub fn decrypt(secret: String, sk: String) -> ([u64; 2], [u64; 2]) {
-    let sk = str2sk(sk);
-    let secret = str2enc(secret);
-    let rqx = point_mul((secret.0, secret.1), sk).0;
-    let mut message = Fr::from_repr(FrRepr::from_serial(secret.2)).unwrap();
-    message.sub_assign(&rqx);
-    let message = message.into_repr().serial();
-    let va = [message[2], message[3]];
-    let rcm = [message[0], message[1]];
-    (va, rcm)
+//enc_key is ivk*P1 of the receiver, see keys
+pub fn encrypt(
+    va: [u64; 2],
+    rcm: [u64; 2],
+    asset: u64,
+    memo: &[u8],
+    random: [u64; 4],
+    enc_key: String,
+) -> Result<String, Error> {
+    if memo.len() > MEMO_SIZE {
+        return Err(Error::WrongLength {
+            expected: MEMO_SIZE,
+            found: memo.len(),
+        });
+    }
+    let enc_key = point2fr(str2point(enc_key)?)?;
+    let random = u64s2bits(&Fr::from_serial(random).into_repr().serial());
+    let s = point_mul(enc_key, random.clone());
+    let (x0, y0) = point_mul(p1(), random);
+
+    let rp = (x0.into_repr().serial(), y0.into_repr().serial());
+
+    //e1 = pad1+va+asset*2^128, e2 = pad2+rcm as proven in common_verify::note
+    let (mut e1, mut e2) = note_pads(s.1, y0)?;
+    e1.add_assign(&Fr::from_serial([va[0], va[1], asset, 0]));
+    e2.add_assign(&Fr::from_serial([rcm[0], rcm[1], 0, 0]));
+    let mut ct = Vec::with_capacity(NOTE_CIPHERTEXT);
+    u6442bytes(e1.into_repr().serial(), &mut ct);
+    u6442bytes(e2.into_repr().serial(), &mut ct);
+
+    let mut plaintext = Vec::with_capacity(NOTE_PLAINTEXT);
+    plaintext.push(memo.len() as u8);
+    plaintext.push((memo.len() >> 8) as u8);
+    plaintext.extend_from_slice(memo);
+    plaintext.resize(NOTE_PLAINTEXT, 0);
+
+    //rP | e1 | e2
+    let mut aad = vec![];
+    u6442bytes(rp.0, &mut aad);
+    u6442bytes(rp.1, &mut aad);
+    aad.extend_from_slice(&ct);
+    let mut memo_ct = Vec::with_capacity(NOTE_PLAINTEXT);
+    let tag = chacha20_poly1305_aead::encrypt(
+        &note_key(s.1, rp),
+        &NOTE_NONCE,
+        &aad,
+        &plaintext,
+        &mut memo_ct,
+    )?;
+    ct.extend_from_slice(&memo_ct);
+    ct.extend_from_slice(&tag);
+    Ok(enc2str(rp, &ct))
 }
 
-pub fn u644add(num1: [u64; 4], num2: [u64; 4]) -> [u64; 4] {
-    let mut fr1 = Fr::from_repr(FrRepr::from_serial(num1)).unwrap();
-    let fr2 = Fr::from_repr(FrRepr::from_serial(num2)).unwrap();
+//(va, rcm, asset, memo); S = ivk*rP = r*enc_key.
+//The coin is not checked here: one proven to the key always matches, see keys.
+pub fn decrypt(secret: String, ivk: String) -> Result<([u64; 2], [u64; 2], u64, Vec<u8>), Error> {
+    let ivk = str2sk(ivk)?;
+    let (rp, ct) = str2enc(secret)?;
+    let rp_fr = point2fr(rp)?;
+    let s = point_mul(rp_fr, ivk);
+
+    let mut aad = vec![];
+    u6442bytes(rp.0, &mut aad);
+    u6442bytes(rp.1, &mut aad);
+    aad.extend_from_slice(&ct[..64]);
+    let (memo_ct, tag) = ct[64..].split_at(NOTE_PLAINTEXT);
+    let mut plaintext = Vec::with_capacity(NOTE_PLAINTEXT);
+    if chacha20_poly1305_aead::decrypt(
+        &note_key(s.1, rp),
+        &NOTE_NONCE,
+        &aad,
+        memo_ct,
+        tag,
+        &mut plaintext,
+    )
+    .is_err()
+    {
+        return Err(Error::DecryptionFailed);
+    }
+    let len = plaintext[0] as usize | (plaintext[1] as usize) << 8;
+    if len > MEMO_SIZE {
+        return Err(Error::DecryptionFailed);
+    }
+
+    let (_, mut m1, mut m2, _) = note_inputs(&(rp, ct.clone()))?;
+    let (pad1, pad2) = note_pads(s.1, rp_fr.1)?;
+    m1.sub_assign(&pad1);
+    m2.sub_assign(&pad2);
+    let m1 = m1.into_repr().serial();
+    let m2 = m2.into_repr().serial();
+    //va < 2^128, asset < 2^64 and rcm < 2^128
+    if m1[3] != 0 || m2[2] != 0 || m2[3] != 0 {
+        return Err(Error::DecryptionFailed);
+    }
+    Ok((
+        [m1[0], m1[1]],
+        [m2[0], m2[1]],
+        m1[2],
+        plaintext[2..2 + len].to_vec(),
+    ))
+}
+
+pub fn u644add(num1: [u64; 4], num2: [u64; 4]) -> Result<[u64; 4], Error> {
+    let mut fr1 = u6442fr(num1)?;
+    let fr2 = u6442fr(num2)?;
     fr1.add_assign(&fr2);
-    fr1.into_repr().serial()
+    Ok(fr1.into_repr().serial())
 }
 
-pub fn u644sub(num1: [u64; 4], num2: [u64; 4]) -> [u64; 4] {
-    let mut fr1 = Fr::from_repr(FrRepr::from_serial(num1)).unwrap();
-    let fr2 = Fr::from_repr(FrRepr::from_serial(num2)).unwrap();
+pub fn u644sub(num1: [u64; 4], num2: [u64; 4]) -> Result<[u64; 4], Error> {
+    let mut fr1 = u6442fr(num1)?;
+    let fr2 = u6442fr(num2)?;
     fr1.sub_assign(&fr2);
-    fr1.into_repr().serial()
+    Ok(fr1.into_repr().serial())
 }
 
-pub fn check(coin: String, enc: String, sk: String) -> bool {
-    let (va, rcm) = decrypt(enc, sk.clone());
-    let coin2 = super::build_coin(address(sk), va, rcm);
-    coin2 == coin
+pub fn check(coin: String, enc: String, address: String, ivk: String) -> Result<bool, Error> {
+    let (va, rcm, asset, _) = match decrypt(enc, ivk.clone()) {
+        Ok(note) => note,
+        Err(Error::DecryptionFailed) => return Ok(false),
+        Err(e) => return Err(e),
+    };
+    let coin2 = super::build_coin(address, self::address(ivk)?, va, rcm, asset)?;
+    Ok(coin2 == coin)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex;
+    use params::test_generators;
+
+    fn ivk(n: u8) -> String {
+        format!("{:02x}", n).repeat(32)
+    }
+
+    fn note() -> String {
+        test_generators();
+        encrypt(
+            [5, 6],
+            [7, 8],
+            9,
+            b"memo",
+            [11, 12, 13, 1],
+            address(ivk(1)).unwrap(),
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn note_roundtrip() {
+        let opened = decrypt(note(), ivk(1)).unwrap();
+        assert_eq!(opened, ([5, 6], [7, 8], 9, b"memo".to_vec()));
+
+        let memo = vec![0xaa; MEMO_SIZE];
+        let enc = encrypt(
+            [1, 0],
+            [2, 0],
+            3,
+            &memo,
+            [4, 0, 0, 0],
+            address(ivk(1)).unwrap(),
+        );
+        assert_eq!(decrypt(enc.unwrap(), ivk(1)).unwrap().3, memo);
+    }
+
+    #[test]
+    fn rejects_wrong_key() {
+        match decrypt(note(), ivk(2)) {
+            Err(Error::DecryptionFailed) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn rejects_tampered_note() {
+        let enc = note();
+        //a byte of e1, of e2, of the memo, of the tag and of rP
+        for &at in [130, 200, 400, enc.len() - 2, 2].iter() {
+            let mut bytes = hex::decode(&enc).unwrap();
+            bytes[at / 2] ^= 1;
+            match decrypt(hex::encode(&bytes), ivk(1)) {
+                Ok(note) => panic!("opened {:?}", note),
+                Err(_) => {}
+            }
+        }
+    }
+
+    #[test]
+    fn rejects_long_memo() {
+        test_generators();
+        let memo = vec![0; MEMO_SIZE + 1];
+        assert!(encrypt(
+            [1, 0],
+            [2, 0],
+            3,
+            &memo,
+            [4, 0, 0, 0],
+            address(ivk(1)).unwrap()
+        )
+        .is_err());
+    }
 }
@@ -0,0 +1,635 @@
+//zktx command line: keys, parameters, proving and verification on JSON files.
+
+extern crate hex;
+extern crate rand;
+#[macro_use]
+extern crate serde_json;
+extern crate zktx;
+
+use rand::{OsRng, Rng};
+use serde_json::Value;
+
+use std::env;
+use std::fs::File;
+use std::io::{stdout, Write};
+use std::process::exit;
+
+use zktx::b2c::{b2c_info, b2c_verify};
+use zktx::c2b::{c2b_info, c2b_verify};
+use zktx::c2p::{c2p_info, c2p_verify};
+use zktx::common_verify::range::{range_info, range_verify};
+use zktx::hd::{new_mnemonic, PURPOSE};
+use zktx::p2c::{p2c_info, p2c_verify};
+use zktx::*;
+
+const USAGE: &'static str = "usage: zktx [--params DIR] COMMAND [-o OUT]
+
+commands:
+  keygen                       new spending key and the keys derived from it
+  address KEY.json             address and payment address of a spending key
+  mnemonic                     new 24 word mnemonic
+  derive IN.json               keys of m/32'/account'/index' from {mnemonic, passphrase}
+  params gen DIR               generate generators and all circuit parameters
+  params generators DIR        generate only the generators, enough for keys, coins and notes
+  params inspect DIR           check the parameter files in DIR against their headers
+  prove b2c|p2c|c2p|c2b|range IN.json
+  verify b2c|p2c|c2p|c2b|range PROOF.json
+  decrypt KEY.json NOTE.json   open a note {coin, enc} with an incoming viewing key
+  coin build IN.json           note {coin, enc} of {to, va, rcm, asset, memo} for a payment address to
+
+Values are decimal strings, keys, points and 256 bit randoms are hex.
+A missing passphrase is empty, a missing account or index is 0.
+A missing asset is asset 0, a missing fee or max_fee is 0; p2c, c2p and c2b
+refuse to prove a fee above max_fee.
+Missing rcm, rcm_new, rh and enc_random are drawn at random and written out.";
+
+type CliResult<T> = Result<T, String>;
+
+fn err<E: ToString>(e: E) -> String {
+    e.to_string()
+}
+
+fn read_json(path: &str) -> CliResult<Value> {
+    let file = File::open(path).map_err(|e| format!("{}: {}", path, e))?;
+    serde_json::from_reader(file).map_err(|e| format!("{}: {}", path, e))
+}
+
+fn write_json(value: &Value, out: Option<&str>) -> CliResult<()> {
+    let text = serde_json::to_string_pretty(value).map_err(err)?;
+    match out {
+        Some(path) => {
+            let mut file = File::create(path).map_err(|e| format!("{}: {}", path, e))?;
+            writeln!(file, "{}", text).map_err(err)
+        }
+        None => writeln!(stdout(), "{}", text).map_err(err),
+    }
+}
+
+fn field(v: &Value, name: &str) -> CliResult<String> {
+    match v.get(name) {
+        Some(&Value::String(ref s)) => Ok(s.clone()),
+        Some(_) => Err(format!("field {} is not a string", name)),
+        None => Err(format!("missing field {}", name)),
+    }
+}
+
+fn value2str(va: [u64; 2]) -> String {
+    value2u128(va).to_string()
+}
+
+fn str2value128(st: &str) -> CliResult<[u64; 2]> {
+    let num = st.parse::<u128>().map_err(|e| format!("{}: {}", st, e))?;
+    Ok(u1282value(num))
+}
+
+fn value(v: &Value, name: &str) -> CliResult<[u64; 2]> {
+    str2value128(&field(v, name)?)
+}
+
+fn nonce(v: &Value) -> CliResult<u64> {
+    let st = field(v, "nonce")?;
+    st.parse::<u64>().map_err(|e| format!("{}: {}", st, e))
+}
+
+fn asset(v: &Value) -> CliResult<u64> {
+    match v.get("asset") {
+        Some(_) => {
+            let st = field(v, "asset")?;
+            st.parse::<u64>().map_err(|e| format!("{}: {}", st, e))
+        }
+        None => Ok(0),
+    }
+}
+
+//optional child index, 0 when missing
+fn child_index(v: &Value, name: &str) -> CliResult<u32> {
+    match v.get(name) {
+        Some(_) => {
+            let st = field(v, name)?;
+            st.parse::<u32>().map_err(|e| format!("{}: {}", st, e))
+        }
+        None => Ok(0),
+    }
+}
+
+//optional value, 0 when missing
+fn value_or_zero(v: &Value, name: &str) -> CliResult<[u64; 2]> {
+    match v.get(name) {
+        Some(_) => value(v, name),
+        None => Ok([0, 0]),
+    }
+}
+
+//(fee, max_fee), the prover refuses a fee above max_fee
+fn fee(v: &Value) -> CliResult<([u64; 2], [u64; 2])> {
+    Ok((value_or_zero(v, "fee")?, value_or_zero(v, "max_fee")?))
+}
+
+fn signed(v: &Value, name: &str) -> CliResult<([u64; 2], bool)> {
+    str2value(field(v, name)?).map_err(err)
+}
+
+fn signed2str(num: ([u64; 2], bool)) -> String {
+    let abs = value2str(num.0);
+    if num.1 {
+        abs
+    } else {
+        format!("-{}", abs)
+    }
+}
+
+//given 128 bit value or a fresh random one
+fn value_or_random<R: Rng>(v: &Value, name: &str, rng: &mut R) -> CliResult<[u64; 2]> {
+    match v.get(name) {
+        Some(_) => value(v, name),
+        None => Ok([rng.gen(), rng.gen()]),
+    }
+}
+
+//given 256 bit number or a fresh random one, below 2^252 so it is a canonical Fr
+fn u644_or_random<R: Rng>(v: &Value, name: &str, rng: &mut R) -> CliResult<[u64; 4]> {
+    match v.get(name) {
+        Some(_) => str2u644(field(v, name)?).map_err(err),
+        None => Ok(random_u644(rng)),
+    }
+}
+
+fn memo(v: &Value) -> CliResult<Vec<u8>> {
+    match v.get("memo") {
+        Some(_) => Ok(field(v, "memo")?.into_bytes()),
+        None => Ok(vec![]),
+    }
+}
+
+fn path(v: &Value) -> CliResult<(Vec<String>, Vec<bool>)> {
+    let auth = match v.get("path") {
+        Some(&Value::Array(ref auth)) => auth
+            .iter()
+            .map(|p| p.as_str().map(|p| p.to_string()))
+            .collect::<Option<Vec<_>>>(),
+        _ => None,
+    };
+    let loc = match v.get("loc") {
+        Some(&Value::Array(ref loc)) => loc.iter().map(|b| b.as_bool()).collect::<Option<Vec<_>>>(),
+        _ => None,
+    };
+    match (auth, loc) {
+        (Some(auth), Some(loc)) => Ok((auth, loc)),
+        _ => Err("path must be an array of hex strings and loc an array of booleans".to_string()),
+    }
+}
+
+fn spending_key(v: &Value) -> CliResult<SpendingKey> {
+    field(v, "spending_key")?.parse().map_err(err)
+}
+
+fn keys(sk: &SpendingKey) -> CliResult<Value> {
+    let fvk = sk.full_viewing_key().map_err(err)?;
+    let ivk = fvk.incoming_viewing_key().map_err(err)?;
+    Ok(json!({
+        "spending_key": sk.to_string(),
+        "full_viewing_key": fvk.to_string(),
+        "incoming_viewing_key": ivk.to_string(),
+        "address": ivk.address().to_string(),
+        "payment_address": ivk.payment_address().map_err(err)?.to_string(),
+    }))
+}
+
+fn keygen() -> CliResult<Value> {
+    let mut rng = OsRng::new().map_err(err)?;
+    let mut bytes = [0u8; 32];
+    rng.fill_bytes(&mut bytes);
+    let sk: SpendingKey = bytes
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect::<String>()
+        .parse()
+        .map_err(err)?;
+    keys(&sk)
+}
+
+fn mnemonic() -> CliResult<Value> {
+    let mut rng = OsRng::new().map_err(err)?;
+    Ok(json!({ "mnemonic": new_mnemonic(&mut rng) }))
+}
+
+fn derive(v: &Value) -> CliResult<Value> {
+    let passphrase = match v.get("passphrase") {
+        Some(_) => field(v, "passphrase")?,
+        None => String::new(),
+    };
+    let account = child_index(v, "account")?;
+    let index = child_index(v, "index")?;
+    let key = ExtendedSpendingKey::from_mnemonic(&field(v, "mnemonic")?, &passphrase)
+        .map_err(err)?
+        .account(account)
+        .map_err(err)?
+        .child(index)
+        .map_err(err)?;
+    let mut res = keys(&key.spending_key())?;
+    res["path"] = json!(format!("m/{}'/{}'/{}'", PURPOSE, account, index));
+    res["extended_spending_key"] = json!(key.to_string());
+    Ok(res)
+}
+
+fn show_address(key: &Value) -> CliResult<Value> {
+    let ivk = spending_key(key)?.incoming_viewing_key().map_err(err)?;
+    Ok(json!({
+        "address": ivk.address().to_string(),
+        "payment_address": ivk.payment_address().map_err(err)?.to_string(),
+    }))
+}
+
+fn params_inspect(dir: &str) -> CliResult<Value> {
+    let mut files = serde_json::Map::new();
+    for file in zktx::params::inspect(dir).into_iter() {
+        let header = file.header.map(|h| {
+            json!({
+                "version": h.version,
+                "circuit": h.circuit.map_or("generators".to_string(), |kind| {
+                    format!("{:?}", kind).to_lowercase()
+                }),
+                "constraints": h.constraints,
+                "length": h.length,
+                "digest": hex::encode(&h.digest[..]),
+            })
+        });
+        files.insert(
+            file.name,
+            json!({
+                "path": file.path.to_string_lossy(),
+                "present": file.bytes.is_some(),
+                "bytes": file.bytes,
+                "header": header,
+                "ok": file.error.is_none(),
+                "error": file.error,
+            }),
+        );
+    }
+    Ok(Value::Object(files))
+}
+
+fn prove(circuit: &str, input: &Value) -> CliResult<Value> {
+    let rng = &mut rand::thread_rng();
+    match circuit {
+        "b2c" => {
+            let sk = spending_key(input)?;
+            let to: PaymentAddress = field(input, "to")?.parse().map_err(err)?;
+            let va = value(input, "va")?;
+            let asset = asset(input)?;
+            let rcm = value_or_random(input, "rcm", rng)?;
+            let enc_random = u644_or_random(input, "enc_random", rng)?;
+            let (proof, coin, enc) = b2c_info(
+                rcm,
+                va,
+                asset,
+                to.addr.to_string(),
+                to.enc_key.to_string(),
+                sk.to_string(),
+                enc_random,
+                memo(input)?,
+            )
+            .map_err(err)?;
+            Ok(json!({
+                "va": value2str(va),
+                "asset": asset.to_string(),
+                "coin": coin,
+                "enc": enc,
+                "address": sk.address().map_err(err)?.to_string(),
+                "proof": proof,
+                "rcm": value2str(rcm),
+            }))
+        }
+        "p2c" => {
+            let sk = spending_key(input)?;
+            let to: PaymentAddress = field(input, "to")?.parse().map_err(err)?;
+            let ba = value(input, "balance")?;
+            let va = value(input, "va")?;
+            let asset = asset(input)?;
+            let (fee, max_fee) = fee(input)?;
+            let nonce = nonce(input)?;
+            let rh = str2u644(field(input, "rh")?).map_err(err)?;
+            let rcm = value_or_random(input, "rcm", rng)?;
+            let enc_random = u644_or_random(input, "enc_random", rng)?;
+            let (proof, hb, coin, delt_ba, enc) = p2c_info(
+                rh,
+                rcm,
+                ba,
+                va,
+                asset,
+                fee,
+                max_fee,
+                nonce,
+                to.addr.to_string(),
+                to.enc_key.to_string(),
+                sk.to_string(),
+                enc_random,
+                memo(input)?,
+            )
+            .map_err(err)?;
+            Ok(json!({
+                "hb": hb,
+                "coin": coin,
+                "delt_ba": delt_ba,
+                "enc": enc,
+                "address": sk.address().map_err(err)?.to_string(),
+                "asset": asset.to_string(),
+                "fee": value2str(fee),
+                "nonce": nonce.to_string(),
+                "proof": proof,
+                "va": value2str(va),
+                "rcm": value2str(rcm),
+            }))
+        }
+        "c2p" => {
+            let sk = spending_key(input)?;
+            let va = value(input, "va")?;
+            let rcm = value(input, "rcm")?;
+            let asset = asset(input)?;
+            let (fee, max_fee) = fee(input)?;
+            let rcm_new = value_or_random(input, "rcm_new", rng)?;
+            let (auth, loc) = path(input)?;
+            let (proof, nullifier, root, delt_ba) = c2p_info(
+                rcm,
+                rcm_new,
+                va,
+                asset,
+                fee,
+                max_fee,
+                sk.to_string(),
+                auth,
+                loc,
+            )
+            .map_err(err)?;
+            Ok(json!({
+                "nullifier": nullifier,
+                "root": root,
+                "delt_ba": delt_ba,
+                "asset": asset.to_string(),
+                "fee": value2str(fee),
+                "proof": proof,
+                "rcm_new": value2str(rcm_new),
+            }))
+        }
+        "c2b" => {
+            let sk = spending_key(input)?;
+            let va = value(input, "va")?;
+            let asset = asset(input)?;
+            let (fee, max_fee) = fee(input)?;
+            let rcm = value(input, "rcm")?;
+            let to = field(input, "address")?;
+            let (auth, loc) = path(input)?;
+            let (proof, nullifier, root) = c2b_info(
+                rcm,
+                va,
+                asset,
+                fee,
+                max_fee,
+                to.clone(),
+                sk.to_string(),
+                auth,
+                loc,
+            )
+            .map_err(err)?;
+            Ok(json!({
+                "address": to,
+                "va": value2str(va),
+                "asset": asset.to_string(),
+                "fee": value2str(fee),
+                "nullifier": nullifier,
+                "root": root,
+                "proof": proof,
+            }))
+        }
+        "range" => {
+            let up = signed(input, "up")?;
+            let low = signed(input, "low")?;
+            let rh = value_or_random(input, "rh", rng)?;
+            let (proof, hv) = range_info(up, signed(input, "va")?, rh, low).map_err(err)?;
+            Ok(json!({
+                "up": signed2str(up),
+                "low": signed2str(low),
+                "hv": hv,
+                "proof": proof,
+                "rh": value2str(rh),
+            }))
+        }
+        _ => Err(format!("unknown circuit {}", circuit)),
+    }
+}
+
+fn verify(circuit: &str, input: &Value) -> CliResult<bool> {
+    let ok = match circuit {
+        "b2c" => b2c_verify(
+            value(input, "va")?,
+            asset(input)?,
+            field(input, "coin")?,
+            field(input, "enc")?,
+            field(input, "address")?,
+            field(input, "proof")?,
+        ),
+        "p2c" => p2c_verify(
+            field(input, "hb")?,
+            field(input, "coin")?,
+            field(input, "delt_ba")?,
+            field(input, "enc")?,
+            field(input, "address")?,
+            asset(input)?,
+            value_or_zero(input, "fee")?,
+            nonce(input)?,
+            field(input, "proof")?,
+        ),
+        "c2p" => c2p_verify(
+            field(input, "nullifier")?,
+            field(input, "root")?,
+            field(input, "delt_ba")?,
+            asset(input)?,
+            value_or_zero(input, "fee")?,
+            field(input, "proof")?,
+        ),
+        "c2b" => c2b_verify(
+            value(input, "va")?,
+            asset(input)?,
+            value_or_zero(input, "fee")?,
+            field(input, "address")?,
+            field(input, "nullifier")?,
+            field(input, "root")?,
+            field(input, "proof")?,
+        ),
+        "range" => range_verify(
+            signed(input, "up")?,
+            field(input, "hv")?,
+            signed(input, "low")?,
+            field(input, "proof")?,
+        ),
+        _ => return Err(format!("unknown circuit {}", circuit)),
+    };
+    ok.map_err(err)
+}
+
+fn open_note(key: &Value, note: &Value) -> CliResult<Value> {
+    let ivk: IncomingViewingKey = match key.get("incoming_viewing_key") {
+        Some(_) => field(key, "incoming_viewing_key")?.parse().map_err(err)?,
+        None => spending_key(key)?.incoming_viewing_key().map_err(err)?,
+    };
+    let coin: Coin = field(note, "coin")?.parse().map_err(err)?;
+    let enc: EncryptedNote = field(note, "enc")?.parse().map_err(err)?;
+    match ivk.open(&coin, &enc).map_err(err)? {
+        Some((va, rcm, asset, memo)) => Ok(json!({
+            "va": value2str(va),
+            "rcm": value2str(rcm),
+            "asset": asset.to_string(),
+            "memo": String::from_utf8_lossy(&memo),
+        })),
+        None => Err("note is not addressed to this key".to_string()),
+    }
+}
+
+//the coin and its note encrypted to the payment address, as decrypt reads them
+fn coin_build(input: &Value) -> CliResult<Value> {
+    let rng = &mut rand::thread_rng();
+    let to: PaymentAddress = field(input, "to")?.parse().map_err(err)?;
+    let va = value(input, "va")?;
+    let rcm = value_or_random(input, "rcm", rng)?;
+    let asset = asset(input)?;
+    let enc_random = u644_or_random(input, "enc_random", rng)?;
+    let coin =
+        build_coin(to.addr.to_string(), to.enc_key.to_string(), va, rcm, asset).map_err(err)?;
+    let enc = zktx::base::encrypt(
+        va,
+        rcm,
+        asset,
+        &memo(input)?,
+        enc_random,
+        to.enc_key.to_string(),
+    )
+    .map_err(err)?;
+    Ok(json!({
+        "coin": coin,
+        "enc": enc,
+        "rcm": value2str(rcm),
+        "enc_random": u6442str(enc_random),
+    }))
+}
+
+fn run(mut args: Vec<String>) -> CliResult<()> {
+    let mut out = None;
+    if let Some(i) = args.iter().position(|a| a == "-o") {
+        if i + 1 >= args.len() {
+            return Err(USAGE.to_string());
+        }
+        out = Some(args.remove(i + 1));
+        args.remove(i);
+    }
+    if args.len() >= 2 && args[0] == "--params" {
+        set_param_path(&args[1]);
+        args.drain(0..2);
+    }
+    let out = out.as_ref().map(|o| o.as_str());
+
+    let args: Vec<&str> = args.iter().map(|a| a.as_str()).collect();
+    match args.as_slice() {
+        ["keygen"] => write_json(&keygen()?, out),
+        ["address", key] => write_json(&show_address(&read_json(key)?)?, out),
+        ["mnemonic"] => write_json(&mnemonic()?, out),
+        ["derive", input] => write_json(&derive(&read_json(input)?)?, out),
+        ["params", "gen", dir] => gen_params(dir).map_err(err),
+        ["params", "generators", dir] => gen_generators(dir).map_err(err),
+        ["params", "inspect", dir] => write_json(&params_inspect(dir)?, out),
+        ["prove", circuit, input] => write_json(&prove(circuit, &read_json(input)?)?, out),
+        ["verify", circuit, input] => {
+            if verify(circuit, &read_json(input)?)? {
+                println!("ok");
+                Ok(())
+            } else {
+                Err("proof is invalid".to_string())
+            }
+        }
+        ["decrypt", key, note] => write_json(&open_note(&read_json(key)?, &read_json(note)?)?, out),
+        ["coin", "build", input] => write_json(&coin_build(&read_json(input)?)?, out),
+        _ => Err(USAGE.to_string()),
+    }
+}
+
+fn main() {
+    if let Err(e) = run(env::args().skip(1).collect()) {
+        eprintln!("zktx: {}", e);
+        exit(1);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs::create_dir_all;
+    use std::path::PathBuf;
+    use std::process;
+
+    fn dir() -> PathBuf {
+        let dir = env::temp_dir().join(format!("zktx-cli-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn zktx(args: &[&str]) -> CliResult<()> {
+        run(args.iter().map(|a| a.to_string()).collect())
+    }
+
+    fn file(name: &str) -> String {
+        dir().join(name).to_str().unwrap().to_string()
+    }
+
+    #[test]
+    fn keygen_address_coin_decrypt() {
+        let params = file("params");
+        zktx(&["params", "generators", &params]).unwrap();
+        let key = file("key.json");
+        zktx(&["--params", &params, "keygen", "-o", &key]).unwrap();
+        let address = file("address.json");
+        zktx(&["--params", &params, "address", &key, "-o", &address]).unwrap();
+        let keys = read_json(&key).unwrap();
+        let addresses = read_json(&address).unwrap();
+        assert_eq!(addresses["address"], keys["address"]);
+        assert_eq!(addresses["payment_address"], keys["payment_address"]);
+
+        let input = file("coin_in.json");
+        write_json(
+            &json!({
+                "to": addresses["payment_address"],
+                "va": "1000",
+                "rcm": "77",
+                "asset": "3",
+                "memo": "rent",
+            }),
+            Some(&input),
+        )
+        .unwrap();
+        let note = file("note.json");
+        zktx(&["--params", &params, "coin", "build", &input, "-o", &note]).unwrap();
+
+        let opened = file("opened.json");
+        zktx(&["--params", &params, "decrypt", &key, &note, "-o", &opened]).unwrap();
+        assert_eq!(
+            read_json(&opened).unwrap(),
+            json!({"va": "1000", "rcm": "77", "asset": "3", "memo": "rent"})
+        );
+
+        //another key neither opens it nor is fooled by a coin of another value
+        let other = file("other.json");
+        zktx(&["--params", &params, "keygen", "-o", &other]).unwrap();
+        assert!(zktx(&["--params", &params, "decrypt", &other, &note, "-o", &opened]).is_err());
+        let mut forged = read_json(&note).unwrap();
+        let mut input = read_json(&input).unwrap();
+        input["va"] = json!("1001");
+        forged["coin"] = coin_build(&input).unwrap()["coin"].clone();
+        write_json(&forged, Some(&note)).unwrap();
+        assert!(zktx(&["--params", &params, "decrypt", &key, &note, "-o", &opened]).is_err());
+    }
+
+    #[test]
+    fn usage() {
+        assert_eq!(zktx(&[]), Err(USAGE.to_string()));
+        assert_eq!(zktx(&["coin", "spend"]), Err(USAGE.to_string()));
+        assert_eq!(zktx(&["keygen", "-o"]), Err(USAGE.to_string()));
+    }
+}
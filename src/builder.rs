@@ -0,0 +1,242 @@
+//Ready-to-submit p2c and c2p messages.
+//
+//rcm, rcm_new and enc_random are drawn from the rng given to the TxBuilder,
+//which has to be a CSPRNG such as OsRng. The secrets the prover must keep
+//come back next to each message.
+
+use rand::Rng;
+
+use balance::BalanceTracker;
+use contract::{ReceiverProof, SenderProof};
+use convert::{random_u644, u1282value, value2u128};
+use error::Error;
+use keys::PaymentAddress;
+use typed::{c2p_info, p2c_info};
+use types::*;
+use wallet::{Wallet, WalletNote};
+
+//the coin a p2c created
+#[derive(Clone, Debug)]
+pub struct SentNote {
+    pub to: PaymentAddress,
+    pub coin: Coin,
+    pub va: [u64; 2],
+    pub rcm: [u64; 2],
+    pub asset: u64,
+    pub fee: [u64; 2],
+    pub memo: Vec<u8>,
+}
+
+impl SentNote {
+    //once the send is accepted, the balance loses va+fee
+    pub fn debit(&self, tracker: &mut BalanceTracker) -> Result<(), Error> {
+        let total = match value2u128(self.va).checked_add(value2u128(self.fee)) {
+            Some(total) => total,
+            None => return Err(Error::BalanceOutOfRange),
+        };
+        tracker.send(u1282value(total), self.rcm)
+    }
+}
+
+//what a c2p moved into the balance
+#[derive(Clone, Debug)]
+pub struct ReceivedNote {
+    pub nullifier: Nullifier,
+    //va-fee
+    pub va: [u64; 2],
+    pub rcm: [u64; 2],
+    pub asset: u64,
+}
+
+impl ReceivedNote {
+    //once the receipt is accepted, the balance gains va-fee
+    pub fn credit(&self, tracker: &mut BalanceTracker) -> Result<(), Error> {
+        tracker.receive(self.va, self.rcm)
+    }
+}
+
+pub struct TxBuilder<'a, R: Rng + 'a> {
+    rng: &'a mut R,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
+}
+
+impl<'a, R: Rng + 'a> TxBuilder<'a, R> {
+    //no fee until fee is called
+    pub fn new(rng: &'a mut R) -> TxBuilder<'a, R> {
+        TxBuilder {
+            rng,
+            fee: [0, 0],
+            max_fee: [0, 0],
+        }
+    }
+
+    pub fn fee(mut self, fee: [u64; 2], max_fee: [u64; 2]) -> TxBuilder<'a, R> {
+        self.fee = fee;
+        self.max_fee = max_fee;
+        self
+    }
+
+    //p2c of va from the balance followed by tracker
+    pub fn send(
+        &mut self,
+        tracker: &BalanceTracker,
+        va: [u64; 2],
+        nonce: u64,
+        to: &PaymentAddress,
+        addr_sk: &SpendingKey,
+        memo: &[u8],
+    ) -> Result<(SenderProof, SentNote), Error> {
+        let rcm = [self.rng.gen(), self.rng.gen()];
+        let enc_random = random_u644(self.rng);
+        let (proof, hb, coin, delt_ba, enc) = p2c_info(
+            tracker.rh(),
+            rcm,
+            tracker.ba(),
+            va,
+            tracker.asset(),
+            self.fee,
+            self.max_fee,
+            nonce,
+            to,
+            addr_sk,
+            enc_random,
+            memo,
+        )?;
+        Ok((
+            SenderProof {
+                proof: proof.to_string(),
+                hb: hb.to_string(),
+                coin: coin.to_string(),
+                delt_ba: delt_ba.to_string(),
+                enc: enc.to_string(),
+                asset: tracker.asset(),
+                fee: value2u128(self.fee),
+                nonce,
+            },
+            SentNote {
+                to: *to,
+                coin,
+                va,
+                rcm,
+                asset: tracker.asset(),
+                fee: self.fee,
+                memo: memo.to_vec(),
+            },
+        ))
+    }
+
+    //c2p of an unspent note of wallet, against the wallet's current root
+    pub fn receive(
+        &mut self,
+        wallet: &Wallet,
+        note: &WalletNote,
+    ) -> Result<(ReceiverProof, ReceivedNote), Error> {
+        let path = match wallet.witness_for(note) {
+            Some(path) => path,
+            None => return Err(Error::UnknownNote),
+        };
+        let rest = match value2u128(note.va).checked_sub(value2u128(self.fee)) {
+            Some(rest) => rest,
+            None => return Err(Error::BalanceOutOfRange),
+        };
+        let rcm_new = [self.rng.gen(), self.rng.gen()];
+        let (proof, nullifier, root, delt_ba) = c2p_info(
+            note.rcm,
+            rcm_new,
+            note.va,
+            note.asset,
+            self.fee,
+            self.max_fee,
+            wallet.spending_key(),
+            &path,
+        )?;
+        Ok((
+            ReceiverProof {
+                proof: proof.to_string(),
+                nullifier: nullifier.to_string(),
+                root: root.to_string(),
+                delt_ba: delt_ba.to_string(),
+                asset: note.asset,
+                fee: value2u128(self.fee),
+            },
+            ReceivedNote {
+                nullifier,
+                va: u1282value(rest),
+                rcm: rcm_new,
+                asset: note.asset,
+            },
+        ))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use balance::EncryptedBalance;
+    use base::value_commitment;
+
+    fn tracker() -> BalanceTracker {
+        BalanceTracker::new([100, 0], 2, [1, 2, 3, 0])
+    }
+
+    //va*G+rcm*P2 as the contract adds or subtracts it
+    fn delta(va: [u64; 2], rcm: [u64; 2]) -> ValueCommitment {
+        value_commitment(va, 2, [rcm[0], rcm[1], 0, 0])
+            .parse()
+            .unwrap()
+    }
+
+    fn sent(va: [u64; 2], fee: [u64; 2]) -> SentNote {
+        let to: SpendingKey = "01".repeat(32).parse().unwrap();
+        SentNote {
+            to: to
+                .incoming_viewing_key()
+                .unwrap()
+                .payment_address()
+                .unwrap(),
+            coin: "00".repeat(32).parse().unwrap(),
+            va,
+            rcm: [7, 8],
+            asset: 2,
+            fee,
+            memo: vec![],
+        }
+    }
+
+    #[test]
+    fn debit_takes_value_and_fee() {
+        let mut tracker = tracker();
+        let balance: EncryptedBalance = tracker.commitment().unwrap();
+        sent([10, 0], [3, 0]).debit(&mut tracker).unwrap();
+        assert_eq!(tracker.ba(), [87, 0]);
+        let balance = balance.sub_commitment(&delta([13, 0], [7, 8])).unwrap();
+        assert!(tracker.opens(&balance).unwrap());
+    }
+
+    #[test]
+    fn debit_rejects_overdraft() {
+        let mut tracker = tracker();
+        assert!(sent([98, 0], [3, 0]).debit(&mut tracker).is_err());
+        assert!(sent([!0, !0], [1, 0]).debit(&mut tracker).is_err());
+        assert_eq!(tracker, self::tracker());
+        sent([97, 0], [3, 0]).debit(&mut tracker).unwrap();
+        assert_eq!(tracker.ba(), [0, 0]);
+    }
+
+    #[test]
+    fn credit_adds_value_less_fee() {
+        let mut tracker = tracker();
+        let balance = tracker.commitment().unwrap();
+        let received = ReceivedNote {
+            nullifier: "00".repeat(32).parse().unwrap(),
+            va: [7, 0],
+            rcm: [9, 1],
+            asset: 2,
+        };
+        received.credit(&mut tracker).unwrap();
+        assert_eq!(tracker.ba(), [107, 0]);
+        let balance = balance.add_commitment(&delta([7, 0], [9, 1])).unwrap();
+        assert!(tracker.opens(&balance).unwrap());
+    }
+}
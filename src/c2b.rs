@@ -1,15 +1,24 @@
 use bellman::groth16::*;
+use bellman::Error as SynthesisError;
 use bellman::*;
 use pairing::bls12_381::{Bls12, Fr, FrRepr};
 use pairing::*;
-use rand::thread_rng;
 
 use jubjub::*;
 
 use base::*;
+use common_verify::batch::verify_batch;
+use common_verify::fee::sub_fee;
+use common_verify::fixed_base::*;
+use common_verify::note::{enc_key_of, payment_coin};
+use common_verify::nullifier::{incoming_viewing_key, nullifier, nullifier_key};
 use convert::*;
+use error::Error;
+use header::{check_params, generate_params, write_params};
+use params::{default_params, ParamSet};
 
-use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
 
 struct C2Bcircuit<'a> {
     generators: &'a [(Vec<Fr>, Vec<Fr>)],
@@ -17,10 +26,14 @@ struct C2Bcircuit<'a> {
 
     //r_cm
     rcm: Assignment<Fr>,
-    //Balance
-    ba: Assignment<Fr>,
     //value
     va: Assignment<Fr>,
+    //asset
+    asset: Assignment<Fr>,
+    //fee
+    fee: Assignment<Fr>,
+    //receiving address
+    to: (Assignment<Fr>, Assignment<Fr>),
     //addr_sk
     addr_sk: Vec<Assignment<bool>>,
     //coin path
@@ -41,8 +54,10 @@ impl<'a> C2Bcircuit<'a> {
             generators,
             j,
             rcm: Assignment::unknown(),
-            ba: Assignment::unknown(),
             va: Assignment::unknown(),
+            asset: Assignment::unknown(),
+            fee: Assignment::unknown(),
+            to: (Assignment::unknown(), Assignment::unknown()),
             addr_sk: (0..ADSK).map(|_| Assignment::unknown()).collect(),
             path: (0..TREEDEPTH)
                 .map(|_| (0..PHOUT).map(|_| Assignment::unknown()).collect())
@@ -56,8 +71,10 @@ impl<'a> C2Bcircuit<'a> {
         generators: &'a [(Vec<Fr>, Vec<Fr>)],
         j: &'a JubJub,
         rcm: Fr,
-        ba: Fr,
         va: Fr,
+        asset: Fr,
+        fee: Fr,
+        to: (Fr, Fr),
         addr_sk: Vec<bool>,
         path: Vec<[u64; 4]>,
         loc: Vec<bool>,
@@ -86,8 +103,10 @@ impl<'a> C2Bcircuit<'a> {
             generators,
             j,
             rcm: Assignment::known(rcm),
-            ba: Assignment::known(ba),
             va: Assignment::known(va),
+            asset: Assignment::known(asset),
+            fee: Assignment::known(fee),
+            to: (Assignment::known(to.0), Assignment::known(to.1)),
             addr_sk: addr_sk.iter().map(|&b| Assignment::known(b)).collect(),
             path: path
                 .iter()
@@ -100,28 +119,33 @@ impl<'a> C2Bcircuit<'a> {
 }
 
 struct C2BcircuitInput {
-    //H_B
-    ba: Num<Bls12>,
-    //H_B-next
+    //value
     va: Num<Bls12>,
     //nullifier
     nullifier: Num<Bls12>,
     //root
     root: Num<Bls12>,
+    //asset
+    asset: Num<Bls12>,
+    //fee
+    fee: Num<Bls12>,
+    //receiving address
+    to: (Num<Bls12>, Num<Bls12>),
 }
 
 impl<'a> Input<Bls12> for C2BcircuitInput {
-    fn synthesize<CS: PublicConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), Error> {
-        let ba_input = cs.alloc_input(|| Ok(*self.ba.getvalue().get()?))?;
+    fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
         let nullifier_input = cs.alloc_input(|| Ok(*self.nullifier.getvalue().get()?))?;
         let va_input = cs.alloc_input(|| Ok(*self.va.getvalue().get()?))?;
         let root_input = cs.alloc_input(|| Ok(*self.root.getvalue().get()?))?;
+        let asset_input = cs.alloc_input(|| Ok(*self.asset.getvalue().get()?))?;
+        let fee_input = cs.alloc_input(|| Ok(*self.fee.getvalue().get()?))?;
+        let tox_input = cs.alloc_input(|| Ok(*self.to.0.getvalue().get()?))?;
+        let toy_input = cs.alloc_input(|| Ok(*self.to.1.getvalue().get()?))?;
 
-        cs.enforce(
-            LinearCombination::zero() + self.ba.getvar(),
-            LinearCombination::zero() + CS::one(),
-            LinearCombination::zero() + ba_input,
-        );
         cs.enforce(
             LinearCombination::zero() + self.nullifier.getvar(),
             LinearCombination::zero() + CS::one(),
@@ -137,6 +161,26 @@ impl<'a> Input<Bls12> for C2BcircuitInput {
             LinearCombination::zero() + CS::one(),
             LinearCombination::zero() + root_input,
         );
+        cs.enforce(
+            LinearCombination::zero() + self.asset.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + asset_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.fee.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + fee_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.to.0.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + tox_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.to.1.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + toy_input,
+        );
 
         Ok(())
     }
@@ -145,55 +189,44 @@ impl<'a> Input<Bls12> for C2BcircuitInput {
 impl<'a> Circuit<Bls12> for C2Bcircuit<'a> {
     type InputMap = C2BcircuitInput;
 
-    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<Self::InputMap, Error> {
-        let rcm_num = Num::new(cs, self.rcm)?;
-        let mut rcm = rcm_num.unpack_sized(cs, RCMBIT)?;
-        let mut addr_sk = Vec::with_capacity(ADSK);
-        for b in self.addr_sk.iter() {
-            addr_sk.push(Bit::alloc(cs, *b)?);
-        }
+    fn synthesize<CS: ConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<Self::InputMap, SynthesisError> {
+        let rcm = Num::new(cs, self.rcm)?;
+        let sk = alloc_addr_sk(cs, &self.addr_sk)?;
 
-        let ba = Num::new(cs, self.ba)?;
         let va = Num::new(cs, self.va)?;
-        let bit_ba = ba.unpack_sized(cs, VBIT)?;
-        let bit_va = va.unpack_sized(cs, VBIT)?;
-        assert_eq!(bit_ba.len(), VBIT);
-        assert_eq!(bit_va.len(), VBIT);
-
-        //nullifier = PH(addr_sk|value|rcm)
-        let mut rcm2 = rcm.clone();
-        let vin = {
-            for b in bit_va.iter() {
-                rcm.push(*b);
-            }
-            for b in addr_sk.iter() {
-                rcm.push(*b);
-            }
-            rcm
-        };
-        assert_eq!(vin.len(), PHIN);
-        let nullifier = pedersen_hash(cs, &vin, self.generators, self.j)?;
+        let asset = Num::new(cs, self.asset)?;
+
+        //the balance receives va-fee
+        let fee = Num::new(cs, self.fee)?;
+        sub_fee(cs, &va, &fee)?.unpack_sized(cs, VBIT)?;
+
+        let addr = address(cs, &sk, ADSK, self.j)?;
+        let nk = nullifier_key(cs, &sk, self.generators, self.j)?;
+        let ivk = incoming_viewing_key(cs, &nk, self.generators, self.j)?;
+        let enc_key = enc_key_of(cs, &ivk, self.j)?;
+
+        //coin = PH(PH(rcm|value|pa)|asset), pa = PH(addr.x|enc_key.y) of the owner;
+        //the spent coin is of the public asset
+        let mut phout = payment_coin(
+            cs,
+            &rcm,
+            &va,
+            &addr.0,
+            &enc_key.1,
+            &asset,
+            self.generators,
+            self.j,
+        )?;
+
+        //nullifier = PH(coin|nk)
+        let nullifier = nullifier(cs, &phout, &nk, self.generators, self.j)?;
         if let Ok(x) = nullifier.getvalue().get() {
             self.res.push(x.into_repr());
         }
 
-        let p1 = Point::enc_point_table(256, 1, cs)?;
-        let addr = Point::multiply(&p1, &addr_sk, cs)?;
-        let addr = addr.0.unpack_sized(cs, PHOUT)?; //取x
-
-        //coin = PH(addr|value|rcm)
-        let vin = {
-            for b in bit_va.iter() {
-                rcm2.push(*b);
-            }
-            for b in addr.iter() {
-                rcm2.push(*b);
-            }
-            rcm2
-        };
-        assert_eq!(vin.len(), PHIN);
-        let mut phout = pedersen_hash(cs, &vin, self.generators, self.j)?;
-
         let mut locs = Vec::with_capacity(TREEDEPTH);
         for b in self.loc.iter() {
             locs.push(Bit::alloc(cs, *b)?);
@@ -223,100 +256,208 @@ impl<'a> Circuit<Bls12> for C2Bcircuit<'a> {
             self.res.push(x.into_repr());
         }
 
+        //the withdrawal is bound to the receiving address and the amount only, not
+        //to its balance, which may change before the proof lands; the address is
+        //an input so the proof cannot be replayed to credit another one
+        let to = (Num::new(cs, self.to.0)?, Num::new(cs, self.to.1)?);
+
         Ok(C2BcircuitInput {
-            ba,
             va,
             nullifier,
             root: phout,
+            asset,
+            fee,
+            to,
         })
     }
 }
 
 pub fn c2b_info(
     rcm: [u64; 2],
-    ba: [u64; 2],
     va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
+    to: String,
+    addr_sk: String,
+    path: Vec<String>,
+    loc: Vec<bool>,
+) -> Result<(String, String, String), Error> {
+    c2b_info_with(
+        &*default_params()?,
+        rcm,
+        va,
+        asset,
+        fee,
+        max_fee,
+        to,
+        addr_sk,
+        path,
+        loc,
+    )
+}
+
+pub fn c2b_info_with(
+    params: &ParamSet,
+    rcm: [u64; 2],
+    va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
+    to: String,
     addr_sk: String,
     path: Vec<String>,
     loc: Vec<bool>,
 ) -> Result<(String, String, String), Error> {
-    let rng = &mut thread_rng();
+    check_fee(fee, max_fee)?;
+    let to = point2fr(str2point(to)?)?;
     let j = JubJub::new();
     let mut res: Vec<FrRepr> = vec![];
-    let path = path.iter().map(|p| str2u644(p.clone())).collect();
-    let addr_sk = str2sk(addr_sk);
-    let proof = create_random_proof::<Bls12, _, _, _>(
-        C2Bcircuit::new(
-            &ph_generator(),
-            &j,
-            Fr::from_repr(FrRepr([rcm[0], rcm[1], 0, 0])).unwrap(),
-            Fr::from_repr(FrRepr([ba[0], ba[1], 0, 0])).unwrap(),
-            Fr::from_repr(FrRepr([va[0], va[1], 0, 0])).unwrap(),
-            addr_sk,
-            path,
-            loc,
-            &mut res,
-        ),
-        c2b_param()?,
-        rng,
-    )?
-    .serial();
+    let path = path
+        .iter()
+        .map(|p| str2u644(p.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_path_len(path.len(), loc.len())?;
+    let addr_sk = str2sk(addr_sk)?;
+    let proof = params
+        .prove(
+            ParamKind::C2b,
+            C2Bcircuit::new(
+                params.generators(),
+                &j,
+                u6442fr([rcm[0], rcm[1], 0, 0])?,
+                u6442fr([va[0], va[1], 0, 0])?,
+                u6442fr([asset, 0, 0, 0])?,
+                u6442fr([fee[0], fee[1], 0, 0])?,
+                to,
+                addr_sk,
+                path,
+                loc,
+                &mut res,
+            ),
+        )?
+        .serial();
     let nullifier = res[0].serial();
     let root = res[1].serial();
     Ok((proof2str(proof), u6442str(nullifier), u6442str(root)))
 }
 
 pub fn c2b_verify(
-    ba: [u64; 2],
     va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    to: String,
     nullifier: String,
     root: String,
     proof: String,
 ) -> Result<bool, Error> {
-    let nullifier = str2u644(nullifier);
-    let root = str2u644(root);
-    let proof = str2proof(proof);
-    verify_proof(&c2b_vk()?, &Proof::from_serial(proof), |cs| {
-        let va = Fr::from_repr(FrRepr([va[0], va[1], 0, 0])).unwrap();
-        let nullifier = Fr::from_repr(FrRepr::from_serial(nullifier)).unwrap();
-        let ba = Fr::from_repr(FrRepr([ba[0], ba[1], 0, 0])).unwrap();
-        let root = Fr::from_repr(FrRepr::from_serial(root)).unwrap();
-        Ok(C2BcircuitInput {
-            ba: Num::new(cs, Assignment::known(ba))?,
-            nullifier: Num::new(cs, Assignment::known(nullifier))?,
-            va: Num::new(cs, Assignment::known(va))?,
-            root: Num::new(cs, Assignment::known(root))?,
-        })
-    })
+    c2b_verify_with(
+        &*default_params()?,
+        va,
+        asset,
+        fee,
+        to,
+        nullifier,
+        root,
+        proof,
+    )
 }
 
-pub(crate) fn gen_c2b_param() {
+pub fn c2b_verify_with(
+    params: &ParamSet,
+    va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    to: String,
+    nullifier: String,
+    root: String,
+    proof: String,
+) -> Result<bool, Error> {
+    let to = point2fr(str2point(to)?)?;
+    let va = u6442fr([va[0], va[1], 0, 0])?;
+    let nullifier = u6442fr(str2u644(nullifier)?)?;
+    let root = u6442fr(str2u644(root)?)?;
+    let asset = u6442fr([asset, 0, 0, 0])?;
+    let fee = u6442fr([fee[0], fee[1], 0, 0])?;
+    let proof = str2proof(proof)?;
+    let ok = verify_proof(
+        &*params.vk(ParamKind::C2b)?,
+        &Proof::from_serial(proof),
+        |cs| {
+            Ok(C2BcircuitInput {
+                nullifier: Num::new(cs, Assignment::known(nullifier))?,
+                va: Num::new(cs, Assignment::known(va))?,
+                root: Num::new(cs, Assignment::known(root))?,
+                asset: Num::new(cs, Assignment::known(asset))?,
+                fee: Num::new(cs, Assignment::known(fee))?,
+                to: (
+                    Num::new(cs, Assignment::known(to.0))?,
+                    Num::new(cs, Assignment::known(to.1))?,
+                ),
+            })
+        },
+    )?;
+    Ok(ok)
+}
+
+//public inputs in the order C2BcircuitInput allocates them
+fn c2b_public_inputs(
+    va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    to: String,
+    nullifier: String,
+    root: String,
+) -> Result<Vec<Fr>, Error> {
+    let to = point2fr(str2point(to)?)?;
+    let va = u6442fr([va[0], va[1], 0, 0])?;
+    let nullifier = u6442fr(str2u644(nullifier)?)?;
+    let root = u6442fr(str2u644(root)?)?;
+    let asset = u6442fr([asset, 0, 0, 0])?;
+    let fee = u6442fr([fee[0], fee[1], 0, 0])?;
+    Ok(vec![nullifier, va, root, asset, fee, to.0, to.1])
+}
+
+//(va, asset, fee, to, nullifier, root, proof) as taken by c2b_verify
+pub type C2bVerifyArgs = ([u64; 2], u64, [u64; 2], String, String, String, String);
+
+pub fn c2b_verify_batch(proofs: Vec<C2bVerifyArgs>) -> Result<Option<usize>, Error> {
+    c2b_verify_batch_with(&*default_params()?, proofs)
+}
+
+//index of the first proof that does not verify, None if all do
+pub fn c2b_verify_batch_with(
+    params: &ParamSet,
+    proofs: Vec<C2bVerifyArgs>,
+) -> Result<Option<usize>, Error> {
+    let mut batch = Vec::with_capacity(proofs.len());
+    for (va, asset, fee, to, nullifier, root, proof) in proofs.into_iter() {
+        batch.push((
+            Proof::from_serial(str2proof(proof)?),
+            c2b_public_inputs(va, asset, fee, to, nullifier, root)?,
+        ));
+    }
+    verify_batch(&*params.verifying_key(ParamKind::C2b)?, &batch)
+}
+
+pub(crate) fn gen_c2b_param() -> Result<(), Error> {
     let c2b_param_path = c2b_param_path();
-    let c2b_param_path = c2b_param_path.to_str().unwrap();
 
-    let rng = &mut thread_rng();
-    let params = generate_random_parameters::<Bls12, _, _>(
-        C2Bcircuit::blank(&ph_generator(), &JubJub::new(), &mut vec![]),
-        rng,
-    )
-    .unwrap();
-    params
-        .write(&mut File::create(c2b_param_path).unwrap())
-        .unwrap();
+    let (params, constraints) = generate_params(C2Bcircuit::blank(
+        &ph_generator()?,
+        &JubJub::new(),
+        &mut vec![],
+    ))?;
+    write_params(ParamKind::C2b, &params, constraints, &c2b_param_path)
 }
 
 pub fn c2b_param() -> Result<ProverStream, Error> {
-    let c2b_param_path = c2b_param_path();
-    let c2b_param_path = c2b_param_path.to_str().unwrap();
-    let params = ProverStream::new(c2b_param_path).unwrap();
+    let c2b_param_path = existing_param_path(c2b_param_path())?;
+    check_params(ParamKind::C2b, Path::new(&c2b_param_path))?;
+    let params = ProverStream::new(&c2b_param_path)?;
     Ok(params)
 }
 
-pub fn c2b_vk() -> Result<(PreparedVerifyingKey<Bls12>), Error> {
-    let c2b_param_path = c2b_param_path();
-    let c2b_param_path = c2b_param_path.to_str().unwrap();
-    let mut params = ProverStream::new(c2b_param_path)?;
-    let vk2 = params.get_vk(5)?;
-    let vk = prepare_verifying_key(&vk2);
-    Ok(vk)
+pub fn c2b_vk() -> Result<Arc<PreparedVerifyingKey<Bls12>>, Error> {
+    default_params()?.vk(ParamKind::C2b)
 }
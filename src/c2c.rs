@@ -0,0 +1,871 @@
+use bellman::groth16::*;
+use bellman::Error as SynthesisError;
+use bellman::*;
+use pairing::bls12_381::{Bls12, Fr, FrRepr};
+use pairing::*;
+
+use jubjub::*;
+
+use base::*;
+use common_verify::batch::verify_batch;
+use common_verify::fixed_base::*;
+use common_verify::note::{enc_key_of, encrypt_note, payment_coin};
+use common_verify::nullifier::{incoming_viewing_key, nullifier, nullifier_key};
+use convert::*;
+use error::Error;
+use header::{check_params, generate_params, write_params};
+use params::{default_params, ParamSet};
+
+use std::path::Path;
+use std::sync::Arc;
+
+//spent coin: (addr_sk of its owner, r_cm, value, coin path, path location)
+type C2CcircuitSpend = (
+    Vec<Assignment<bool>>,
+    Assignment<Fr>,
+    Assignment<Fr>,
+    Vec<Vec<Assignment<bool>>>,
+    Vec<Assignment<bool>>,
+);
+
+//created coin: (r_cm, value, addr, enc_key, random number, hash of rP and the note ciphertext)
+type C2CcircuitOutput = (
+    Assignment<Fr>,
+    Assignment<Fr>,
+    (Assignment<Fr>, Assignment<Fr>),
+    (Assignment<Fr>, Assignment<Fr>),
+    Assignment<Fr>,
+    Assignment<Fr>,
+);
+
+struct C2Ccircuit<'a> {
+    generators: &'a [(Vec<Fr>, Vec<Fr>)],
+    j: &'a JubJub,
+
+    //asset of every spent and created coin, kept private
+    asset: Assignment<Fr>,
+    //spent coins
+    inputs: Vec<C2CcircuitSpend>,
+    //created coins
+    outputs: Vec<C2CcircuitOutput>,
+    //result
+    res: &'a mut Vec<FrRepr>,
+}
+
+fn path_bits(path: Vec<[u64; 4]>) -> Vec<Vec<bool>> {
+    path.into_iter()
+        .map(|u644| {
+            let mut v = Vec::with_capacity(PHOUT);
+            for u in u644.into_iter() {
+                let mut u = *u;
+                v.push((u & 1) == 1);
+                for _ in 0..63 {
+                    u >>= 1;
+                    v.push((u & 1) == 1);
+                }
+            }
+            v
+        })
+        .collect()
+}
+
+impl<'a> C2Ccircuit<'a> {
+    fn blank(
+        generators: &'a [(Vec<Fr>, Vec<Fr>)],
+        j: &'a JubJub,
+        res: &'a mut Vec<FrRepr>,
+    ) -> C2Ccircuit<'a> {
+        C2Ccircuit {
+            generators,
+            j,
+            asset: Assignment::unknown(),
+            inputs: (0..C2CIN)
+                .map(|_| {
+                    (
+                        (0..ADSK).map(|_| Assignment::unknown()).collect(),
+                        Assignment::unknown(),
+                        Assignment::unknown(),
+                        (0..TREEDEPTH)
+                            .map(|_| (0..PHOUT).map(|_| Assignment::unknown()).collect())
+                            .collect(),
+                        (0..TREEDEPTH).map(|_| Assignment::unknown()).collect(),
+                    )
+                })
+                .collect(),
+            outputs: (0..C2COUT)
+                .map(|_| {
+                    (
+                        Assignment::unknown(),
+                        Assignment::unknown(),
+                        (Assignment::unknown(), Assignment::unknown()),
+                        (Assignment::unknown(), Assignment::unknown()),
+                        Assignment::unknown(),
+                        Assignment::unknown(),
+                    )
+                })
+                .collect(),
+            res,
+        }
+    }
+
+    fn new(
+        generators: &'a [(Vec<Fr>, Vec<Fr>)],
+        j: &'a JubJub,
+        asset: Fr,
+        inputs: Vec<(Vec<bool>, Fr, Fr, Vec<[u64; 4]>, Vec<bool>)>,
+        outputs: Vec<(Fr, Fr, (Fr, Fr), (Fr, Fr), Fr, Fr)>,
+        res: &'a mut Vec<FrRepr>,
+    ) -> C2Ccircuit<'a> {
+        assert_eq!(inputs.len(), C2CIN);
+        assert_eq!(outputs.len(), C2COUT);
+        assert_eq!(res.len(), 0);
+        C2Ccircuit {
+            generators,
+            j,
+            asset: Assignment::known(asset),
+            inputs: inputs
+                .into_iter()
+                .map(|(addr_sk, rcm, va, path, loc)| {
+                    assert_eq!(addr_sk.len(), ADSK);
+                    assert_eq!(path.len(), TREEDEPTH);
+                    assert_eq!(loc.len(), TREEDEPTH);
+                    (
+                        addr_sk.iter().map(|&b| Assignment::known(b)).collect(),
+                        Assignment::known(rcm),
+                        Assignment::known(va),
+                        path_bits(path)
+                            .iter()
+                            .map(|ref ph| ph.iter().map(|&b| Assignment::known(b)).collect())
+                            .collect(),
+                        loc.iter().map(|&b| Assignment::known(b)).collect(),
+                    )
+                })
+                .collect(),
+            outputs: outputs
+                .into_iter()
+                .map(|(rcm, va, addr, enc_key, random, ct_hash)| {
+                    (
+                        Assignment::known(rcm),
+                        Assignment::known(va),
+                        (Assignment::known(addr.0), Assignment::known(addr.1)),
+                        (Assignment::known(enc_key.0), Assignment::known(enc_key.1)),
+                        Assignment::known(random),
+                        Assignment::known(ct_hash),
+                    )
+                })
+                .collect(),
+            res,
+        }
+    }
+}
+
+struct C2CcircuitInput {
+    //root
+    root: Num<Bls12>,
+    //nullifiers of the spent coins
+    nullifiers: Vec<Num<Bls12>>,
+    //created coins
+    coins: Vec<Num<Bls12>>,
+    //rP of each created coin
+    rps: Vec<(Num<Bls12>, Num<Bls12>)>,
+    //e1 and e2 of each created coin, its note encrypted in the circuit
+    es: Vec<(Num<Bls12>, Num<Bls12>)>,
+    //hash of rP and the note ciphertext of each created coin
+    ct_hashes: Vec<Num<Bls12>>,
+}
+
+impl<'a> Input<Bls12> for C2CcircuitInput {
+    fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
+        let root_input = cs.alloc_input(|| Ok(*self.root.getvalue().get()?))?;
+        cs.enforce(
+            LinearCombination::zero() + self.root.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + root_input,
+        );
+
+        for nullifier in self.nullifiers.iter() {
+            let nullifier_input = cs.alloc_input(|| Ok(*nullifier.getvalue().get()?))?;
+            cs.enforce(
+                LinearCombination::zero() + nullifier.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + nullifier_input,
+            );
+        }
+
+        for coin in self.coins.iter() {
+            let coin_input = cs.alloc_input(|| Ok(*coin.getvalue().get()?))?;
+            cs.enforce(
+                LinearCombination::zero() + coin.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + coin_input,
+            );
+        }
+
+        for ((rp, e), ct_hash) in self
+            .rps
+            .iter()
+            .zip(self.es.iter())
+            .zip(self.ct_hashes.iter())
+        {
+            let rpx_input = cs.alloc_input(|| Ok(*rp.0.getvalue().get()?))?;
+            let rpy_input = cs.alloc_input(|| Ok(*rp.1.getvalue().get()?))?;
+            let e1_input = cs.alloc_input(|| Ok(*e.0.getvalue().get()?))?;
+            let e2_input = cs.alloc_input(|| Ok(*e.1.getvalue().get()?))?;
+            let ct_hash_input = cs.alloc_input(|| Ok(*ct_hash.getvalue().get()?))?;
+            cs.enforce(
+                LinearCombination::zero() + rp.0.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + rpx_input,
+            );
+            cs.enforce(
+                LinearCombination::zero() + rp.1.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + rpy_input,
+            );
+            cs.enforce(
+                LinearCombination::zero() + e.0.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + e1_input,
+            );
+            cs.enforce(
+                LinearCombination::zero() + e.1.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + e2_input,
+            );
+            cs.enforce(
+                LinearCombination::zero() + ct_hash.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + ct_hash_input,
+            );
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> Circuit<Bls12> for C2Ccircuit<'a> {
+    type InputMap = C2CcircuitInput;
+
+    fn synthesize<CS: ConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<Self::InputMap, SynthesisError> {
+        //a single asset for all coins, so inputs and outputs cannot mix assets
+        let asset = Num::new(cs, self.asset)?;
+
+        let mut nullifiers = Vec::with_capacity(C2CIN);
+        let mut spent = Vec::with_capacity(C2CIN);
+        let mut value_in = LinearCombination::zero();
+        for (addr_sk, rcm, va, path, loc) in self.inputs.into_iter() {
+            //each spent coin may have its own owner
+            let sk = alloc_addr_sk(cs, &addr_sk)?;
+            let addr = address(cs, &sk, ADSK, self.j)?;
+            let nk = nullifier_key(cs, &sk, self.generators, self.j)?;
+            let ivk = incoming_viewing_key(cs, &nk, self.generators, self.j)?;
+            let owner_key = enc_key_of(cs, &ivk, self.j)?;
+
+            let rcm = Num::new(cs, rcm)?;
+            let va = Num::new(cs, va)?;
+            value_in = value_in + va.getvar();
+
+            //coin = PH(PH(rcm|value|pa)|asset), pa = PH(addr.x|enc_key.y) of the owner
+            let mut phout = payment_coin(
+                cs,
+                &rcm,
+                &va,
+                &addr.0,
+                &owner_key.1,
+                &asset,
+                self.generators,
+                self.j,
+            )?;
+
+            //nullifier = PH(coin|nk)
+            let nullifier = nullifier(cs, &phout, &nk, self.generators, self.j)?;
+            if let Ok(x) = nullifier.getvalue().get() {
+                self.res.push(x.into_repr());
+            }
+            nullifiers.push(nullifier);
+
+            let mut locs = Vec::with_capacity(TREEDEPTH);
+            for b in loc.iter() {
+                locs.push(Bit::alloc(cs, *b)?);
+            }
+
+            for (loc, sib) in locs.iter().zip(path.iter()) {
+                let phbits = phout.unpack_sized(cs, PHOUT)?;
+
+                let mut vin = vec![];
+                for (a, b) in sib.iter().zip(phbits.iter()) {
+                    let bit_ph = &b;
+                    let bit_sib = &Bit::alloc(cs, *a)?;
+                    let bit_out = loc.choose_bit(cs, bit_ph, bit_sib)?;
+                    vin.push(bit_out);
+                }
+                for (a, b) in sib.iter().zip(phbits.iter()) {
+                    let bit_ph = &b;
+                    let bit_sib = &Bit::alloc(cs, *a)?;
+                    let bit_out = loc.choose_bit(cs, bit_sib, bit_ph)?;
+                    vin.push(bit_out);
+                }
+                assert_eq!(vin.len(), PHIN);
+
+                phout = pedersen_hash(cs, &vin, self.generators, self.j)?;
+            }
+
+            spent.push((va, phout));
+        }
+
+        //the root of the first spent coin of nonzero value, of the first if all are zero
+        let value = {
+            let mut value = Assignment::unknown();
+            for &(ref va, ref phout) in spent.iter().rev() {
+                if let (Ok(va), Ok(phout)) = (va.getvalue().get(), phout.getvalue().get()) {
+                    if !va.is_zero() || value.get().is_err() {
+                        value = Assignment::known(*phout);
+                    }
+                }
+            }
+            value
+        };
+        let root = Num::new(cs, value)?;
+        //every spent coin of nonzero value is in the tree, zero value dummies need no path
+        for &(ref va, ref phout) in spent.iter() {
+            cs.enforce(
+                LinearCombination::zero() + va.getvar(),
+                LinearCombination::zero() + phout.getvar() - root.getvar(),
+                LinearCombination::zero(),
+            );
+        }
+        if let Ok(x) = root.getvalue().get() {
+            self.res.push(x.into_repr());
+        }
+
+        let mut coins = Vec::with_capacity(C2COUT);
+        let mut rps = Vec::with_capacity(C2COUT);
+        let mut es = Vec::with_capacity(C2COUT);
+        let mut ct_hashes = Vec::with_capacity(C2COUT);
+        let mut value_out = LinearCombination::zero();
+        for (rcm, va, addr, enc_key, random, ct_hash) in self.outputs.into_iter() {
+            let rcm = Num::new(cs, rcm)?;
+            let random = Num::new(cs, random)?;
+            let addr_x = Num::new(cs, addr.0)?;
+            let enc_key = (Num::new(cs, enc_key.0)?, Num::new(cs, enc_key.1)?);
+            let va = Num::new(cs, va)?;
+            value_out = value_out + va.getvar();
+
+            //coin = PH(PH(rcm|value|pa)|asset), pa = PH(addr.x|enc_key.y)
+            let coin = payment_coin(
+                cs,
+                &rcm,
+                &va,
+                &addr_x,
+                &enc_key.1,
+                &asset,
+                self.generators,
+                self.j,
+            )?;
+            if let Ok(x) = coin.getvalue().get() {
+                self.res.push(x.into_repr());
+            }
+            coins.push(coin);
+
+            //(rP, e1, e2), the opening of the coin encrypted to enc_key; the memo
+            //is encrypted outside the circuit and bound through ct_hash
+            let (rp, e1, e2) = encrypt_note(
+                cs,
+                &random,
+                &enc_key,
+                &va,
+                &rcm,
+                &asset,
+                self.generators,
+                self.j,
+            )?;
+            if let (Ok(x), Ok(y)) = (rp.0.getvalue().get(), rp.1.getvalue().get()) {
+                self.res.push(x.into_repr());
+                self.res.push(y.into_repr());
+            }
+            rps.push(rp);
+            es.push((e1, e2));
+            ct_hashes.push(Num::new(cs, ct_hash)?);
+        }
+
+        //sum of spent values = sum of created values
+        cs.enforce(value_in, LinearCombination::zero() + CS::one(), value_out);
+
+        Ok(C2CcircuitInput {
+            root,
+            nullifiers,
+            coins,
+            rps,
+            es,
+            ct_hashes,
+        })
+    }
+}
+
+pub struct C2cSpend {
+    pub addr_sk: String,
+    pub rcm: [u64; 2],
+    pub va: [u64; 2],
+    pub path: Vec<String>,
+    pub loc: Vec<bool>,
+}
+
+impl C2cSpend {
+    //a zero value input with no coin behind it, for spending a single coin;
+    //rcm must be fresh as the nullifier is still published
+    pub fn dummy(addr_sk: String, rcm: [u64; 2]) -> C2cSpend {
+        C2cSpend {
+            addr_sk,
+            rcm,
+            va: [0, 0],
+            path: vec![u6442str([0; 4]); TREEDEPTH],
+            loc: vec![false; TREEDEPTH],
+        }
+    }
+}
+
+pub struct C2cOutput {
+    pub rcm: [u64; 2],
+    pub va: [u64; 2],
+    pub addr: String,
+    pub enc_key: String,
+    pub enc_random: [u64; 4],
+    pub memo: Vec<u8>,
+}
+
+fn value_sum(values: &[[u64; 2]]) -> Option<u128> {
+    let mut sum: u128 = 0;
+    for va in values.iter() {
+        sum = sum.checked_add(value2u128(*va))?;
+    }
+    Some(sum)
+}
+
+pub fn c2c_info(
+    asset: u64,
+    inputs: Vec<C2cSpend>,
+    outputs: Vec<C2cOutput>,
+) -> Result<(String, Vec<String>, String, Vec<String>, Vec<String>), Error> {
+    c2c_info_with(&*default_params()?, asset, inputs, outputs)
+}
+
+pub fn c2c_info_with(
+    params: &ParamSet,
+    asset: u64,
+    inputs: Vec<C2cSpend>,
+    outputs: Vec<C2cOutput>,
+) -> Result<(String, Vec<String>, String, Vec<String>, Vec<String>), Error> {
+    if inputs.len() != C2CIN {
+        return Err(Error::WrongLength {
+            expected: C2CIN,
+            found: inputs.len(),
+        });
+    }
+    if outputs.len() != C2COUT {
+        return Err(Error::WrongLength {
+            expected: C2COUT,
+            found: outputs.len(),
+        });
+    }
+    let va_in: Vec<[u64; 2]> = inputs.iter().map(|i| i.va).collect();
+    let va_out: Vec<[u64; 2]> = outputs.iter().map(|o| o.va).collect();
+    if value_sum(&va_in) != value_sum(&va_out) {
+        return Err(Error::Unbalanced);
+    }
+    prove_c2c(params, asset, inputs, outputs)
+}
+
+//proves without checking the values, the circuit does that
+fn prove_c2c(
+    params: &ParamSet,
+    asset: u64,
+    inputs: Vec<C2cSpend>,
+    outputs: Vec<C2cOutput>,
+) -> Result<(String, Vec<String>, String, Vec<String>, Vec<String>), Error> {
+    let j = JubJub::new();
+    let mut spends = Vec::with_capacity(C2CIN);
+    for input in inputs.into_iter() {
+        let path = input
+            .path
+            .iter()
+            .map(|p| str2u644(p.clone()))
+            .collect::<Result<Vec<_>, _>>()?;
+        check_path_len(path.len(), input.loc.len())?;
+        spends.push((
+            str2sk(input.addr_sk)?,
+            u6442fr([input.rcm[0], input.rcm[1], 0, 0])?,
+            u6442fr([input.va[0], input.va[1], 0, 0])?,
+            path,
+            input.loc,
+        ));
+    }
+    let mut outs = Vec::with_capacity(C2COUT);
+    let mut encs = Vec::with_capacity(C2COUT);
+    for output in outputs.into_iter() {
+        let enc = encrypt(
+            output.va,
+            output.rcm,
+            asset,
+            &output.memo,
+            output.enc_random,
+            output.enc_key.clone(),
+        )?;
+        outs.push((
+            u6442fr([output.rcm[0], output.rcm[1], 0, 0])?,
+            u6442fr([output.va[0], output.va[1], 0, 0])?,
+            point2fr(str2point(output.addr)?)?,
+            point2fr(str2point(output.enc_key)?)?,
+            Fr::from_serial(output.enc_random),
+            ct_hash(&str2enc(enc.clone())?)?,
+        ));
+        encs.push(enc);
+    }
+
+    let mut res: Vec<FrRepr> = vec![];
+    let proof = params
+        .prove(
+            ParamKind::C2c,
+            C2Ccircuit::new(
+                params.generators(),
+                &j,
+                u6442fr([asset, 0, 0, 0])?,
+                spends,
+                outs,
+                &mut res,
+            ),
+        )?
+        .serial();
+    let nullifiers = (0..C2CIN).map(|i| u6442str(res[i].serial())).collect();
+    let root = u6442str(res[C2CIN].serial());
+    let coins = (0..C2COUT)
+        .map(|i| u6442str(res[C2CIN + 1 + i * 3].serial()))
+        .collect();
+    Ok((proof2str(proof), nullifiers, root, coins, encs))
+}
+
+fn check_lengths(nullifiers: &[String], coins: &[String], encs: &[String]) -> Result<(), Error> {
+    if nullifiers.len() != C2CIN {
+        return Err(Error::WrongLength {
+            expected: C2CIN,
+            found: nullifiers.len(),
+        });
+    }
+    if coins.len() != C2COUT || encs.len() != C2COUT {
+        return Err(Error::WrongLength {
+            expected: C2COUT,
+            found: if coins.len() != C2COUT {
+                coins.len()
+            } else {
+                encs.len()
+            },
+        });
+    }
+    Ok(())
+}
+
+pub fn c2c_verify(
+    nullifiers: Vec<String>,
+    root: String,
+    coins: Vec<String>,
+    encs: Vec<String>,
+    proof: String,
+) -> Result<bool, Error> {
+    c2c_verify_with(&*default_params()?, nullifiers, root, coins, encs, proof)
+}
+
+pub fn c2c_verify_with(
+    params: &ParamSet,
+    nullifiers: Vec<String>,
+    root: String,
+    coins: Vec<String>,
+    encs: Vec<String>,
+    proof: String,
+) -> Result<bool, Error> {
+    check_lengths(&nullifiers, &coins, &encs)?;
+    let root = u6442fr(str2u644(root)?)?;
+    let nullifiers = nullifiers
+        .into_iter()
+        .map(|n| u6442fr(str2u644(n)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let coins = coins
+        .into_iter()
+        .map(|c| u6442fr(str2u644(c)?))
+        .collect::<Result<Vec<_>, _>>()?;
+    let mut notes = Vec::with_capacity(C2COUT);
+    for enc in encs.into_iter() {
+        notes.push(note_inputs(&str2enc(enc)?)?);
+    }
+    let proof = str2proof(proof)?;
+    let ok = verify_proof(
+        &*params.vk(ParamKind::C2c)?,
+        &Proof::from_serial(proof),
+        |cs| {
+            let mut nullifier_nums = Vec::with_capacity(C2CIN);
+            for nullifier in nullifiers.iter() {
+                nullifier_nums.push(Num::new(cs, Assignment::known(*nullifier))?);
+            }
+            let mut coin_nums = Vec::with_capacity(C2COUT);
+            for coin in coins.iter() {
+                coin_nums.push(Num::new(cs, Assignment::known(*coin))?);
+            }
+            let mut rp_nums = Vec::with_capacity(C2COUT);
+            let mut e_nums = Vec::with_capacity(C2COUT);
+            let mut ct_hash_nums = Vec::with_capacity(C2COUT);
+            for &(rp, e1, e2, ct_hash) in notes.iter() {
+                rp_nums.push((
+                    Num::new(cs, Assignment::known(rp.0))?,
+                    Num::new(cs, Assignment::known(rp.1))?,
+                ));
+                e_nums.push((
+                    Num::new(cs, Assignment::known(e1))?,
+                    Num::new(cs, Assignment::known(e2))?,
+                ));
+                ct_hash_nums.push(Num::new(cs, Assignment::known(ct_hash))?);
+            }
+            Ok(C2CcircuitInput {
+                root: Num::new(cs, Assignment::known(root))?,
+                nullifiers: nullifier_nums,
+                coins: coin_nums,
+                rps: rp_nums,
+                es: e_nums,
+                ct_hashes: ct_hash_nums,
+            })
+        },
+    )?;
+    Ok(ok)
+}
+
+//public inputs in the order C2CcircuitInput allocates them
+fn c2c_public_inputs(
+    nullifiers: Vec<String>,
+    root: String,
+    coins: Vec<String>,
+    encs: Vec<String>,
+) -> Result<Vec<Fr>, Error> {
+    check_lengths(&nullifiers, &coins, &encs)?;
+    let mut inputs = Vec::with_capacity(1 + C2CIN + C2COUT * 6);
+    inputs.push(u6442fr(str2u644(root)?)?);
+    for nullifier in nullifiers.into_iter() {
+        inputs.push(u6442fr(str2u644(nullifier)?)?);
+    }
+    for coin in coins.into_iter() {
+        inputs.push(u6442fr(str2u644(coin)?)?);
+    }
+    for enc in encs.into_iter() {
+        let ((rpx, rpy), e1, e2, ct_hash) = note_inputs(&str2enc(enc)?)?;
+        inputs.push(rpx);
+        inputs.push(rpy);
+        inputs.push(e1);
+        inputs.push(e2);
+        inputs.push(ct_hash);
+    }
+    Ok(inputs)
+}
+
+//(nullifiers, root, coins, encs, proof) as taken by c2c_verify
+pub type C2cVerifyArgs = (Vec<String>, String, Vec<String>, Vec<String>, String);
+
+pub fn c2c_verify_batch(proofs: Vec<C2cVerifyArgs>) -> Result<Option<usize>, Error> {
+    c2c_verify_batch_with(&*default_params()?, proofs)
+}
+
+//index of the first proof that does not verify, None if all do
+pub fn c2c_verify_batch_with(
+    params: &ParamSet,
+    proofs: Vec<C2cVerifyArgs>,
+) -> Result<Option<usize>, Error> {
+    let mut batch = Vec::with_capacity(proofs.len());
+    for (nullifiers, root, coins, encs, proof) in proofs.into_iter() {
+        batch.push((
+            Proof::from_serial(str2proof(proof)?),
+            c2c_public_inputs(nullifiers, root, coins, encs)?,
+        ));
+    }
+    verify_batch(&*params.verifying_key(ParamKind::C2c)?, &batch)
+}
+
+pub(crate) fn gen_c2c_param() -> Result<(), Error> {
+    let c2c_param_path = c2c_param_path();
+
+    let (params, constraints) = generate_params(C2Ccircuit::blank(
+        &ph_generator()?,
+        &JubJub::new(),
+        &mut vec![],
+    ))?;
+    write_params(ParamKind::C2c, &params, constraints, &c2c_param_path)
+}
+
+pub fn c2c_param() -> Result<ProverStream, Error> {
+    let c2c_param_path = existing_param_path(c2c_param_path())?;
+    check_params(ParamKind::C2c, Path::new(&c2c_param_path))?;
+    let params = ProverStream::new(&c2c_param_path)?;
+    Ok(params)
+}
+
+pub fn c2c_vk() -> Result<Arc<PreparedVerifyingKey<Bls12>>, Error> {
+    default_params()?.vk(ParamKind::C2c)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use incrementalmerkletree::{IncrementalMerkleTree, MerklePath};
+    use params::test_generators;
+    use pedersen::PedersenDigest;
+    use std::collections::VecDeque;
+    use types::SpendingKey;
+
+    fn params() -> Arc<ParamSet> {
+        test_generators();
+        if !c2c_param_path().exists() {
+            gen_c2c_param().unwrap();
+        }
+        default_params().unwrap()
+    }
+
+    fn key(n: u8) -> SpendingKey {
+        format!("{:02x}", n).repeat(32).parse().unwrap()
+    }
+
+    fn coin(sk: &SpendingKey, va: [u64; 2], rcm: [u64; 2]) -> String {
+        let enc_key = sk.incoming_viewing_key().unwrap().enc_key().unwrap();
+        ::build_coin(
+            sk.address().unwrap().to_string(),
+            enc_key.to_string(),
+            va,
+            rcm,
+            0,
+        )
+        .unwrap()
+    }
+
+    fn spend(
+        sk: &SpendingKey,
+        va: [u64; 2],
+        rcm: [u64; 2],
+        path: &MerklePath<PedersenDigest>,
+    ) -> C2cSpend {
+        C2cSpend {
+            addr_sk: sk.to_string(),
+            rcm,
+            va,
+            path: path
+                .authentication_path
+                .iter()
+                .map(|p| u6442str(p.0))
+                .collect(),
+            loc: path.index.clone(),
+        }
+    }
+
+    fn outputs(values: &[[u64; 2]]) -> Vec<C2cOutput> {
+        let to = key(9)
+            .incoming_viewing_key()
+            .unwrap()
+            .payment_address()
+            .unwrap();
+        values
+            .iter()
+            .enumerate()
+            .map(|(i, &va)| C2cOutput {
+                rcm: [i as u64 + 20, 1],
+                va,
+                addr: to.addr.to_string(),
+                enc_key: to.enc_key.to_string(),
+                enc_random: [i as u64 + 1, 2, 3, 0],
+                memo: vec![],
+            })
+            .collect()
+    }
+
+    //coins of 10 and 5 held by key(1) and key(2), both in one tree
+    fn two_coins() -> (Vec<C2cSpend>, Vec<String>) {
+        let (alice, bob) = (key(1), key(2));
+        let (va1, va2) = ([10, 0], [5, 0]);
+        let (rcm1, rcm2) = ([7, 1], [8, 1]);
+        let coins = vec![coin(&alice, va1, rcm1), coin(&bob, va2, rcm2)];
+        let mut tree = IncrementalMerkleTree::new(TREEDEPTH);
+        tree.append(PedersenDigest(str2u644(coins[0].clone()).unwrap()));
+        let mut witness = tree.clone().witness();
+        let second = PedersenDigest(str2u644(coins[1].clone()).unwrap());
+        tree.append(second);
+        witness.append(second);
+        let inputs = vec![
+            spend(&alice, va1, rcm1, &witness.path()),
+            spend(&bob, va2, rcm2, &tree.path(VecDeque::new())),
+        ];
+        (inputs, coins)
+    }
+
+    fn verifies(
+        params: &ParamSet,
+        res: (String, Vec<String>, String, Vec<String>, Vec<String>),
+    ) -> bool {
+        let (proof, nullifiers, root, coins, encs) = res;
+        c2c_verify_with(params, nullifiers, root, coins, encs, proof).unwrap()
+    }
+
+    //proves with freshly generated c2c parameters, which takes minutes
+    #[test]
+    #[ignore]
+    fn spends_coins_of_two_owners() {
+        let params = params();
+        let (inputs, coins) = two_coins();
+        let res = c2c_info_with(&params, 0, inputs, outputs(&[[12, 0], [3, 0]])).unwrap();
+        //each nullifier comes from the key of its own coin
+        for (i, coin) in coins.into_iter().enumerate() {
+            let nk = ::nullifier_key(key(i as u8 + 1).to_string()).unwrap();
+            assert_eq!(res.1[i], ::build_nullifier(nk, coin).unwrap());
+        }
+        assert!(verifies(&params, res));
+    }
+
+    //proves with freshly generated c2c parameters, which takes minutes
+    #[test]
+    #[ignore]
+    fn conservation_is_enforced() {
+        let params = params();
+        let (inputs, _) = two_coins();
+        match c2c_info_with(&params, 0, inputs, outputs(&[[12, 0], [4, 0]])) {
+            Err(Error::Unbalanced) => {}
+            other => panic!("{:?}", other.err()),
+        }
+
+        //past the native check the circuit still refuses it
+        let (inputs, _) = two_coins();
+        let res = prove_c2c(&params, 0, inputs, outputs(&[[12, 0], [4, 0]])).unwrap();
+        assert!(!verifies(&params, res));
+    }
+
+    //proves with freshly generated c2c parameters, which takes minutes
+    #[test]
+    #[ignore]
+    fn dummy_input_only_at_zero_value() {
+        let params = params();
+        let alice = key(1);
+        let (va, rcm) = ([10, 0], [7, 1]);
+        let mut tree = IncrementalMerkleTree::new(TREEDEPTH);
+        tree.append(PedersenDigest(str2u644(coin(&alice, va, rcm)).unwrap()));
+        let path = tree.path(VecDeque::new());
+
+        let inputs = vec![
+            spend(&alice, va, rcm, &path),
+            C2cSpend::dummy(alice.to_string(), [30, 1]),
+        ];
+        let res = c2c_info_with(&params, 0, inputs, outputs(&[[6, 0], [4, 0]])).unwrap();
+        assert!(verifies(&params, res));
+
+        //a dummy carrying value, so no coin behind it
+        let mut dummy = C2cSpend::dummy(alice.to_string(), [31, 1]);
+        dummy.va = [5, 0];
+        let inputs = vec![spend(&alice, va, rcm, &path), dummy];
+        let res = c2c_info_with(&params, 0, inputs, outputs(&[[11, 0], [4, 0]])).unwrap();
+        assert!(!verifies(&params, res));
+    }
+}
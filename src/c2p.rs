@@ -1,15 +1,26 @@
 use bellman::groth16::*;
+use bellman::Error as SynthesisError;
 use bellman::*;
 use pairing::bls12_381::{Bls12, Fr, FrRepr};
 use pairing::*;
-use rand::thread_rng;
 
 use jubjub::*;
 
 use base::*;
+use common_verify::asset::asset_commitment;
+use common_verify::batch::verify_batch;
+use common_verify::fee::sub_fee;
+use common_verify::fixed_base::*;
+use common_verify::note::{enc_key_of, payment_coin};
+use common_verify::nullifier::{incoming_viewing_key, nullifier, nullifier_key};
 use convert::*;
+use error::Error;
+use generators::asset_generator;
+use header::{check_params, generate_params, write_params};
+use params::{default_params, ParamSet};
 
-use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
 
 struct C2Pcircuit<'a> {
     generators: &'a [(Vec<Fr>, Vec<Fr>)],
@@ -21,6 +32,12 @@ struct C2Pcircuit<'a> {
     rcm_new: Assignment<Fr>,
     //value
     va: Assignment<Fr>,
+    //asset
+    asset: Assignment<Fr>,
+    //value generator of the asset
+    generator: (Assignment<Fr>, Assignment<Fr>),
+    //fee
+    fee: Assignment<Fr>,
     //addr_sk
     addr_sk: Vec<Assignment<bool>>,
     //coin path
@@ -43,6 +60,9 @@ impl<'a> C2Pcircuit<'a> {
             rcm: Assignment::unknown(),
             rcm_new: Assignment::unknown(),
             va: Assignment::unknown(),
+            asset: Assignment::unknown(),
+            generator: (Assignment::unknown(), Assignment::unknown()),
+            fee: Assignment::unknown(),
             addr_sk: (0..ADSK).map(|_| Assignment::unknown()).collect(),
             path: (0..TREEDEPTH)
                 .map(|_| (0..PHOUT).map(|_| Assignment::unknown()).collect())
@@ -58,6 +78,9 @@ impl<'a> C2Pcircuit<'a> {
         rcm: Fr,
         rcm_new: Fr,
         va: Fr,
+        asset: Fr,
+        generator: (Fr, Fr),
+        fee: Fr,
         addr_sk: Vec<bool>,
         path: Vec<[u64; 4]>,
         loc: Vec<bool>,
@@ -88,6 +111,12 @@ impl<'a> C2Pcircuit<'a> {
             rcm: Assignment::known(rcm),
             rcm_new: Assignment::known(rcm_new),
             va: Assignment::known(va),
+            asset: Assignment::known(asset),
+            generator: (
+                Assignment::known(generator.0),
+                Assignment::known(generator.1),
+            ),
+            fee: Assignment::known(fee),
             addr_sk: addr_sk.iter().map(|&b| Assignment::known(b)).collect(),
             path: path
                 .iter()
@@ -100,20 +129,33 @@ impl<'a> C2Pcircuit<'a> {
 }
 
 struct C2PcircuitInput {
-    //delta(Balance)
+    //delta(Balance), value less fee
     delt_ba: (Num<Bls12>, Num<Bls12>),
     //nullifier
     nullifier: Num<Bls12>,
     //root
     root: Num<Bls12>,
+    //asset
+    asset: Num<Bls12>,
+    //G, the value generator of the asset
+    generator: (Num<Bls12>, Num<Bls12>),
+    //fee
+    fee: Num<Bls12>,
 }
 
 impl<'a> Input<Bls12> for C2PcircuitInput {
-    fn synthesize<CS: PublicConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), Error> {
+    fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
         let delt_x_input = cs.alloc_input(|| Ok(*self.delt_ba.0.getvalue().get()?))?;
         let delt_y_input = cs.alloc_input(|| Ok(*self.delt_ba.1.getvalue().get()?))?;
         let nullifier_input = cs.alloc_input(|| Ok(*self.nullifier.getvalue().get()?))?;
         let root_input = cs.alloc_input(|| Ok(*self.root.getvalue().get()?))?;
+        let asset_input = cs.alloc_input(|| Ok(*self.asset.getvalue().get()?))?;
+        let gx_input = cs.alloc_input(|| Ok(*self.generator.0.getvalue().get()?))?;
+        let gy_input = cs.alloc_input(|| Ok(*self.generator.1.getvalue().get()?))?;
+        let fee_input = cs.alloc_input(|| Ok(*self.fee.getvalue().get()?))?;
 
         cs.enforce(
             LinearCombination::zero() + self.delt_ba.0.getvar(),
@@ -135,6 +177,26 @@ impl<'a> Input<Bls12> for C2PcircuitInput {
             LinearCombination::zero() + CS::one(),
             LinearCombination::zero() + root_input,
         );
+        cs.enforce(
+            LinearCombination::zero() + self.asset.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + asset_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.generator.0.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + gx_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.generator.1.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + gy_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.fee.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + fee_input,
+        );
 
         Ok(())
     }
@@ -143,54 +205,45 @@ impl<'a> Input<Bls12> for C2PcircuitInput {
 impl<'a> Circuit<Bls12> for C2Pcircuit<'a> {
     type InputMap = C2PcircuitInput;
 
-    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<Self::InputMap, Error> {
-        let rcm_num = Num::new(cs, self.rcm)?;
-        let mut rcm = rcm_num.unpack_sized(cs, RCMBIT)?;
-        let rcm_new = Num::new(cs, self.rcm_new)?.unpack_sized(cs, RCMBIT)?;
-        let mut addr_sk = Vec::with_capacity(ADSK);
-        for b in self.addr_sk.iter() {
-            addr_sk.push(Bit::alloc(cs, *b)?);
-        }
+    fn synthesize<CS: ConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<Self::InputMap, SynthesisError> {
+        let rcm = Num::new(cs, self.rcm)?;
+        let rcm_new = Num::new(cs, self.rcm_new)?;
+        let sk = alloc_addr_sk(cs, &self.addr_sk)?;
 
         let va = Num::new(cs, self.va)?;
-        let bit_va = va.unpack_sized(cs, VBIT)?;
-        assert_eq!(bit_va.len(), VBIT);
-
-        //nullifier = PH(addr_sk|value|rcm)
-        let mut rcm2 = rcm.clone();
-        let vin = {
-            for b in bit_va.iter() {
-                rcm.push(*b);
-            }
-            for b in addr_sk.iter() {
-                rcm.push(*b);
-            }
-            rcm
-        };
-        assert_eq!(vin.len(), PHIN);
-        let nullifier = pedersen_hash(cs, &vin, self.generators, self.j)?;
+        let asset = Num::new(cs, self.asset)?;
+        let generator = (
+            Num::new(cs, self.generator.0)?,
+            Num::new(cs, self.generator.1)?,
+        );
+
+        let addr = address(cs, &sk, ADSK, self.j)?;
+        let nk = nullifier_key(cs, &sk, self.generators, self.j)?;
+        let ivk = incoming_viewing_key(cs, &nk, self.generators, self.j)?;
+        let enc_key = enc_key_of(cs, &ivk, self.j)?;
+
+        //coin = PH(PH(rcm|value|pa)|asset), pa = PH(addr.x|enc_key.y) of the owner;
+        //the spent coin is of the public asset
+        let mut phout = payment_coin(
+            cs,
+            &rcm,
+            &va,
+            &addr.0,
+            &enc_key.1,
+            &asset,
+            self.generators,
+            self.j,
+        )?;
+
+        //nullifier = PH(coin|nk)
+        let nullifier = nullifier(cs, &phout, &nk, self.generators, self.j)?;
         if let Ok(x) = nullifier.getvalue().get() {
             self.res.push(x.into_repr());
         }
 
-        let p1 = Point::enc_point_table(ADSK, 1, cs)?;
-        let p2 = Point::enc_point_table(RCMBIT, 2, cs)?;
-        let addr = Point::multiply(&p1, &addr_sk, cs)?;
-        let addr = addr.0.unpack_sized(cs, PHOUT)?; //取x
-
-        //coin = PH(addr|value|rcm)
-        let vin = {
-            for b in bit_va.iter() {
-                rcm2.push(*b);
-            }
-            for b in addr.iter() {
-                rcm2.push(*b);
-            }
-            rcm2
-        };
-        assert_eq!(vin.len(), PHIN);
-        let mut phout = pedersen_hash(cs, &vin, self.generators, self.j)?;
-
         let mut locs = Vec::with_capacity(TREEDEPTH);
         for b in self.loc.iter() {
             locs.push(Bit::alloc(cs, *b)?);
@@ -220,8 +273,10 @@ impl<'a> Circuit<Bls12> for C2Pcircuit<'a> {
             self.res.push(x.into_repr());
         }
 
-        //delta_ba
-        let delt_ba = Point::encrypt((&p1, &p2), &bit_va, &rcm_new, cs)?;
+        //delta_ba = (va-fee)*G+rcm_new*P2, on the generator of the same asset
+        let fee = Num::new(cs, self.fee)?;
+        let rest = sub_fee(cs, &va, &fee)?;
+        let delt_ba = asset_commitment(cs, &rest, &rcm_new, RCMBIT, &generator, self.j)?;
         if let (Ok(x), Ok(y)) = (delt_ba.0.getvalue().get(), delt_ba.1.getvalue().get()) {
             self.res.push(x.into_repr());
             self.res.push(y.into_repr());
@@ -231,6 +286,9 @@ impl<'a> Circuit<Bls12> for C2Pcircuit<'a> {
             delt_ba,
             nullifier,
             root: phout,
+            asset,
+            generator,
+            fee,
         })
     }
 }
@@ -239,31 +297,67 @@ pub fn c2p_info(
     rcm: [u64; 2],
     rcm_new: [u64; 2],
     va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
     addr_sk: String,
     path: Vec<String>,
     loc: Vec<bool>,
 ) -> Result<(String, String, String, String), Error> {
-    let rng = &mut thread_rng();
+    c2p_info_with(
+        &*default_params()?,
+        rcm,
+        rcm_new,
+        va,
+        asset,
+        fee,
+        max_fee,
+        addr_sk,
+        path,
+        loc,
+    )
+}
+
+pub fn c2p_info_with(
+    params: &ParamSet,
+    rcm: [u64; 2],
+    rcm_new: [u64; 2],
+    va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
+    addr_sk: String,
+    path: Vec<String>,
+    loc: Vec<bool>,
+) -> Result<(String, String, String, String), Error> {
+    check_fee(fee, max_fee)?;
     let j = JubJub::new();
-    let path = path.iter().map(|p| str2u644(p.clone())).collect();
-    let addr_sk = str2sk(addr_sk);
+    let path = path
+        .iter()
+        .map(|p| str2u644(p.clone()))
+        .collect::<Result<Vec<_>, _>>()?;
+    check_path_len(path.len(), loc.len())?;
+    let addr_sk = str2sk(addr_sk)?;
     let mut res: Vec<FrRepr> = vec![];
-    let proof = create_random_proof::<Bls12, _, _, _>(
-        C2Pcircuit::new(
-            &ph_generator(),
-            &j,
-            Fr::from_repr(FrRepr([rcm[0], rcm[1], 0, 0])).unwrap(),
-            Fr::from_repr(FrRepr([rcm_new[0], rcm_new[1], 0, 0])).unwrap(),
-            Fr::from_repr(FrRepr([va[0], va[1], 0, 0])).unwrap(),
-            addr_sk,
-            path,
-            loc,
-            &mut res,
-        ),
-        c2p_param()?,
-        rng,
-    )?
-    .serial();
+    let proof = params
+        .prove(
+            ParamKind::C2p,
+            C2Pcircuit::new(
+                params.generators(),
+                &j,
+                u6442fr([rcm[0], rcm[1], 0, 0])?,
+                u6442fr([rcm_new[0], rcm_new[1], 0, 0])?,
+                u6442fr([va[0], va[1], 0, 0])?,
+                u6442fr([asset, 0, 0, 0])?,
+                asset_generator(asset),
+                u6442fr([fee[0], fee[1], 0, 0])?,
+                addr_sk,
+                path,
+                loc,
+                &mut res,
+            ),
+        )?
+        .serial();
     let nullifier = res[0].serial();
     let root = res[1].serial();
     let delt_ba = (res[2].serial(), res[3].serial());
@@ -279,55 +373,117 @@ pub fn c2p_verify(
     nullifier: String,
     root: String,
     delt_ba: String,
+    asset: u64,
+    fee: [u64; 2],
     proof: String,
 ) -> Result<bool, Error> {
-    let nullifier = str2u644(nullifier);
-    let root = str2u644(root);
-    let delt_ba = str2point(delt_ba);
-    let proof = str2proof(proof);
-    verify_proof(&c2p_vk()?, &Proof::from_serial(proof), |cs| {
-        let nullifier = Fr::from_repr(FrRepr::from_serial(nullifier)).unwrap();
-        let delt_x = Fr::from_repr(FrRepr::from_serial(delt_ba.0)).unwrap();
-        let delt_y = Fr::from_repr(FrRepr::from_serial(delt_ba.1)).unwrap();
-        let root = Fr::from_repr(FrRepr::from_serial(root)).unwrap();
-        Ok(C2PcircuitInput {
-            nullifier: Num::new(cs, Assignment::known(nullifier))?,
-            delt_ba: (
-                Num::new(cs, Assignment::known(delt_x))?,
-                Num::new(cs, Assignment::known(delt_y))?,
-            ),
-            root: Num::new(cs, Assignment::known(root))?,
-        })
-    })
+    c2p_verify_with(
+        &*default_params()?,
+        nullifier,
+        root,
+        delt_ba,
+        asset,
+        fee,
+        proof,
+    )
+}
+
+pub fn c2p_verify_with(
+    params: &ParamSet,
+    nullifier: String,
+    root: String,
+    delt_ba: String,
+    asset: u64,
+    fee: [u64; 2],
+    proof: String,
+) -> Result<bool, Error> {
+    let nullifier = u6442fr(str2u644(nullifier)?)?;
+    let root = u6442fr(str2u644(root)?)?;
+    let (delt_x, delt_y) = point2fr(str2point(delt_ba)?)?;
+    let (gx, gy) = asset_generator(asset);
+    let asset = u6442fr([asset, 0, 0, 0])?;
+    let fee = u6442fr([fee[0], fee[1], 0, 0])?;
+    let proof = str2proof(proof)?;
+    let ok = verify_proof(
+        &*params.vk(ParamKind::C2p)?,
+        &Proof::from_serial(proof),
+        |cs| {
+            Ok(C2PcircuitInput {
+                nullifier: Num::new(cs, Assignment::known(nullifier))?,
+                delt_ba: (
+                    Num::new(cs, Assignment::known(delt_x))?,
+                    Num::new(cs, Assignment::known(delt_y))?,
+                ),
+                root: Num::new(cs, Assignment::known(root))?,
+                asset: Num::new(cs, Assignment::known(asset))?,
+                generator: (
+                    Num::new(cs, Assignment::known(gx))?,
+                    Num::new(cs, Assignment::known(gy))?,
+                ),
+                fee: Num::new(cs, Assignment::known(fee))?,
+            })
+        },
+    )?;
+    Ok(ok)
+}
+
+//public inputs in the order C2PcircuitInput allocates them
+fn c2p_public_inputs(
+    nullifier: String,
+    root: String,
+    delt_ba: String,
+    asset: u64,
+    fee: [u64; 2],
+) -> Result<Vec<Fr>, Error> {
+    let nullifier = u6442fr(str2u644(nullifier)?)?;
+    let root = u6442fr(str2u644(root)?)?;
+    let (delt_x, delt_y) = point2fr(str2point(delt_ba)?)?;
+    let (gx, gy) = asset_generator(asset);
+    let asset = u6442fr([asset, 0, 0, 0])?;
+    let fee = u6442fr([fee[0], fee[1], 0, 0])?;
+    Ok(vec![delt_x, delt_y, nullifier, root, asset, gx, gy, fee])
 }
 
-pub(crate) fn gen_c2p_param() {
+//(nullifier, root, delt_ba, asset, fee, proof) as taken by c2p_verify
+pub type C2pVerifyArgs = (String, String, String, u64, [u64; 2], String);
+
+pub fn c2p_verify_batch(proofs: Vec<C2pVerifyArgs>) -> Result<Option<usize>, Error> {
+    c2p_verify_batch_with(&*default_params()?, proofs)
+}
+
+//index of the first proof that does not verify, None if all do
+pub fn c2p_verify_batch_with(
+    params: &ParamSet,
+    proofs: Vec<C2pVerifyArgs>,
+) -> Result<Option<usize>, Error> {
+    let mut batch = Vec::with_capacity(proofs.len());
+    for (nullifier, root, delt_ba, asset, fee, proof) in proofs.into_iter() {
+        batch.push((
+            Proof::from_serial(str2proof(proof)?),
+            c2p_public_inputs(nullifier, root, delt_ba, asset, fee)?,
+        ));
+    }
+    verify_batch(&*params.verifying_key(ParamKind::C2p)?, &batch)
+}
+
+pub(crate) fn gen_c2p_param() -> Result<(), Error> {
     let c2p_param_path = c2p_param_path();
-    let c2p_param_path = c2p_param_path.to_str().unwrap();
 
-    let rng = &mut thread_rng();
-    let params = generate_random_parameters::<Bls12, _, _>(
-        C2Pcircuit::blank(&ph_generator(), &JubJub::new(), &mut vec![]),
-        rng,
-    )
-    .unwrap();
-    params
-        .write(&mut File::create(c2p_param_path).unwrap())
-        .unwrap();
+    let (params, constraints) = generate_params(C2Pcircuit::blank(
+        &ph_generator()?,
+        &JubJub::new(),
+        &mut vec![],
+    ))?;
+    write_params(ParamKind::C2p, &params, constraints, &c2p_param_path)
 }
 
 pub fn c2p_param() -> Result<ProverStream, Error> {
-    let c2p_param_path = c2p_param_path();
-    let c2p_param_path = c2p_param_path.to_str().unwrap();
-    let params = ProverStream::new(c2p_param_path).unwrap();
+    let c2p_param_path = existing_param_path(c2p_param_path())?;
+    check_params(ParamKind::C2p, Path::new(&c2p_param_path))?;
+    let params = ProverStream::new(&c2p_param_path)?;
     Ok(params)
 }
 
-pub fn c2p_vk() -> Result<(PreparedVerifyingKey<Bls12>), Error> {
-    let c2p_param_path = c2p_param_path();
-    let c2p_param_path = c2p_param_path.to_str().unwrap();
-    let mut params = ProverStream::new(c2p_param_path)?;
-    let vk2 = params.get_vk(5)?;
-    let vk = prepare_verifying_key(&vk2);
-    Ok(vk)
+pub fn c2p_vk() -> Result<Arc<PreparedVerifyingKey<Bls12>>, Error> {
+    default_params()?.vk(ParamKind::C2p)
 }
@@ -0,0 +1,605 @@
+//Phase-2 multi-party setup for the Groth16 parameter files.
+//
+//Each contribution multiplies delta by a secret scalar and divides the
+//h and l queries by it, so delta stays unknown as long as one participant
+//discards their scalar. That alone does not make the parameters sound: tau,
+//alpha and beta still come from generate_random_parameters in gen_params, with
+//no powers-of-tau phase before this one, so whoever generated the files can
+//forge proofs. Deriving the initial files from a phase-1 transcript needs the
+//QAP of each circuit, which this bellman does not expose, so until then the
+//files must be generated by a trusted party. Parameter files keep the format read by
+//`ProverStream`; the transcript is stored beside them with a `.mpc` suffix,
+//and the header is rewritten after each contribution.
+
+use bellman::groth16::*;
+use blake2_rfc::blake2b::Blake2b;
+use pairing::bls12_381::{Bls12, Fr, G1Affine, G1Uncompressed, G2Affine, G2Uncompressed, G1, G2};
+use pairing::*;
+use rand::chacha::ChaChaRng;
+use rand::{thread_rng, Rand, Rng, SeedableRng};
+
+use std::fs::File;
+use std::io::{self, BufReader, Read, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use base::{existing_param_path, ParamKind, PARAM_KINDS};
+use error::Error;
+use header::{check_params, write_atomic, write_params};
+
+pub type TranscriptHash = [u8; 64];
+
+#[derive(Clone)]
+pub struct Contribution {
+    //delta_g1 after this contribution
+    delta_after: G1Affine,
+    //random s and s*delta
+    s: G1Affine,
+    s_delta: G1Affine,
+    //hash_to_g2(transcript)*delta
+    r_delta: G2Affine,
+    //blake2b(cs_hash|previous contributions|s|s_delta)
+    transcript: TranscriptHash,
+}
+
+impl Contribution {
+    fn write<W: Write>(&self, writer: &mut W) -> io::Result<()> {
+        writer.write_all(self.delta_after.into_uncompressed().as_ref())?;
+        writer.write_all(self.s.into_uncompressed().as_ref())?;
+        writer.write_all(self.s_delta.into_uncompressed().as_ref())?;
+        writer.write_all(self.r_delta.into_uncompressed().as_ref())?;
+        writer.write_all(&self.transcript)?;
+        Ok(())
+    }
+
+    fn read<R: Read>(reader: &mut R) -> Result<Contribution, Error> {
+        let delta_after = read_g1(reader)?;
+        let s = read_g1(reader)?;
+        let s_delta = read_g1(reader)?;
+        let r_delta = read_g2(reader)?;
+        let mut transcript = [0u8; 64];
+        reader.read_exact(&mut transcript)?;
+        Ok(Contribution {
+            delta_after,
+            s,
+            s_delta,
+            r_delta,
+            transcript,
+        })
+    }
+
+    fn hash(&self) -> TranscriptHash {
+        let mut bytes = vec![];
+        self.write(&mut bytes).unwrap();
+        blake2b(&[&bytes])
+    }
+}
+
+pub struct MPCParameters {
+    params: Parameters<Bls12>,
+    //hash of the initial parameters
+    cs_hash: TranscriptHash,
+    contributions: Vec<Contribution>,
+}
+
+impl Clone for MPCParameters {
+    fn clone(&self) -> MPCParameters {
+        MPCParameters {
+            params: self.params.clone(),
+            cs_hash: self.cs_hash,
+            contributions: self.contributions.clone(),
+        }
+    }
+}
+
+impl MPCParameters {
+    //Starts a ceremony from freshly generated parameters.
+    pub fn new(params: Parameters<Bls12>) -> Result<MPCParameters, Error> {
+        let mut bytes = vec![];
+        params.write(&mut bytes)?;
+        Ok(MPCParameters {
+            params,
+            cs_hash: blake2b(&[&bytes]),
+            contributions: vec![],
+        })
+    }
+
+    pub fn params(&self) -> &Parameters<Bls12> {
+        &self.params
+    }
+
+    pub fn contributions(&self) -> usize {
+        self.contributions.len()
+    }
+
+    pub fn read(kind: ParamKind) -> Result<MPCParameters, Error> {
+        let param_path = existing_param_path(kind.path())?;
+        check_params(kind, Path::new(&param_path))?;
+        let params = Parameters::<Bls12>::read(&mut BufReader::new(File::open(param_path)?), true)?;
+
+        let transcript_path = existing_param_path(transcript_path(kind))?;
+        let mut reader = BufReader::new(File::open(transcript_path)?);
+        let mut cs_hash = [0u8; 64];
+        reader.read_exact(&mut cs_hash)?;
+        let mut len = [0u8; 4];
+        reader.read_exact(&mut len)?;
+        let len =
+            (len[0] as u32) | (len[1] as u32) << 8 | (len[2] as u32) << 16 | (len[3] as u32) << 24;
+        let mut contributions = Vec::with_capacity(len as usize);
+        for _ in 0..len {
+            contributions.push(Contribution::read(&mut reader)?);
+        }
+
+        Ok(MPCParameters {
+            params,
+            cs_hash,
+            contributions,
+        })
+    }
+
+    pub fn write(&self, kind: ParamKind) -> Result<(), Error> {
+        //a contribution leaves the circuit as it was
+        let constraints = check_params(kind, &kind.path())?.constraints;
+        write_params(kind, &self.params, constraints, &kind.path())?;
+        write_atomic(&transcript_path(kind), |writer| {
+            writer.write_all(&self.cs_hash)?;
+            let len = self.contributions.len() as u32;
+            writer.write_all(&[
+                len as u8,
+                (len >> 8) as u8,
+                (len >> 16) as u8,
+                (len >> 24) as u8,
+            ])?;
+            for contribution in self.contributions.iter() {
+                contribution.write(writer)?;
+            }
+            Ok(())
+        })
+    }
+
+    fn transcript(&self, s: &G1Affine, s_delta: &G1Affine) -> TranscriptHash {
+        transcript(&self.cs_hash, &self.contributions, s, s_delta)
+    }
+}
+
+//blake2b(cs_hash|previous contributions|s|s_delta)
+fn transcript(
+    cs_hash: &TranscriptHash,
+    previous: &[Contribution],
+    s: &G1Affine,
+    s_delta: &G1Affine,
+) -> TranscriptHash {
+    let mut bytes = vec![];
+    for contribution in previous.iter() {
+        contribution.write(&mut bytes).unwrap();
+    }
+    blake2b(&[
+        cs_hash,
+        &bytes,
+        s.into_uncompressed().as_ref(),
+        s_delta.into_uncompressed().as_ref(),
+    ])
+}
+
+fn transcript_path(kind: ParamKind) -> PathBuf {
+    kind.path().with_extension("mpc")
+}
+
+fn blake2b(parts: &[&[u8]]) -> TranscriptHash {
+    let mut h = Blake2b::new(64);
+    for part in parts.iter() {
+        h.update(part);
+    }
+    let mut res = [0u8; 64];
+    res.copy_from_slice(h.finalize().as_bytes());
+    res
+}
+
+fn read_g1<R: Read>(reader: &mut R) -> Result<G1Affine, Error> {
+    let mut repr = G1Uncompressed::empty();
+    reader.read_exact(repr.as_mut())?;
+    repr.into_affine().map_err(|_| Error::InvalidContribution)
+}
+
+fn read_g2<R: Read>(reader: &mut R) -> Result<G2Affine, Error> {
+    let mut repr = G2Uncompressed::empty();
+    reader.read_exact(repr.as_mut())?;
+    repr.into_affine().map_err(|_| Error::InvalidContribution)
+}
+
+fn hash_to_g2(digest: &TranscriptHash) -> G2Affine {
+    let seed: Vec<u32> = digest[..32]
+        .chunks(4)
+        .map(|c| (c[0] as u32) | (c[1] as u32) << 8 | (c[2] as u32) << 16 | (c[3] as u32) << 24)
+        .collect();
+    let mut rng = ChaChaRng::from_seed(&seed);
+    G2::rand(&mut rng).into_affine()
+}
+
+//e(g1.0, g2.1) == e(g1.1, g2.0)
+fn same_ratio(g1: (G1Affine, G1Affine), g2: (G2Affine, G2Affine)) -> bool {
+    Bls12::pairing(g1.0, g2.1) == Bls12::pairing(g1.1, g2.0)
+}
+
+//random linear combination of two vectors with the same coefficients
+fn merge_pairs(v1: &[G1Affine], v2: &[G1Affine]) -> (G1Affine, G1Affine) {
+    let rng = &mut thread_rng();
+    let mut s = G1::zero();
+    let mut sx = G1::zero();
+    for (a, b) in v1.iter().zip(v2.iter()) {
+        let rho = Fr::rand(rng);
+        s.add_assign(&a.mul(rho));
+        sx.add_assign(&b.mul(rho));
+    }
+    (s.into_affine(), sx.into_affine())
+}
+
+fn scale(v: &[G1Affine], scalar: Fr) -> Vec<G1Affine> {
+    v.iter().map(|p| p.mul(scalar).into_affine()).collect()
+}
+
+//Adds a contribution drawn from `entropy` and returns the new parameters
+//together with the hash identifying the contribution.
+pub fn contribute<R: Rng>(
+    mut params: MPCParameters,
+    entropy: &mut R,
+) -> (MPCParameters, TranscriptHash) {
+    let delta = Fr::rand(entropy);
+    let delta_inv = delta
+        .inverse()
+        .expect("nonzero with overwhelming probability");
+
+    let s = G1::rand(entropy).into_affine();
+    let s_delta = s.mul(delta).into_affine();
+    let transcript = params.transcript(&s, &s_delta);
+    let r_delta = hash_to_g2(&transcript).mul(delta).into_affine();
+
+    params.params.vk.delta_g1 = params.params.vk.delta_g1.mul(delta).into_affine();
+    params.params.vk.delta_g2 = params.params.vk.delta_g2.mul(delta).into_affine();
+    params.params.h = Arc::new(scale(&params.params.h, delta_inv));
+    params.params.l = Arc::new(scale(&params.params.l, delta_inv));
+
+    let contribution = Contribution {
+        delta_after: params.params.vk.delta_g1,
+        s,
+        s_delta,
+        r_delta,
+        transcript,
+    };
+    let hash = contribution.hash();
+    params.contributions.push(contribution);
+    (params, hash)
+}
+
+//Checks the contribution made on top of `previous` contributions, moving
+//delta_g1 from delta_before to contribution.delta_after.
+fn check_step(
+    cs_hash: &TranscriptHash,
+    previous: &[Contribution],
+    delta_before: G1Affine,
+    contribution: &Contribution,
+) -> Result<(), Error> {
+    if contribution.transcript
+        != transcript(cs_hash, previous, &contribution.s, &contribution.s_delta)
+    {
+        return Err(Error::InvalidContribution);
+    }
+
+    let r = hash_to_g2(&contribution.transcript);
+    //the contributor knows delta
+    if !same_ratio(
+        (contribution.s, contribution.s_delta),
+        (r, contribution.r_delta),
+    ) {
+        return Err(Error::InvalidContribution);
+    }
+    //delta_g1 was multiplied by the same delta
+    if !same_ratio(
+        (delta_before, contribution.delta_after),
+        (r, contribution.r_delta),
+    ) {
+        return Err(Error::InvalidContribution);
+    }
+    Ok(())
+}
+
+//Checks that `a` differs from `b` only in delta and in h and l divided by it.
+fn check_ends(b: &Parameters<Bls12>, a: &Parameters<Bls12>) -> Result<(), Error> {
+    if b.vk.alpha_g1 != a.vk.alpha_g1
+        || b.vk.beta_g1 != a.vk.beta_g1
+        || b.vk.beta_g2 != a.vk.beta_g2
+        || b.vk.gamma_g2 != a.vk.gamma_g2
+        || b.vk.ic != a.vk.ic
+        || b.a != a.a
+        || b.b_g1 != a.b_g1
+        || b.b_g2 != a.b_g2
+        || b.h.len() != a.h.len()
+        || b.l.len() != a.l.len()
+    {
+        return Err(Error::InvalidContribution);
+    }
+    //delta_g1 and delta_g2 agree
+    if !same_ratio(
+        (G1Affine::one(), a.vk.delta_g1),
+        (G2Affine::one(), a.vk.delta_g2),
+    ) {
+        return Err(Error::InvalidContribution);
+    }
+    //h and l were divided by delta
+    if !same_ratio(merge_pairs(&b.h, &a.h), (a.vk.delta_g2, b.vk.delta_g2)) {
+        return Err(Error::InvalidContribution);
+    }
+    if !same_ratio(merge_pairs(&b.l, &a.l), (a.vk.delta_g2, b.vk.delta_g2)) {
+        return Err(Error::InvalidContribution);
+    }
+    Ok(())
+}
+
+//Checks that `after` is `before` plus exactly one valid contribution and
+//returns the hash of that contribution.
+pub fn verify_contribution(
+    before: &MPCParameters,
+    after: &MPCParameters,
+) -> Result<TranscriptHash, Error> {
+    if before.cs_hash != after.cs_hash
+        || after.contributions.len() != before.contributions.len() + 1
+    {
+        return Err(Error::InvalidContribution);
+    }
+    for (old, new) in before.contributions.iter().zip(after.contributions.iter()) {
+        if old.hash() != new.hash() {
+            return Err(Error::InvalidContribution);
+        }
+    }
+
+    let contribution = after.contributions.last().unwrap();
+    check_step(
+        &before.cs_hash,
+        &before.contributions,
+        before.params.vk.delta_g1,
+        contribution,
+    )?;
+    if contribution.delta_after != after.params.vk.delta_g1 {
+        return Err(Error::InvalidContribution);
+    }
+    check_ends(&before.params, &after.params)?;
+
+    Ok(contribution.hash())
+}
+
+//Checks a whole ceremony from the parameters it started with: cs_hash is
+//recomputed from `initial`, every contribution in `last` is checked against
+//the delta before it, and `last` against `initial`. Returns the hash of each
+//contribution in order, so every participant can find theirs.
+pub fn verify_transcript(
+    initial: &Parameters<Bls12>,
+    last: &MPCParameters,
+) -> Result<Vec<TranscriptHash>, Error> {
+    let start = MPCParameters::new(initial.clone())?;
+    if start.cs_hash != last.cs_hash {
+        return Err(Error::InvalidContribution);
+    }
+
+    let mut delta = initial.vk.delta_g1;
+    let mut hashes = Vec::with_capacity(last.contributions.len());
+    for (i, contribution) in last.contributions.iter().enumerate() {
+        check_step(&last.cs_hash, &last.contributions[..i], delta, contribution)?;
+        delta = contribution.delta_after;
+        hashes.push(contribution.hash());
+    }
+    if delta != last.params.vk.delta_g1 {
+        return Err(Error::InvalidContribution);
+    }
+    check_ends(initial, &last.params)?;
+
+    Ok(hashes)
+}
+
+//Records the current parameter file of `kind` as the start of a ceremony,
+//trusting whoever generated it with tau, alpha and beta.
+pub fn init(kind: ParamKind) -> Result<(), Error> {
+    let param_path = existing_param_path(kind.path())?;
+    check_params(kind, Path::new(&param_path))?;
+    let params = Parameters::<Bls12>::read(&mut BufReader::new(File::open(param_path)?), true)?;
+    MPCParameters::new(params)?.write(kind)
+}
+
+//Contributes to the parameter file of `kind` in place.
+pub fn contribute_file<R: Rng>(kind: ParamKind, entropy: &mut R) -> Result<TranscriptHash, Error> {
+    let before = MPCParameters::read(kind)?;
+    let (after, hash) = contribute(before, entropy);
+    after.write(kind)?;
+    Ok(hash)
+}
+
+//Runs a ceremony over all five parameter files of the current param path,
+//one participant after another, verifying every step. Returns the
+//contribution hashes of each participant in order.
+pub fn local_ceremony<R: Rng>(participants: &mut [R]) -> Result<Vec<Vec<TranscriptHash>>, Error> {
+    let mut hashes = vec![vec![]; participants.len()];
+    for kind in PARAM_KINDS.iter() {
+        init(*kind)?;
+        let mut params = MPCParameters::read(*kind)?;
+        let initial = params.params.clone();
+        for (i, rng) in participants.iter_mut().enumerate() {
+            let (next, hash) = contribute(params.clone(), rng);
+            if verify_contribution(&params, &next)? != hash {
+                return Err(Error::InvalidContribution);
+            }
+            hashes[i].push(hash);
+            params = next;
+        }
+        if verify_transcript(&initial, &params)?.len() != participants.len() {
+            return Err(Error::InvalidContribution);
+        }
+        params.write(*kind)?;
+    }
+    Ok(hashes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bellman::Error as SynthesisError;
+    use bellman::*;
+    use jubjub::*;
+    use rand::XorShiftRng;
+
+    //x*x = y with y public
+    struct Square {
+        x: Assignment<Fr>,
+    }
+
+    struct SquareInput {
+        y: Num<Bls12>,
+    }
+
+    impl Input<Bls12> for SquareInput {
+        fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<(), SynthesisError> {
+            let y_input = cs.alloc_input(|| Ok(*self.y.getvalue().get()?))?;
+            cs.enforce(
+                LinearCombination::zero() + self.y.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + y_input,
+            );
+            Ok(())
+        }
+    }
+
+    impl Circuit<Bls12> for Square {
+        type InputMap = SquareInput;
+
+        fn synthesize<CS: ConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<SquareInput, SynthesisError> {
+            let x = Num::new(cs, self.x)?;
+            let value = match x.getvalue().get() {
+                Ok(x) => {
+                    let mut y = *x;
+                    y.square();
+                    Assignment::known(y)
+                }
+                Err(_) => Assignment::unknown(),
+            };
+            let y = Num::new(cs, value)?;
+            cs.enforce(
+                LinearCombination::zero() + x.getvar(),
+                LinearCombination::zero() + x.getvar(),
+                LinearCombination::zero() + y.getvar(),
+            );
+            Ok(SquareInput { y })
+        }
+    }
+
+    fn start(seed: u32) -> MPCParameters {
+        let rng = &mut XorShiftRng::from_seed([seed, 2, 3, 4]);
+        let params = generate_random_parameters::<Bls12, _, _>(
+            Square {
+                x: Assignment::unknown(),
+            },
+            rng,
+        )
+        .unwrap();
+        MPCParameters::new(params).unwrap()
+    }
+
+    fn proves(params: &MPCParameters) -> bool {
+        let rng = &mut XorShiftRng::from_seed([5, 6, 7, 8]);
+        let x = Fr::from_str("3").unwrap();
+        let y = Fr::from_str("9").unwrap();
+        let proof = create_random_proof::<Bls12, _, _, _>(
+            Square {
+                x: Assignment::known(x),
+            },
+            params.params(),
+            rng,
+        )
+        .unwrap();
+        let pvk = prepare_verifying_key(&params.params().vk);
+        verify_proof(&pvk, &proof, |cs| {
+            Ok(SquareInput {
+                y: Num::new(cs, Assignment::known(y))?,
+            })
+        })
+        .unwrap()
+    }
+
+    #[test]
+    fn contributions_verify() {
+        let rng = &mut XorShiftRng::from_seed([9, 10, 11, 12]);
+        let first = start(1);
+        let (second, hash) = contribute(first.clone(), rng);
+        assert_eq!(verify_contribution(&first, &second).unwrap(), hash);
+        let (third, hash) = contribute(second.clone(), rng);
+        assert_eq!(verify_contribution(&second, &third).unwrap(), hash);
+        assert_eq!(third.contributions(), 2);
+        assert!(proves(&third));
+
+        //not exactly one contribution apart
+        assert!(verify_contribution(&first, &third).is_err());
+        assert!(verify_contribution(&first, &first).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_contributions() {
+        let rng = &mut XorShiftRng::from_seed([9, 10, 11, 12]);
+        let before = start(1);
+        let (after, _) = contribute(before.clone(), rng);
+
+        //delta changed but h left as it was
+        let mut bad = after.clone();
+        bad.params.h = before.params.h.clone();
+        assert!(verify_contribution(&before, &bad).is_err());
+
+        //delta_g2 not moved with delta_g1
+        let mut bad = after.clone();
+        bad.params.vk.delta_g2 = before.params.vk.delta_g2;
+        assert!(verify_contribution(&before, &bad).is_err());
+
+        //a proof of knowledge for another transcript
+        let mut bad = after.clone();
+        bad.contributions.last_mut().unwrap().transcript[0] ^= 1;
+        assert!(verify_contribution(&before, &bad).is_err());
+
+        //a contribution on top of different parameters
+        let (bad, _) = contribute(start(2), rng);
+        assert!(verify_contribution(&before, &bad).is_err());
+    }
+
+    #[test]
+    fn transcript_verifies() {
+        let rng = &mut XorShiftRng::from_seed([9, 10, 11, 12]);
+        let first = start(1);
+        let (second, first_hash) = contribute(first.clone(), rng);
+        let (third, second_hash) = contribute(second.clone(), rng);
+        let (last, third_hash) = contribute(third, rng);
+        assert_eq!(
+            verify_transcript(first.params(), &last).unwrap(),
+            vec![first_hash, second_hash, third_hash]
+        );
+        assert_eq!(verify_transcript(first.params(), &first).unwrap().len(), 0);
+
+        //started from other parameters
+        assert!(verify_transcript(start(2).params(), &last).is_err());
+
+        //a middle contribution replaced by another one on the same delta
+        let (other, _) = contribute(first.clone(), rng);
+        let mut bad = last.clone();
+        bad.contributions[0] = other.contributions[0].clone();
+        assert!(verify_transcript(first.params(), &bad).is_err());
+
+        //a middle contribution tampered with
+        let mut bad = last.clone();
+        bad.contributions[1].delta_after = second.params.vk.delta_g1;
+        assert!(verify_transcript(first.params(), &bad).is_err());
+
+        //contributions dropped from the transcript
+        let mut bad = last.clone();
+        bad.contributions.truncate(1);
+        assert!(verify_transcript(first.params(), &bad).is_err());
+    }
+}
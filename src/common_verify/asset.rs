@@ -0,0 +1,419 @@
+//Gadgets binding coins and value commitments to an asset.
+
+use bellman::Error as SynthesisError;
+use bellman::*;
+use pairing::bls12_381::{Bls12, Fr};
+use pairing::*;
+
+use jubjub::*;
+
+use base::*;
+use common_verify::fixed_base::{boolean_bits, fixed_base_mul};
+use common_verify::nullifier::canonical_bits;
+use generators::p2;
+
+pub(crate) fn product<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    a: &Num<Bls12>,
+    b: &Num<Bls12>,
+) -> Result<Num<Bls12>, SynthesisError> {
+    let value = match (a.getvalue().get(), b.getvalue().get()) {
+        (Ok(a), Ok(b)) => {
+            let mut ab = *a;
+            ab.mul_assign(b);
+            Assignment::known(ab)
+        }
+        _ => Assignment::unknown(),
+    };
+    let ab = Num::new(cs, value)?;
+    cs.enforce(
+        LinearCombination::zero() + a.getvar(),
+        LinearCombination::zero() + b.getvar(),
+        LinearCombination::zero() + ab.getvar(),
+    );
+    Ok(ab)
+}
+
+//p+q on the twisted Edwards curve -x^2+y^2 = 1+d*x^2*y^2
+pub(crate) fn add_points<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    p: &(Num<Bls12>, Num<Bls12>),
+    q: &(Num<Bls12>, Num<Bls12>),
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let x1y2 = product(cs, &p.0, &q.1)?;
+    let y1x2 = product(cs, &p.1, &q.0)?;
+    let x1x2 = product(cs, &p.0, &q.0)?;
+    let y1y2 = product(cs, &p.1, &q.1)?;
+
+    //d*x1x2*y1y2
+    let value = match (x1x2.getvalue().get(), y1y2.getvalue().get()) {
+        (Ok(x1x2), Ok(y1y2)) => {
+            let mut t = j.d;
+            t.mul_assign(x1x2);
+            t.mul_assign(y1y2);
+            Assignment::known(t)
+        }
+        _ => Assignment::unknown(),
+    };
+    let t = Num::new(cs, value)?;
+    cs.enforce(
+        LinearCombination::zero() + (j.d, x1x2.getvar()),
+        LinearCombination::zero() + y1y2.getvar(),
+        LinearCombination::zero() + t.getvar(),
+    );
+
+    let (x, y) = match (
+        p.0.getvalue().get(),
+        p.1.getvalue().get(),
+        q.0.getvalue().get(),
+        q.1.getvalue().get(),
+    ) {
+        (Ok(x1), Ok(y1), Ok(x2), Ok(y2)) => {
+            let (x, y) = point_add(x1, y1, x2, y2, j);
+            (Assignment::known(x), Assignment::known(y))
+        }
+        _ => (Assignment::unknown(), Assignment::unknown()),
+    };
+    let x = Num::new(cs, x)?;
+    let y = Num::new(cs, y)?;
+    //(1+t)*x = x1y2+y1x2
+    cs.enforce(
+        LinearCombination::zero() + CS::one() + t.getvar(),
+        LinearCombination::zero() + x.getvar(),
+        LinearCombination::zero() + x1y2.getvar() + y1x2.getvar(),
+    );
+    //(1-t)*y = y1y2+x1x2
+    cs.enforce(
+        LinearCombination::zero() + CS::one() - t.getvar(),
+        LinearCombination::zero() + y.getvar(),
+        LinearCombination::zero() + y1y2.getvar() + x1x2.getvar(),
+    );
+
+    Ok((x, y))
+}
+
+//a num equal to a, for a point that is changed in place
+fn copy<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    a: &Num<Bls12>,
+) -> Result<Num<Bls12>, SynthesisError> {
+    let value = match a.getvalue().get() {
+        Ok(a) => Assignment::known(*a),
+        Err(_) => Assignment::unknown(),
+    };
+    let c = Num::new(cs, value)?;
+    cs.enforce(
+        LinearCombination::zero() + a.getvar(),
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero() + c.getvar(),
+    );
+    Ok(c)
+}
+
+//a if bit is 0, b if it is 1
+fn choose<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    bit: &Num<Bls12>,
+    a: &Num<Bls12>,
+    b: &Num<Bls12>,
+) -> Result<Num<Bls12>, SynthesisError> {
+    let value = match (bit.getvalue().get(), a.getvalue().get(), b.getvalue().get()) {
+        (Ok(bit), Ok(a), Ok(b)) => Assignment::known(if bit.is_zero() { *a } else { *b }),
+        _ => Assignment::unknown(),
+    };
+    let c = Num::new(cs, value)?;
+    //bit*(b-a) = c-a
+    cs.enforce(
+        LinearCombination::zero() + bit.getvar(),
+        LinearCombination::zero() + b.getvar() - a.getvar(),
+        LinearCombination::zero() + c.getvar() - a.getvar(),
+    );
+    Ok(c)
+}
+
+//num*base for num < 2^n, with base a point of the circuit rather than a constant
+pub(crate) fn variable_base_mul<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    base: &(Num<Bls12>, Num<Bls12>),
+    num: &Num<Bls12>,
+    n: usize,
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let bits = boolean_bits(cs, num, n)?;
+    multiply_base(cs, base, &bits, j)
+}
+
+//sum of bits[i]*2^i*base
+pub(crate) fn multiply_base<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    base: &(Num<Bls12>, Num<Bls12>),
+    bits: &[Num<Bls12>],
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let x = Num::new(cs, Assignment::known(Fr::zero()))?;
+    let y = Num::new(cs, Assignment::known(Fr::one()))?;
+    cs.enforce(
+        LinearCombination::zero() + x.getvar(),
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero(),
+    );
+    cs.enforce(
+        LinearCombination::zero() + y.getvar(),
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero() + CS::one(),
+    );
+
+    //acc is the identity, power is 2^i*base; the formulas of add_points are
+    //complete on JubJub, so doubling and adding the identity need no special case
+    let mut acc = (x, y);
+    let mut power = (copy(cs, &base.0)?, copy(cs, &base.1)?);
+    for (i, bit) in bits.iter().enumerate() {
+        let sum = add_points(cs, &acc, &power, j)?;
+        acc = (
+            choose(cs, bit, &acc.0, &sum.0)?,
+            choose(cs, bit, &acc.1, &sum.1)?,
+        );
+        if i + 1 < bits.len() {
+            power = add_points(cs, &power, &power, j)?;
+        }
+    }
+    Ok(acc)
+}
+
+//va*G+r*P2 for r < 2^rbit, G the value generator of the asset.
+//G is a public input the verifier computes with generators::asset_generator
+//from the public asset, so it is bound to the asset without hashing to the
+//curve in the circuit.
+pub(crate) fn asset_commitment<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    va: &Num<Bls12>,
+    r: &Num<Bls12>,
+    rbit: usize,
+    generator: &(Num<Bls12>, Num<Bls12>),
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let vg = variable_base_mul(cs, generator, va, VBIT, j)?;
+    let rp = fixed_base_mul(cs, p2(), r, rbit, j)?;
+    add_points(cs, &vg, &rp, j)
+}
+
+//PH(note|asset), note = PH(rcm|va|pa), see note::payment_coin.
+//The note is unpacked below r and the asset to ASSETBIT bits, so the coin of a
+//note and asset is the one build_coin computes.
+pub(crate) fn asset_coin<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    note: &Num<Bls12>,
+    asset: &Num<Bls12>,
+    generators: &[(Vec<Fr>, Vec<Fr>)],
+    j: &JubJub,
+) -> Result<Num<Bls12>, SynthesisError> {
+    let mut vin = vec![];
+    for bit in canonical_bits(cs, note, PHOUT)?.iter() {
+        vin.extend(bit.unpack_sized(cs, 1)?);
+    }
+    vin.extend(asset.unpack_sized(cs, ASSETBIT)?);
+    let zero = Num::new(cs, Assignment::known(Fr::zero()))?;
+    cs.enforce(
+        LinearCombination::zero() + zero.getvar(),
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero(),
+    );
+    vin.extend(zero.unpack_sized(cs, PHIN - PHOUT - ASSETBIT)?);
+    assert_eq!(vin.len(), PHIN);
+    pedersen_hash(cs, &vin, generators, j)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base::{point2fr, u64s2bits, value_commitment, RCMBIT};
+    use bellman::groth16::*;
+    use convert::str2point;
+    use generators::asset_generator;
+    use params::{default_params, test_generators};
+    use rand::{SeedableRng, XorShiftRng};
+
+    enum Gadget {
+        //a*G+b*P2 for a < 2^VBIT and b < 2^RCMBIT, inputs G and the point
+        Commitment,
+        //PH(a|b), inputs the asset b and the coin
+        Coin,
+    }
+
+    struct GadgetCircuit<'a> {
+        gadget: &'a Gadget,
+        generators: &'a [(Vec<Fr>, Vec<Fr>)],
+        j: &'a JubJub,
+        a: Assignment<Fr>,
+        b: Assignment<Fr>,
+        generator: (Assignment<Fr>, Assignment<Fr>),
+    }
+
+    struct GadgetInput {
+        inputs: Vec<Num<Bls12>>,
+    }
+
+    impl Input<Bls12> for GadgetInput {
+        fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<(), SynthesisError> {
+            for num in self.inputs.iter() {
+                let input = cs.alloc_input(|| Ok(*num.getvalue().get()?))?;
+                cs.enforce(
+                    LinearCombination::zero() + num.getvar(),
+                    LinearCombination::zero() + CS::one(),
+                    LinearCombination::zero() + input,
+                );
+            }
+            Ok(())
+        }
+    }
+
+    impl<'a> Circuit<Bls12> for GadgetCircuit<'a> {
+        type InputMap = GadgetInput;
+
+        fn synthesize<CS: ConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<GadgetInput, SynthesisError> {
+            let a = Num::new(cs, self.a)?;
+            let b = Num::new(cs, self.b)?;
+            let inputs = match *self.gadget {
+                Gadget::Commitment => {
+                    let g = (
+                        Num::new(cs, self.generator.0)?,
+                        Num::new(cs, self.generator.1)?,
+                    );
+                    let out = asset_commitment(cs, &a, &b, RCMBIT, &g, self.j)?;
+                    vec![g.0, g.1, out.0, out.1]
+                }
+                Gadget::Coin => {
+                    let coin = asset_coin(cs, &a, &b, self.generators, self.j)?;
+                    vec![b, coin]
+                }
+            };
+            Ok(GadgetInput { inputs })
+        }
+    }
+
+    fn circuit<'a>(
+        gadget: &'a Gadget,
+        generators: &'a [(Vec<Fr>, Vec<Fr>)],
+        j: &'a JubJub,
+    ) -> GadgetCircuit<'a> {
+        GadgetCircuit {
+            gadget,
+            generators,
+            j,
+            a: Assignment::unknown(),
+            b: Assignment::unknown(),
+            generator: (Assignment::unknown(), Assignment::unknown()),
+        }
+    }
+
+    //which of the candidate inputs a proof of the circuit verifies against
+    fn proves(
+        gadget: Gadget,
+        a: [u64; 4],
+        b: [u64; 4],
+        generator: (Fr, Fr),
+        candidates: &[Vec<Fr>],
+    ) -> Vec<bool> {
+        test_generators();
+        let params = default_params().unwrap();
+        let j = JubJub::new();
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let groth = generate_random_parameters::<Bls12, _, _>(
+            circuit(&gadget, params.generators(), &j),
+            rng,
+        )
+        .unwrap();
+        let mut known = circuit(&gadget, params.generators(), &j);
+        known.a = Assignment::known(Fr::from_serial(a));
+        known.b = Assignment::known(Fr::from_serial(b));
+        known.generator = (
+            Assignment::known(generator.0),
+            Assignment::known(generator.1),
+        );
+        let proof = create_random_proof::<Bls12, _, _, _>(known, &groth, rng).unwrap();
+
+        let pvk = prepare_verifying_key(&groth.vk);
+        candidates
+            .iter()
+            .map(|inputs| {
+                verify_proof(&pvk, &proof, |cs| {
+                    let mut nums = vec![];
+                    for x in inputs.iter() {
+                        nums.push(Num::new(cs, Assignment::known(*x))?);
+                    }
+                    Ok(GadgetInput { inputs: nums })
+                })
+                .unwrap()
+            })
+            .collect()
+    }
+
+    fn commitment(v: [u64; 2], asset: u64, r: [u64; 2]) -> Vec<Fr> {
+        let g = asset_generator(asset);
+        let point = value_commitment(v, asset, [r[0], r[1], 0, 0]);
+        let point = point2fr(str2point(point).unwrap()).unwrap();
+        vec![g.0, g.1, point.0, point.1]
+    }
+
+    fn coin(note: [u64; 4], asset: u64) -> Vec<Fr> {
+        let mut bits = u64s2bits(&note);
+        bits.extend(u64s2bits(&[asset, 0, 0, 0]));
+        let coin = ::pedersen_hash(&bits).unwrap();
+        vec![Fr::from_serial([asset, 0, 0, 0]), Fr::from_serial(coin)]
+    }
+
+    #[test]
+    fn commitment_matches_native() {
+        let (v, r) = ([!0, 5], [9, 0x0fff_ffff_ffff_ffff]);
+        let verified = proves(
+            Gadget::Commitment,
+            [v[0], v[1], 0, 0],
+            [r[0], r[1], 0, 0],
+            asset_generator(3),
+            &[commitment(v, 3, r)],
+        );
+        assert_eq!(verified, vec![true]);
+    }
+
+    #[test]
+    fn commitment_is_bound_to_its_asset() {
+        let (v, r) = ([10, 0], [7, 1]);
+        let mut mixed = commitment(v, 3, r);
+        let other = commitment(v, 2, r);
+        mixed[0] = other[0];
+        mixed[1] = other[1];
+        //made on the generator of asset 3, it opens neither as asset 2 nor
+        //under the generator of asset 2
+        let verified = proves(
+            Gadget::Commitment,
+            [v[0], v[1], 0, 0],
+            [r[0], r[1], 0, 0],
+            asset_generator(3),
+            &[commitment(v, 3, r), other, mixed],
+        );
+        assert_eq!(verified, vec![true, false, false]);
+    }
+
+    #[test]
+    fn coin_is_bound_to_its_asset() {
+        let note = [1, 2, 3, 0x0fff_ffff_ffff_ffff];
+        let mut mixed = coin(note, 5);
+        mixed[0] = Fr::from_serial([6, 0, 0, 0]);
+        //a coin of asset 5 is not one of asset 6
+        let verified = proves(
+            Gadget::Coin,
+            note,
+            [5, 0, 0, 0],
+            (Fr::zero(), Fr::one()),
+            &[coin(note, 5), coin(note, 6), mixed],
+        );
+        assert_eq!(verified, vec![true, false, false]);
+    }
+}
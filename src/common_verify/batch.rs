@@ -0,0 +1,242 @@
+//Groth16 batch verification by random linear combination.
+//
+//For random r_i the n checks e(A_i, B_i) = e(alpha, beta) e(acc_i, gamma) e(C_i, delta)
+//are folded into
+//prod e(r_i A_i, B_i) e(-sum r_i acc_i, gamma) e(-sum r_i C_i, delta) = e(alpha, beta)^(sum r_i),
+//one multi-Miller loop and one final exponentiation for the whole batch.
+
+use bellman::groth16::*;
+use pairing::bls12_381::{Bls12, Fr, G1};
+use pairing::*;
+use rand::{thread_rng, Rand};
+
+use error::Error;
+
+fn check(vk: &VerifyingKey<Bls12>, proofs: &[(Proof<Bls12>, Vec<Fr>)]) -> Result<bool, Error> {
+    let rng = &mut thread_rng();
+    let mut sum_r = Fr::zero();
+    let mut acc_ic = G1::zero();
+    let mut acc_c = G1::zero();
+    let mut ab = Vec::with_capacity(proofs.len());
+    for &(ref proof, ref inputs) in proofs.iter() {
+        if inputs.len() + 1 != vk.ic.len() {
+            return Err(Error::WrongLength {
+                expected: vk.ic.len() - 1,
+                found: inputs.len(),
+            });
+        }
+        let r = Fr::rand(rng);
+        sum_r.add_assign(&r);
+
+        //ic[0] + sum x_j*ic[j+1]
+        let mut acc = vk.ic[0].into_projective();
+        for (x, ic) in inputs.iter().zip(vk.ic.iter().skip(1)) {
+            acc.add_assign(&ic.mul(*x));
+        }
+        acc.mul_assign(r);
+        acc_ic.add_assign(&acc);
+        acc_c.add_assign(&proof.c.mul(r));
+        ab.push((proof.a.mul(r).into_affine().prepare(), proof.b.prepare()));
+    }
+    acc_ic.negate();
+    acc_c.negate();
+
+    let ic = acc_ic.into_affine().prepare();
+    let gamma = vk.gamma_g2.prepare();
+    let c = acc_c.into_affine().prepare();
+    let delta = vk.delta_g2.prepare();
+    let mut terms = Vec::with_capacity(ab.len() + 2);
+    for &(ref a, ref b) in ab.iter() {
+        terms.push((a, b));
+    }
+    terms.push((&ic, &gamma));
+    terms.push((&c, &delta));
+
+    let lhs = match Bls12::final_exponentiation(&Bls12::miller_loop(terms.iter())) {
+        Some(lhs) => lhs,
+        None => return Ok(false),
+    };
+    let rhs = Bls12::pairing(vk.alpha_g1, vk.beta_g2).pow(sum_r.into_repr());
+    Ok(lhs == rhs)
+}
+
+//index of the first proof that does not verify, None if all do
+pub(crate) fn verify_batch(
+    vk: &VerifyingKey<Bls12>,
+    proofs: &[(Proof<Bls12>, Vec<Fr>)],
+) -> Result<Option<usize>, Error> {
+    if proofs.is_empty() || check(vk, proofs)? {
+        return Ok(None);
+    }
+    //bisect, the failing half is checked again with fresh randomness
+    let mut start = 0;
+    let mut end = proofs.len();
+    while end - start > 1 {
+        let mid = start + (end - start) / 2;
+        if check(vk, &proofs[start..mid])? {
+            start = mid;
+        } else {
+            end = mid;
+        }
+    }
+    Ok(Some(start))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bellman::Error as SynthesisError;
+    use bellman::*;
+    use jubjub::*;
+    use pairing::bls12_381::FrRepr;
+    use rand::{SeedableRng, XorShiftRng};
+
+    use std::time::Instant;
+
+    //x*x = y with y public
+    struct Square {
+        x: Assignment<Fr>,
+    }
+
+    struct SquareInput {
+        y: Num<Bls12>,
+    }
+
+    impl Input<Bls12> for SquareInput {
+        fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<(), SynthesisError> {
+            let y_input = cs.alloc_input(|| Ok(*self.y.getvalue().get()?))?;
+            cs.enforce(
+                LinearCombination::zero() + self.y.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + y_input,
+            );
+            Ok(())
+        }
+    }
+
+    impl Circuit<Bls12> for Square {
+        type InputMap = SquareInput;
+
+        fn synthesize<CS: ConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<SquareInput, SynthesisError> {
+            let x = Num::new(cs, self.x)?;
+            let value = match x.getvalue().get() {
+                Ok(x) => {
+                    let mut y = *x;
+                    y.square();
+                    Assignment::known(y)
+                }
+                Err(_) => Assignment::unknown(),
+            };
+            let y = Num::new(cs, value)?;
+            cs.enforce(
+                LinearCombination::zero() + x.getvar(),
+                LinearCombination::zero() + x.getvar(),
+                LinearCombination::zero() + y.getvar(),
+            );
+            Ok(SquareInput { y })
+        }
+    }
+
+    //n proofs of x*x = y for x = 1..n
+    fn proofs(n: u64) -> (Parameters<Bls12>, Vec<(Proof<Bls12>, Vec<Fr>)>) {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let params = generate_random_parameters::<Bls12, _, _>(
+            Square {
+                x: Assignment::unknown(),
+            },
+            rng,
+        )
+        .unwrap();
+        let mut proofs = vec![];
+        for i in 1..n + 1 {
+            let x = Fr::from_repr(FrRepr::from_serial([i, 0, 0, 0])).unwrap();
+            let mut y = x;
+            y.square();
+            let proof = create_random_proof::<Bls12, _, _, _>(
+                Square {
+                    x: Assignment::known(x),
+                },
+                &params,
+                rng,
+            )
+            .unwrap();
+            proofs.push((proof, vec![y]));
+        }
+        (params, proofs)
+    }
+
+    fn verify_each(vk: &VerifyingKey<Bls12>, proofs: &[(Proof<Bls12>, Vec<Fr>)]) -> Option<usize> {
+        let pvk = prepare_verifying_key(vk);
+        proofs.iter().position(|&(ref proof, ref inputs)| {
+            !verify_proof(&pvk, proof, |cs| {
+                Ok(SquareInput {
+                    y: Num::new(cs, Assignment::known(inputs[0]))?,
+                })
+            })
+            .unwrap()
+        })
+    }
+
+    #[test]
+    fn all_valid() {
+        let (params, proofs) = proofs(8);
+        assert_eq!(verify_each(&params.vk, &proofs), None);
+        assert_eq!(verify_batch(&params.vk, &proofs).unwrap(), None);
+        assert_eq!(verify_batch(&params.vk, &proofs[..1]).unwrap(), None);
+    }
+
+    #[test]
+    fn empty_batch() {
+        let (params, _) = proofs(0);
+        assert_eq!(verify_batch(&params.vk, &[]).unwrap(), None);
+    }
+
+    #[test]
+    fn locates_the_bad_proof() {
+        let (params, mut proofs) = proofs(8);
+        let n = proofs.len();
+        for bad in 0..n {
+            //a valid proof for other inputs
+            let inputs = proofs[bad].1.clone();
+            proofs[bad].1 = proofs[(bad + 1) % n].1.clone();
+            assert_eq!(verify_each(&params.vk, &proofs), Some(bad));
+            assert_eq!(verify_batch(&params.vk, &proofs).unwrap(), Some(bad));
+            proofs[bad].1 = inputs;
+        }
+        assert_eq!(verify_batch(&params.vk, &proofs).unwrap(), None);
+
+        //several bad proofs, the first one is reported
+        proofs[2].1 = proofs[3].1.clone();
+        let (left, right) = proofs.split_at_mut(6);
+        ::std::mem::swap(&mut left[5].0, &mut right[0].0);
+        assert_eq!(verify_batch(&params.vk, &proofs).unwrap(), Some(2));
+    }
+
+    #[test]
+    fn wrong_number_of_inputs() {
+        let (params, mut proofs) = proofs(2);
+        proofs[1].1.push(Fr::one());
+        assert!(verify_batch(&params.vk, &proofs).is_err());
+    }
+
+    //cargo test --release -- --ignored batch_against_sequential --nocapture
+    #[test]
+    #[ignore]
+    fn batch_against_sequential() {
+        let (params, proofs) = proofs(64);
+
+        let start = Instant::now();
+        assert_eq!(verify_each(&params.vk, &proofs), None);
+        println!("sequential: {:?}", start.elapsed());
+
+        let start = Instant::now();
+        assert_eq!(verify_batch(&params.vk, &proofs).unwrap(), None);
+        println!("batch: {:?}", start.elapsed());
+    }
+}
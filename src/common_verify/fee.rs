@@ -0,0 +1,186 @@
+//Gadgets moving a public fee in and out of a value.
+
+use bellman::Error as SynthesisError;
+use bellman::*;
+use pairing::bls12_381::Bls12;
+use pairing::*;
+
+use jubjub::*;
+
+use base::*;
+
+//va+fee, the caller unpacks it to VBIT
+pub(crate) fn add_fee<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    va: &Num<Bls12>,
+    fee: &Num<Bls12>,
+) -> Result<Num<Bls12>, SynthesisError> {
+    fee.unpack_sized(cs, VBIT)?;
+    let value = match (va.getvalue().get(), fee.getvalue().get()) {
+        (Ok(va), Ok(fee)) => {
+            let mut total = *va;
+            total.add_assign(fee);
+            Assignment::known(total)
+        }
+        _ => Assignment::unknown(),
+    };
+    let total = Num::new(cs, value)?;
+    cs.enforce(
+        LinearCombination::zero() + va.getvar() + fee.getvar(),
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero() + total.getvar(),
+    );
+    Ok(total)
+}
+
+//va-fee, the caller unpacks it to VBIT so that fee <= va
+pub(crate) fn sub_fee<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    va: &Num<Bls12>,
+    fee: &Num<Bls12>,
+) -> Result<Num<Bls12>, SynthesisError> {
+    fee.unpack_sized(cs, VBIT)?;
+    let value = match (va.getvalue().get(), fee.getvalue().get()) {
+        (Ok(va), Ok(fee)) => {
+            let mut rest = *va;
+            rest.sub_assign(fee);
+            Assignment::known(rest)
+        }
+        _ => Assignment::unknown(),
+    };
+    let rest = Num::new(cs, value)?;
+    cs.enforce(
+        LinearCombination::zero() + rest.getvar() + fee.getvar(),
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero() + va.getvar(),
+    );
+    Ok(rest)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bellman::groth16::*;
+    use pairing::bls12_381::Fr;
+    use rand::{SeedableRng, XorShiftRng};
+
+    struct FeeCircuit {
+        //add_fee when true, sub_fee otherwise
+        add: bool,
+        va: Assignment<Fr>,
+        fee: Assignment<Fr>,
+    }
+
+    struct FeeInput {
+        //fee and the result
+        inputs: Vec<Num<Bls12>>,
+    }
+
+    impl Input<Bls12> for FeeInput {
+        fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<(), SynthesisError> {
+            for num in self.inputs.iter() {
+                let input = cs.alloc_input(|| Ok(*num.getvalue().get()?))?;
+                cs.enforce(
+                    LinearCombination::zero() + num.getvar(),
+                    LinearCombination::zero() + CS::one(),
+                    LinearCombination::zero() + input,
+                );
+            }
+            Ok(())
+        }
+    }
+
+    impl Circuit<Bls12> for FeeCircuit {
+        type InputMap = FeeInput;
+
+        fn synthesize<CS: ConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<FeeInput, SynthesisError> {
+            let va = Num::new(cs, self.va)?;
+            let fee = Num::new(cs, self.fee)?;
+            let out = if self.add {
+                add_fee(cs, &va, &fee)?
+            } else {
+                sub_fee(cs, &va, &fee)?
+            };
+            out.unpack_sized(cs, VBIT)?;
+            Ok(FeeInput {
+                inputs: vec![fee, out],
+            })
+        }
+    }
+
+    fn fr(v: [u64; 2]) -> Fr {
+        Fr::from_serial([v[0], v[1], 0, 0])
+    }
+
+    //which of the candidate (fee, result) pairs a proof verifies against
+    fn proves(add: bool, va: [u64; 2], fee: [u64; 2], candidates: &[[[u64; 2]; 2]]) -> Vec<bool> {
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let blank = FeeCircuit {
+            add,
+            va: Assignment::unknown(),
+            fee: Assignment::unknown(),
+        };
+        let groth = generate_random_parameters::<Bls12, _, _>(blank, rng).unwrap();
+        let known = FeeCircuit {
+            add,
+            va: Assignment::known(fr(va)),
+            fee: Assignment::known(fr(fee)),
+        };
+        let proof = create_random_proof::<Bls12, _, _, _>(known, &groth, rng).unwrap();
+
+        let pvk = prepare_verifying_key(&groth.vk);
+        candidates
+            .iter()
+            .map(|c| {
+                verify_proof(&pvk, &proof, |cs| {
+                    Ok(FeeInput {
+                        inputs: vec![
+                            Num::new(cs, Assignment::known(fr(c[0])))?,
+                            Num::new(cs, Assignment::known(fr(c[1])))?,
+                        ],
+                    })
+                })
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn add_fee_matches() {
+        //the sum carries into the high word
+        let verified = proves(
+            true,
+            [!0, 1],
+            [1, 0],
+            &[[[1, 0], [0, 2]], [[1, 0], [1, 2]], [[2, 0], [0, 2]]],
+        );
+        assert_eq!(verified, vec![true, false, false]);
+    }
+
+    #[test]
+    fn sub_fee_keeps_input_equal_to_output_plus_fee() {
+        let verified = proves(
+            false,
+            [15, 0],
+            [5, 0],
+            &[[[5, 0], [10, 0]], [[5, 0], [11, 0]], [[4, 0], [10, 0]]],
+        );
+        assert_eq!(verified, vec![true, false, false]);
+
+        //borrowing from the high word
+        let verified = proves(false, [0, 1], [1, 0], &[[[1, 0], [!0, 0]]]);
+        assert_eq!(verified, vec![true]);
+    }
+
+    #[test]
+    fn zero_fee() {
+        let verified = proves(false, [7, 3], [0, 0], &[[[0, 0], [7, 3]]]);
+        assert_eq!(verified, vec![true]);
+    }
+}
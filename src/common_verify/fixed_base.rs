@@ -0,0 +1,413 @@
+//Multiplication by the fixed generators P1 and P2 of `generators`.
+//
+//Each bit of the scalar adds its doubling of the generator or the identity,
+//both constants, so the tables are those of the native code.
+
+use bellman::Error as SynthesisError;
+use bellman::*;
+use pairing::bls12_381::{Bls12, Fr};
+use pairing::*;
+
+use jubjub::*;
+
+use base::point_add;
+use common_verify::asset::add_points;
+use generators::{doublings, p1, p2};
+
+//bits of num, lowest first, each 0 or 1 and summing to num.
+//The sum is taken modulo the Fr modulus r < 2^255, so from n = 255 on it is not
+//a unique decomposition: num + r < 2^n has bits too, and as r is not a multiple
+//of the order of the generators, multiplying by them gives another point. With
+//n = 256, e.g. rh and r in p2c, a prover may pick either; the points are only
+//compared with ones the prover made, so nothing else is bound by the bits.
+pub(crate) fn boolean_bits<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    num: &Num<Bls12>,
+    n: usize,
+) -> Result<Vec<Num<Bls12>>, SynthesisError> {
+    let serial = num.getvalue().get().ok().map(|x| x.into_repr().serial());
+    let mut bits = Vec::with_capacity(n);
+    let mut sum = LinearCombination::zero();
+    let mut coeff = Fr::one();
+    for i in 0..n {
+        let value = match serial {
+            Some(serial) if i < 256 && (serial[i / 64] >> (i % 64)) & 1 == 1 => {
+                Assignment::known(Fr::one())
+            }
+            Some(_) => Assignment::known(Fr::zero()),
+            None => Assignment::unknown(),
+        };
+        let bit = Num::new(cs, value)?;
+        //b*(1-b) = 0
+        cs.enforce(
+            LinearCombination::zero() + bit.getvar(),
+            LinearCombination::zero() + CS::one() - bit.getvar(),
+            LinearCombination::zero(),
+        );
+        sum = sum + (coeff, bit.getvar());
+        coeff.double();
+        bits.push(bit);
+    }
+    cs.enforce(
+        sum,
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero() + num.getvar(),
+    );
+    Ok(bits)
+}
+
+//the constant q if bit is 1, the identity if it is 0
+fn select(bit: &Num<Bls12>, q: (Fr, Fr)) -> Option<(Fr, Fr)> {
+    match bit.getvalue().get() {
+        Ok(b) if b.is_zero() => Some((Fr::zero(), Fr::one())),
+        Ok(_) => Some(q),
+        Err(_) => None,
+    }
+}
+
+//a*(c0+c1*bit) for c0 of 0 or 1, value is c0+c1*bit
+fn times<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    a: &Num<Bls12>,
+    value: Option<Fr>,
+    bit: &Num<Bls12>,
+    c0: bool,
+    c1: Fr,
+) -> Result<Num<Bls12>, SynthesisError> {
+    let product = match (a.getvalue().get(), value) {
+        (Ok(a), Some(v)) => {
+            let mut ab = *a;
+            ab.mul_assign(&v);
+            Assignment::known(ab)
+        }
+        _ => Assignment::unknown(),
+    };
+    let ab = Num::new(cs, product)?;
+    let b = if c0 {
+        LinearCombination::zero() + CS::one() + (c1, bit.getvar())
+    } else {
+        LinearCombination::zero() + (c1, bit.getvar())
+    };
+    cs.enforce(
+        LinearCombination::zero() + a.getvar(),
+        b,
+        LinearCombination::zero() + ab.getvar(),
+    );
+    Ok(ab)
+}
+
+//p+(b*qx, 1+b*(qy-1)), as add_points with the second point linear in b
+fn add_selected<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    p: &(Num<Bls12>, Num<Bls12>),
+    bit: &Num<Bls12>,
+    q: (Fr, Fr),
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let mut qy = q.1;
+    qy.sub_assign(&Fr::one());
+    let s = select(bit, q);
+    let x1y2 = times(cs, &p.0, s.map(|s| s.1), bit, true, qy)?;
+    let y1x2 = times(cs, &p.1, s.map(|s| s.0), bit, false, q.0)?;
+    let x1x2 = times(cs, &p.0, s.map(|s| s.0), bit, false, q.0)?;
+    let y1y2 = times(cs, &p.1, s.map(|s| s.1), bit, true, qy)?;
+
+    //d*x1x2*y1y2
+    let value = match (x1x2.getvalue().get(), y1y2.getvalue().get()) {
+        (Ok(x1x2), Ok(y1y2)) => {
+            let mut t = j.d;
+            t.mul_assign(x1x2);
+            t.mul_assign(y1y2);
+            Assignment::known(t)
+        }
+        _ => Assignment::unknown(),
+    };
+    let t = Num::new(cs, value)?;
+    cs.enforce(
+        LinearCombination::zero() + (j.d, x1x2.getvar()),
+        LinearCombination::zero() + y1y2.getvar(),
+        LinearCombination::zero() + t.getvar(),
+    );
+
+    let (x, y) = match (p.0.getvalue().get(), p.1.getvalue().get(), s) {
+        (Ok(x1), Ok(y1), Some((x2, y2))) => {
+            let (x, y) = point_add(x1, y1, &x2, &y2, j);
+            (Assignment::known(x), Assignment::known(y))
+        }
+        _ => (Assignment::unknown(), Assignment::unknown()),
+    };
+    let x = Num::new(cs, x)?;
+    let y = Num::new(cs, y)?;
+    //(1+t)*x = x1y2+y1x2
+    cs.enforce(
+        LinearCombination::zero() + CS::one() + t.getvar(),
+        LinearCombination::zero() + x.getvar(),
+        LinearCombination::zero() + x1y2.getvar() + y1x2.getvar(),
+    );
+    //(1-t)*y = y1y2+x1x2
+    cs.enforce(
+        LinearCombination::zero() + CS::one() - t.getvar(),
+        LinearCombination::zero() + y.getvar(),
+        LinearCombination::zero() + y1y2.getvar() + x1x2.getvar(),
+    );
+
+    Ok((x, y))
+}
+
+//sum of bits[i]*table[i]
+pub(crate) fn multiply_bits<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    bits: &[Num<Bls12>],
+    table: &[(Fr, Fr)],
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    assert_eq!(bits.len(), table.len());
+    let mut acc: Option<(Num<Bls12>, Num<Bls12>)> = None;
+    for (bit, &q) in bits.iter().zip(table.iter()) {
+        acc = Some(match acc {
+            None => {
+                //(b*qx, 1+b*(qy-1)) is linear in b
+                let mut qy = q.1;
+                qy.sub_assign(&Fr::one());
+                let value = select(bit, q);
+                let x = Num::new(
+                    cs,
+                    value.map_or(Assignment::unknown(), |v| Assignment::known(v.0)),
+                )?;
+                let y = Num::new(
+                    cs,
+                    value.map_or(Assignment::unknown(), |v| Assignment::known(v.1)),
+                )?;
+                cs.enforce(
+                    LinearCombination::zero() + x.getvar(),
+                    LinearCombination::zero() + CS::one(),
+                    LinearCombination::zero() + (q.0, bit.getvar()),
+                );
+                cs.enforce(
+                    LinearCombination::zero() + y.getvar(),
+                    LinearCombination::zero() + CS::one(),
+                    LinearCombination::zero() + CS::one() + (qy, bit.getvar()),
+                );
+                (x, y)
+            }
+            Some(p) => add_selected(cs, &p, bit, q, j)?,
+        });
+    }
+    Ok(acc.expect("at least one bit"))
+}
+
+//num*base for num < 2^n
+pub(crate) fn fixed_base_mul<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    base: (Fr, Fr),
+    num: &Num<Bls12>,
+    n: usize,
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let bits = boolean_bits(cs, num, n)?;
+    multiply_bits(cs, &bits, &doublings(base, n, j), j)
+}
+
+//v*P1+r*P2 for v < 2^vbit and r < 2^rbit
+pub(crate) fn commitment<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    v: &Num<Bls12>,
+    vbit: usize,
+    r: &Num<Bls12>,
+    rbit: usize,
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let vp = fixed_base_mul(cs, p1(), v, vbit, j)?;
+    let rp = fixed_base_mul(cs, p2(), r, rbit, j)?;
+    add_points(cs, &vp, &rp, j)
+}
+
+//addr_sk as its low and high 128 bits, addr_sk[i] weighs 2^i
+pub(crate) fn alloc_addr_sk<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    addr_sk: &[Assignment<bool>],
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let half = addr_sk.len() / 2;
+    let mut halves = vec![];
+    for bits in addr_sk.chunks(half) {
+        let mut serial = [0u64; 4];
+        let mut known = true;
+        for (i, b) in bits.iter().enumerate() {
+            match b.get() {
+                Ok(&true) => serial[i / 64] |= 1 << (i % 64),
+                Ok(&false) => {}
+                Err(_) => known = false,
+            }
+        }
+        let value = if known {
+            Assignment::known(Fr::from_serial(serial))
+        } else {
+            Assignment::unknown()
+        };
+        halves.push(Num::new(cs, value)?);
+    }
+    let hi = halves.pop().expect("two halves");
+    let lo = halves.pop().expect("two halves");
+    Ok((lo, hi))
+}
+
+//addr_sk*P1
+pub(crate) fn address<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    addr_sk: &(Num<Bls12>, Num<Bls12>),
+    n: usize,
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let mut bits = boolean_bits(cs, &addr_sk.0, n / 2)?;
+    bits.extend(boolean_bits(cs, &addr_sk.1, n / 2)?);
+    multiply_bits(cs, &bits, &doublings(p1(), n, j), j)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base::{point2fr, point_mul, u64s2bits, value_commitment, ADSK, RHBIT, VBIT};
+    use bellman::groth16::*;
+    use convert::str2point;
+    use rand::{SeedableRng, XorShiftRng};
+
+    enum Gadget {
+        //a*P1 for a < 2^256
+        RP1,
+        //a*P1+b*P2 for a < 2^VBIT and b < 2^RHBIT
+        Commitment,
+        //addr_sk*P1
+        Address,
+    }
+
+    struct GadgetCircuit<'a> {
+        gadget: &'a Gadget,
+        j: &'a JubJub,
+        a: Assignment<Fr>,
+        b: Assignment<Fr>,
+        addr_sk: Vec<Assignment<bool>>,
+    }
+
+    struct GadgetInput {
+        out: (Num<Bls12>, Num<Bls12>),
+    }
+
+    impl Input<Bls12> for GadgetInput {
+        fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<(), SynthesisError> {
+            let x_input = cs.alloc_input(|| Ok(*self.out.0.getvalue().get()?))?;
+            let y_input = cs.alloc_input(|| Ok(*self.out.1.getvalue().get()?))?;
+            cs.enforce(
+                LinearCombination::zero() + self.out.0.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + x_input,
+            );
+            cs.enforce(
+                LinearCombination::zero() + self.out.1.getvar(),
+                LinearCombination::zero() + CS::one(),
+                LinearCombination::zero() + y_input,
+            );
+            Ok(())
+        }
+    }
+
+    impl<'a> Circuit<Bls12> for GadgetCircuit<'a> {
+        type InputMap = GadgetInput;
+
+        fn synthesize<CS: ConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<GadgetInput, SynthesisError> {
+            let out = match *self.gadget {
+                Gadget::RP1 => {
+                    let a = Num::new(cs, self.a)?;
+                    fixed_base_mul(cs, p1(), &a, 256, self.j)?
+                }
+                Gadget::Commitment => {
+                    let a = Num::new(cs, self.a)?;
+                    let b = Num::new(cs, self.b)?;
+                    commitment(cs, &a, VBIT, &b, RHBIT, self.j)?
+                }
+                Gadget::Address => {
+                    let sk = alloc_addr_sk(cs, &self.addr_sk)?;
+                    address(cs, &sk, ADSK, self.j)?
+                }
+            };
+            Ok(GadgetInput { out })
+        }
+    }
+
+    fn circuit<'a>(gadget: &'a Gadget, j: &'a JubJub) -> GadgetCircuit<'a> {
+        GadgetCircuit {
+            gadget,
+            j,
+            a: Assignment::unknown(),
+            b: Assignment::unknown(),
+            addr_sk: (0..ADSK).map(|_| Assignment::unknown()).collect(),
+        }
+    }
+
+    //whether a proof of the circuit verifies against out
+    fn proves(gadget: Gadget, a: [u64; 4], b: [u64; 4], addr_sk: Vec<bool>, out: (Fr, Fr)) -> bool {
+        let j = JubJub::new();
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let params = generate_random_parameters::<Bls12, _, _>(circuit(&gadget, &j), rng).unwrap();
+        let mut known = circuit(&gadget, &j);
+        known.a = Assignment::known(Fr::from_serial(a));
+        known.b = Assignment::known(Fr::from_serial(b));
+        known.addr_sk = addr_sk.iter().map(|&b| Assignment::known(b)).collect();
+        let proof = create_random_proof::<Bls12, _, _, _>(known, &params, rng).unwrap();
+
+        let pvk = prepare_verifying_key(&params.vk);
+        let verifies = |out: (Fr, Fr)| {
+            verify_proof(&pvk, &proof, |cs| {
+                Ok(GadgetInput {
+                    out: (
+                        Num::new(cs, Assignment::known(out.0))?,
+                        Num::new(cs, Assignment::known(out.1))?,
+                    ),
+                })
+            })
+            .unwrap()
+        };
+        //a wrong output never verifies
+        let mut wrong = out;
+        wrong.0.add_assign(&Fr::one());
+        assert!(!verifies(wrong));
+        assert!(!verifies((out.1, out.0)));
+        verifies(out)
+    }
+
+    fn point(s: String) -> (Fr, Fr) {
+        point2fr(str2point(s).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn r_p1() {
+        let r = [5, 6, 7, 0x0fff_ffff_ffff_ffff];
+        let native = point_mul(p1(), u64s2bits(&r));
+        assert!(proves(Gadget::RP1, r, [0; 4], vec![false; ADSK], native));
+    }
+
+    #[test]
+    fn value_commitment_matches() {
+        let (v, rh) = ([!0, !0], [9, 10, 11, 0x0fff_ffff_ffff_ffff]);
+        let native = point(value_commitment(v, 0, rh));
+        assert!(proves(
+            Gadget::Commitment,
+            [v[0], v[1], 0, 0],
+            rh,
+            vec![false; ADSK],
+            native
+        ));
+    }
+
+    #[test]
+    fn address_matches() {
+        let sk: Vec<bool> = (0..ADSK).map(|i| i % 3 == 0 || i > 250).collect();
+        let native = point_mul(p1(), sk.clone());
+        assert!(proves(Gadget::Address, [0; 4], [0; 4], sk, native));
+    }
+}
@@ -1 +1,7 @@
+pub mod asset;
+pub mod batch;
+pub mod fee;
+pub mod fixed_base;
+pub mod note;
+pub mod nullifier;
 pub mod range;
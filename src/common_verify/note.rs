@@ -0,0 +1,326 @@
+//Coins paid to a payment address and the note opening them, encrypted in the circuit.
+//
+//pa = PH(addr.x|enc_key.y), coin = PH(PH(rcm|va|pa)|asset). The spend circuits
+//derive enc_key = ivk*P1 from addr_sk, so a coin is spendable only if it was
+//paid to the enc_key of its owner.
+//
+//The outputs encrypt (va, rcm, asset) to that enc_key with rP = r*P1 and
+//S = r*enc_key = ivk*rP:
+//e1 = PH(S.y|rP.y) + va + asset*2^128, e2 = PH(rP.y|S.y) + rcm.
+//The two pads take S.y through different generators, so one does not give the
+//other. enc_key is checked to be on the curve; with its y fixed by the coin it
+//is then +-enc_key, and S.y is the same for both. The recipient recomputes S
+//and the pads, so every coin proven to it can be opened.
+
+use bellman::Error as SynthesisError;
+use bellman::*;
+use pairing::bls12_381::{Bls12, Fr};
+use pairing::*;
+
+use jubjub::*;
+
+use base::*;
+use common_verify::asset::{asset_coin, multiply_base, product};
+use common_verify::fixed_base::multiply_bits;
+use common_verify::nullifier::{canonical_bits, hash_pair};
+use generators::{doublings, p1};
+
+//-x^2+y^2 = 1+d*x^2*y^2
+fn on_curve<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    p: &(Num<Bls12>, Num<Bls12>),
+    j: &JubJub,
+) -> Result<(), SynthesisError> {
+    let xx = product(cs, &p.0, &p.0)?;
+    let yy = product(cs, &p.1, &p.1)?;
+    cs.enforce(
+        LinearCombination::zero() + (j.d, xx.getvar()),
+        LinearCombination::zero() + yy.getvar(),
+        LinearCombination::zero() + yy.getvar() - xx.getvar() - CS::one(),
+    );
+    Ok(())
+}
+
+//PH(PH(rcm|va|pa)|asset), pa = PH(addr.x|enc_key.y)
+pub(crate) fn payment_coin<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    rcm: &Num<Bls12>,
+    va: &Num<Bls12>,
+    addr_x: &Num<Bls12>,
+    enc_key_y: &Num<Bls12>,
+    asset: &Num<Bls12>,
+    generators: &[(Vec<Fr>, Vec<Fr>)],
+    j: &JubJub,
+) -> Result<Num<Bls12>, SynthesisError> {
+    let pa = hash_pair(cs, addr_x, enc_key_y, generators, j)?;
+    let mut vin = rcm.unpack_sized(cs, RCMBIT)?;
+    vin.extend(va.unpack_sized(cs, VBIT)?);
+    for bit in canonical_bits(cs, &pa, PHOUT)?.iter() {
+        vin.extend(bit.unpack_sized(cs, 1)?);
+    }
+    assert_eq!(vin.len(), PHIN);
+    let note = pedersen_hash(cs, &vin, generators, j)?;
+    asset_coin(cs, &note, asset, generators, j)
+}
+
+//pad+sum of c*m over terms (c, m)
+fn masked<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    pad: &Num<Bls12>,
+    terms: &[(Fr, &Num<Bls12>)],
+) -> Result<Num<Bls12>, SynthesisError> {
+    let mut value = pad.getvalue().get().ok().map(|pad| *pad);
+    let mut sum = LinearCombination::zero() + pad.getvar();
+    for &(c, m) in terms.iter() {
+        value = match (value, m.getvalue().get()) {
+            (Some(mut e), Ok(m)) => {
+                let mut cm = *m;
+                cm.mul_assign(&c);
+                e.add_assign(&cm);
+                Some(e)
+            }
+            _ => None,
+        };
+        sum = sum + (c, m.getvar());
+    }
+    let e = Num::new(
+        cs,
+        value.map_or(Assignment::unknown(), |e| Assignment::known(e)),
+    )?;
+    cs.enforce(
+        sum,
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero() + e.getvar(),
+    );
+    Ok(e)
+}
+
+//(rP, e1, e2) of the note (va, rcm, asset) under the random r; the ranges of
+//va, rcm and asset are checked by payment_coin
+pub(crate) fn encrypt_note<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    random: &Num<Bls12>,
+    enc_key: &(Num<Bls12>, Num<Bls12>),
+    va: &Num<Bls12>,
+    rcm: &Num<Bls12>,
+    asset: &Num<Bls12>,
+    generators: &[(Vec<Fr>, Vec<Fr>)],
+    j: &JubJub,
+) -> Result<((Num<Bls12>, Num<Bls12>), Num<Bls12>, Num<Bls12>), SynthesisError> {
+    on_curve(cs, enc_key, j)?;
+    //the same bits for rP and S
+    let bits = canonical_bits(cs, random, 256)?;
+    let rp = multiply_bits(cs, &bits, &doublings(p1(), 256, j), j)?;
+    let s = multiply_base(cs, enc_key, &bits, j)?;
+
+    let pad1 = hash_pair(cs, &s.1, &rp.1, generators, j)?;
+    let pad2 = hash_pair(cs, &rp.1, &s.1, generators, j)?;
+
+    //2^128, va < 2^VBIT and asset < 2^ASSETBIT so va+asset*2^128 is below r
+    let shift = Fr::from_serial([0, 0, 1, 0]);
+    let e1 = masked(cs, &pad1, &[(Fr::one(), va), (shift, asset)])?;
+    let e2 = masked(cs, &pad2, &[(Fr::one(), rcm)])?;
+    Ok((rp, e1, e2))
+}
+
+//ivk*P1 over the canonical bits of ivk, the enc_key of keys::IncomingViewingKey
+pub(crate) fn enc_key_of<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    ivk: &Num<Bls12>,
+    j: &JubJub,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let bits = canonical_bits(cs, ivk, 256)?;
+    multiply_bits(cs, &bits, &doublings(p1(), 256, j), j)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use bellman::groth16::*;
+    use convert::{str2enc, str2point, str2u644};
+    use keys::PaymentAddress;
+    use params::{default_params, test_generators};
+    use rand::{SeedableRng, XorShiftRng};
+    use types::SpendingKey;
+
+    //(rcm, va, asset, addr, enc_key, random)
+    type Note = (Fr, Fr, Fr, (Fr, Fr), (Fr, Fr), Fr);
+
+    struct NoteCircuit<'a> {
+        generators: &'a [(Vec<Fr>, Vec<Fr>)],
+        j: &'a JubJub,
+        note: Option<Note>,
+    }
+
+    struct NoteInput {
+        //coin, rP, e1 and e2
+        inputs: Vec<Num<Bls12>>,
+    }
+
+    impl Input<Bls12> for NoteInput {
+        fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<(), SynthesisError> {
+            for num in self.inputs.iter() {
+                let input = cs.alloc_input(|| Ok(*num.getvalue().get()?))?;
+                cs.enforce(
+                    LinearCombination::zero() + num.getvar(),
+                    LinearCombination::zero() + CS::one(),
+                    LinearCombination::zero() + input,
+                );
+            }
+            Ok(())
+        }
+    }
+
+    impl<'a> Circuit<Bls12> for NoteCircuit<'a> {
+        type InputMap = NoteInput;
+
+        fn synthesize<CS: ConstraintSystem<Bls12>>(
+            self,
+            cs: &mut CS,
+        ) -> Result<NoteInput, SynthesisError> {
+            let known = |f: &Fn(&Note) -> Fr| match self.note {
+                Some(ref note) => Assignment::known(f(note)),
+                None => Assignment::unknown(),
+            };
+            let rcm = Num::new(cs, known(&|n| n.0))?;
+            let va = Num::new(cs, known(&|n| n.1))?;
+            let asset = Num::new(cs, known(&|n| n.2))?;
+            let addr_x = Num::new(cs, known(&|n| (n.3).0))?;
+            let enc_key = (
+                Num::new(cs, known(&|n| (n.4).0))?,
+                Num::new(cs, known(&|n| (n.4).1))?,
+            );
+            let random = Num::new(cs, known(&|n| n.5))?;
+
+            let coin = payment_coin(
+                cs,
+                &rcm,
+                &va,
+                &addr_x,
+                &enc_key.1,
+                &asset,
+                self.generators,
+                self.j,
+            )?;
+            let (rp, e1, e2) = encrypt_note(
+                cs,
+                &random,
+                &enc_key,
+                &va,
+                &rcm,
+                &asset,
+                self.generators,
+                self.j,
+            )?;
+            Ok(NoteInput {
+                inputs: vec![coin, rp.0, rp.1, e1, e2],
+            })
+        }
+    }
+
+    fn to() -> PaymentAddress {
+        test_generators();
+        let sk: SpendingKey = "01".repeat(32).parse().unwrap();
+        sk.incoming_viewing_key()
+            .unwrap()
+            .payment_address()
+            .unwrap()
+    }
+
+    fn point(p: String) -> (Fr, Fr) {
+        let p = str2point(p).unwrap();
+        (Fr::from_serial(p.0), Fr::from_serial(p.1))
+    }
+
+    //coin, rP, e1 and e2 as computed natively
+    fn native(
+        to: &PaymentAddress,
+        va: [u64; 2],
+        rcm: [u64; 2],
+        asset: u64,
+        r: [u64; 4],
+    ) -> Vec<Fr> {
+        let coin = ::build_coin(to.addr.to_string(), to.enc_key.to_string(), va, rcm, asset);
+        let enc = encrypt(va, rcm, asset, b"", r, to.enc_key.to_string()).unwrap();
+        let (rp, e1, e2, _) = note_inputs(&str2enc(enc).unwrap()).unwrap();
+        let coin = Fr::from_serial(str2u644(coin.unwrap()).unwrap());
+        vec![coin, rp.0, rp.1, e1, e2]
+    }
+
+    //whether a proof made with enc_key verifies against each candidate
+    fn proves(
+        enc_key: (Fr, Fr),
+        note: ([u64; 2], [u64; 2], u64, [u64; 4]),
+        candidates: &[Vec<Fr>],
+    ) -> Vec<bool> {
+        test_generators();
+        let params = default_params().unwrap();
+        let j = JubJub::new();
+        let rng = &mut XorShiftRng::from_seed([1, 2, 3, 4]);
+        let blank = NoteCircuit {
+            generators: params.generators(),
+            j: &j,
+            note: None,
+        };
+        let groth = generate_random_parameters::<Bls12, _, _>(blank, rng).unwrap();
+        let (va, rcm, asset, r) = note;
+        let known = NoteCircuit {
+            generators: params.generators(),
+            j: &j,
+            note: Some((
+                Fr::from_serial([rcm[0], rcm[1], 0, 0]),
+                Fr::from_serial([va[0], va[1], 0, 0]),
+                Fr::from_serial([asset, 0, 0, 0]),
+                point(to().addr.to_string()),
+                enc_key,
+                Fr::from_serial(r),
+            )),
+        };
+        let proof = create_random_proof::<Bls12, _, _, _>(known, &groth, rng).unwrap();
+
+        let pvk = prepare_verifying_key(&groth.vk);
+        candidates
+            .iter()
+            .map(|inputs| {
+                verify_proof(&pvk, &proof, |cs| {
+                    let mut nums = vec![];
+                    for x in inputs.iter() {
+                        nums.push(Num::new(cs, Assignment::known(*x))?);
+                    }
+                    Ok(NoteInput { inputs: nums })
+                })
+                .unwrap()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn note_matches_native() {
+        let to = to();
+        let note = ([!0, 5], [7, 0x0fff_ffff_ffff_ffff], 3, [11, 12, 13, 1]);
+        let honest = native(&to, note.0, note.1, note.2, note.3);
+        //the same coin with another opening in e2
+        let mut other = honest.clone();
+        other[4] = native(&to, note.0, [8, 0], note.2, note.3)[4];
+        let verified = proves(point(to.enc_key.to_string()), note, &[honest, other]);
+        assert_eq!(verified, vec![true, false]);
+    }
+
+    #[test]
+    fn negated_key_gives_the_same_note() {
+        let to = to();
+        let note = ([10, 0], [7, 1], 0, [4, 5, 6, 0]);
+        let mut negated = point(to.enc_key.to_string());
+        negated.0.negate();
+        //-enc_key shares y with enc_key, and so the coin and S.y
+        let verified = proves(
+            negated,
+            note,
+            &[native(&to, note.0, note.1, note.2, note.3)],
+        );
+        assert_eq!(verified, vec![true]);
+    }
+}
@@ -0,0 +1,168 @@
+//Nullifier of a spent coin, PH(coin|nk) with nk = PH(addr_sk|NK_TAG), and the
+//incoming viewing key ivk = PH(nk|IVK_TAG) derived from nk.
+//
+//The coin commits to the asset, value, rcm and payment address, so coins differing in
+//any of them have different nullifiers. nk lets a FullViewingKey compute
+//nullifiers without addr_sk. The bits of the coin and of nk are checked to be
+//below the Fr modulus r: 256 bits summing to them modulo r are otherwise not
+//unique, and each choice would give the coin another nullifier.
+
+use bellman::Error as SynthesisError;
+use bellman::*;
+use pairing::bls12_381::{Bls12, Fr};
+use pairing::*;
+
+use jubjub::*;
+
+use base::*;
+use common_verify::asset::product;
+use common_verify::fixed_base::boolean_bits;
+
+//and of two or more bits
+fn and_all<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    bits: &[&Num<Bls12>],
+) -> Result<Num<Bls12>, SynthesisError> {
+    let mut acc = product(cs, bits[0], bits[1])?;
+    for bit in bits[2..].iter() {
+        acc = product(cs, &acc, bit)?;
+    }
+    Ok(acc)
+}
+
+//bits of num, lowest first, as the one n bit string below r summing to it
+pub(crate) fn canonical_bits<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    num: &Num<Bls12>,
+    n: usize,
+) -> Result<Vec<Num<Bls12>>, SynthesisError> {
+    let bits = boolean_bits(cs, num, n)?;
+    let mut max = Fr::zero();
+    max.sub_assign(&Fr::one());
+    let max = max.into_repr().serial();
+    let top = 255;
+    //r-1 < 2^255
+    for bit in bits[top..].iter() {
+        cs.enforce(
+            LinearCombination::zero() + bit.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero(),
+        );
+    }
+
+    //from the top, run is 1 while every bit under a 1 of r-1 is 1, where a 0
+    //of r-1 then takes a 1 the bits are above r-1
+    let mut run: Option<Num<Bls12>> = None;
+    let mut ones: Vec<usize> = vec![];
+    for i in (0..top).rev() {
+        if (max[i / 64] >> (i % 64)) & 1 == 1 {
+            ones.push(i);
+            continue;
+        }
+        if !ones.is_empty() {
+            //the top run of r-1 has more than one bit, later ones are joined by run
+            let next = {
+                let mut all: Vec<&Num<Bls12>> = ones.iter().map(|&k| &bits[k]).collect();
+                if let Some(ref run) = run {
+                    all.push(run);
+                }
+                and_all(cs, &all)?
+            };
+            run = Some(next);
+            ones.clear();
+        }
+        if let Some(ref run) = run {
+            cs.enforce(
+                LinearCombination::zero() + run.getvar(),
+                LinearCombination::zero() + bits[i].getvar(),
+                LinearCombination::zero(),
+            );
+        }
+    }
+    Ok(bits)
+}
+
+//tag as the constants of its low and high 128 bits, whose unpacking is unique
+//as the tag is below 2^128
+fn tag<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    tag: u64,
+) -> Result<(Num<Bls12>, Num<Bls12>), SynthesisError> {
+    let tag = Fr::from_serial([tag, 0, 0, 0]);
+    let low = Num::new(cs, Assignment::known(tag))?;
+    let high = Num::new(cs, Assignment::known(Fr::zero()))?;
+    cs.enforce(
+        LinearCombination::zero() + low.getvar(),
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero() + (tag, CS::one()),
+    );
+    cs.enforce(
+        LinearCombination::zero() + high.getvar(),
+        LinearCombination::zero() + CS::one(),
+        LinearCombination::zero(),
+    );
+    Ok((low, high))
+}
+
+//nk = PH(addr_sk|NK_TAG) with addr_sk as its low and high 128 bits
+pub(crate) fn nullifier_key<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    addr_sk: &(Num<Bls12>, Num<Bls12>),
+    generators: &[(Vec<Fr>, Vec<Fr>)],
+    j: &JubJub,
+) -> Result<Num<Bls12>, SynthesisError> {
+    let mut vin = addr_sk.0.unpack_sized(cs, ADSK / 2)?;
+    vin.extend(addr_sk.1.unpack_sized(cs, ADSK / 2)?);
+    let (low, high) = tag(cs, NK_TAG)?;
+    vin.extend(low.unpack_sized(cs, (PHIN - ADSK) / 2)?);
+    vin.extend(high.unpack_sized(cs, (PHIN - ADSK) / 2)?);
+    assert_eq!(vin.len(), PHIN);
+    pedersen_hash(cs, &vin, generators, j)
+}
+
+//ivk = PH(nk|IVK_TAG), notes are encrypted to ivk*P1
+pub(crate) fn incoming_viewing_key<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    nk: &Num<Bls12>,
+    generators: &[(Vec<Fr>, Vec<Fr>)],
+    j: &JubJub,
+) -> Result<Num<Bls12>, SynthesisError> {
+    let mut vin = vec![];
+    for bit in canonical_bits(cs, nk, PHOUT)?.iter() {
+        vin.extend(bit.unpack_sized(cs, 1)?);
+    }
+    let (low, high) = tag(cs, IVK_TAG)?;
+    vin.extend(low.unpack_sized(cs, (PHIN - PHOUT) / 2)?);
+    vin.extend(high.unpack_sized(cs, (PHIN - PHOUT) / 2)?);
+    assert_eq!(vin.len(), PHIN);
+    pedersen_hash(cs, &vin, generators, j)
+}
+
+//PH(a|b) over the canonical bits of a and b
+pub(crate) fn hash_pair<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    a: &Num<Bls12>,
+    b: &Num<Bls12>,
+    generators: &[(Vec<Fr>, Vec<Fr>)],
+    j: &JubJub,
+) -> Result<Num<Bls12>, SynthesisError> {
+    let mut vin = vec![];
+    for num in [a, b].iter() {
+        for bit in canonical_bits(cs, num, PHOUT)?.iter() {
+            vin.extend(bit.unpack_sized(cs, 1)?);
+        }
+    }
+    assert_eq!(vin.len(), PHIN);
+    pedersen_hash(cs, &vin, generators, j)
+}
+
+//PH(coin|nk)
+pub(crate) fn nullifier<CS: ConstraintSystem<Bls12>>(
+    cs: &mut CS,
+    coin: &Num<Bls12>,
+    nk: &Num<Bls12>,
+    generators: &[(Vec<Fr>, Vec<Fr>)],
+    j: &JubJub,
+) -> Result<Num<Bls12>, SynthesisError> {
+    hash_pair(cs, coin, nk, generators, j)
+}
@@ -1,16 +1,18 @@
 use bellman::groth16::*;
+use bellman::Error as SynthesisError;
 use bellman::*;
 use pairing::bls12_381::{Bls12, Fr, FrRepr};
 use pairing::*;
-use rand::thread_rng;
 
 use jubjub::*;
 
 use convert::*;
+use error::Error;
+use header::{generate_params, write_params};
+use params::{default_params, ParamSet};
 
-use std::fs::File;
-
-use base::range_param_path;
+use base::{range_param_path, ParamKind};
+use common_verify::fixed_base::commitment;
 
 struct RangeCircuit<'a> {
     //upper bound
@@ -57,7 +59,10 @@ struct RangeCircuitInput {
 }
 
 impl<'a> Input<Bls12> for RangeCircuitInput {
-    fn synthesize<CS: PublicConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), Error> {
+    fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
         let up_input = cs.alloc_input(|| Ok(*self.up.getvalue().get()?))?;
         let low_input = cs.alloc_input(|| Ok(*self.low.getvalue().get()?))?;
         let hvx = cs.alloc_input(|| Ok(*self.hv.0.getvalue().get()?))?;
@@ -91,7 +96,10 @@ impl<'a> Input<Bls12> for RangeCircuitInput {
 impl<'a> Circuit<Bls12> for RangeCircuit<'a> {
     type InputMap = RangeCircuitInput;
 
-    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<Self::InputMap, Error> {
+    fn synthesize<CS: ConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<Self::InputMap, SynthesisError> {
         let up_num = Num::new(cs, self.up)?;
         let up = up_num.unpack_sized(cs, 256)?;
         let low_num = Num::new(cs, self.low)?;
@@ -112,13 +120,10 @@ impl<'a> Circuit<Bls12> for RangeCircuit<'a> {
         assert_nonless_with_minus(&up, &va, &mp, &mm, cs)?;
         assert_nonless_with_minus(&va, &low, &mp, &mm, cs)?;
 
-        //prepare table
-        let p1 = Point::enc_point_table(256, 1, cs)?;
-        let p2 = Point::enc_point_table(256, 2, cs)?;
-
         //va*P1+rh*P2
-        let rh = Num::new(cs, self.rh)?.unpack_sized(cs, 256)?;
-        let hv = Point::encrypt((&p1, &p2), &va, &rh, cs)?;
+        let j = JubJub::new();
+        let rh = Num::new(cs, self.rh)?;
+        let hv = commitment(cs, &va_num, 256, &rh, 256, &j)?;
         if let (Ok(x), Ok(y)) = (hv.0.getvalue().get(), hv.1.getvalue().get()) {
             self.res.push(x.into_repr());
             self.res.push(y.into_repr());
@@ -132,42 +137,41 @@ impl<'a> Circuit<Bls12> for RangeCircuit<'a> {
     }
 }
 
+fn signed2fr(num: ([u64; 2], bool)) -> Result<Fr, Error> {
+    let mut res = u6442fr([(num.0)[0], (num.0)[1], 0, 0])?;
+    if !num.1 {
+        res.negate();
+    }
+    Ok(res)
+}
+
 pub fn range_info(
     up: ([u64; 2], bool),
     va: ([u64; 2], bool),
     rh: [u64; 2],
     low: ([u64; 2], bool),
 ) -> Result<(String, String), Error> {
-    let rng = &mut thread_rng();
-    let up = {
-        let mut res = Fr::from_repr(FrRepr::from_serial([(up.0)[0], (up.0)[1], 0, 0])).unwrap();
-        if !up.1 {
-            res.negate();
-        }
-        res
-    };
-    let va = {
-        let mut res = Fr::from_repr(FrRepr::from_serial([(va.0)[0], (va.0)[1], 0, 0])).unwrap();
-        if !va.1 {
-            res.negate();
-        }
-        res
-    };
-    let rh = Fr::from_repr(FrRepr::from_serial([rh[0], rh[1], 0, 0])).unwrap();
-    let low = {
-        let mut res = Fr::from_repr(FrRepr::from_serial([(low.0)[0], (low.0)[1], 0, 0])).unwrap();
-        if !low.1 {
-            res.negate();
-        }
-        res
-    };
+    range_info_with(&*default_params()?, up, va, rh, low)
+}
+
+pub fn range_info_with(
+    params: &ParamSet,
+    up: ([u64; 2], bool),
+    va: ([u64; 2], bool),
+    rh: [u64; 2],
+    low: ([u64; 2], bool),
+) -> Result<(String, String), Error> {
+    let up = signed2fr(up)?;
+    let va = signed2fr(va)?;
+    let rh = u6442fr([rh[0], rh[1], 0, 0])?;
+    let low = signed2fr(low)?;
     let mut res: Vec<FrRepr> = vec![];
-    let proof = create_random_proof::<Bls12, _, _, _>(
-        RangeCircuit::new(up, va, rh, low, &mut res),
-        range_param()?,
-        rng,
-    )?
-    .serial();
+    let proof = params
+        .prove(
+            ParamKind::Range,
+            RangeCircuit::new(up, va, rh, low, &mut res),
+        )?
+        .serial();
     let hv = (res[0].serial(), res[1].serial());
     Ok((proof2str(proof), point2str(hv)))
 }
@@ -178,62 +182,40 @@ pub fn range_verify(
     low: ([u64; 2], bool),
     proof: String,
 ) -> Result<bool, Error> {
-    let hv = str2point(hv);
-    let proof = str2proof(proof);
-    verify_proof(&range_vk()?, &Proof::from_serial(proof), |cs| {
-        let up = {
-            let mut res = Fr::from_repr(FrRepr::from_serial([(up.0)[0], (up.0)[1], 0, 0])).unwrap();
-            if !up.1 {
-                res.negate();
-            }
-            res
-        };
-        let low = {
-            let mut res =
-                Fr::from_repr(FrRepr::from_serial([(low.0)[0], (low.0)[1], 0, 0])).unwrap();
-            if !low.1 {
-                res.negate();
-            }
-            res
-        };
-        let hv = (
-            Fr::from_repr(FrRepr::from_serial(hv.0)).unwrap(),
-            Fr::from_repr(FrRepr::from_serial(hv.1)).unwrap(),
-        );
-        Ok(RangeCircuitInput {
-            up: Num::new(cs, Assignment::known(up))?,
-            hv: (
-                Num::new(cs, Assignment::known(hv.0))?,
-                Num::new(cs, Assignment::known(hv.1))?,
-            ),
-            low: Num::new(cs, Assignment::known(low))?,
-        })
-    })
-}
-
-pub(crate) fn gen_range_param() {
-    let range_param_path = range_param_path();
-    let range_param_path = range_param_path.to_str().unwrap();
-    let rng = &mut thread_rng();
-    let params =
-        generate_random_parameters::<Bls12, _, _>(RangeCircuit::blank(&mut vec![]), rng).unwrap();
-    params
-        .write(&mut File::create(range_param_path).unwrap())
-        .unwrap();
+    range_verify_with(&*default_params()?, up, hv, low, proof)
 }
 
-fn range_param() -> Result<ProverStream, Error> {
-    let range_param_path = range_param_path();
-    let range_param_path = range_param_path.to_str().unwrap();
-    let params = ProverStream::new(range_param_path).unwrap();
-    Ok(params)
+pub fn range_verify_with(
+    params: &ParamSet,
+    up: ([u64; 2], bool),
+    hv: String,
+    low: ([u64; 2], bool),
+    proof: String,
+) -> Result<bool, Error> {
+    let up = signed2fr(up)?;
+    let low = signed2fr(low)?;
+    let hv = str2point(hv)?;
+    let hv = (u6442fr(hv.0)?, u6442fr(hv.1)?);
+    let proof = str2proof(proof)?;
+    let ok = verify_proof(
+        &*params.vk(ParamKind::Range)?,
+        &Proof::from_serial(proof),
+        |cs| {
+            Ok(RangeCircuitInput {
+                up: Num::new(cs, Assignment::known(up))?,
+                hv: (
+                    Num::new(cs, Assignment::known(hv.0))?,
+                    Num::new(cs, Assignment::known(hv.1))?,
+                ),
+                low: Num::new(cs, Assignment::known(low))?,
+            })
+        },
+    )?;
+    Ok(ok)
 }
 
-fn range_vk() -> Result<(PreparedVerifyingKey<Bls12>), Error> {
+pub(crate) fn gen_range_param() -> Result<(), Error> {
     let range_param_path = range_param_path();
-    let range_param_path = range_param_path.to_str().unwrap();
-    let mut params = ProverStream::new(range_param_path)?;
-    let vk2 = params.get_vk(5)?;
-    let vk = prepare_verifying_key(&vk2);
-    Ok(vk)
+    let (params, constraints) = generate_params(RangeCircuit::blank(&mut vec![]))?;
+    write_params(ParamKind::Range, &params, constraints, &range_param_path)
 }
@@ -1,21 +1,36 @@
+use b2c::b2c_verify;
+use balance::EncryptedBalance;
 use base::*;
+use c2b::c2b_verify;
+use c2c::*;
 use c2p::*;
 use convert::*;
+use error::Error;
 use incrementalmerkletree::*;
 use p2c::*;
 use pedersen::PedersenDigest;
+use std::collections::BTreeMap;
 use std::collections::HashMap;
 use std::collections::HashSet;
 use std::collections::VecDeque;
+use std::fmt;
+use store::*;
+use types::{Coin, Nullifier};
 
 #[derive(Clone)]
 pub struct SenderProof {
     pub proof: String,
-    //hb:([u64;4],[u64;4]),
+    //balance the proof was made against, the account's once the sends
+    //before nonce are applied
+    pub hb: String,
     pub coin: String,
     pub delt_ba: String,
     pub enc: String,
-    pub block_number: u64,
+    pub asset: u64,
+    //paid on top of the coin's value, credited to the fee collector
+    pub fee: u128,
+    //account nonce the proof is bound to
+    pub nonce: u64,
 }
 
 #[derive(Clone)]
@@ -24,103 +39,1312 @@ pub struct ReceiverProof {
     pub nullifier: String,
     pub root: String,
     pub delt_ba: String,
+    pub asset: u64,
+    //taken from the coin's value, delt_ba commits to the rest
+    pub fee: u128,
+}
+
+//b2c: va leaves the transparent balance of the proof's address as coin
+#[derive(Clone)]
+pub struct DepositProof {
+    pub proof: String,
+    pub va: u128,
+    pub asset: u64,
+    pub coin: String,
+    pub enc: String,
+}
+
+//c2b: the coin behind nullifier is redeemed into a transparent balance
+#[derive(Clone)]
+pub struct WithdrawProof {
+    pub proof: String,
+    pub nullifier: String,
+    pub root: String,
+    pub va: u128,
+    pub asset: u64,
+    //taken from va
+    pub fee: u128,
+}
+
+#[derive(Clone)]
+pub struct TransferProof {
+    pub proof: String,
+    pub nullifiers: Vec<String>,
+    pub root: String,
+    pub coins: Vec<String>,
+    pub encs: Vec<String>,
+}
+
+#[derive(Debug)]
+pub enum Rejection {
+    //no balance is stored for the address
+    UnknownAccount,
+    //coin has already been created
+    DuplicateCoin,
+    //nonce has already been used by the account
+    StaleNonce,
+    //the account's balance is not the one the send was proven against
+    StaleBalance,
+    //the account has too many sends waiting, or the nonce is too far ahead
+    QueueFull,
+    //the account's nonce cannot advance any further
+    NonceExhausted,
+    //transparent balance is lower than the deposit
+    InsufficientFunds,
+    //a fee is paid but no fee collector is set
+    NoFeeCollector,
+    //root is not one of the recent roots of the coin tree
+    UnknownRoot,
+    //nullifier has already been spent
+    DuplicateNullifier,
+    //message is malformed or its proof does not verify
+    Invalid(Error),
+    //the state change could not be persisted
+    Storage(Error),
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Rejection::UnknownAccount => write!(f, "unknown account"),
+            Rejection::DuplicateCoin => write!(f, "duplicate coin"),
+            Rejection::StaleNonce => write!(f, "stale nonce"),
+            Rejection::StaleBalance => write!(f, "stale balance"),
+            Rejection::QueueFull => write!(f, "send queue full"),
+            Rejection::NonceExhausted => write!(f, "nonce exhausted"),
+            Rejection::InsufficientFunds => write!(f, "insufficient funds"),
+            Rejection::NoFeeCollector => write!(f, "no fee collector"),
+            Rejection::UnknownRoot => write!(f, "invalid root"),
+            Rejection::DuplicateNullifier => write!(f, "duplicate nullifier"),
+            Rejection::Invalid(ref e) => write!(f, "invalid message: {}", e),
+            Rejection::Storage(ref e) => write!(f, "storage failure: {}", e),
+        }
+    }
+}
+
+impl From<Error> for Rejection {
+    fn from(e: Error) -> Rejection {
+        Rejection::Invalid(e)
+    }
+}
+
+fn check_proof(ok: bool) -> Result<(), Rejection> {
+    if ok {
+        Ok(())
+    } else {
+        Err(Rejection::Invalid(Error::ProofInvalid))
+    }
+}
+
+//hex is read in either case, so nullifiers and coins are kept in the form
+//Display gives them
+fn canonical_nullifier(nullifier: &str) -> Result<String, Rejection> {
+    Ok(nullifier.parse::<Nullifier>()?.to_string())
+}
+
+fn canonical_coin(coin: &str) -> Result<String, Rejection> {
+    Ok(coin.parse::<Coin>()?.to_string())
+}
+
+//number of recent roots accepted by default
+pub const ROOT_HISTORY: usize = 100;
+
+//sends an account may have waiting for earlier nonces; only nonces below
+//nonce + MAX_QUEUED are queued
+pub const MAX_QUEUED: usize = 64;
+
+//sends queued under one nonce, of which the first still matching the
+//balance is applied
+pub const MAX_QUEUED_PER_NONCE: usize = 4;
+
+//inverse of an applied StateOp
+enum Undo {
+    Balance((String, u64), Option<String>),
+    Nonce(String, Option<u64>),
+    Transparent((String, u64), Option<u128>),
+    FeeCollector(Option<String>),
+    Nullifier(String),
+    Coin(String),
+}
+
+//what rewind needs besides the tree's own checkpoint
+struct Checkpoint {
+    undo: Vec<Undo>,
+    roots: VecDeque<PedersenDigest>,
 }
 
 pub struct PrivacyContract {
-    balances: HashMap<String, String>,
-    last_spent: HashMap<String, u64>,
+    //keyed by (address, asset)
+    balances: HashMap<(String, u64), String>,
+    //next nonce of each account
+    nonces: HashMap<String, u64>,
+    //public balances moved in and out of coins by b2c and c2b
+    transparent: HashMap<(String, u64), u128>,
+    //transparent account credited with all fees
+    fee_collector: Option<String>,
+    //sends ahead of their account's nonce, not persisted
+    queued: HashMap<String, BTreeMap<u64, Vec<SenderProof>>>,
     coins: HashSet<String>,
     nullifier_set: HashSet<String>,
     tree: IncrementalMerkleTree<PedersenDigest>,
+    //the last root_history roots, oldest first
+    roots: VecDeque<PedersenDigest>,
+    root_history: usize,
+    //kept in step with the tree's checkpoints, oldest first
+    checkpoints: VecDeque<Checkpoint>,
+    store: Box<dyn StateStore>,
 }
 
 impl PrivacyContract {
     pub fn new() -> Self {
+        PrivacyContract::with_root_history(ROOT_HISTORY)
+    }
+
+    pub fn with_root_history(root_history: usize) -> Self {
+        PrivacyContract::empty(Box::new(MemoryStore::new()), root_history)
+    }
+
+    fn empty(store: Box<dyn StateStore>, root_history: usize) -> Self {
+        assert!(root_history > 0);
+        let tree = IncrementalMerkleTree::new(TREEDEPTH);
+        let mut roots = VecDeque::with_capacity(root_history);
+        roots.push_back(tree.root());
         PrivacyContract {
             balances: HashMap::new(),
-            last_spent: HashMap::new(),
+            nonces: HashMap::new(),
+            transparent: HashMap::new(),
+            fee_collector: None,
+            queued: HashMap::new(),
             coins: HashSet::new(),
             nullifier_set: HashSet::new(),
-            tree: IncrementalMerkleTree::new(60 as usize),
+            tree,
+            roots,
+            root_history,
+            checkpoints: VecDeque::new(),
+            store,
+        }
+    }
+
+    //rebuild the contract from the last snapshot and the batches after it
+    pub fn open(store: Box<dyn StateStore>, root_history: usize) -> Result<Self, Error> {
+        let mut contract = PrivacyContract::empty(store, root_history);
+        let (snapshot, batches) = contract.store.load()?;
+        if let Some(snapshot) = snapshot {
+            contract.restore(snapshot);
+        }
+        for ops in batches.into_iter() {
+            for op in ops.into_iter() {
+                contract.apply(op)?;
+            }
+        }
+        Ok(contract)
+    }
+
+    fn restore(&mut self, snapshot: ContractSnapshot) {
+        self.balances = snapshot
+            .balances
+            .into_iter()
+            .map(|(a, asset, b)| ((a, asset), b))
+            .collect();
+        self.nonces = snapshot.nonces.into_iter().collect();
+        self.transparent = snapshot
+            .transparent
+            .into_iter()
+            .map(|(a, asset, b)| ((a, asset), b))
+            .collect();
+        self.fee_collector = snapshot.fee_collector;
+        self.coins = snapshot.coins.into_iter().collect();
+        self.nullifier_set = snapshot.nullifiers.into_iter().collect();
+        self.tree.restore(
+            snapshot.tree_left,
+            snapshot.tree_right,
+            snapshot.tree_parents,
+        );
+        self.roots = snapshot.roots.into_iter().collect();
+        while self.roots.len() > self.root_history {
+            self.roots.pop_front();
+        }
+        if self.roots.is_empty() {
+            self.roots.push_back(self.tree.root());
+        }
+    }
+
+    pub fn snapshot(&self) -> ContractSnapshot {
+        ContractSnapshot {
+            balances: self
+                .balances
+                .iter()
+                .map(|(&(ref a, asset), b)| (a.clone(), asset, b.clone()))
+                .collect(),
+            nonces: self.nonces.iter().map(|(a, n)| (a.clone(), *n)).collect(),
+            transparent: self
+                .transparent
+                .iter()
+                .map(|(&(ref a, asset), b)| (a.clone(), asset, *b))
+                .collect(),
+            fee_collector: self.fee_collector.clone(),
+            coins: self.coins.iter().cloned().collect(),
+            nullifiers: self.nullifier_set.iter().cloned().collect(),
+            tree_left: self.tree.export_left(),
+            tree_right: self.tree.export_right(),
+            tree_parents: self.tree.export_parents(),
+            roots: self.roots.iter().cloned().collect(),
         }
     }
 
-    pub fn set_banlance(&mut self, address: String, balance: String) {
-        self.balances.insert(address, balance);
+    //replace the store's log by a snapshot of the current state;
+    //checkpoints are not part of a snapshot and are dropped
+    pub fn compact(&mut self) -> Result<(), Error> {
+        let snapshot = self.snapshot();
+        self.store.snapshot(&snapshot)?;
+        self.checkpoints.clear();
+        self.tree.clear_checkpoints();
+        Ok(())
     }
 
-    pub fn get_banlance(&mut self, address: String) -> String {
-        self.balances.get(&address).unwrap().clone()
+    //mark the state at a block boundary, to be restored by rewind
+    pub fn checkpoint(&mut self) -> Result<(), Error> {
+        self.store.append(&[StateOp::Checkpoint])?;
+        self.apply(StateOp::Checkpoint)?;
+        Ok(())
     }
 
+    //undo everything since the last checkpoint and drop it; false if there is none
+    pub fn rewind(&mut self) -> Result<bool, Error> {
+        if self.checkpoints.is_empty() {
+            return Ok(false);
+        }
+        self.store.append(&[StateOp::Rewind])?;
+        self.apply(StateOp::Rewind)?;
+        Ok(true)
+    }
+
+    pub fn checkpoints(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn is_known_root(&self, root: &PedersenDigest) -> bool {
+        self.roots.contains(root)
+    }
+
+    fn check_root(&self, root: String) -> Result<(), Rejection> {
+        if self.is_known_root(&PedersenDigest(str2u644(root)?)) {
+            Ok(())
+        } else {
+            Err(Rejection::UnknownRoot)
+        }
+    }
+
+    fn apply(&mut self, op: StateOp) -> Result<Option<MerklePath<PedersenDigest>>, Error> {
+        let (undo, path) = match op {
+            StateOp::SetBalance(address, asset, balance) => {
+                let key = (address, asset);
+                let old = self.balances.insert(key.clone(), balance);
+                (Undo::Balance(key, old), None)
+            }
+            StateOp::SetNonce(address, nonce) => {
+                let old = self.nonces.insert(address.clone(), nonce);
+                (Undo::Nonce(address, old), None)
+            }
+            StateOp::SetTransparent(address, asset, balance) => {
+                let key = (address, asset);
+                let old = self.transparent.insert(key.clone(), balance);
+                (Undo::Transparent(key, old), None)
+            }
+            StateOp::SetFeeCollector(address) => {
+                let old = self.fee_collector.replace(address);
+                (Undo::FeeCollector(old), None)
+            }
+            StateOp::AddNullifier(nullifier) => {
+                self.nullifier_set.insert(nullifier.clone());
+                (Undo::Nullifier(nullifier), None)
+            }
+            StateOp::AddCoin(coin) => {
+                let digest = PedersenDigest(str2u644(coin.clone())?);
+                self.coins.insert(coin.clone());
+                self.tree.append(digest);
+                if self.roots.len() == self.root_history {
+                    self.roots.pop_front();
+                }
+                self.roots.push_back(self.tree.root());
+                (Undo::Coin(coin), Some(self.tree.path(VecDeque::new())))
+            }
+            StateOp::Checkpoint => {
+                if self.checkpoints.len() == MAX_CHECKPOINTS {
+                    self.checkpoints.pop_front();
+                }
+                self.checkpoints.push_back(Checkpoint {
+                    undo: vec![],
+                    roots: self.roots.clone(),
+                });
+                self.tree.checkpoint();
+                return Ok(None);
+            }
+            StateOp::Rewind => {
+                let checkpoint = match self.checkpoints.pop_back() {
+                    Some(checkpoint) => checkpoint,
+                    None => return Err(Error::CorruptStore),
+                };
+                for undo in checkpoint.undo.into_iter().rev() {
+                    self.revert(undo);
+                }
+                self.roots = checkpoint.roots;
+                self.tree.rewind();
+                return Ok(None);
+            }
+        };
+        if let Some(checkpoint) = self.checkpoints.back_mut() {
+            checkpoint.undo.push(undo);
+        }
+        Ok(path)
+    }
+
+    fn revert(&mut self, undo: Undo) {
+        match undo {
+            Undo::Balance(key, Some(balance)) => {
+                self.balances.insert(key, balance);
+            }
+            Undo::Balance(key, None) => {
+                self.balances.remove(&key);
+            }
+            Undo::Nonce(address, Some(nonce)) => {
+                self.nonces.insert(address, nonce);
+            }
+            Undo::Nonce(address, None) => {
+                self.nonces.remove(&address);
+            }
+            Undo::Transparent(key, Some(balance)) => {
+                self.transparent.insert(key, balance);
+            }
+            Undo::Transparent(key, None) => {
+                self.transparent.remove(&key);
+            }
+            Undo::FeeCollector(address) => {
+                self.fee_collector = address;
+            }
+            Undo::Nullifier(nullifier) => {
+                self.nullifier_set.remove(&nullifier);
+            }
+            Undo::Coin(coin) => {
+                self.coins.remove(&coin);
+            }
+        }
+    }
+
+    //persist a batch, then apply it; returns the paths of the appended coins
+    fn commit(&mut self, ops: Vec<StateOp>) -> Result<Vec<MerklePath<PedersenDigest>>, Rejection> {
+        self.store.append(&ops).map_err(Rejection::Storage)?;
+        let mut paths = vec![];
+        for op in ops.into_iter() {
+            if let Some(path) = self.apply(op)? {
+                paths.push(path);
+            }
+        }
+        Ok(paths)
+    }
+
+    pub fn set_banlance(
+        &mut self,
+        address: String,
+        asset: u64,
+        balance: String,
+    ) -> Result<(), Error> {
+        balance.parse::<EncryptedBalance>()?;
+        let op = StateOp::SetBalance(address, asset, balance);
+        self.store.append(&[op.clone()])?;
+        self.apply(op)?;
+        Ok(())
+    }
+
+    pub fn get_banlance(&mut self, address: String, asset: u64) -> Option<String> {
+        self.balances.get(&(address, asset)).cloned()
+    }
+
+    pub fn set_transparent_balance(
+        &mut self,
+        address: String,
+        asset: u64,
+        balance: u128,
+    ) -> Result<(), Error> {
+        let op = StateOp::SetTransparent(address, asset, balance);
+        self.store.append(&[op.clone()])?;
+        self.apply(op)?;
+        Ok(())
+    }
+
+    pub fn transparent_balance(&self, address: &str, asset: u64) -> Option<u128> {
+        self.transparent.get(&(address.to_string(), asset)).cloned()
+    }
+
+    pub fn set_fee_collector(&mut self, address: String) -> Result<(), Error> {
+        let op = StateOp::SetFeeCollector(address);
+        self.store.append(&[op.clone()])?;
+        self.apply(op)?;
+        Ok(())
+    }
+
+    pub fn fee_collector(&self) -> Option<&str> {
+        self.fee_collector.as_ref().map(|a| a.as_str())
+    }
+
+    //add the SetTransparent crediting fee to the collector, after any earlier op of ops on it
+    fn credit_fee(&self, asset: u64, fee: u128, ops: &mut Vec<StateOp>) -> Result<(), Rejection> {
+        if fee == 0 {
+            return Ok(());
+        }
+        let collector = match self.fee_collector {
+            Some(ref collector) => collector.clone(),
+            None => return Err(Rejection::NoFeeCollector),
+        };
+        let mut balance = self
+            .transparent
+            .get(&(collector.clone(), asset))
+            .cloned()
+            .unwrap_or(0);
+        for op in ops.iter() {
+            if let StateOp::SetTransparent(ref address, a, b) = *op {
+                if *address == collector && a == asset {
+                    balance = b;
+                }
+            }
+        }
+        let balance = match balance.checked_add(fee) {
+            Some(balance) => balance,
+            None => return Err(Rejection::Invalid(Error::BalanceOutOfRange)),
+        };
+        ops.push(StateOp::SetTransparent(collector, asset, balance));
+        Ok(())
+    }
+
+    pub fn encrypted_balance(
+        &self,
+        address: &str,
+        asset: u64,
+    ) -> Result<Option<EncryptedBalance>, Error> {
+        match self.balances.get(&(address.to_string(), asset)) {
+            Some(balance) => Ok(Some(balance.parse()?)),
+            None => Ok(None),
+        }
+    }
+
+    //nonce the next send of address must carry
+    pub fn nonce(&self, address: &str) -> u64 {
+        self.nonces.get(address).cloned().unwrap_or(0)
+    }
+
+    pub fn queued(&self, address: &str) -> usize {
+        self.queued
+            .get(address)
+            .map_or(0, |queued| queued.values().map(|sends| sends.len()).sum())
+    }
+
+    //apply a send carrying the next nonce, then the queued sends it unblocks;
+    //a send ahead of the nonce is verified against its hb and queued, to be
+    //applied if hb is the balance when its nonce comes up. Only the account's
+    //owner can prove a send, so no one else can fill its queue.
+    //Returns (nonce, path of the coin) of the applied sends.
     pub fn send_verify(
         &mut self,
         address: String,
         message: SenderProof,
-    ) -> (bool, Option<MerklePath<PedersenDigest>>) {
-        if self.coins.contains(&message.coin) {
-            println!("Dup coin");
-            return (false, None);
+    ) -> Result<Vec<(u64, MerklePath<PedersenDigest>)>, Rejection> {
+        let next = self.nonce(&address);
+        if message.nonce < next {
+            return Err(Rejection::StaleNonce);
         }
+        if message.nonce > next {
+            if !self
+                .balances
+                .contains_key(&(address.clone(), message.asset))
+            {
+                return Err(Rejection::UnknownAccount);
+            }
+            let waiting = self
+                .queued
+                .get(&address)
+                .and_then(|queued| queued.get(&message.nonce))
+                .map_or(0, |sends| sends.len());
+            if message.nonce - next >= MAX_QUEUED as u64
+                || self.queued(&address) >= MAX_QUEUED
+                || waiting >= MAX_QUEUED_PER_NONCE
+            {
+                return Err(Rejection::QueueFull);
+            }
+            if self.coins.contains(&canonical_coin(&message.coin)?) {
+                return Err(Rejection::DuplicateCoin);
+            }
+            self.verify_send(&address, &message)?;
+            self.queued
+                .entry(address)
+                .or_insert_with(BTreeMap::new)
+                .entry(message.nonce)
+                .or_insert_with(Vec::new)
+                .push(message);
+            return Ok(vec![]);
+        }
+
+        self.check_send(&address, &message)?;
+        self.verify_send(&address, &message)?;
+        let mut paths = vec![(next, self.apply_send(address.clone(), message)?)];
+        self.apply_queued(&address, &mut paths);
+        Ok(paths)
+    }
 
-        // compare block number
-        if let Some(block_number) = self.last_spent.get_mut(&address) {
-            if *block_number >= message.block_number {
-                println!("invalid block number");
-                return (false, None);
+    //apply the queued sends the current nonce unblocks; those failing when
+    //their nonce comes up, and any left behind the nonce, are dropped
+    fn apply_queued(&mut self, address: &str, paths: &mut Vec<(u64, MerklePath<PedersenDigest>)>) {
+        loop {
+            let next = self.nonce(address);
+            let sends = match self.queued.get_mut(address) {
+                Some(queued) => {
+                    *queued = queued.split_off(&next);
+                    match queued.remove(&next) {
+                        Some(sends) => sends,
+                        None => break,
+                    }
+                }
+                None => break,
+            };
+            //verified when queued, the first still matching the balance wins
+            let mut applied = false;
+            for send in sends.into_iter() {
+                if let Ok(path) = self.apply_send(address.to_string(), send) {
+                    paths.push((next, path));
+                    applied = true;
+                    break;
+                }
+            }
+            if !applied {
+                break;
             }
         }
+        if self.queued(address) == 0 {
+            self.queued.remove(address);
+        }
+    }
+
+    //the proof of message against its hb, not the current balance
+    fn verify_send(&self, address: &str, message: &SenderProof) -> Result<(), Rejection> {
+        check_proof(p2c_verify(
+            message.hb.clone(),
+            canonical_coin(&message.coin)?,
+            message.delt_ba.clone(),
+            message.enc.clone(),
+            address.to_string(),
+            message.asset,
+            u1282value(message.fee),
+            message.nonce,
+            message.proof.clone(),
+        )?)
+    }
+
+    //whether a verified send can be applied now; returns the balance it leaves
+    fn check_send(&self, address: &str, message: &SenderProof) -> Result<String, Rejection> {
+        if message.nonce.checked_add(1).is_none() {
+            return Err(Rejection::NonceExhausted);
+        }
+        if self.coins.contains(&canonical_coin(&message.coin)?) {
+            return Err(Rejection::DuplicateCoin);
+        }
+        if message.fee > 0 && self.fee_collector.is_none() {
+            return Err(Rejection::NoFeeCollector);
+        }
+
+        let balance = match self.balances.get(&(address.to_string(), message.asset)) {
+            Some(balance) => balance.parse::<EncryptedBalance>()?,
+            None => return Err(Rejection::UnknownAccount),
+        };
+        if message.hb.parse::<EncryptedBalance>()? != balance {
+            return Err(Rejection::StaleBalance);
+        }
+        Ok(balance
+            .sub_commitment(&message.delt_ba.parse()?)?
+            .to_string())
+    }
+
+    //message must have been verified
+    fn apply_send(
+        &mut self,
+        address: String,
+        message: SenderProof,
+    ) -> Result<MerklePath<PedersenDigest>, Rejection> {
+        let new_balance = self.check_send(&address, &message)?;
+        let mut ops = vec![
+            StateOp::SetNonce(address.clone(), message.nonce + 1),
+            StateOp::SetBalance(address, message.asset, new_balance),
+        ];
+        self.credit_fee(message.asset, message.fee, &mut ops)?;
+        ops.push(StateOp::AddCoin(canonical_coin(&message.coin)?));
+        let mut paths = self.commit(ops)?;
+        Ok(paths.pop().unwrap())
+    }
 
-        let balance = self.balances.get_mut(&address).unwrap();
-        assert!(p2c_verify(
-            balance.clone(),
-            message.coin.clone(),
+    pub fn receive_verify(
+        &mut self,
+        address: String,
+        message: ReceiverProof,
+    ) -> Result<(), Rejection> {
+        self.check_root(message.root.clone())?;
+
+        let nullifier = canonical_nullifier(&message.nullifier)?;
+        if self.nullifier_set.contains(&nullifier) {
+            return Err(Rejection::DuplicateNullifier);
+        }
+        if message.fee > 0 && self.fee_collector.is_none() {
+            return Err(Rejection::NoFeeCollector);
+        }
+
+        let balance = match self.balances.get(&(address.clone(), message.asset)) {
+            Some(balance) => balance.clone(),
+            None => return Err(Rejection::UnknownAccount),
+        };
+        check_proof(c2p_verify(
+            nullifier.clone(),
+            message.root,
             message.delt_ba.clone(),
+            message.asset,
+            u1282value(message.fee),
+            message.proof,
+        )?)?;
+        let new_balance = balance
+            .parse::<EncryptedBalance>()?
+            .add_commitment(&message.delt_ba.parse()?)?
+            .to_string();
+
+        let mut ops = vec![
+            StateOp::AddNullifier(nullifier),
+            StateOp::SetBalance(address, message.asset, new_balance),
+        ];
+        self.credit_fee(message.asset, message.fee, &mut ops)?;
+        self.commit(ops)?;
+        Ok(())
+    }
+
+    pub fn deposit_verify(
+        &mut self,
+        address: String,
+        message: DepositProof,
+    ) -> Result<MerklePath<PedersenDigest>, Rejection> {
+        let coin = canonical_coin(&message.coin)?;
+        if self.coins.contains(&coin) {
+            return Err(Rejection::DuplicateCoin);
+        }
+
+        let balance = match self.transparent.get(&(address.clone(), message.asset)) {
+            Some(balance) => *balance,
+            None => return Err(Rejection::UnknownAccount),
+        };
+        let new_balance = match balance.checked_sub(message.va) {
+            Some(balance) => balance,
+            None => return Err(Rejection::InsufficientFunds),
+        };
+        check_proof(b2c_verify(
+            u1282value(message.va),
+            message.asset,
+            coin.clone(),
             message.enc,
             address.clone(),
-            message.proof
-        )
-        .unwrap());
-
-        self.last_spent.insert(address, message.block_number);
-        self.coins.insert(message.coin.clone());
-        self.tree
-            .append(PedersenDigest(str2u644(message.coin.clone())));
-        *balance = ecc_sub(balance.clone(), message.delt_ba);
-        println!(
-            "sender proof verify ok! root {:?} coin {:?}",
-            self.tree.root(),
-            message.coin
-        );
-        (true, Some(self.tree.path(VecDeque::new())))
+            message.proof,
+        )?)?;
+
+        let mut paths = self.commit(vec![
+            StateOp::SetTransparent(address, message.asset, new_balance),
+            StateOp::AddCoin(coin),
+        ])?;
+        Ok(paths.pop().unwrap())
     }
 
-    pub fn receive_verify(&mut self, address: String, message: ReceiverProof) -> bool {
-        if str2u644(message.root.clone()) != self.tree.root().0 {
-            println!(
-                "invalid root, message.root {:?}, tree.root {:?}",
-                message.root,
-                self.tree.root()
-            );
-            return false;
+    //the c2b proof binds the receiving address and the balance it held when proving
+    pub fn withdraw_verify(
+        &mut self,
+        address: String,
+        message: WithdrawProof,
+    ) -> Result<(), Rejection> {
+        self.check_root(message.root.clone())?;
+
+        let nullifier = canonical_nullifier(&message.nullifier)?;
+        if self.nullifier_set.contains(&nullifier) {
+            return Err(Rejection::DuplicateNullifier);
         }
 
-        if self.nullifier_set.contains(&message.nullifier) {
-            println!("Dup nullifier");
-            return false;
+        let balance = match self.transparent.get(&(address.clone(), message.asset)) {
+            Some(&balance) => balance,
+            None => return Err(Rejection::UnknownAccount),
+        };
+        if message.fee > 0 && self.fee_collector.is_none() {
+            return Err(Rejection::NoFeeCollector);
         }
+        let new_balance = match message
+            .va
+            .checked_sub(message.fee)
+            .and_then(|va| balance.checked_add(va))
+        {
+            Some(balance) => balance,
+            None => return Err(Rejection::Invalid(Error::BalanceOutOfRange)),
+        };
+        check_proof(c2b_verify(
+            u1282value(message.va),
+            message.asset,
+            u1282value(message.fee),
+            address.clone(),
+            nullifier.clone(),
+            message.root,
+            message.proof,
+        )?)?;
+
+        let mut ops = vec![
+            StateOp::AddNullifier(nullifier),
+            StateOp::SetTransparent(address, message.asset, new_balance),
+        ];
+        self.credit_fee(message.asset, message.fee, &mut ops)?;
+        self.commit(ops)?;
+        Ok(())
+    }
 
-        assert!(c2p_verify(
-            message.nullifier.clone(),
+    pub fn transfer_verify(
+        &mut self,
+        message: TransferProof,
+    ) -> Result<Vec<MerklePath<PedersenDigest>>, Rejection> {
+        self.check_root(message.root.clone())?;
+
+        let mut nullifiers = Vec::with_capacity(message.nullifiers.len());
+        for nullifier in message.nullifiers.iter() {
+            let nullifier = canonical_nullifier(nullifier)?;
+            if self.nullifier_set.contains(&nullifier) || nullifiers.contains(&nullifier) {
+                return Err(Rejection::DuplicateNullifier);
+            }
+            nullifiers.push(nullifier);
+        }
+
+        let mut coins = Vec::with_capacity(message.coins.len());
+        for coin in message.coins.iter() {
+            let coin = canonical_coin(coin)?;
+            if self.coins.contains(&coin) || coins.contains(&coin) {
+                return Err(Rejection::DuplicateCoin);
+            }
+            coins.push(coin);
+        }
+
+        check_proof(c2c_verify(
+            nullifiers.clone(),
             message.root,
-            message.delt_ba.clone(),
-            message.proof
+            coins.clone(),
+            message.encs,
+            message.proof,
+        )?)?;
+
+        let mut ops = vec![];
+        for nullifier in nullifiers.into_iter() {
+            ops.push(StateOp::AddNullifier(nullifier));
+        }
+        for coin in coins.into_iter() {
+            ops.push(StateOp::AddCoin(coin));
+        }
+        self.commit(ops)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use balance::BalanceTracker;
+    use params::test_generators;
+    use std::env;
+    use std::fs::remove_dir_all;
+    use std::process;
+    use types::SpendingKey;
+
+    fn contract() -> PrivacyContract {
+        test_generators();
+        PrivacyContract::new()
+    }
+
+    fn num(n: u64) -> String {
+        u6442str([n, 0, 0, 0])
+    }
+
+    //the duplicate checks come before the proof, so a dummy proof is enough
+    fn transfer(contract: &PrivacyContract, nullifiers: &[u64], coins: &[u64]) -> TransferProof {
+        TransferProof {
+            proof: String::new(),
+            nullifiers: nullifiers.iter().map(|&n| num(n)).collect(),
+            root: u6442str(contract.tree.root().0),
+            coins: coins.iter().map(|&n| num(n)).collect(),
+            encs: vec![String::new(); coins.len()],
+        }
+    }
+
+    #[test]
+    fn open_replays_store() {
+        test_generators();
+        let dir = env::temp_dir().join(format!("zktx-contract-{}", process::id()));
+        let _ = remove_dir_all(&dir);
+        let open = || {
+            PrivacyContract::open(Box::new(FileStore::open(&dir).unwrap()), ROOT_HISTORY).unwrap()
+        };
+
+        let (root, checked_root) = {
+            let mut contract = open();
+            contract.set_fee_collector("collector".to_string()).unwrap();
+            contract
+                .commit(vec![
+                    StateOp::AddNullifier(num(1)),
+                    StateOp::AddCoin(num(2)),
+                ])
+                .unwrap();
+            let checked_root = contract.tree.root();
+            contract.compact().unwrap();
+            contract.checkpoint().unwrap();
+            contract
+                .set_transparent_balance("address".to_string(), 0, 5)
+                .unwrap();
+            contract.commit(vec![StateOp::AddCoin(num(3))]).unwrap();
+            (contract.tree.root(), checked_root)
+        };
+
+        let mut contract = open();
+        assert_eq!(contract.fee_collector(), Some("collector"));
+        assert!(contract.nullifier_set.contains(&num(1)));
+        assert!(contract.coins.contains(&num(2)) && contract.coins.contains(&num(3)));
+        assert_eq!(contract.transparent_balance("address", 0), Some(5));
+        assert_eq!(contract.tree.root(), root);
+        assert!(contract.is_known_root(&checked_root));
+        //the checkpoint after the snapshot is replayed too
+        assert!(contract.rewind().unwrap());
+        assert_eq!(contract.tree.root(), checked_root);
+        assert_eq!(contract.transparent_balance("address", 0), None);
+    }
+
+    fn account(contract: &mut PrivacyContract) -> String {
+        let balance = EncryptedBalance::commit([1000, 0], 0, [1, 2, 3, 0]).unwrap();
+        contract
+            .set_banlance("address".to_string(), 0, balance.to_string())
+            .unwrap();
+        "address".to_string()
+    }
+
+    //fails its proof, and its balance once its nonce comes up
+    fn junk_send(nonce: u64) -> SenderProof {
+        SenderProof {
+            proof: String::new(),
+            hb: String::new(),
+            coin: num(nonce + 100),
+            delt_ba: String::new(),
+            enc: String::new(),
+            asset: 0,
+            fee: 0,
+            nonce,
+        }
+    }
+
+    fn send(contract: &mut PrivacyContract, address: &str, nonce: u64) -> Result<usize, Rejection> {
+        contract
+            .send_verify(address.to_string(), junk_send(nonce))
+            .map(|paths| paths.len())
+    }
+
+    //as if a send had been verified and queued
+    fn queue(contract: &mut PrivacyContract, address: &str, nonce: u64) {
+        contract
+            .queued
+            .entry(address.to_string())
+            .or_insert_with(BTreeMap::new)
+            .entry(nonce)
+            .or_insert_with(Vec::new)
+            .push(junk_send(nonce));
+    }
+
+    #[test]
+    fn send_queue_limits() {
+        let mut contract = contract();
+        match send(&mut contract, "nobody", 1) {
+            Err(Rejection::UnknownAccount) => {}
+            other => panic!("{:?}", other),
+        }
+
+        let address = account(&mut contract);
+        match send(&mut contract, &address, MAX_QUEUED as u64) {
+            Err(Rejection::QueueFull) => {}
+            other => panic!("{:?}", other),
+        }
+        //a send failing its proof takes no slot
+        match send(&mut contract, &address, MAX_QUEUED as u64 - 1) {
+            Err(Rejection::Invalid(_)) => {}
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(contract.queued(&address), 0);
+
+        for _ in 0..MAX_QUEUED_PER_NONCE {
+            queue(&mut contract, &address, 1);
+        }
+        match send(&mut contract, &address, 1) {
+            Err(Rejection::QueueFull) => {}
+            other => panic!("{:?}", other),
+        }
+
+        let mut nonce = 0;
+        while contract.queued(&address) < MAX_QUEUED {
+            queue(
+                &mut contract,
+                &address,
+                nonce / MAX_QUEUED_PER_NONCE as u64 + 2,
+            );
+            nonce += 1;
+        }
+        match send(&mut contract, &address, MAX_QUEUED as u64 - 2) {
+            Err(Rejection::QueueFull) => {}
+            other => panic!("{:?}", other),
+        }
+
+        contract
+            .commit(vec![StateOp::SetNonce(address.clone(), 5)])
+            .unwrap();
+        match send(&mut contract, &address, 4) {
+            Err(Rejection::StaleNonce) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn send_queue_evicts_junk() {
+        let mut contract = contract();
+        let address = account(&mut contract);
+        for &nonce in [1, 2, 2].iter() {
+            queue(&mut contract, &address, nonce);
+        }
+
+        //the send for nonce 1 does not match the balance once the nonce comes up
+        contract
+            .commit(vec![StateOp::SetNonce(address.clone(), 1)])
+            .unwrap();
+        let mut paths = vec![];
+        contract.apply_queued(&address, &mut paths);
+        assert!(paths.is_empty());
+        assert_eq!(contract.queued(&address), 2);
+
+        //sends left behind the nonce are dropped
+        contract
+            .commit(vec![StateOp::SetNonce(address.clone(), 3)])
+            .unwrap();
+        contract.apply_queued(&address, &mut paths);
+        assert_eq!(contract.queued(&address), 0);
+        assert!(!contract.queued.contains_key(&address));
+    }
+
+    #[test]
+    fn send_rejects_last_nonce() {
+        let mut contract = contract();
+        let address = account(&mut contract);
+        contract
+            .commit(vec![StateOp::SetNonce(address.clone(), u64::max_value())])
+            .unwrap();
+        match send(&mut contract, &address, u64::max_value()) {
+            Err(Rejection::NonceExhausted) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    //proves with freshly generated p2c parameters, which takes minutes
+    #[test]
+    #[ignore]
+    fn send_out_of_order() {
+        let mut contract = contract();
+        if !p2c_param_path().exists() {
+            ::p2c::gen_p2c_param().unwrap();
+        }
+        let sk: SpendingKey = "01".repeat(32).parse().unwrap();
+        let address = sk.address().unwrap().to_string();
+        let to = sk
+            .incoming_viewing_key()
+            .unwrap()
+            .payment_address()
+            .unwrap();
+        let mut tracker = BalanceTracker::new([1000, 0], 0, [1, 2, 3, 0]);
+        contract
+            .set_banlance(
+                address.clone(),
+                0,
+                tracker.commitment().unwrap().to_string(),
+            )
+            .unwrap();
+
+        let mut sends = vec![];
+        for nonce in 0..3 {
+            let rcm = [nonce + 7, 1];
+            let (proof, hb, coin, delt_ba, enc) = p2c_info(
+                tracker.rh(),
+                rcm,
+                tracker.ba(),
+                [10, 0],
+                0,
+                [0, 0],
+                [0, 0],
+                nonce,
+                to.addr.to_string(),
+                to.enc_key.to_string(),
+                sk.to_string(),
+                [nonce + 1, 2, 3, 0],
+                vec![],
+            )
+            .unwrap();
+            tracker.send([10, 0], rcm).unwrap();
+            sends.push(SenderProof {
+                proof,
+                hb,
+                coin,
+                delt_ba,
+                enc,
+                asset: 0,
+                fee: 0,
+                nonce,
+            });
+        }
+
+        let last = sends.pop().unwrap();
+        let second = sends.pop().unwrap();
+        let first = sends.pop().unwrap();
+        assert!(contract
+            .send_verify(address.clone(), last)
+            .unwrap()
+            .is_empty());
+        //junk is not queued
+        match contract.send_verify(address.clone(), junk_send(1)) {
+            Err(Rejection::Invalid(_)) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(contract
+            .send_verify(address.clone(), second)
+            .unwrap()
+            .is_empty());
+        assert_eq!(contract.queued(&address), 2);
+
+        let applied = contract.send_verify(address.clone(), first).unwrap();
+        assert_eq!(
+            applied.iter().map(|a| a.0).collect::<Vec<_>>(),
+            vec![0, 1, 2]
+        );
+        assert_eq!(contract.nonce(&address), 3);
+        assert_eq!(contract.queued(&address), 0);
+        let balance: EncryptedBalance = contract.get_banlance(address, 0).unwrap().parse().unwrap();
+        assert!(tracker.opens(&balance).unwrap());
+    }
+
+    #[test]
+    fn withdraw_rejects_unknown_account() {
+        let mut contract = contract();
+        let message = WithdrawProof {
+            proof: String::new(),
+            nullifier: num(1),
+            root: u6442str(contract.tree.root().0),
+            va: 10,
+            asset: 0,
+            fee: 0,
+        };
+        match contract.withdraw_verify("address".to_string(), message) {
+            Err(Rejection::UnknownAccount) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn duplicate_checks_ignore_hex_case() {
+        let mut contract = contract();
+        let address = account(&mut contract);
+        contract
+            .set_transparent_balance(address.clone(), 0, 100)
+            .unwrap();
+        contract
+            .commit(vec![
+                StateOp::AddNullifier(num(0xab)),
+                StateOp::AddCoin(num(0xcd)),
+            ])
+            .unwrap();
+        let root = u6442str(contract.tree.root().0);
+        let nullifier = num(0xab).to_uppercase();
+        let coin = num(0xcd).to_uppercase();
+
+        let message = ReceiverProof {
+            proof: String::new(),
+            nullifier: nullifier.clone(),
+            root: root.clone(),
+            delt_ba: String::new(),
+            asset: 0,
+            fee: 0,
+        };
+        match contract.receive_verify(address.clone(), message) {
+            Err(Rejection::DuplicateNullifier) => {}
+            other => panic!("{:?}", other),
+        }
+
+        let message = WithdrawProof {
+            proof: String::new(),
+            nullifier,
+            root,
+            va: 10,
+            asset: 0,
+            fee: 0,
+        };
+        match contract.withdraw_verify(address.clone(), message) {
+            Err(Rejection::DuplicateNullifier) => {}
+            other => panic!("{:?}", other),
+        }
+
+        let message = DepositProof {
+            proof: String::new(),
+            va: 10,
+            asset: 0,
+            coin: coin.clone(),
+            enc: String::new(),
+        };
+        match contract.deposit_verify(address.clone(), message) {
+            Err(Rejection::DuplicateCoin) => {}
+            other => panic!("{:?}", other.err()),
+        }
+
+        let mut message = junk_send(0);
+        message.coin = coin;
+        match contract.send_verify(address, message) {
+            Err(Rejection::DuplicateCoin) => {}
+            other => panic!("{:?}", other.err()),
+        }
+    }
+
+    //proves with freshly generated c2b parameters, which takes minutes
+    #[test]
+    #[ignore]
+    fn withdraw_is_bound_to_its_address() {
+        let mut contract = contract();
+        if !c2b_param_path().exists() {
+            ::c2b::gen_c2b_param().unwrap();
+        }
+        let sk: SpendingKey = "01".repeat(32).parse().unwrap();
+        let owner = sk.address().unwrap().to_string();
+        let thief = "02"
+            .repeat(32)
+            .parse::<SpendingKey>()
+            .unwrap()
+            .address()
+            .unwrap()
+            .to_string();
+        for address in [&owner, &thief].iter() {
+            contract
+                .set_transparent_balance(address.to_string(), 0, 0)
+                .unwrap();
+        }
+
+        let (va, rcm) = ([10, 0], [7, 1]);
+        let enc_key = sk.incoming_viewing_key().unwrap().enc_key().unwrap();
+        let coin = ::build_coin(owner.clone(), enc_key.to_string(), va, rcm, 0).unwrap();
+        let path = contract
+            .commit(vec![StateOp::AddCoin(coin)])
+            .unwrap()
+            .pop()
+            .unwrap();
+        let auth = path
+            .authentication_path
+            .iter()
+            .map(|p| u6442str(p.0))
+            .collect();
+        let (proof, nullifier, root) = ::c2b::c2b_info(
+            rcm,
+            va,
+            0,
+            [0, 0],
+            [0, 0],
+            owner.clone(),
+            sk.to_string(),
+            auth,
+            path.index.clone(),
         )
-        .unwrap());
+        .unwrap();
+        let message = WithdrawProof {
+            proof,
+            nullifier,
+            root,
+            va: 10,
+            asset: 0,
+            fee: 0,
+        };
+
+        //the same proof replayed to credit another address
+        match contract.withdraw_verify(thief.clone(), message.clone()) {
+            Err(Rejection::Invalid(Error::ProofInvalid)) => {}
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(contract.transparent_balance(&thief, 0), Some(0));
 
-        self.nullifier_set.insert(message.nullifier);
-        let balance = self.balances.get_mut(&address).unwrap();
-        *balance = ecc_add(balance.clone(), message.delt_ba);
-        true
+        //the balance changing while the proof is in flight does not matter
+        contract
+            .set_transparent_balance(owner.clone(), 0, 5)
+            .unwrap();
+        contract.withdraw_verify(owner.clone(), message).unwrap();
+        assert_eq!(contract.transparent_balance(&owner, 0), Some(15));
+    }
+
+    #[test]
+    fn transfer_rejects_duplicate_nullifiers() {
+        let mut contract = contract();
+        let message = transfer(&contract, &[1, 1], &[3, 4]);
+        match contract.transfer_verify(message) {
+            Err(Rejection::DuplicateNullifier) => {}
+            other => panic!("{:?}", other.err()),
+        }
+
+        contract
+            .commit(vec![StateOp::AddNullifier(num(2))])
+            .unwrap();
+        let message = transfer(&contract, &[1, 2], &[3, 4]);
+        match contract.transfer_verify(message) {
+            Err(Rejection::DuplicateNullifier) => {}
+            other => panic!("{:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn transfer_rejects_duplicate_coins() {
+        let mut contract = contract();
+        let message = transfer(&contract, &[1, 2], &[3, 3]);
+        match contract.transfer_verify(message) {
+            Err(Rejection::DuplicateCoin) => {}
+            other => panic!("{:?}", other.err()),
+        }
+
+        contract.commit(vec![StateOp::AddCoin(num(4))]).unwrap();
+        let message = transfer(&contract, &[1, 2], &[3, 4]);
+        match contract.transfer_verify(message) {
+            Err(Rejection::DuplicateCoin) => {}
+            other => panic!("{:?}", other.err()),
+        }
+    }
+
+    #[test]
+    fn transfer_duplicates_ignore_hex_case() {
+        let mut contract = contract();
+        let mut message = transfer(&contract, &[0xab, 0xab], &[3, 4]);
+        message.nullifiers[1] = message.nullifiers[1].to_uppercase();
+        match contract.transfer_verify(message) {
+            Err(Rejection::DuplicateNullifier) => {}
+            other => panic!("{:?}", other.err()),
+        }
+
+        contract
+            .commit(vec![
+                StateOp::AddNullifier(num(0xab)),
+                StateOp::AddCoin(num(0xcd)),
+            ])
+            .unwrap();
+        let mut message = transfer(&contract, &[0xab, 2], &[3, 4]);
+        message.nullifiers[0] = message.nullifiers[0].to_uppercase();
+        match contract.transfer_verify(message) {
+            Err(Rejection::DuplicateNullifier) => {}
+            other => panic!("{:?}", other.err()),
+        }
+
+        let mut message = transfer(&contract, &[1, 2], &[0xcd, 4]);
+        message.coins[0] = message.coins[0].to_uppercase();
+        match contract.transfer_verify(message) {
+            Err(Rejection::DuplicateCoin) => {}
+            other => panic!("{:?}", other.err()),
+        }
     }
 }
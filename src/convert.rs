@@ -1,4 +1,12 @@
-extern crate hex;
+use hex;
+use pairing::bls12_381::{Fr, FrRepr};
+use pairing::PrimeField;
+use rand::Rng;
+
+use jubjub::*;
+
+use base::NOTE_CIPHERTEXT;
+use error::Error;
 
 #[inline(always)]
 pub fn u64to8(mut num: u64) -> [u8; 8] {
@@ -20,6 +28,25 @@ pub fn u8to64(nums: [u8; 8]) -> u64 {
     res
 }
 
+//128 bit value, low limb first
+pub fn value2u128(va: [u64; 2]) -> u128 {
+    (va[1] as u128) << 64 | va[0] as u128
+}
+
+pub fn u1282value(va: u128) -> [u64; 2] {
+    [va as u64, (va >> 64) as u64]
+}
+
+//below 2^252 so it is a canonical Fr
+pub fn random_u644<R: Rng>(rng: &mut R) -> [u64; 4] {
+    [
+        rng.gen(),
+        rng.gen(),
+        rng.gen(),
+        rng.gen::<u64>() & 0x0fff_ffff_ffff_ffff,
+    ]
+}
+
 pub fn proof2str(
     proof: (
         ([u64; 6], [u64; 6], bool),
@@ -77,13 +104,27 @@ pub fn u8sto64(nums: &[u8]) -> u64 {
     }
     res
 }
+pub(crate) fn decode(serial: String, len: usize) -> Result<Vec<u8>, Error> {
+    let v: Vec<u8> = hex::decode(serial)?;
+    if v.len() != len {
+        return Err(Error::WrongLength {
+            expected: len,
+            found: v.len(),
+        });
+    }
+    Ok(v)
+}
+
 pub fn str2proof(
     serial: String,
-) -> (
-    ([u64; 6], [u64; 6], bool),
-    (([u64; 6], [u64; 6]), ([u64; 6], [u64; 6]), bool),
-    ([u64; 6], [u64; 6], bool),
-) {
+) -> Result<
+    (
+        ([u64; 6], [u64; 6], bool),
+        (([u64; 6], [u64; 6]), ([u64; 6], [u64; 6]), bool),
+        ([u64; 6], [u64; 6], bool),
+    ),
+    Error,
+> {
     let mut proof: (
         ([u64; 6], [u64; 6], bool),
         (([u64; 6], [u64; 6]), ([u64; 6], [u64; 6]), bool),
@@ -93,7 +134,7 @@ pub fn str2proof(
         (([0; 6], [0; 6]), ([0; 6], [0; 6]), false),
         ([0; 6], [0; 6], false),
     );
-    let v: Vec<u8> = hex::decode(serial).unwrap();
+    let v = decode(serial, 385)?;
     for i in 0..6 {
         ((proof.0).0)[i] = u8sto64(&v[i * 8..(i + 1) * 8]);
     }
@@ -122,11 +163,10 @@ pub fn str2proof(
     (proof.0).2 = b & 0b00000100 != 0;
     (proof.1).2 = b & 0b00000010 != 0;
     (proof.2).2 = b & 0b00000001 != 0;
-    proof
+    Ok(proof)
 }
 
-use std::num::ParseIntError;
-pub fn str2value(st: String) -> Result<([u64; 2], bool), ParseIntError> {
+pub fn str2value(st: String) -> Result<([u64; 2], bool), Error> {
     let st: &str = st.as_ref();
     let mut res: ([u64; 2], bool) = ([0; 2], true);
     if st.get(0..1) == Some("-") {
@@ -146,13 +186,17 @@ pub fn u6442str(u644: [u64; 4]) -> String {
     res
 }
 
-pub fn str2u644(serial: String) -> [u64; 4] {
+pub fn str2u644(serial: String) -> Result<[u64; 4], Error> {
     let mut coin: [u64; 4] = [0; 4];
-    let v: Vec<u8> = hex::decode(serial).unwrap();
+    let v = decode(serial, 32)?;
     for i in 0..4 {
         coin[i] = u8sto64(&v[i * 8..(i + 1) * 8]);
     }
-    coin
+    Ok(coin)
+}
+
+pub fn u6442fr(num: [u64; 4]) -> Result<Fr, Error> {
+    Fr::from_repr(FrRepr::from_serial(num)).map_err(|_| Error::NonCanonical)
 }
 
 pub fn point2str(point: ([u64; 4], [u64; 4])) -> String {
@@ -166,49 +210,44 @@ pub fn point2str(point: ([u64; 4], [u64; 4])) -> String {
     res
 }
 
-pub fn str2point(serial: String) -> ([u64; 4], [u64; 4]) {
+pub fn str2point(serial: String) -> Result<([u64; 4], [u64; 4]), Error> {
     let mut point: ([u64; 4], [u64; 4]) = ([0; 4], [0; 4]);
-    let v: Vec<u8> = hex::decode(serial).unwrap();
+    let v = decode(serial, 64)?;
     for i in 0..4 {
         (point.0)[i] = u8sto64(&v[i * 8..(i + 1) * 8]);
     }
     for i in 4..8 {
         (point.1)[i - 4] = u8sto64(&v[i * 8..(i + 1) * 8]);
     }
-    point
+    Ok(point)
 }
 
-pub fn enc2str(enc: ([u64; 4], [u64; 4], [u64; 4])) -> String {
-    let mut res = String::with_capacity(192);
-    for i in 0..4 {
-        res.push_str(hex::encode(u64to8((enc.0)[i]).as_ref()).as_ref());
-    }
-    for i in 0..4 {
-        res.push_str(hex::encode(u64to8((enc.1)[i]).as_ref()).as_ref());
-    }
-    for i in 0..4 {
-        res.push_str(hex::encode(u64to8((enc.2)[i]).as_ref()).as_ref());
-    }
+//rP | note ciphertext
+pub fn enc2str(rp: ([u64; 4], [u64; 4]), ct: &[u8]) -> String {
+    let mut res = point2str(rp);
+    res.push_str(hex::encode(ct).as_ref());
     res
 }
 
-pub fn str2enc(serial: String) -> ([u64; 4], [u64; 4], [u64; 4]) {
-    let mut enc: ([u64; 4], [u64; 4], [u64; 4]) = ([0; 4], [0; 4], [0; 4]);
-    let v: Vec<u8> = hex::decode(serial).unwrap();
+pub fn str2enc(serial: String) -> Result<(([u64; 4], [u64; 4]), Vec<u8>), Error> {
+    let mut rp: ([u64; 4], [u64; 4]) = ([0; 4], [0; 4]);
+    let v = decode(serial, 64 + NOTE_CIPHERTEXT)?;
     for i in 0..4 {
-        (enc.0)[i] = u8sto64(&v[i * 8..(i + 1) * 8]);
+        (rp.0)[i] = u8sto64(&v[i * 8..(i + 1) * 8]);
     }
     for i in 4..8 {
-        (enc.1)[i - 4] = u8sto64(&v[i * 8..(i + 1) * 8]);
-    }
-    for i in 8..12 {
-        (enc.2)[i - 8] = u8sto64(&v[i * 8..(i + 1) * 8]);
+        (rp.1)[i - 4] = u8sto64(&v[i * 8..(i + 1) * 8]);
     }
-    enc
+    Ok((rp, v[64..].to_vec()))
 }
 
-pub fn sk2str(sk: Vec<bool>) -> String {
-    assert_eq!(sk.len(), 256);
+pub fn sk2str(sk: Vec<bool>) -> Result<String, Error> {
+    if sk.len() != 256 {
+        return Err(Error::WrongLength {
+            expected: 256,
+            found: sk.len(),
+        });
+    }
     let mut u8s: Vec<u8> = Vec::with_capacity(32);
     for u in sk.chunks(8) {
         let mut num: u8 = 0;
@@ -224,11 +263,11 @@ pub fn sk2str(sk: Vec<bool>) -> String {
         }
         u8s.push(num);
     }
-    hex::encode(u8s)
+    Ok(hex::encode(u8s))
 }
 
-pub fn str2sk(serial: String) -> Vec<bool> {
-    let serial: Vec<u8> = hex::decode(serial).unwrap();
+pub fn str2sk(serial: String) -> Result<Vec<bool>, Error> {
+    let serial = decode(serial, 32)?;
     let mut res: Vec<bool> = Vec::with_capacity(256);
     for u in serial.iter() {
         let mut num = *u;
@@ -237,5 +276,5 @@ pub fn str2sk(serial: String) -> Vec<bool> {
             num <<= 1;
         }
     }
-    res
+    Ok(res)
 }
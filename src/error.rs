@@ -0,0 +1,155 @@
+use bellman;
+use bip39;
+use hex::FromHexError;
+use std::error;
+use std::fmt;
+use std::io;
+use std::num::ParseIntError;
+use std::path::PathBuf;
+
+#[derive(Debug)]
+pub enum Error {
+    //input is not valid hex
+    BadHex(FromHexError),
+    //decoded input has the wrong number of bytes or bits
+    WrongLength { expected: usize, found: usize },
+    //input is not a decimal number
+    BadNumber(ParseIntError),
+    //mnemonic has unknown words, a bad word count or a bad checksum
+    BadMnemonic(bip39::Error),
+    //hd seed is shorter than 16 or longer than 64 bytes, the length found
+    BadSeedLength(usize),
+    //hd derivation beyond the 255 levels an extended key can record
+    TooDeep,
+    //value is not a canonical element of Fr
+    NonCanonical,
+    //coordinates do not satisfy the JubJub curve equation
+    NotOnCurve,
+    //a proof point is not on its BLS12-381 curve or not in the prime order subgroup
+    BadProof,
+    //parameter or generator file does not exist
+    MissingParamFile(PathBuf),
+    //parameter or generator file has a bad header or does not match it
+    BadParamFile(PathBuf, String),
+    //input and output values of a transfer differ
+    Unbalanced,
+    //a send exceeds the balance, or a receipt overflows it
+    BalanceOutOfRange,
+    //the fee is above the prover's max_fee
+    FeeTooHigh,
+    //the note is spent or not held by the wallet
+    UnknownNote,
+    //the proof does not verify against its public inputs
+    ProofInvalid,
+    //note ciphertext is not addressed to the key or has been tampered with
+    DecryptionFailed,
+    //a trusted setup contribution does not extend the previous parameters
+    InvalidContribution,
+    //persisted contract or wallet state fails its checksum or cannot be decoded
+    CorruptStore,
+    //a job queued on a ProverPool panicked
+    ProverPanicked,
+    //circuit synthesis or proving failed
+    Synthesis(bellman::Error),
+    Io(io::Error),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::BadHex(ref e) => write!(f, "bad hex: {}", e),
+            Error::WrongLength { expected, found } => {
+                write!(f, "wrong length: expected {}, found {}", expected, found)
+            }
+            Error::BadNumber(ref e) => write!(f, "bad number: {}", e),
+            Error::BadMnemonic(ref e) => write!(f, "bad mnemonic: {}", e),
+            Error::BadSeedLength(found) => {
+                write!(
+                    f,
+                    "bad seed length: expected 16 to 64 bytes, found {}",
+                    found
+                )
+            }
+            Error::TooDeep => write!(f, "derivation deeper than 255 levels"),
+            Error::NonCanonical => write!(f, "non-canonical field element"),
+            Error::NotOnCurve => write!(f, "point is not on the curve"),
+            Error::BadProof => write!(f, "proof point is not in the group"),
+            Error::MissingParamFile(ref path) => {
+                write!(f, "missing parameter file {}", path.display())
+            }
+            Error::BadParamFile(ref path, ref reason) => {
+                write!(f, "bad parameter file {}: {}", path.display(), reason)
+            }
+            Error::Unbalanced => write!(f, "input and output values differ"),
+            Error::BalanceOutOfRange => write!(f, "balance out of range"),
+            Error::FeeTooHigh => write!(f, "fee above max_fee"),
+            Error::UnknownNote => write!(f, "unknown or spent note"),
+            Error::ProofInvalid => write!(f, "proof is invalid"),
+            Error::DecryptionFailed => write!(f, "note decryption failed"),
+            Error::InvalidContribution => write!(f, "invalid setup contribution"),
+            Error::CorruptStore => write!(f, "corrupt stored state"),
+            Error::ProverPanicked => write!(f, "proving job panicked"),
+            Error::Synthesis(ref e) => write!(f, "synthesis error: {:?}", e),
+            Error::Io(ref e) => write!(f, "io error: {}", e),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn description(&self) -> &str {
+        match *self {
+            Error::BadHex(_) => "bad hex",
+            Error::WrongLength { .. } => "wrong length",
+            Error::BadNumber(_) => "bad number",
+            Error::BadMnemonic(_) => "bad mnemonic",
+            Error::BadSeedLength(_) => "bad seed length",
+            Error::TooDeep => "derivation deeper than 255 levels",
+            Error::NonCanonical => "non-canonical field element",
+            Error::NotOnCurve => "point is not on the curve",
+            Error::BadProof => "proof point is not in the group",
+            Error::MissingParamFile(_) => "missing parameter file",
+            Error::BadParamFile(..) => "bad parameter file",
+            Error::Unbalanced => "input and output values differ",
+            Error::BalanceOutOfRange => "balance out of range",
+            Error::FeeTooHigh => "fee above max_fee",
+            Error::UnknownNote => "unknown or spent note",
+            Error::ProofInvalid => "proof is invalid",
+            Error::DecryptionFailed => "note decryption failed",
+            Error::InvalidContribution => "invalid setup contribution",
+            Error::CorruptStore => "corrupt stored state",
+            Error::ProverPanicked => "proving job panicked",
+            Error::Synthesis(_) => "synthesis error",
+            Error::Io(_) => "io error",
+        }
+    }
+}
+
+impl From<FromHexError> for Error {
+    fn from(e: FromHexError) -> Error {
+        Error::BadHex(e)
+    }
+}
+
+impl From<ParseIntError> for Error {
+    fn from(e: ParseIntError) -> Error {
+        Error::BadNumber(e)
+    }
+}
+
+impl From<bip39::Error> for Error {
+    fn from(e: bip39::Error) -> Error {
+        Error::BadMnemonic(e)
+    }
+}
+
+impl From<bellman::Error> for Error {
+    fn from(e: bellman::Error) -> Error {
+        Error::Synthesis(e)
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Error {
+        Error::Io(e)
+    }
+}
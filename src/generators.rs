@@ -0,0 +1,225 @@
+//Nothing-up-my-sleeve generators.
+//
+//GH(tag) hashes onto the prime order subgroup of JubJub:
+//h = BLAKE2s-256(tag | i) personalised with "Zktx_GH_", for the first counter
+//byte i that gives a point, where h read little-endian with its top bit cleared
+//is y, the top bit is the sign of x, and the point is multiplied by the
+//cofactor 8 and must not be the identity.
+//
+//P1 = GH("P1"), P2 = GH("P2"). The circuits multiply by them through
+//common_verify::fixed_base, whose tables are the doublings below, so proofs and
+//native code share the points.
+//
+//The value generator of asset a > 0 is GH("asset"|a), a as 8 little-endian
+//bytes, and that of asset 0 is P1. None is a known multiple of another, so a
+//commitment to one asset cannot be opened as one to another.
+//
+//The Pedersen hash takes its 512 bits in 128 windows of 4. Window w holds
+//G, 2G, .., 16G for G = GH("PH"|w), w as one byte, the layout jubjub's
+//generate_constant_table gives its random points; a window never looks up the
+//identity. The tags differ in length, so no window base is P1, P2 or an asset
+//generator.
+
+use blake2_rfc::blake2s::Blake2s;
+use pairing::bls12_381::Fr;
+use pairing::{Field, PrimeField, SqrtField};
+
+use jubjub::*;
+
+use base::{point_add, point_double};
+use convert::{u6442fr, u8sto64};
+
+const GH_PERSONAL: &'static [u8; 8] = b"Zktx_GH_";
+//windows of the Pedersen hash and the points in each
+const PH_WINDOWS: usize = 128;
+const PH_WINDOW_POINTS: usize = 16;
+
+lazy_static! {
+    static ref POINTS: [(Fr, Fr); 2] = {
+        let j = JubJub::new();
+        [group_hash(b"P1", &j), group_hash(b"P2", &j)]
+    };
+}
+
+//the value generator of asset 0, and the base of addresses and of rP
+pub fn p1() -> (Fr, Fr) {
+    POINTS[0]
+}
+
+//the blinding generator
+pub fn p2() -> (Fr, Fr) {
+    POINTS[1]
+}
+
+//the value generator of asset
+pub fn asset_generator(asset: u64) -> (Fr, Fr) {
+    if asset == 0 {
+        return p1();
+    }
+    let mut tag = b"asset".to_vec();
+    for i in 0..8 {
+        tag.push((asset >> (8 * i)) as u8);
+    }
+    group_hash(&tag, &JubJub::new())
+}
+
+pub fn group_hash(tag: &[u8], j: &JubJub) -> (Fr, Fr) {
+    for i in 0..256 {
+        let mut h = Blake2s::with_params(32, &[], &[], GH_PERSONAL);
+        h.update(tag);
+        h.update(&[i as u8]);
+        if let Some(point) = hash_to_point(h.finalize().as_bytes(), j) {
+            return point;
+        }
+    }
+    panic!("no counter gives a point");
+}
+
+fn hash_to_point(h: &[u8], j: &JubJub) -> Option<(Fr, Fr)> {
+    let sign = h[31] >> 7 == 1;
+    let mut y = [0u64; 4];
+    for i in 0..4 {
+        y[i] = u8sto64(&h[i * 8..(i + 1) * 8]);
+    }
+    y[3] &= 0x7fff_ffff_ffff_ffff;
+    let y = match u6442fr(y) {
+        Ok(y) => y,
+        Err(_) => return None,
+    };
+
+    //-x^2+y^2 = 1+d*x^2*y^2, so x^2 = (y^2-1)/(d*y^2+1)
+    let mut yy = y;
+    yy.square();
+    let mut num = yy;
+    num.sub_assign(&Fr::one());
+    let mut den = yy;
+    den.mul_assign(&j.d);
+    den.add_assign(&Fr::one());
+    let mut xx = match den.inverse() {
+        Some(inv) => inv,
+        None => return None,
+    };
+    xx.mul_assign(&num);
+    let mut x = match xx.sqrt() {
+        Some(x) => x,
+        None => return None,
+    };
+    if (x.into_repr().serial()[0] & 1 == 1) != sign {
+        x.negate();
+    }
+
+    let (mut x, mut y) = (x, y);
+    for _ in 0..3 {
+        let res = point_double(x, y, j);
+        x = res.0;
+        y = res.1;
+    }
+    if x.is_zero() {
+        return None;
+    }
+    Some((x, y))
+}
+
+//base, 2*base, .., 2^(n-1)*base
+pub(crate) fn doublings(base: (Fr, Fr), n: usize, j: &JubJub) -> Vec<(Fr, Fr)> {
+    let mut table = Vec::with_capacity(n);
+    let (mut x, mut y) = base;
+    for _ in 0..n {
+        table.push((x, y));
+        let res = point_double(x, y, j);
+        x = res.0;
+        y = res.1;
+    }
+    table
+}
+
+//x and y coordinates of G, 2G, .., 16G for each window base G
+pub(crate) fn ph_windows(j: &JubJub) -> Vec<(Vec<Fr>, Vec<Fr>)> {
+    let mut windows = Vec::with_capacity(PH_WINDOWS);
+    for w in 0..PH_WINDOWS {
+        let g = group_hash(&[b'P', b'H', w as u8], j);
+        let mut xs = Vec::with_capacity(PH_WINDOW_POINTS);
+        let mut ys = Vec::with_capacity(PH_WINDOW_POINTS);
+        let mut cur = g;
+        for _ in 0..PH_WINDOW_POINTS {
+            xs.push(cur.0);
+            ys.push(cur.1);
+            cur = point_add(&cur.0, &cur.1, &g.0, &g.1, j);
+        }
+        windows.push((xs, ys));
+    }
+    windows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base::{point2fr, point_add, point_mul, u64s2bits};
+
+    //order of the prime order subgroup of JubJub
+    const R_J: [u64; 4] = [
+        0xd0970e5ed6f72cb7,
+        0xa6682093ccc81082,
+        0x06673b0101343b00,
+        0x0e7db4ea6533afa9,
+    ];
+
+    #[test]
+    fn generators_are_in_the_subgroup() {
+        let j = JubJub::new();
+        let points = [
+            p1(),
+            p2(),
+            asset_generator(1),
+            asset_generator(2),
+            asset_generator(1 << 8),
+            asset_generator(u64::max_value()),
+        ];
+        for (i, p) in points.iter().enumerate() {
+            let serial = (p.0.into_repr().serial(), p.1.into_repr().serial());
+            assert_eq!(point2fr(serial).unwrap(), *p);
+            assert_eq!(point_mul(*p, u64s2bits(&R_J)), (Fr::zero(), Fr::one()));
+            for q in points[..i].iter() {
+                assert!(p != q);
+            }
+        }
+        assert_eq!(group_hash(b"P1", &j), p1());
+        assert_eq!(asset_generator(0), p1());
+    }
+
+    #[test]
+    fn doublings_match_multiplication() {
+        let j = JubJub::new();
+        let table = doublings(p1(), 256, &j);
+        for &i in [0, 1, 63, 64, 200, 255].iter() {
+            let mut num = [0u64; 4];
+            num[i / 64] = 1 << (i % 64);
+            assert_eq!(table[i], point_mul(p1(), u64s2bits(&num)));
+        }
+        let three = point_add(&table[0].0, &table[0].1, &table[1].0, &table[1].1, &j);
+        assert_eq!(three, point_mul(p1(), u64s2bits(&[3])));
+    }
+
+    #[test]
+    fn windows_are_multiples_of_their_base() {
+        let j = JubJub::new();
+        let windows = ph_windows(&j);
+        assert_eq!(windows.len(), PH_WINDOWS);
+        let mut bases = vec![];
+        for (w, &(ref xs, ref ys)) in windows.iter().enumerate() {
+            assert_eq!(xs.len(), PH_WINDOW_POINTS);
+            assert_eq!(ys.len(), PH_WINDOW_POINTS);
+            let g = (xs[0], ys[0]);
+            assert_eq!(g, group_hash(&[b'P', b'H', w as u8], &j));
+            for &k in [1, 7, 15].iter() {
+                assert_eq!((xs[k], ys[k]), point_mul(g, u64s2bits(&[k as u64 + 1])));
+            }
+            assert!(g != p1() && g != p2() && !bases.contains(&g));
+            bases.push(g);
+        }
+        assert_eq!(
+            point_mul(bases[5], u64s2bits(&R_J)),
+            (Fr::zero(), Fr::one())
+        );
+    }
+}
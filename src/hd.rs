@@ -0,0 +1,302 @@
+//Hierarchical deterministic spending keys.
+//
+//Like BIP32 with HMAC-SHA512 replaced by keyed BLAKE2b-512, and hardened
+//derivation only since addr_sk has no public derivation:
+//
+//master: I = BLAKE2b(key "Zktx_HD_seed", seed)
+//child i: I = BLAKE2b(key chain, 0x00|addr_sk|i), i big-endian with the top bit set
+//
+//addr_sk is the left half of I and chain the right half. Seeds usually come
+//from a BIP39 mnemonic.
+//
+//There are no diversified addresses: the circuits bind a coin to addr_sk*P1
+//and encrypt its note to ivk*P1, with no diversifier to vary the base point.
+//The addresses of an account are instead child keys of their own,
+//m/32'/account'/index', each with its own viewing keys, so a wallet scans
+//with the key of every index it handed out.
+
+use bip39::Mnemonic;
+use blake2_rfc::blake2b::Blake2b;
+use hex;
+use rand::Rng;
+
+use std::fmt;
+use std::str::FromStr;
+
+use convert::decode;
+use error::Error;
+use keys::PaymentAddress;
+use types::SpendingKey;
+
+pub const HARDENED: u32 = 0x8000_0000;
+//first level of the account paths
+pub const PURPOSE: u32 = 32;
+
+const SEED_KEY: &'static [u8] = b"Zktx_HD_seed";
+//depth|index|chain|addr_sk
+const EXTENDED_BYTES: usize = 1 + 4 + 32 + 32;
+
+//a fresh 24 word mnemonic
+pub fn new_mnemonic<R: Rng>(rng: &mut R) -> String {
+    let mut entropy = [0u8; 32];
+    rng.fill_bytes(&mut entropy);
+    mnemonic_from_entropy(&entropy).expect("32 bytes of entropy make a mnemonic")
+}
+
+pub fn mnemonic_from_entropy(entropy: &[u8]) -> Result<String, Error> {
+    Ok(Mnemonic::from_entropy(entropy)?.to_string())
+}
+
+pub fn mnemonic_to_entropy(phrase: &str) -> Result<Vec<u8>, Error> {
+    Ok(Mnemonic::parse(phrase)?.to_entropy())
+}
+
+//the 64 byte BIP39 seed
+pub fn mnemonic_to_seed(phrase: &str, passphrase: &str) -> Result<Vec<u8>, Error> {
+    Ok(Mnemonic::parse(phrase)?.to_seed(passphrase).to_vec())
+}
+
+fn split(key: &[u8], data: &[u8]) -> ([u8; 32], [u8; 32]) {
+    let mut h = Blake2b::with_key(64, key);
+    h.update(data);
+    let i = h.finalize();
+    let mut sk = [0u8; 32];
+    let mut chain = [0u8; 32];
+    sk.copy_from_slice(&i.as_bytes()[..32]);
+    chain.copy_from_slice(&i.as_bytes()[32..]);
+    (sk, chain)
+}
+
+#[derive(Clone, Eq, PartialEq)]
+pub struct ExtendedSpendingKey {
+    depth: u8,
+    //with HARDENED set, 0 for the master key
+    index: u32,
+    chain: [u8; 32],
+    sk: [u8; 32],
+}
+
+impl ExtendedSpendingKey {
+    //seed of 16 to 64 bytes
+    pub fn master(seed: &[u8]) -> Result<ExtendedSpendingKey, Error> {
+        if seed.len() < 16 || seed.len() > 64 {
+            return Err(Error::BadSeedLength(seed.len()));
+        }
+        let (sk, chain) = split(SEED_KEY, seed);
+        Ok(ExtendedSpendingKey {
+            depth: 0,
+            index: 0,
+            chain,
+            sk,
+        })
+    }
+
+    pub fn from_mnemonic(phrase: &str, passphrase: &str) -> Result<ExtendedSpendingKey, Error> {
+        ExtendedSpendingKey::master(&mnemonic_to_seed(phrase, passphrase)?)
+    }
+
+    //always hardened, i and i|HARDENED give the same child
+    pub fn child(&self, i: u32) -> Result<ExtendedSpendingKey, Error> {
+        let depth = self.depth.checked_add(1).ok_or(Error::TooDeep)?;
+        let index = i | HARDENED;
+        let mut data = Vec::with_capacity(37);
+        data.push(0);
+        data.extend_from_slice(&self.sk);
+        data.extend_from_slice(&[
+            (index >> 24) as u8,
+            (index >> 16) as u8,
+            (index >> 8) as u8,
+            index as u8,
+        ]);
+        let (sk, chain) = split(&self.chain, &data);
+        Ok(ExtendedSpendingKey {
+            depth,
+            index,
+            chain,
+            sk,
+        })
+    }
+
+    pub fn derive(&self, path: &[u32]) -> Result<ExtendedSpendingKey, Error> {
+        let mut key = self.clone();
+        for &i in path.iter() {
+            key = key.child(i)?;
+        }
+        Ok(key)
+    }
+
+    //m/32'/account'
+    pub fn account(&self, account: u32) -> Result<ExtendedSpendingKey, Error> {
+        self.derive(&[PURPOSE, account])
+    }
+
+    pub fn depth(&self) -> u8 {
+        self.depth
+    }
+
+    pub fn index(&self) -> u32 {
+        self.index
+    }
+
+    pub fn spending_key(&self) -> SpendingKey {
+        hex::encode(&self.sk[..])
+            .parse()
+            .expect("32 bytes are a spending key")
+    }
+
+    //of the index'th child, for an account key m/32'/account'/index'
+    pub fn payment_address(&self, index: u32) -> Result<PaymentAddress, Error> {
+        self.child(index)?
+            .spending_key()
+            .incoming_viewing_key()?
+            .payment_address()
+    }
+}
+
+impl FromStr for ExtendedSpendingKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<ExtendedSpendingKey, Error> {
+        let v = decode(s.to_string(), EXTENDED_BYTES)?;
+        let mut chain = [0u8; 32];
+        let mut sk = [0u8; 32];
+        chain.copy_from_slice(&v[5..37]);
+        sk.copy_from_slice(&v[37..]);
+        Ok(ExtendedSpendingKey {
+            depth: v[0],
+            index: (v[1] as u32) << 24 | (v[2] as u32) << 16 | (v[3] as u32) << 8 | v[4] as u32,
+            chain,
+            sk,
+        })
+    }
+}
+
+impl fmt::Display for ExtendedSpendingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut v = Vec::with_capacity(EXTENDED_BYTES);
+        v.push(self.depth);
+        v.extend_from_slice(&[
+            (self.index >> 24) as u8,
+            (self.index >> 16) as u8,
+            (self.index >> 8) as u8,
+            self.index as u8,
+        ]);
+        v.extend_from_slice(&self.chain);
+        v.extend_from_slice(&self.sk);
+        write!(f, "{}", hex::encode(v))
+    }
+}
+
+impl fmt::Debug for ExtendedSpendingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "ExtendedSpendingKey({}, {:x}, ..)",
+            self.depth, self.index
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn seed() -> Vec<u8> {
+        (0..16).collect()
+    }
+
+    #[test]
+    fn seed_vectors() {
+        let m = ExtendedSpendingKey::master(&seed()).unwrap();
+        assert_eq!(
+            m.spending_key().to_string(),
+            "202d8cce6852675fdf2125fafae38e4d67f7a102ac44039cbda4870df60e5f52"
+        );
+        assert_eq!(
+            m.child(0).unwrap().spending_key().to_string(),
+            "d7f93994c714fe31a9fdc29c62e9a2fbfde9de16673df047919a2647749d1a2f"
+        );
+        let key = m.derive(&[32, 0, 1]).unwrap();
+        assert_eq!(key.depth(), 3);
+        assert_eq!(key.index(), 1 | HARDENED);
+        assert_eq!(
+            key.spending_key().to_string(),
+            "6a7520ce928d1d64dfdaafd02e2d94a96e1bdb225d4644c64a2de9b52591d596"
+        );
+        assert_eq!(key, m.account(0).unwrap().child(1 | HARDENED).unwrap());
+    }
+
+    #[test]
+    fn mnemonic_vectors() {
+        let phrase = mnemonic_from_entropy(&[0u8; 16]).unwrap();
+        assert_eq!(
+            phrase,
+            "abandon abandon abandon abandon abandon abandon \
+             abandon abandon abandon abandon abandon about"
+        );
+        assert_eq!(mnemonic_to_entropy(&phrase).unwrap(), vec![0u8; 16]);
+        assert_eq!(
+            hex::encode(mnemonic_to_seed(&phrase, "TREZOR").unwrap()),
+            "c55257c360c07c72029aebc1b53c05ed0362ada38ead3e3e9efa3708e53495531f09a6987599d18264c1e1c92f2cf141630c7a3c4ab7c81b2f001698e7463b04"
+        );
+        let m = ExtendedSpendingKey::from_mnemonic(&phrase, "TREZOR").unwrap();
+        assert_eq!(
+            m.spending_key().to_string(),
+            "8db0193afff733226917d0dea4bfbcedf0f2a80b67dde08eca62f4365f57198f"
+        );
+        assert_eq!(
+            m.account(0)
+                .unwrap()
+                .child(0)
+                .unwrap()
+                .spending_key()
+                .to_string(),
+            "fbf14a00946e42e1cd9ef3d4709fa4e4308b183a0e0d8a677cab8a8e2c8e24c9"
+        );
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        assert!(mnemonic_to_entropy(
+            "abandon abandon abandon abandon abandon abandon \
+             abandon abandon abandon abandon abandon abandon"
+        )
+        .is_err());
+    }
+
+    #[test]
+    fn string_roundtrip() {
+        let key = ExtendedSpendingKey::master(&seed())
+            .unwrap()
+            .derive(&[32, 7])
+            .unwrap();
+        let s = key.to_string();
+        assert_eq!(s.len(), 2 * EXTENDED_BYTES);
+        assert_eq!(s.parse::<ExtendedSpendingKey>().unwrap(), key);
+    }
+
+    #[test]
+    fn seed_lengths() {
+        for &len in [15, 65].iter() {
+            match ExtendedSpendingKey::master(&vec![0u8; len]) {
+                Err(Error::BadSeedLength(found)) => assert_eq!(found, len),
+                other => panic!("{:?}", other),
+            }
+        }
+        assert!(ExtendedSpendingKey::master(&[0u8; 16]).is_ok());
+        assert!(ExtendedSpendingKey::master(&[0u8; 64]).is_ok());
+    }
+
+    #[test]
+    fn depth_does_not_wrap() {
+        let mut key = ExtendedSpendingKey::master(&seed()).unwrap();
+        for _ in 0..255 {
+            key = key.child(0).unwrap();
+        }
+        assert_eq!(key.depth(), 255);
+        match key.child(0) {
+            Err(Error::TooDeep) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,355 @@
+//Versioned headers of the generators and parameter files.
+//
+//magic "ZKTX" | version u16 | circuit u8 | constraints u64 | length u64 | digest
+//
+//integers little-endian, circuit 0 for the generators and 1 to 6 for the
+//ParamKinds in PARAM_KINDS order, digest the BLAKE2b-256 of the contents.
+//The generators file starts with its header. Parameter files keep the format
+//read by `ProverStream`, so their header is stored beside them with a
+//`.header` suffix; ParamSet checks the two agree before a prover streams the
+//file, and again whenever either changes.
+
+use bellman::groth16::{generate_random_parameters, Parameters};
+use bellman::Error as SynthesisError;
+use bellman::{Circuit, ConstraintSystem, LinearCombination, Variable};
+use blake2_rfc::blake2b::Blake2b;
+use pairing::bls12_381::{Bls12, Fr};
+use rand::thread_rng;
+
+use std::cell::Cell;
+use std::fs::{rename, File};
+use std::io::{BufReader, BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+use base::{ParamKind, PARAM_KINDS};
+use error::Error;
+
+pub const MAGIC: [u8; 4] = *b"ZKTX";
+pub const VERSION: u16 = 2;
+pub const HEADER_BYTES: usize = 4 + 2 + 1 + 8 + 8 + 32;
+
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub struct FileHeader {
+    pub version: u16,
+    //None for the generators
+    pub circuit: Option<ParamKind>,
+    //constraints the circuit enforces, the bindings of its public inputs
+    //not counted; 0 for the generators
+    pub constraints: u64,
+    //bytes of contents after, or beside, the header
+    pub length: u64,
+    pub digest: [u8; 32],
+}
+
+fn circuit_id(circuit: Option<ParamKind>) -> u8 {
+    match circuit {
+        None => 0,
+        Some(kind) => PARAM_KINDS.iter().position(|&k| k == kind).unwrap() as u8 + 1,
+    }
+}
+
+fn circuit_name(circuit: Option<ParamKind>) -> String {
+    match circuit {
+        None => "generators".to_string(),
+        Some(kind) => format!("{:?}", kind).to_lowercase(),
+    }
+}
+
+fn bad(path: &Path, reason: String) -> Error {
+    Error::BadParamFile(path.to_path_buf(), reason)
+}
+
+//BLAKE2b-256 and length of everything left in reader
+pub(crate) fn digest<R: Read>(reader: &mut R) -> Result<([u8; 32], u64), Error> {
+    let mut h = Blake2b::new(32);
+    let mut length = 0u64;
+    let mut buf = [0u8; 64 * 1024];
+    loop {
+        let n = reader.read(&mut buf)?;
+        if n == 0 {
+            break;
+        }
+        h.update(&buf[..n]);
+        length += n as u64;
+    }
+    let mut digest = [0u8; 32];
+    digest.copy_from_slice(h.finalize().as_bytes());
+    Ok((digest, length))
+}
+
+impl FileHeader {
+    pub fn new(circuit: Option<ParamKind>, constraints: u64, contents: &[u8]) -> FileHeader {
+        let (digest, length) = digest(&mut &contents[..]).expect("reading a slice");
+        FileHeader {
+            version: VERSION,
+            circuit,
+            constraints,
+            length,
+            digest,
+        }
+    }
+
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        writer.write_all(&MAGIC)?;
+        writer.write_all(&[self.version as u8, (self.version >> 8) as u8])?;
+        writer.write_all(&[circuit_id(self.circuit)])?;
+        for &n in [self.constraints, self.length].iter() {
+            let mut bytes = [0u8; 8];
+            for i in 0..8 {
+                bytes[i] = (n >> (8 * i)) as u8;
+            }
+            writer.write_all(&bytes)?;
+        }
+        writer.write_all(&self.digest)?;
+        Ok(())
+    }
+
+    //path only names the file in errors
+    pub fn read<R: Read>(reader: &mut R, path: &Path) -> Result<FileHeader, Error> {
+        let mut bytes = [0u8; HEADER_BYTES];
+        let mut n = 0;
+        while n < HEADER_BYTES {
+            match reader.read(&mut bytes[n..])? {
+                0 => {
+                    return Err(bad(
+                        path,
+                        format!("header truncated at {} of {} bytes", n, HEADER_BYTES),
+                    ))
+                }
+                read => n += read,
+            }
+        }
+        if bytes[..4] != MAGIC[..] {
+            return Err(bad(
+                path,
+                "no zktx header, files from before version 1 must be regenerated".to_string(),
+            ));
+        }
+        let version = bytes[4] as u16 | (bytes[5] as u16) << 8;
+        if version != VERSION {
+            return Err(bad(
+                path,
+                format!("format version {}, this build reads {}", version, VERSION),
+            ));
+        }
+        let circuit = match bytes[6] {
+            0 => None,
+            id if (id as usize) <= PARAM_KINDS.len() => Some(PARAM_KINDS[id as usize - 1]),
+            id => return Err(bad(path, format!("unknown circuit id {}", id))),
+        };
+        let mut nums = [0u64; 2];
+        for (k, num) in nums.iter_mut().enumerate() {
+            for i in 0..8 {
+                *num |= (bytes[7 + 8 * k + i] as u64) << (8 * i);
+            }
+        }
+        let mut digest = [0u8; 32];
+        digest.copy_from_slice(&bytes[23..]);
+        Ok(FileHeader {
+            version,
+            circuit,
+            constraints: nums[0],
+            length: nums[1],
+            digest,
+        })
+    }
+
+    pub fn expect_circuit(&self, circuit: Option<ParamKind>, path: &Path) -> Result<(), Error> {
+        if self.circuit != circuit {
+            return Err(bad(
+                path,
+                format!(
+                    "header is for {}, expected {}",
+                    circuit_name(self.circuit),
+                    circuit_name(circuit)
+                ),
+            ));
+        }
+        Ok(())
+    }
+
+    //contents read from the start, e.g. the rest of the generators file
+    pub fn check<R: Read>(&self, contents: &mut R, path: &Path) -> Result<(), Error> {
+        let (digest, length) = digest(contents)?;
+        if length != self.length {
+            return Err(bad(
+                path,
+                format!(
+                    "{} bytes of contents, the header records {}",
+                    length, self.length
+                ),
+            ));
+        }
+        if digest != self.digest {
+            return Err(bad(
+                path,
+                "contents do not match the header digest".to_string(),
+            ));
+        }
+        Ok(())
+    }
+}
+
+pub fn header_path<P: AsRef<Path>>(param_path: P) -> PathBuf {
+    param_path.as_ref().with_extension("header")
+}
+
+//path with .tmp appended
+fn tmp_path(path: &Path) -> PathBuf {
+    let mut tmp = path.as_os_str().to_owned();
+    tmp.push(".tmp");
+    PathBuf::from(tmp)
+}
+
+//writes the file at path through a temporary file renamed over it, so a
+//reader finds the old or the new contents but never a partial file
+pub(crate) fn write_atomic<F>(path: &Path, write: F) -> Result<(), Error>
+where
+    F: FnOnce(&mut BufWriter<File>) -> Result<(), Error>,
+{
+    let tmp = tmp_path(path);
+    {
+        let mut writer = BufWriter::new(File::create(&tmp)?);
+        write(&mut writer)?;
+        writer.flush()?;
+        writer.get_ref().sync_all()?;
+    }
+    rename(&tmp, path)?;
+    Ok(())
+}
+
+//passes everything to cs, counting the constraints
+struct Counting<'a, CS: 'a> {
+    cs: &'a mut CS,
+    constraints: u64,
+}
+
+impl<'a, CS: ConstraintSystem<Bls12>> ConstraintSystem<Bls12> for Counting<'a, CS> {
+    fn one() -> Variable {
+        CS::one()
+    }
+
+    fn alloc<F>(&mut self, f: F) -> Result<Variable, SynthesisError>
+    where
+        F: FnOnce() -> Result<Fr, SynthesisError>,
+    {
+        self.cs.alloc(f)
+    }
+
+    fn enforce(
+        &mut self,
+        a: LinearCombination<Bls12>,
+        b: LinearCombination<Bls12>,
+        c: LinearCombination<Bls12>,
+    ) {
+        self.constraints += 1;
+        self.cs.enforce(a, b, c);
+    }
+}
+
+//circuit synthesized through Counting
+struct Counted<'a, C> {
+    circuit: C,
+    constraints: &'a Cell<u64>,
+}
+
+impl<'a, C: Circuit<Bls12>> Circuit<Bls12> for Counted<'a, C> {
+    type InputMap = C::InputMap;
+
+    fn synthesize<CS: ConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<C::InputMap, SynthesisError> {
+        let mut counting = Counting { cs, constraints: 0 };
+        let input = self.circuit.synthesize(&mut counting)?;
+        self.constraints.set(counting.constraints);
+        Ok(input)
+    }
+}
+
+//fresh parameters of the blank circuit and the number of its constraints
+pub(crate) fn generate_params<C: Circuit<Bls12>>(
+    circuit: C,
+) -> Result<(Parameters<Bls12>, u64), Error> {
+    let constraints = Cell::new(0);
+    let params = generate_random_parameters::<Bls12, _, _>(
+        Counted {
+            circuit,
+            constraints: &constraints,
+        },
+        &mut thread_rng(),
+    )?;
+    Ok((params, constraints.get()))
+}
+
+//writes params to path, then their header beside them
+pub(crate) fn write_params(
+    kind: ParamKind,
+    params: &Parameters<Bls12>,
+    constraints: u64,
+    path: &Path,
+) -> Result<(), Error> {
+    write_atomic(path, |writer| Ok(params.write(writer)?))?;
+    let (digest, length) = digest(&mut BufReader::new(File::open(path)?))?;
+    let header = FileHeader {
+        version: VERSION,
+        circuit: Some(kind),
+        constraints,
+        length,
+        digest,
+    };
+    write_atomic(&header_path(path), |writer| header.write(writer))
+}
+
+//header of the parameter file at path, checked against kind and the contents
+pub(crate) fn check_params(kind: ParamKind, path: &Path) -> Result<FileHeader, Error> {
+    let hpath = header_path(path);
+    if !hpath.exists() {
+        return Err(Error::MissingParamFile(hpath));
+    }
+    let header = FileHeader::read(&mut File::open(&hpath)?, &hpath)?;
+    header.expect_circuit(Some(kind), &hpath)?;
+    header.check(&mut BufReader::new(File::open(path)?), path)?;
+    Ok(header)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn path() -> &'static Path {
+        Path::new("generators")
+    }
+
+    #[test]
+    fn header_roundtrip() {
+        let header = FileHeader::new(Some(ParamKind::C2c), 1 << 17, b"contents");
+        let mut bytes = vec![];
+        header.write(&mut bytes).unwrap();
+        assert_eq!(bytes.len(), HEADER_BYTES);
+        let read = FileHeader::read(&mut &bytes[..], path()).unwrap();
+        assert_eq!(read, header);
+        read.check(&mut &b"contents"[..], path()).unwrap();
+        assert!(read.expect_circuit(Some(ParamKind::C2c), path()).is_ok());
+        assert!(read.expect_circuit(None, path()).is_err());
+    }
+
+    #[test]
+    fn rejects_changed_contents() {
+        let header = FileHeader::new(None, 0, b"contents");
+        assert!(header.check(&mut &b"content"[..], path()).is_err());
+        assert!(header.check(&mut &b"Contents"[..], path()).is_err());
+    }
+
+    #[test]
+    fn rejects_bad_headers() {
+        let mut bytes = vec![];
+        FileHeader::new(None, 0, b"").write(&mut bytes).unwrap();
+        assert!(FileHeader::read(&mut &bytes[..HEADER_BYTES - 1], path()).is_err());
+        let mut version = bytes.clone();
+        version[4] = 2;
+        assert!(FileHeader::read(&mut &version[..], path()).is_err());
+        //a file written before headers starts with field elements
+        assert!(FileHeader::read(&mut &[0u8; HEADER_BYTES][..], path()).is_err());
+    }
+}
@@ -1,12 +1,65 @@
 extern crate rand;
 
+use hex;
 use std::collections::VecDeque;
+use std::io::{Read, Write};
+
+use error::Error;
+use store::{read_u64, write_u64};
 
 pub trait Hashable: Clone + Copy {
     fn combine(&Self, &Self) -> Self;
     fn blank() -> Self;
 }
 
+//fixed size binary encoding of a node, used to persist trees and witnesses
+pub trait Encodable: Sized {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error>;
+    fn read<R: Read>(reader: &mut R) -> Result<Self, Error>;
+}
+
+//deepest tree accepted when decoding
+const MAX_DEPTH: usize = 64;
+
+//number of checkpoints kept by trees and witnesses, older ones are forgotten
+pub const MAX_CHECKPOINTS: usize = 100;
+
+fn write_node<T: Encodable, W: Write>(writer: &mut W, node: &Option<T>) -> Result<(), Error> {
+    match *node {
+        Some(ref node) => {
+            writer.write_all(&[1])?;
+            node.write(writer)
+        }
+        None => {
+            writer.write_all(&[0])?;
+            Ok(())
+        }
+    }
+}
+
+fn read_node<T: Encodable, R: Read>(reader: &mut R) -> Result<Option<T>, Error> {
+    let mut flag = [0u8; 1];
+    reader.read_exact(&mut flag)?;
+    match flag[0] {
+        0 => Ok(None),
+        1 => Ok(Some(T::read(reader)?)),
+        _ => Err(Error::CorruptStore),
+    }
+}
+
+fn from_hex<T, F>(serial: &str, read: F) -> Result<T, Error>
+where
+    F: Fn(&mut &[u8]) -> Result<T, Error>,
+{
+    let bytes = hex::decode(serial)?;
+    let mut reader = &bytes[..];
+    let res = read(&mut reader)?;
+    if !reader.is_empty() {
+        return Err(Error::CorruptStore);
+    }
+    Ok(res)
+}
+
 #[derive(Debug)]
 pub struct MerklePath<T: Hashable> {
     pub authentication_path: Vec<T>,
@@ -72,6 +125,8 @@ pub struct IncrementalMerkleTree<T: Hashable> {
     right: Option<T>,
     parents: Vec<Option<T>>,
     depth: usize,
+    //(left, right, parents) at each checkpoint, oldest first
+    checkpoints: VecDeque<(Option<T>, Option<T>, Vec<Option<T>>)>,
 }
 
 impl<T: Hashable> IncrementalMerkleTree<T> {
@@ -101,9 +156,40 @@ impl<T: Hashable> IncrementalMerkleTree<T> {
             right: None,
             parents: Vec::new(),
             depth: d,
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    //remember the current state, to be restored by rewind
+    pub fn checkpoint(&mut self) {
+        if self.checkpoints.len() == MAX_CHECKPOINTS {
+            self.checkpoints.pop_front();
+        }
+        self.checkpoints
+            .push_back((self.left, self.right, self.parents.clone()));
+    }
+
+    //go back to the last checkpoint and drop it; false if there is none
+    pub fn rewind(&mut self) -> bool {
+        match self.checkpoints.pop_back() {
+            Some((left, right, parents)) => {
+                self.left = left;
+                self.right = right;
+                self.parents = parents;
+                true
+            }
+            None => false,
         }
     }
 
+    pub fn checkpoints(&self) -> usize {
+        self.checkpoints.len()
+    }
+
+    pub fn clear_checkpoints(&mut self) {
+        self.checkpoints.clear();
+    }
+
     pub fn size(&self) -> usize {
         let mut ret = 0;
         if self.left.is_some() {
@@ -281,12 +367,62 @@ impl<T: Hashable> IncrementalMerkleTree<T> {
     }
 }
 
+impl<T: Hashable + Encodable> IncrementalMerkleTree<T> {
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        write_u64(writer, self.depth as u64)?;
+        write_node(writer, &self.left)?;
+        write_node(writer, &self.right)?;
+        write_u64(writer, self.parents.len() as u64)?;
+        for parent in self.parents.iter() {
+            write_node(writer, parent)?;
+        }
+        Ok(())
+    }
+
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let depth = read_u64(reader)?;
+        if depth == 0 || depth > MAX_DEPTH as u64 {
+            return Err(Error::CorruptStore);
+        }
+        let left = read_node(reader)?;
+        let right = read_node(reader)?;
+        if left.is_none() && right.is_some() {
+            return Err(Error::CorruptStore);
+        }
+        let len = read_u64(reader)?;
+        if len >= depth {
+            return Err(Error::CorruptStore);
+        }
+        let mut parents = Vec::new();
+        for _ in 0..len {
+            parents.push(read_node(reader)?);
+        }
+        let mut tree = IncrementalMerkleTree::new(depth as usize);
+        tree.restore(left, right, parents);
+        Ok(tree)
+    }
+
+    pub fn to_hex(&self) -> String {
+        let mut bytes = vec![];
+        self.write(&mut bytes)
+            .expect("writing to a Vec does not fail");
+        hex::encode(&bytes)
+    }
+
+    pub fn from_hex(serial: &str) -> Result<Self, Error> {
+        from_hex(serial, |reader| IncrementalMerkleTree::read(reader))
+    }
+}
+
+#[derive(Clone)]
 pub struct IncrementalWitness<T: Hashable> {
     tree: IncrementalMerkleTree<T>,
     filled: Vec<T>,
     cursor: Option<IncrementalMerkleTree<T>>,
     cursor_depth: usize,
     depth: usize,
+    //filled only grows, so its length is enough to restore it
+    checkpoints: VecDeque<(usize, Option<IncrementalMerkleTree<T>>, usize)>,
 }
 
 impl<T: Hashable> IncrementalWitness<T> {
@@ -297,6 +433,7 @@ impl<T: Hashable> IncrementalWitness<T> {
             cursor: None,
             cursor_depth: 0,
             depth: d,
+            checkpoints: VecDeque::new(),
         }
     }
 
@@ -307,7 +444,32 @@ impl<T: Hashable> IncrementalWitness<T> {
             cursor: None,
             cursor_depth: 0,
             depth: d,
+            checkpoints: VecDeque::new(),
+        }
+    }
+
+    pub fn checkpoint(&mut self) {
+        if self.checkpoints.len() == MAX_CHECKPOINTS {
+            self.checkpoints.pop_front();
         }
+        self.checkpoints
+            .push_back((self.filled.len(), self.cursor.clone(), self.cursor_depth));
+    }
+
+    pub fn rewind(&mut self) -> bool {
+        match self.checkpoints.pop_back() {
+            Some((filled, cursor, cursor_depth)) => {
+                self.filled.truncate(filled);
+                self.cursor = cursor;
+                self.cursor_depth = cursor_depth;
+                true
+            }
+            None => false,
+        }
+    }
+
+    pub fn checkpoints(&self) -> usize {
+        self.checkpoints.len()
     }
 
     fn partial_path(&self) -> VecDeque<T> {
@@ -366,5 +528,263 @@ impl<T: Hashable> IncrementalWitness<T> {
     }
 }
 
+fn write_cursor<T: Hashable + Encodable, W: Write>(
+    writer: &mut W,
+    cursor: &Option<IncrementalMerkleTree<T>>,
+    cursor_depth: usize,
+) -> Result<(), Error> {
+    match *cursor {
+        Some(ref cursor) => {
+            writer.write_all(&[1])?;
+            cursor.write(writer)?;
+            write_u64(writer, cursor_depth as u64)?;
+        }
+        None => writer.write_all(&[0])?,
+    }
+    Ok(())
+}
+
+fn read_cursor<T: Hashable + Encodable, R: Read>(
+    reader: &mut R,
+    depth: usize,
+) -> Result<(Option<IncrementalMerkleTree<T>>, usize), Error> {
+    let mut flag = [0u8; 1];
+    reader.read_exact(&mut flag)?;
+    match flag[0] {
+        0 => Ok((None, 0)),
+        1 => {
+            let cursor = IncrementalMerkleTree::read(reader)?;
+            let cursor_depth = read_u64(reader)?;
+            if cursor.depth != depth || cursor_depth == 0 || cursor_depth >= depth as u64 {
+                return Err(Error::CorruptStore);
+            }
+            Ok((Some(cursor), cursor_depth as usize))
+        }
+        _ => Err(Error::CorruptStore),
+    }
+}
+
+//the checkpoints are written too, so a stored witness can still be rewound
+impl<T: Hashable + Encodable> IncrementalWitness<T> {
+    pub fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        self.tree.write(writer)?;
+        write_u64(writer, self.filled.len() as u64)?;
+        for hash in self.filled.iter() {
+            hash.write(writer)?;
+        }
+        write_cursor(writer, &self.cursor, self.cursor_depth)?;
+        write_u64(writer, self.checkpoints.len() as u64)?;
+        for &(filled, ref cursor, cursor_depth) in self.checkpoints.iter() {
+            write_u64(writer, filled as u64)?;
+            write_cursor(writer, cursor, cursor_depth)?;
+        }
+        Ok(())
+    }
+
+    //the witness of an empty tree is accepted, it only follows the root
+    pub fn read<R: Read>(reader: &mut R) -> Result<Self, Error> {
+        let tree = IncrementalMerkleTree::read(reader)?;
+        let depth = tree.depth;
+        let len = read_u64(reader)?;
+        if len >= depth as u64 {
+            return Err(Error::CorruptStore);
+        }
+        let mut filled = Vec::new();
+        for _ in 0..len {
+            filled.push(T::read(reader)?);
+        }
+        let (cursor, cursor_depth) = read_cursor(reader, depth)?;
+        let count = read_u64(reader)?;
+        if count > MAX_CHECKPOINTS as u64 {
+            return Err(Error::CorruptStore);
+        }
+        let mut checkpoints = VecDeque::new();
+        for _ in 0..count {
+            let filled_len = read_u64(reader)?;
+            if filled_len > len {
+                return Err(Error::CorruptStore);
+            }
+            let (cursor, cursor_depth) = read_cursor(reader, depth)?;
+            checkpoints.push_back((filled_len as usize, cursor, cursor_depth));
+        }
+        Ok(IncrementalWitness {
+            tree,
+            filled,
+            cursor,
+            cursor_depth,
+            depth,
+            checkpoints,
+        })
+    }
+
+    pub fn to_hex(&self) -> String {
+        let mut bytes = vec![];
+        self.write(&mut bytes)
+            .expect("writing to a Vec does not fail");
+        hex::encode(&bytes)
+    }
+
+    pub fn from_hex(serial: &str) -> Result<Self, Error> {
+        from_hex(serial, |reader| IncrementalWitness::read(reader))
+    }
+}
+
 #[cfg(test)]
-mod tests {}
+mod tests {
+    use super::*;
+
+    //cheap stand-in for PedersenDigest, so the tests need no generator file
+    #[derive(Clone, Copy, Debug, PartialEq)]
+    struct Node(u64);
+
+    impl Hashable for Node {
+        fn combine(left: &Node, right: &Node) -> Node {
+            Node(left.0.wrapping_mul(31).wrapping_add(right.0) ^ 0x9e37_79b9)
+        }
+
+        fn blank() -> Node {
+            Node(0)
+        }
+    }
+
+    impl Encodable for Node {
+        fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+            write_u64(writer, self.0)
+        }
+
+        fn read<R: Read>(reader: &mut R) -> Result<Node, Error> {
+            Ok(Node(read_u64(reader)?))
+        }
+    }
+
+    fn assert_same_witness(a: &IncrementalWitness<Node>, b: &IncrementalWitness<Node>) {
+        assert_eq!(a.root(), b.root());
+        assert_eq!(a.element(), b.element());
+        let (pa, pb) = (a.path(), b.path());
+        assert_eq!(pa.authentication_path, pb.authentication_path);
+        assert_eq!(pa.index, pb.index);
+    }
+
+    #[test]
+    fn tree_round_trip() {
+        let mut tree = IncrementalMerkleTree::new(8);
+        for i in 0..11 {
+            let decoded = IncrementalMerkleTree::<Node>::from_hex(&tree.to_hex()).unwrap();
+            assert_eq!(decoded.root(), tree.root());
+            assert_eq!(decoded.size(), tree.size());
+            assert_eq!(decoded.to_hex(), tree.to_hex());
+            tree.append(Node(i + 1));
+        }
+    }
+
+    #[test]
+    fn witness_round_trip() {
+        let mut tree = IncrementalMerkleTree::new(8);
+        for i in 0..5 {
+            tree.append(Node(i + 1));
+        }
+        let mut witness = tree.clone().witness();
+        for i in 5..40 {
+            let mut decoded = IncrementalWitness::<Node>::from_hex(&witness.to_hex()).unwrap();
+            assert_same_witness(&decoded, &witness);
+
+            //a restored witness keeps following the tree
+            tree.append(Node(i + 1));
+            witness.append(Node(i + 1));
+            decoded.append(Node(i + 1));
+            assert_same_witness(&decoded, &witness);
+            assert_eq!(witness.root(), tree.root());
+        }
+
+        let mut bytes = vec![];
+        witness.write(&mut bytes).unwrap();
+        let decoded = IncrementalWitness::<Node>::read(&mut &bytes[..]).unwrap();
+        assert_same_witness(&decoded, &witness);
+    }
+
+    #[test]
+    fn rewind_to_checkpoint() {
+        let mut tree = IncrementalMerkleTree::new(8);
+        tree.append(Node(1));
+        let mut witness = tree.clone().witness();
+        let mut roots = vec![];
+        let mut paths = vec![];
+        for i in 1..20 {
+            tree.checkpoint();
+            witness.checkpoint();
+            roots.push(tree.root());
+            paths.push(witness.path().authentication_path);
+            //a block of one to three coins
+            for j in 0..(i % 3 + 1) {
+                tree.append(Node(i * 10 + j));
+                witness.append(Node(i * 10 + j));
+            }
+        }
+        while let Some(root) = roots.pop() {
+            assert!(tree.rewind());
+            assert!(witness.rewind());
+            assert_eq!(tree.root(), root);
+            assert_eq!(witness.root(), root);
+            assert_eq!(witness.path().authentication_path, paths.pop().unwrap());
+        }
+        assert!(!tree.rewind());
+        assert!(!witness.rewind());
+
+        //appends after a rewind take the place of the dropped ones
+        tree.append(Node(7));
+        witness.append(Node(7));
+        assert_eq!(witness.root(), tree.root());
+    }
+
+    #[test]
+    fn forgets_old_checkpoints() {
+        let mut tree = IncrementalMerkleTree::new(8);
+        for i in 0..(MAX_CHECKPOINTS + 5) {
+            tree.checkpoint();
+            tree.append(Node(i as u64));
+        }
+        assert_eq!(tree.checkpoints(), MAX_CHECKPOINTS);
+    }
+
+    #[test]
+    fn rejects_malformed() {
+        let mut tree = IncrementalMerkleTree::new(8);
+        tree.append(Node(1));
+        let serial = tree.witness().to_hex();
+        assert!(IncrementalWitness::<Node>::from_hex(&serial[..serial.len() - 2]).is_err());
+        assert!(IncrementalWitness::<Node>::from_hex(&format!("{}00", serial)).is_err());
+        assert!(IncrementalWitness::<Node>::from_hex("zz").is_err());
+        let empty = IncrementalMerkleTree::<Node>::new(8).to_hex();
+        assert!(
+            IncrementalWitness::<Node>::from_hex(&format!("{}0000000000000000", empty)).is_err()
+        );
+    }
+
+    #[test]
+    fn empty_witness_round_trip() {
+        let witness = IncrementalMerkleTree::<Node>::new(8).witness();
+        let decoded = IncrementalWitness::<Node>::from_hex(&witness.to_hex()).unwrap();
+        assert_eq!(decoded.root(), witness.root());
+        assert_eq!(decoded.to_hex(), witness.to_hex());
+    }
+
+    #[test]
+    fn witness_keeps_checkpoints() {
+        let mut tree = IncrementalMerkleTree::new(8);
+        tree.append(Node(1));
+        let mut witness = tree.clone().witness();
+        let mut roots = vec![];
+        for i in 2..12 {
+            witness.checkpoint();
+            roots.push(witness.root());
+            witness.append(Node(i));
+        }
+        let mut decoded = IncrementalWitness::<Node>::from_hex(&witness.to_hex()).unwrap();
+        assert_eq!(decoded.checkpoints(), witness.checkpoints());
+        while let Some(root) = roots.pop() {
+            assert!(decoded.rewind());
+            assert_eq!(decoded.root(), root);
+        }
+        assert!(!decoded.rewind());
+    }
+}
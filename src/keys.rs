@@ -0,0 +1,387 @@
+//Key hierarchy: SpendingKey -> FullViewingKey -> IncomingViewingKey.
+//
+//A FullViewingKey is (addr, nk), nk = PH(addr_sk|NK_TAG). Nullifiers are
+//PH(coin|nk), so it sees spends as well as receipts. It cannot prove
+//c2p/c2b/c2c spends: the circuits derive both addr and nk from addr_sk, which
+//neither gives back.
+//
+//Notes are encrypted to enc_key = ivk*P1 with ivk = PH(nk|IVK_TAG), so an
+//IncomingViewingKey can find and open incoming notes but cannot compute
+//their nullifiers. Coins commit to enc_key and the spend circuits derive it
+//from addr_sk, so a coin its owner can spend is one its note opens.
+
+use std::fmt;
+use std::str::FromStr;
+
+use base::{address, decrypt, u64s2bits};
+use convert::*;
+use error::Error;
+use types::*;
+use {build_coin, build_nullifier, incoming_viewing_key, nullifier_key};
+
+//hex length of a point
+const POINT_HEX: usize = 128;
+
+//split s into a point and the rest
+fn split_point(s: &str, rest: usize) -> Result<(Address, &str), Error> {
+    if s.len() != POINT_HEX + rest || !s.is_char_boundary(POINT_HEX) {
+        return Err(Error::WrongLength {
+            expected: (POINT_HEX + rest) / 2,
+            found: s.len() / 2,
+        });
+    }
+    let (point, rest) = s.split_at(POINT_HEX);
+    Ok((point.parse()?, rest))
+}
+
+impl SpendingKey {
+    pub fn full_viewing_key(&self) -> Result<FullViewingKey, Error> {
+        Ok(FullViewingKey {
+            addr: self.address()?,
+            nk: str2u644(nullifier_key(self.to_string())?)?,
+        })
+    }
+
+    pub fn incoming_viewing_key(&self) -> Result<IncomingViewingKey, Error> {
+        self.full_viewing_key()?.incoming_viewing_key()
+    }
+}
+
+//(addr, nk)
+#[derive(Clone, Eq, PartialEq)]
+pub struct FullViewingKey {
+    addr: Address,
+    nk: [u64; 4],
+}
+
+impl FullViewingKey {
+    pub fn address(&self) -> Address {
+        self.addr
+    }
+
+    pub fn incoming_viewing_key(&self) -> Result<IncomingViewingKey, Error> {
+        let ivk = str2u644(incoming_viewing_key(u6442str(self.nk))?)?;
+        Ok(IncomingViewingKey {
+            addr: self.addr,
+            ivk: u64s2bits(&ivk),
+        })
+    }
+
+    //nullifier the coin is spent under, if it is one of ours
+    pub fn nullifier(&self, coin: &Coin) -> Result<Nullifier, Error> {
+        build_nullifier(u6442str(self.nk), coin.to_string())?.parse()
+    }
+}
+
+impl FromStr for FullViewingKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<FullViewingKey, Error> {
+        let (addr, nk) = split_point(s, 64)?;
+        let nk = str2u644(nk.to_string())?;
+        u6442fr(nk)?;
+        Ok(FullViewingKey { addr, nk })
+    }
+}
+
+impl fmt::Display for FullViewingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.addr, u6442str(self.nk))
+    }
+}
+
+impl fmt::Debug for FullViewingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "FullViewingKey({}, ..)", self.addr)
+    }
+}
+
+//(addr, ivk)
+#[derive(Clone, Eq, PartialEq)]
+pub struct IncomingViewingKey {
+    addr: Address,
+    ivk: Vec<bool>,
+}
+
+impl IncomingViewingKey {
+    pub fn address(&self) -> Address {
+        self.addr
+    }
+
+    //ivk*P1
+    pub fn enc_key(&self) -> Result<Address, Error> {
+        address(sk2str(self.ivk.clone())?)?.parse()
+    }
+
+    pub fn payment_address(&self) -> Result<PaymentAddress, Error> {
+        Ok(PaymentAddress {
+            addr: self.addr,
+            enc_key: self.enc_key()?,
+        })
+    }
+
+    //(va, rcm, asset, memo) of the note if it is addressed to this key. A
+    //proven note of ours always matches its coin; notes not made by a proof
+    //are checked against it
+    pub fn open(
+        &self,
+        coin: &Coin,
+        enc: &EncryptedNote,
+    ) -> Result<Option<([u64; 2], [u64; 2], u64, Vec<u8>)>, Error> {
+        let (va, rcm, asset, memo) = match decrypt(enc.to_string(), sk2str(self.ivk.clone())?) {
+            Ok(note) => note,
+            Err(Error::DecryptionFailed) => return Ok(None),
+            Err(e) => return Err(e),
+        };
+        let enc_key = self.enc_key()?.to_string();
+        if build_coin(self.addr.to_string(), enc_key, va, rcm, asset)? == coin.to_string() {
+            Ok(Some((va, rcm, asset, memo)))
+        } else {
+            Ok(None)
+        }
+    }
+}
+
+impl FromStr for IncomingViewingKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<IncomingViewingKey, Error> {
+        let (addr, ivk) = split_point(s, 64)?;
+        Ok(IncomingViewingKey {
+            addr,
+            ivk: str2sk(ivk.to_string())?,
+        })
+    }
+}
+
+impl fmt::Display for IncomingViewingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let ivk = sk2str(self.ivk.clone()).map_err(|_| fmt::Error)?;
+        write!(f, "{}{}", self.addr, ivk)
+    }
+}
+
+impl fmt::Debug for IncomingViewingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "IncomingViewingKey({}, ..)", self.addr)
+    }
+}
+
+//what a sender needs: addr goes into the coin, enc_key encrypts the note
+#[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+pub struct PaymentAddress {
+    pub addr: Address,
+    pub enc_key: Address,
+}
+
+impl FromStr for PaymentAddress {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<PaymentAddress, Error> {
+        let (addr, enc_key) = split_point(s, POINT_HEX)?;
+        Ok(PaymentAddress {
+            addr,
+            enc_key: enc_key.parse()?,
+        })
+    }
+}
+
+impl fmt::Display for PaymentAddress {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}{}", self.addr, self.enc_key)
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ScannedNote {
+    //position in the scanned list
+    pub index: usize,
+    pub coin: Coin,
+    pub va: [u64; 2],
+    pub rcm: [u64; 2],
+    pub asset: u64,
+    pub memo: Vec<u8>,
+}
+
+//watch-only detection of the notes addressed to ivk
+pub fn scan(
+    ivk: &IncomingViewingKey,
+    notes: &[(Coin, EncryptedNote)],
+) -> Result<Vec<ScannedNote>, Error> {
+    let mut found = vec![];
+    for (index, &(coin, ref enc)) in notes.iter().enumerate() {
+        if let Some((va, rcm, asset, memo)) = ivk.open(&coin, enc)? {
+            found.push(ScannedNote {
+                index,
+                coin,
+                va,
+                rcm,
+                asset,
+                memo,
+            });
+        }
+    }
+    Ok(found)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base::{c2b_param_path, encrypt};
+    use c2b::{c2b_info, c2b_verify};
+    use params::test_generators;
+    use wallet::Wallet;
+
+    fn key(n: u8) -> SpendingKey {
+        format!("{:02x}", n).repeat(32).parse().unwrap()
+    }
+
+    fn note(to: &PaymentAddress) -> (Coin, EncryptedNote) {
+        let (va, rcm, asset) = ([5, 0], [7, 9], 3);
+        let coin = build_coin(to.addr.to_string(), to.enc_key.to_string(), va, rcm, asset).unwrap();
+        let enc = encrypt(
+            va,
+            rcm,
+            asset,
+            b"memo",
+            [11, 12, 13, 1],
+            to.enc_key.to_string(),
+        )
+        .unwrap();
+        (coin.parse().unwrap(), enc.parse().unwrap())
+    }
+
+    #[test]
+    fn opens_only_for_recipient() {
+        test_generators();
+        let ivk = key(1).incoming_viewing_key().unwrap();
+        let other = key(2).incoming_viewing_key().unwrap();
+        let (coin, enc) = note(&ivk.payment_address().unwrap());
+
+        let opened = ivk.open(&coin, &enc).unwrap();
+        assert_eq!(opened, Some(([5, 0], [7, 9], 3, b"memo".to_vec())));
+        assert_eq!(other.open(&coin, &enc).unwrap(), None);
+
+        let found = scan(&other, &[(coin, enc.clone())]).unwrap();
+        assert!(found.is_empty());
+        let found = scan(&ivk, &[(coin, enc)]).unwrap();
+        assert_eq!(found.len(), 1);
+        assert_eq!(found[0].coin, coin);
+    }
+
+    #[test]
+    fn rejects_note_for_another_address() {
+        test_generators();
+        let ivk = key(1).incoming_viewing_key().unwrap();
+        //encrypted to ivk but the coin pays someone else
+        let to = PaymentAddress {
+            addr: key(2).address().unwrap(),
+            enc_key: ivk.enc_key().unwrap(),
+        };
+        let (coin, enc) = note(&to);
+        assert_eq!(ivk.open(&coin, &enc).unwrap(), None);
+    }
+
+    #[test]
+    fn viewing_key_roundtrip() {
+        test_generators();
+        let ivk = key(1).incoming_viewing_key().unwrap();
+        let parsed: IncomingViewingKey = ivk.to_string().parse().unwrap();
+        assert_eq!(parsed, ivk);
+        let to = ivk.payment_address().unwrap();
+        assert_eq!(to.to_string().parse::<PaymentAddress>().unwrap(), to);
+    }
+
+    #[test]
+    fn full_viewing_key_sees_spends() {
+        test_generators();
+        let sk = key(1);
+        let fvk = sk.full_viewing_key().unwrap();
+        let parsed: FullViewingKey = fvk.to_string().parse().unwrap();
+        assert_eq!(parsed, fvk);
+        assert_eq!(fvk.address(), sk.address().unwrap());
+        assert_eq!(
+            fvk.incoming_viewing_key().unwrap(),
+            sk.incoming_viewing_key().unwrap()
+        );
+
+        let (coin, _) = note(
+            &fvk.incoming_viewing_key()
+                .unwrap()
+                .payment_address()
+                .unwrap(),
+        );
+        let nk = nullifier_key(sk.to_string()).unwrap();
+        let nullifier = build_nullifier(nk, coin.to_string()).unwrap();
+        assert_eq!(fvk.nullifier(&coin).unwrap(), nullifier.parse().unwrap());
+        let other = key(2).full_viewing_key().unwrap();
+        assert_ne!(
+            other.nullifier(&coin).unwrap(),
+            fvk.nullifier(&coin).unwrap()
+        );
+    }
+
+    #[test]
+    fn full_viewing_key_is_not_a_spending_key() {
+        test_generators();
+        let fvk = key(1).full_viewing_key().unwrap();
+        assert!(fvk.to_string().parse::<SpendingKey>().is_err());
+        //nk in place of addr_sk neither owns the coins nor derives nk
+        let nk = u6442str(fvk.nk);
+        let addr: Address = address(nk.clone()).unwrap().parse().unwrap();
+        assert_ne!(addr, fvk.address());
+        assert_ne!(nullifier_key(nk.clone()).unwrap(), nk);
+    }
+
+    //proves with freshly generated c2b parameters, which takes minutes
+    #[test]
+    #[ignore]
+    fn full_viewing_key_cannot_prove() {
+        if !c2b_param_path().exists() {
+            ::c2b::gen_c2b_param().unwrap();
+        }
+        let sk = key(1);
+        let fvk = sk.full_viewing_key().unwrap();
+        let to = fvk
+            .incoming_viewing_key()
+            .unwrap()
+            .payment_address()
+            .unwrap();
+        let (coin, enc) = note(&to);
+        let mut wallet = Wallet::new(sk).unwrap();
+        wallet.append(coin, &enc).unwrap();
+        let path = wallet.witness_for(&wallet.notes()[0]).unwrap();
+        let auth = path
+            .authentication_path
+            .iter()
+            .map(|p| u6442str(p.0))
+            .collect();
+
+        //nk is all an fvk holder has in place of addr_sk
+        let (proof, nullifier, root) = c2b_info(
+            [7, 9],
+            [5, 0],
+            3,
+            [0, 0],
+            [0, 0],
+            to.addr.to_string(),
+            u6442str(fvk.nk),
+            auth,
+            path.index.clone(),
+        )
+        .unwrap();
+        let spent = fvk.nullifier(&coin).unwrap();
+        assert_ne!(nullifier.parse::<Nullifier>().unwrap(), spent);
+        assert_ne!(root.parse::<MerkleRoot>().unwrap(), wallet.root());
+        assert!(!c2b_verify(
+            [5, 0],
+            3,
+            [0, 0],
+            to.addr.to_string(),
+            spent.to_string(),
+            wallet.root().to_string(),
+            proof,
+        )
+        .unwrap());
+    }
+}
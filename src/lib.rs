@@ -1,78 +1,130 @@
 extern crate bellman;
+extern crate bip39;
+extern crate blake2_rfc;
+extern crate chacha20_poly1305_aead;
+extern crate hex;
 extern crate jubjub;
 extern crate pairing;
 extern crate rand;
 #[macro_use]
 extern crate lazy_static;
 
+pub mod balance;
+
 pub mod base;
 
+pub mod builder;
+
 pub mod b2c;
 
 pub mod c2b;
 
+pub mod c2c;
+
 pub mod c2p;
 
 pub mod p2c;
 
 pub mod common_verify;
 
+pub mod ceremony;
+
 pub mod contract;
 
 pub mod incrementalmerkletree;
 
+pub mod keys;
+
+pub mod params;
+
 pub mod pedersen;
 
+pub mod prover;
+
+pub mod store;
+
 pub mod convert;
 
-pub use convert::{sk2str, str2point, str2u644, str2value, u6442str};
+pub mod error;
+
+pub mod generators;
+
+pub mod header;
+
+pub mod hd;
+
+pub mod types;
+
+pub mod typed;
+
+pub mod wallet;
 
-pub fn pedersen_hash(bits: &[bool]) -> [u64; 4] {
-    assert_eq!(bits.len(), base::PHIN);
-    jubjub::pedersen_hash_real(bits, &base::ph_generator())
-        .unwrap()
-        .serial()
+pub use balance::{BalanceTracker, EncryptedBalance};
+pub use builder::TxBuilder;
+pub use convert::{
+    random_u644, sk2str, str2point, str2u644, str2value, u1282value, u6442str, value2u128,
+};
+pub use error::Error;
+pub use hd::ExtendedSpendingKey;
+pub use keys::{FullViewingKey, IncomingViewingKey, PaymentAddress};
+pub use params::ParamSet;
+pub use prover::ProverPool;
+pub use types::{
+    Address, Coin, EncryptedNote, Groth16Proof, MerkleRoot, Nullifier, SpendingKey, ValueCommitment,
+};
+pub use wallet::{Wallet, WalletNote};
+
+pub fn pedersen_hash(bits: &[bool]) -> Result<[u64; 4], Error> {
+    if bits.len() != base::PHIN {
+        return Err(Error::WrongLength {
+            expected: base::PHIN,
+            found: bits.len(),
+        });
+    }
+    Ok(jubjub::pedersen_hash_real(bits, params::default_params()?.generators())?.serial())
 }
 
-pub fn build_coin(address: String, va: [u64; 2], rcm: [u64; 2]) -> String {
-    let coin = pedersen_hash(
-        {
-            let addr = str2point(address).0;
-            let mut v = Vec::with_capacity(256);
-            for num in addr.into_iter() {
-                let mut num = *num;
-                for _ in 0..64 {
-                    v.push(num & 1 == 1);
-                    num >>= 1;
-                }
-            }
-            let addr = v;
-            let mut node = Vec::with_capacity(256);
-            for num in rcm.into_iter() {
-                let mut num = *num;
-                for _ in 0..64 {
-                    node.push(num & 1 == 1);
-                    num >>= 1;
-                }
-            }
-            for num in va.into_iter() {
-                let mut num = *num;
-                for _ in 0..64 {
-                    node.push(num & 1 == 1);
-                    num >>= 1;
-                }
-            }
-            for b in addr.iter() {
-                node.push(*b);
-            }
-            node
-        }
-        .as_slice(),
-    );
-    u6442str(coin)
+//coin = PH(PH(rcm|va|pa)|asset), pa = PH(addr.x|enc_key.y) of a PaymentAddress
+pub fn build_coin(
+    address: String,
+    enc_key: String,
+    va: [u64; 2],
+    rcm: [u64; 2],
+    asset: u64,
+) -> Result<String, Error> {
+    let mut v = base::u64s2bits(&str2point(address)?.0);
+    v.extend(base::u64s2bits(&str2point(enc_key)?.1));
+    let pa = pedersen_hash(v.as_slice())?;
+    let mut v = base::u64s2bits(&[rcm[0], rcm[1], va[0], va[1]]);
+    v.extend(base::u64s2bits(&pa));
+    let note = pedersen_hash(v.as_slice())?;
+    let mut v = base::u64s2bits(&note);
+    v.extend(base::u64s2bits(&[asset, 0, 0, 0]));
+    Ok(u6442str(pedersen_hash(v.as_slice())?))
+}
+
+//nk = PH(addr_sk|NK_TAG), the nullifier key of a FullViewingKey
+pub fn nullifier_key(addr_sk: String) -> Result<String, Error> {
+    let mut v = convert::str2sk(addr_sk)?;
+    v.extend(base::u64s2bits(&[base::NK_TAG, 0, 0, 0]));
+    Ok(u6442str(pedersen_hash(v.as_slice())?))
+}
+
+//ivk = PH(nk|IVK_TAG), notes are encrypted to ivk*P1
+pub fn incoming_viewing_key(nk: String) -> Result<String, Error> {
+    let mut v = base::u64s2bits(&str2u644(nk)?);
+    v.extend(base::u64s2bits(&[base::IVK_TAG, 0, 0, 0]));
+    Ok(u6442str(pedersen_hash(v.as_slice())?))
 }
 
-pub fn pedersen_hash_root(c0: [u64; 4], c1: [u64; 4]) -> [u64; 4] {
+//nullifier = PH(coin|nk), as computed by the c2p, c2b and c2c circuits
+pub fn build_nullifier(nk: String, coin: String) -> Result<String, Error> {
+    let mut v = base::u64s2bits(&str2u644(coin)?);
+    v.extend(base::u64s2bits(&str2u644(nk)?));
+    Ok(u6442str(pedersen_hash(v.as_slice())?))
+}
+
+pub fn pedersen_hash_root(c0: [u64; 4], c1: [u64; 4]) -> Result<[u64; 4], Error> {
     let mut v = Vec::with_capacity(512);
     for num in c0.into_iter() {
         let mut num = *num;
@@ -88,36 +140,46 @@ pub fn pedersen_hash_root(c0: [u64; 4], c1: [u64; 4]) -> [u64; 4] {
             num >>= 1;
         }
     }
-    jubjub::pedersen_hash_real(v.as_slice(), &base::ph_generator())
-        .unwrap()
-        .serial()
+    Ok(jubjub::pedersen_hash_real(v.as_slice(), params::default_params()?.generators())?.serial())
 }
 
 use b2c::gen_b2c_param;
 use base::gen_ph_generator;
 pub use base::set_param_path;
 use c2b::gen_c2b_param;
+use c2c::gen_c2c_param;
 use c2p::gen_c2p_param;
 use common_verify::range::gen_range_param;
 use p2c::gen_p2c_param;
 
-pub fn gen_params(path: &str) {
+pub fn gen_params(path: &str) -> Result<(), Error> {
     use std::fs::{create_dir, remove_dir_all};
     use std::path::Path;
 
     {
         let path = Path::new(path);
         if path.exists() {
-            remove_dir_all(path).unwrap();
+            remove_dir_all(path)?;
         }
-        create_dir(path).unwrap();
+        create_dir(path)?;
     }
 
     set_param_path(path);
-    gen_ph_generator();
-    gen_b2c_param();
-    gen_c2b_param();
-    gen_c2p_param();
-    gen_p2c_param();
-    gen_range_param();
+    gen_ph_generator()?;
+    gen_b2c_param()?;
+    gen_c2b_param()?;
+    gen_c2c_param()?;
+    gen_c2p_param()?;
+    gen_p2c_param()?;
+    gen_range_param()?;
+    Ok(())
+}
+
+//only the generators, enough for keys, coins and notes; leaves other files in path
+pub fn gen_generators(path: &str) -> Result<(), Error> {
+    use std::fs::create_dir_all;
+
+    create_dir_all(path)?;
+    set_param_path(path);
+    gen_ph_generator()
 }
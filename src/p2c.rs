@@ -1,15 +1,25 @@
 use bellman::groth16::*;
+use bellman::Error as SynthesisError;
 use bellman::*;
 use pairing::bls12_381::{Bls12, Fr, FrRepr};
 use pairing::*;
-use rand::thread_rng;
 
 use jubjub::*;
 
 use base::*;
+use common_verify::asset::asset_commitment;
+use common_verify::batch::verify_batch;
+use common_verify::fee::add_fee;
+use common_verify::fixed_base::*;
+use common_verify::note::{encrypt_note, payment_coin};
 use convert::*;
+use error::Error;
+use generators::asset_generator;
+use header::{check_params, generate_params, write_params};
+use params::{default_params, ParamSet};
 
-use std::fs::File;
+use std::path::Path;
+use std::sync::Arc;
 
 struct P2Ccircuit<'a> {
     generators: &'a [(Vec<Fr>, Vec<Fr>)],
@@ -23,12 +33,24 @@ struct P2Ccircuit<'a> {
     ba: Assignment<Fr>,
     //value
     va: Assignment<Fr>,
+    //asset
+    asset: Assignment<Fr>,
+    //value generator of the asset
+    generator: (Assignment<Fr>, Assignment<Fr>),
+    //fee
+    fee: Assignment<Fr>,
     //addr
     addr: (Assignment<Fr>, Assignment<Fr>),
+    //enc_key of the receiver
+    enc_key: (Assignment<Fr>, Assignment<Fr>),
     //random number,
     random: Assignment<Fr>,
     //addr_sk
     addr_sk: Vec<Assignment<bool>>,
+    //hash of rP and the note ciphertext
+    ct_hash: Assignment<Fr>,
+    //account nonce
+    nonce: Assignment<Fr>,
     //result
     res: &'a mut Vec<FrRepr>,
 }
@@ -46,9 +68,15 @@ impl<'a> P2Ccircuit<'a> {
             rcm: Assignment::unknown(),
             ba: Assignment::unknown(),
             va: Assignment::unknown(),
+            asset: Assignment::unknown(),
+            generator: (Assignment::unknown(), Assignment::unknown()),
+            fee: Assignment::unknown(),
             addr: (Assignment::unknown(), Assignment::unknown()),
+            enc_key: (Assignment::unknown(), Assignment::unknown()),
             random: Assignment::unknown(),
             addr_sk: (0..ADSK).map(|_| Assignment::unknown()).collect(),
+            ct_hash: Assignment::unknown(),
+            nonce: Assignment::unknown(),
             res,
         }
     }
@@ -59,9 +87,15 @@ impl<'a> P2Ccircuit<'a> {
         rcm: Fr,
         ba: Fr,
         va: Fr,
+        asset: Fr,
+        generator: (Fr, Fr),
+        fee: Fr,
         addr: (Fr, Fr),
+        enc_key: (Fr, Fr),
         random: Fr,
         addr_sk: Vec<bool>,
+        ct_hash: Fr,
+        nonce: Fr,
         res: &'a mut Vec<FrRepr>,
     ) -> P2Ccircuit<'a> {
         assert_eq!(res.len(), 0);
@@ -72,31 +106,53 @@ impl<'a> P2Ccircuit<'a> {
             rcm: Assignment::known(rcm),
             ba: Assignment::known(ba),
             va: Assignment::known(va),
+            asset: Assignment::known(asset),
+            generator: (
+                Assignment::known(generator.0),
+                Assignment::known(generator.1),
+            ),
+            fee: Assignment::known(fee),
             addr: (Assignment::known(addr.0), Assignment::known(addr.1)),
+            enc_key: (Assignment::known(enc_key.0), Assignment::known(enc_key.1)),
             random: Assignment::known(random),
             addr_sk: addr_sk.iter().map(|&b| Assignment::known(b)).collect(),
+            ct_hash: Assignment::known(ct_hash),
+            nonce: Assignment::known(nonce),
             res,
         }
     }
 }
 
 struct P2CcircuitInput {
-    //ba*P1+rh*P2
+    //ba*G+rh*P2
     hb: (Num<Bls12>, Num<Bls12>),
     //coin
     coin: Num<Bls12>,
-    //delta_balance,
+    //delta_balance, value and fee
     delt_ba: (Num<Bls12>, Num<Bls12>),
     //rP
     rp: (Num<Bls12>, Num<Bls12>),
-    //enc
-    enc: Num<Bls12>,
+    //e1 and e2, the note encrypted in the circuit
+    e: (Num<Bls12>, Num<Bls12>),
+    //hash of rP and the note ciphertext
+    ct_hash: Num<Bls12>,
     //addr
     addr: (Num<Bls12>, Num<Bls12>),
+    //account nonce
+    nonce: Num<Bls12>,
+    //asset
+    asset: Num<Bls12>,
+    //G, the value generator of the asset
+    generator: (Num<Bls12>, Num<Bls12>),
+    //fee
+    fee: Num<Bls12>,
 }
 
 impl<'a> Input<Bls12> for P2CcircuitInput {
-    fn synthesize<CS: PublicConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<(), Error> {
+    fn synthesize<CS: PublicConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<(), SynthesisError> {
         let delt_x_input = cs.alloc_input(|| Ok(*self.delt_ba.0.getvalue().get()?))?;
         let delt_y_input = cs.alloc_input(|| Ok(*self.delt_ba.1.getvalue().get()?))?;
         let hb_x_input = cs.alloc_input(|| Ok(*self.hb.0.getvalue().get()?))?;
@@ -104,9 +160,16 @@ impl<'a> Input<Bls12> for P2CcircuitInput {
         let coin_input = cs.alloc_input(|| Ok(*self.coin.getvalue().get()?))?;
         let rpx_input = cs.alloc_input(|| Ok(*self.rp.0.getvalue().get()?))?;
         let rpy_input = cs.alloc_input(|| Ok(*self.rp.1.getvalue().get()?))?;
-        let enc_input = cs.alloc_input(|| Ok(*self.enc.getvalue().get()?))?;
+        let e1_input = cs.alloc_input(|| Ok(*self.e.0.getvalue().get()?))?;
+        let e2_input = cs.alloc_input(|| Ok(*self.e.1.getvalue().get()?))?;
+        let ct_hash_input = cs.alloc_input(|| Ok(*self.ct_hash.getvalue().get()?))?;
         let addrx_input = cs.alloc_input(|| Ok(*self.addr.0.getvalue().get()?))?;
         let addry_input = cs.alloc_input(|| Ok(*self.addr.1.getvalue().get()?))?;
+        let nonce_input = cs.alloc_input(|| Ok(*self.nonce.getvalue().get()?))?;
+        let asset_input = cs.alloc_input(|| Ok(*self.asset.getvalue().get()?))?;
+        let gx_input = cs.alloc_input(|| Ok(*self.generator.0.getvalue().get()?))?;
+        let gy_input = cs.alloc_input(|| Ok(*self.generator.1.getvalue().get()?))?;
+        let fee_input = cs.alloc_input(|| Ok(*self.fee.getvalue().get()?))?;
 
         cs.enforce(
             LinearCombination::zero() + self.delt_ba.0.getvar(),
@@ -144,9 +207,19 @@ impl<'a> Input<Bls12> for P2CcircuitInput {
             LinearCombination::zero() + rpy_input,
         );
         cs.enforce(
-            LinearCombination::zero() + self.enc.getvar(),
+            LinearCombination::zero() + self.e.0.getvar(),
             LinearCombination::zero() + CS::one(),
-            LinearCombination::zero() + enc_input,
+            LinearCombination::zero() + e1_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.e.1.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + e2_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.ct_hash.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + ct_hash_input,
         );
         cs.enforce(
             LinearCombination::zero() + self.addr.0.getvar(),
@@ -158,6 +231,31 @@ impl<'a> Input<Bls12> for P2CcircuitInput {
             LinearCombination::zero() + CS::one(),
             LinearCombination::zero() + addry_input,
         );
+        cs.enforce(
+            LinearCombination::zero() + self.nonce.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + nonce_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.asset.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + asset_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.generator.0.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + gx_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.generator.1.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + gy_input,
+        );
+        cs.enforce(
+            LinearCombination::zero() + self.fee.getvar(),
+            LinearCombination::zero() + CS::one(),
+            LinearCombination::zero() + fee_input,
+        );
 
         Ok(())
     }
@@ -166,98 +264,98 @@ impl<'a> Input<Bls12> for P2CcircuitInput {
 impl<'a> Circuit<Bls12> for P2Ccircuit<'a> {
     type InputMap = P2CcircuitInput;
 
-    fn synthesize<CS: ConstraintSystem<Bls12>>(self, cs: &mut CS) -> Result<Self::InputMap, Error> {
+    fn synthesize<CS: ConstraintSystem<Bls12>>(
+        self,
+        cs: &mut CS,
+    ) -> Result<Self::InputMap, SynthesisError> {
         let rh_num = Num::new(cs, self.rh)?;
-        let rh = rh_num.unpack_sized(cs, RHBIT)?;
         let rcm_num = Num::new(cs, self.rcm)?;
-        let mut rcm = rcm_num.unpack_sized(cs, RCMBIT)?;
         let random_num = Num::new(cs, self.random)?;
-        let random = random_num.unpack_sized(cs, 256)?;
-
-        let addr_x_num = Num::new(cs, self.addr.0)?;
-        let addr_x_bit = addr_x_num.unpack_sized(cs, PHOUT)?;
-        let addr_y_num = Num::new(cs, self.addr.1)?;
+        let addr_x = Num::new(cs, self.addr.0)?;
+        let enc_key = (Num::new(cs, self.enc_key.0)?, Num::new(cs, self.enc_key.1)?);
 
-        let bit_ba = Num::new(cs, self.ba)?.unpack_sized(cs, VBIT)?;
+        let ba = Num::new(cs, self.ba)?;
+        let bit_ba = ba.unpack_sized(cs, VBIT)?;
         let va = Num::new(cs, self.va)?;
-        let bit_va = va.unpack_sized(cs, VBIT)?;
         assert_eq!(bit_ba.len(), VBIT);
-        assert_eq!(bit_va.len(), VBIT);
 
-        assert_nonless_than(&bit_ba, &bit_va, cs)?;
-
-        //prepare table
-        let p1 = Point::enc_point_table(256, 1, cs)?;
-        let p2 = Point::enc_point_table(256, 2, cs)?;
+        //the balance pays for value and fee
+        let fee = Num::new(cs, self.fee)?;
+        let total = add_fee(cs, &va, &fee)?;
+        let bit_total = total.unpack_sized(cs, VBIT)?;
+        assert_nonless_than(&bit_ba, &bit_total, cs)?;
+        let asset = Num::new(cs, self.asset)?;
+        let generator = (
+            Num::new(cs, self.generator.0)?,
+            Num::new(cs, self.generator.1)?,
+        );
 
-        //ba*P1+rh*P2
-        let hb = Point::encrypt((&p1, &p2), &bit_ba, &rh, cs)?;
+        //ba*G+rh*P2, the balance and the value share the asset
+        let hb = asset_commitment(cs, &ba, &rh_num, RHBIT, &generator, self.j)?;
         if let (Ok(x), Ok(y)) = (hb.0.getvalue().get(), hb.1.getvalue().get()) {
             self.res.push(x.into_repr());
             self.res.push(y.into_repr());
         }
 
-        //coin = PH(addr|value|rcm)
-        let rcm2 = rcm.clone();
-        let vin = {
-            for b in bit_va.iter() {
-                rcm.push(*b);
-            }
-            for b in addr_x_bit.iter() {
-                rcm.push(*b);
-            }
-            rcm
-        };
-        assert_eq!(vin.len(), PHIN);
-        let coin = pedersen_hash(cs, &vin, self.generators, self.j)?;
+        //coin = PH(PH(rcm|value|pa)|asset), pa = PH(addr.x|enc_key.y); checks va < 2^VBIT
+        let coin = payment_coin(
+            cs,
+            &rcm_num,
+            &va,
+            &addr_x,
+            &enc_key.1,
+            &asset,
+            self.generators,
+            self.j,
+        )?;
         if let Ok(x) = coin.getvalue().get() {
             self.res.push(x.into_repr());
         }
 
-        //delta_ba
-        let rcm = rcm2;
-        let p0 = Point::encrypt((&p1, &p2), &bit_va, &rcm, cs)?;
+        //delta_ba = (va+fee)*G+rcm*P2
+        let p0 = asset_commitment(cs, &total, &rcm_num, RCMBIT, &generator, self.j)?;
         if let (Ok(x), Ok(y)) = (p0.0.getvalue().get(), p0.1.getvalue().get()) {
             self.res.push(x.into_repr());
             self.res.push(y.into_repr());
         }
         let delt_ba = (p0.0, p0.1);
 
-        //Enc
-        let message = {
-            let b128 = Num::new(
-                cs,
-                Assignment::known(Fr::from_repr(FrRepr::from_serial([0, 0, 1, 0])).unwrap()),
-            )?;
-            va.mul(cs, &b128)?.add(cs, &rcm_num)
-        }?;
-        let qtable = Point::point_mul_table((&addr_x_num, &addr_y_num), 256, cs)?;
-        let rp = Point::multiply(&p1, &random, cs)?;
-        let rq = Point::multiply(&qtable, &random, cs)?;
+        //(rP, e1, e2), the opening of the coin encrypted to enc_key
+        let (rp, e1, e2) = encrypt_note(
+            cs,
+            &random_num,
+            &enc_key,
+            &va,
+            &rcm_num,
+            &asset,
+            self.generators,
+            self.j,
+        )?;
         if let (Ok(x), Ok(y)) = (rp.0.getvalue().get(), rp.1.getvalue().get()) {
             self.res.push(x.into_repr());
             self.res.push(y.into_repr());
         }
-        let key = rq.0;
-        let enc = key.add(cs, &message)?;
-        if let Ok(x) = enc.getvalue().get() {
-            self.res.push(x.into_repr());
-        }
+        //the memo is encrypted outside the circuit and bound through ct_hash
+        let ct_hash = Num::new(cs, self.ct_hash)?;
 
-        let mut addr_sk = Vec::with_capacity(ADSK);
-        for b in self.addr_sk.iter() {
-            addr_sk.push(Bit::alloc(cs, *b)?);
-        }
-        let p1 = Point::enc_point_table(ADSK, 1, cs)?;
-        let addr = Point::multiply(&p1, &addr_sk, cs)?;
+        let addr_sk = alloc_addr_sk(cs, &self.addr_sk)?;
+        let addr = address(cs, &addr_sk, ADSK, self.j)?;
+
+        //only carried to the public inputs, so the proof cannot be replayed under another nonce
+        let nonce = Num::new(cs, self.nonce)?;
 
         Ok(P2CcircuitInput {
             hb,
             coin,
             delt_ba,
             rp,
-            enc,
+            e: (e1, e2),
+            ct_hash,
             addr,
+            nonce,
+            asset,
+            generator,
+            fee,
         })
     }
 }
@@ -267,46 +365,90 @@ pub fn p2c_info(
     rcm: [u64; 2],
     ba: [u64; 2],
     va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
+    nonce: u64,
+    addr: String,
+    enc_key: String,
+    addr_sk: String,
+    enc_random: [u64; 4],
+    memo: Vec<u8>,
+) -> Result<(String, String, String, String, String), Error> {
+    p2c_info_with(
+        &*default_params()?,
+        rh,
+        rcm,
+        ba,
+        va,
+        asset,
+        fee,
+        max_fee,
+        nonce,
+        addr,
+        enc_key,
+        addr_sk,
+        enc_random,
+        memo,
+    )
+}
+
+pub fn p2c_info_with(
+    params: &ParamSet,
+    rh: [u64; 4],
+    rcm: [u64; 2],
+    ba: [u64; 2],
+    va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
+    nonce: u64,
     addr: String,
+    enc_key: String,
     addr_sk: String,
     enc_random: [u64; 4],
+    memo: Vec<u8>,
 ) -> Result<(String, String, String, String, String), Error> {
-    let addr = str2point(addr);
-    let addr_sk = str2sk(addr_sk);
-    let rng = &mut thread_rng();
+    check_fee(fee, max_fee)?;
+    let addr = point2fr(str2point(addr)?)?;
+    let addr_sk = str2sk(addr_sk)?;
+    let enc = encrypt(va, rcm, asset, &memo, enc_random, enc_key.clone())?;
+    let enc_key = point2fr(str2point(enc_key)?)?;
     let j = JubJub::new();
     //TODO:Balance&value<2^vbit
     let mut res: Vec<FrRepr> = vec![];
-    let proof = create_random_proof::<Bls12, _, _, _>(
-        P2Ccircuit::new(
-            &ph_generator(),
-            &j,
-            Fr::from_repr(FrRepr(rh)).unwrap(),
-            Fr::from_repr(FrRepr([rcm[0], rcm[1], 0, 0])).unwrap(),
-            Fr::from_repr(FrRepr([ba[0], ba[1], 0, 0])).unwrap(),
-            Fr::from_repr(FrRepr([va[0], va[1], 0, 0])).unwrap(),
-            (
-                Fr::from_repr(FrRepr(addr.0)).unwrap(),
-                Fr::from_repr(FrRepr(addr.1)).unwrap(),
+    let proof = params
+        .prove(
+            ParamKind::P2c,
+            P2Ccircuit::new(
+                params.generators(),
+                &j,
+                u6442fr(rh)?,
+                u6442fr([rcm[0], rcm[1], 0, 0])?,
+                u6442fr([ba[0], ba[1], 0, 0])?,
+                u6442fr([va[0], va[1], 0, 0])?,
+                u6442fr([asset, 0, 0, 0])?,
+                asset_generator(asset),
+                u6442fr([fee[0], fee[1], 0, 0])?,
+                addr,
+                enc_key,
+                Fr::from_serial(enc_random),
+                addr_sk,
+                ct_hash(&str2enc(enc.clone())?)?,
+                u6442fr([nonce, 0, 0, 0])?,
+                &mut res,
             ),
-            Fr::from_serial(enc_random),
-            addr_sk,
-            &mut res,
-        ),
-        p2c_param()?,
-        rng,
-    )?
-    .serial();
+        )?
+        .serial();
     let hb = (res[0].serial(), res[1].serial());
     let coin = res[2].serial();
     let delt_ba = (res[3].serial(), res[4].serial());
-    let enc = (res[5].serial(), res[6].serial(), res[7].serial());
     Ok((
         proof2str(proof),
         point2str(hb),
         u6442str(coin),
         point2str(delt_ba),
-        enc2str(enc),
+        enc,
     ))
 }
 
@@ -316,75 +458,163 @@ pub fn p2c_verify(
     delt_ba: String,
     enc: String,
     address: String,
+    asset: u64,
+    fee: [u64; 2],
+    nonce: u64,
     proof: String,
 ) -> Result<bool, Error> {
-    let hb = str2point(hb);
-    let coin = str2u644(coin);
-    let delt_ba = str2point(delt_ba);
-    let enc = str2enc(enc);
-    let address = str2point(address);
-    let proof = str2proof(proof);
-    verify_proof(&p2c_vk()?, &Proof::from_serial(proof), |cs| {
-        let delt_x = Fr::from_repr(FrRepr::from_serial(delt_ba.0)).unwrap();
-        let delt_y = Fr::from_repr(FrRepr::from_serial(delt_ba.1)).unwrap();
-        let hb_x = Fr::from_repr(FrRepr::from_serial(hb.0)).unwrap();
-        let hb_y = Fr::from_repr(FrRepr::from_serial(hb.1)).unwrap();
-        let coin = Fr::from_repr(FrRepr::from_serial(coin)).unwrap();
-        let rpx = Fr::from_repr(FrRepr::from_serial(enc.0)).unwrap();
-        let rpy = Fr::from_repr(FrRepr::from_serial(enc.1)).unwrap();
-        let enc = Fr::from_repr(FrRepr::from_serial(enc.2)).unwrap();
-        let addrx = Fr::from_repr(FrRepr::from_serial(address.0)).unwrap();
-        let addry = Fr::from_repr(FrRepr::from_serial(address.1)).unwrap();
-        Ok(P2CcircuitInput {
-            hb: (
-                Num::new(cs, Assignment::known(hb_x))?,
-                Num::new(cs, Assignment::known(hb_y))?,
-            ),
-            coin: Num::new(cs, Assignment::known(coin))?,
-            delt_ba: (
-                Num::new(cs, Assignment::known(delt_x))?,
-                Num::new(cs, Assignment::known(delt_y))?,
-            ),
-            rp: (
-                Num::new(cs, Assignment::known(rpx))?,
-                Num::new(cs, Assignment::known(rpy))?,
-            ),
-            enc: Num::new(cs, Assignment::known(enc))?,
-            addr: (
-                Num::new(cs, Assignment::known(addrx))?,
-                Num::new(cs, Assignment::known(addry))?,
-            ),
-        })
-    })
+    p2c_verify_with(
+        &*default_params()?,
+        hb,
+        coin,
+        delt_ba,
+        enc,
+        address,
+        asset,
+        fee,
+        nonce,
+        proof,
+    )
+}
+
+pub fn p2c_verify_with(
+    params: &ParamSet,
+    hb: String,
+    coin: String,
+    delt_ba: String,
+    enc: String,
+    address: String,
+    asset: u64,
+    fee: [u64; 2],
+    nonce: u64,
+    proof: String,
+) -> Result<bool, Error> {
+    let (hb_x, hb_y) = point2fr(str2point(hb)?)?;
+    let coin = u6442fr(str2u644(coin)?)?;
+    let (delt_x, delt_y) = point2fr(str2point(delt_ba)?)?;
+    let ((rpx, rpy), e1, e2, ct_hash) = note_inputs(&str2enc(enc)?)?;
+    let (addrx, addry) = point2fr(str2point(address)?)?;
+    let nonce = u6442fr([nonce, 0, 0, 0])?;
+    let (gx, gy) = asset_generator(asset);
+    let asset = u6442fr([asset, 0, 0, 0])?;
+    let fee = u6442fr([fee[0], fee[1], 0, 0])?;
+    let proof = str2proof(proof)?;
+    let ok = verify_proof(
+        &*params.vk(ParamKind::P2c)?,
+        &Proof::from_serial(proof),
+        |cs| {
+            Ok(P2CcircuitInput {
+                hb: (
+                    Num::new(cs, Assignment::known(hb_x))?,
+                    Num::new(cs, Assignment::known(hb_y))?,
+                ),
+                coin: Num::new(cs, Assignment::known(coin))?,
+                delt_ba: (
+                    Num::new(cs, Assignment::known(delt_x))?,
+                    Num::new(cs, Assignment::known(delt_y))?,
+                ),
+                rp: (
+                    Num::new(cs, Assignment::known(rpx))?,
+                    Num::new(cs, Assignment::known(rpy))?,
+                ),
+                e: (
+                    Num::new(cs, Assignment::known(e1))?,
+                    Num::new(cs, Assignment::known(e2))?,
+                ),
+                ct_hash: Num::new(cs, Assignment::known(ct_hash))?,
+                addr: (
+                    Num::new(cs, Assignment::known(addrx))?,
+                    Num::new(cs, Assignment::known(addry))?,
+                ),
+                nonce: Num::new(cs, Assignment::known(nonce))?,
+                asset: Num::new(cs, Assignment::known(asset))?,
+                generator: (
+                    Num::new(cs, Assignment::known(gx))?,
+                    Num::new(cs, Assignment::known(gy))?,
+                ),
+                fee: Num::new(cs, Assignment::known(fee))?,
+            })
+        },
+    )?;
+    Ok(ok)
+}
+
+//public inputs in the order P2CcircuitInput allocates them
+fn p2c_public_inputs(
+    hb: String,
+    coin: String,
+    delt_ba: String,
+    enc: String,
+    address: String,
+    asset: u64,
+    fee: [u64; 2],
+    nonce: u64,
+) -> Result<Vec<Fr>, Error> {
+    let (hb_x, hb_y) = point2fr(str2point(hb)?)?;
+    let coin = u6442fr(str2u644(coin)?)?;
+    let (delt_x, delt_y) = point2fr(str2point(delt_ba)?)?;
+    let ((rpx, rpy), e1, e2, ct_hash) = note_inputs(&str2enc(enc)?)?;
+    let (addrx, addry) = point2fr(str2point(address)?)?;
+    let nonce = u6442fr([nonce, 0, 0, 0])?;
+    let (gx, gy) = asset_generator(asset);
+    let asset = u6442fr([asset, 0, 0, 0])?;
+    let fee = u6442fr([fee[0], fee[1], 0, 0])?;
+    Ok(vec![
+        delt_x, delt_y, hb_x, hb_y, coin, rpx, rpy, e1, e2, ct_hash, addrx, addry, nonce, asset,
+        gx, gy, fee,
+    ])
 }
 
-pub(crate) fn gen_p2c_param() {
+//(hb, coin, delt_ba, enc, address, asset, fee, nonce, proof) as taken by p2c_verify
+pub type P2cVerifyArgs = (
+    String,
+    String,
+    String,
+    String,
+    String,
+    u64,
+    [u64; 2],
+    u64,
+    String,
+);
+
+pub fn p2c_verify_batch(proofs: Vec<P2cVerifyArgs>) -> Result<Option<usize>, Error> {
+    p2c_verify_batch_with(&*default_params()?, proofs)
+}
+
+//index of the first proof that does not verify, None if all do
+pub fn p2c_verify_batch_with(
+    params: &ParamSet,
+    proofs: Vec<P2cVerifyArgs>,
+) -> Result<Option<usize>, Error> {
+    let mut batch = Vec::with_capacity(proofs.len());
+    for (hb, coin, delt_ba, enc, address, asset, fee, nonce, proof) in proofs.into_iter() {
+        batch.push((
+            Proof::from_serial(str2proof(proof)?),
+            p2c_public_inputs(hb, coin, delt_ba, enc, address, asset, fee, nonce)?,
+        ));
+    }
+    verify_batch(&*params.verifying_key(ParamKind::P2c)?, &batch)
+}
+
+pub(crate) fn gen_p2c_param() -> Result<(), Error> {
     let p2c_param_path = p2c_param_path();
-    let p2c_param_path = p2c_param_path.to_str().unwrap();
 
-    let rng = &mut thread_rng();
-    let params = generate_random_parameters::<Bls12, _, _>(
-        P2Ccircuit::blank(&ph_generator(), &JubJub::new(), &mut vec![]),
-        rng,
-    )
-    .unwrap();
-    params
-        .write(&mut File::create(p2c_param_path).unwrap())
-        .unwrap();
+    let (params, constraints) = generate_params(P2Ccircuit::blank(
+        &ph_generator()?,
+        &JubJub::new(),
+        &mut vec![],
+    ))?;
+    write_params(ParamKind::P2c, &params, constraints, &p2c_param_path)
 }
 
 pub fn p2c_param() -> Result<ProverStream, Error> {
-    let p2c_param_path = p2c_param_path();
-    let p2c_param_path = p2c_param_path.to_str().unwrap();
-    let params = ProverStream::new(p2c_param_path).unwrap();
+    let p2c_param_path = existing_param_path(p2c_param_path())?;
+    check_params(ParamKind::P2c, Path::new(&p2c_param_path))?;
+    let params = ProverStream::new(&p2c_param_path)?;
     Ok(params)
 }
 
-pub fn p2c_vk() -> Result<(PreparedVerifyingKey<Bls12>), Error> {
-    let p2c_param_path = p2c_param_path();
-    let p2c_param_path = p2c_param_path.to_str().unwrap();
-    let mut params = ProverStream::new(p2c_param_path)?;
-    let vk2 = params.get_vk(11)?;
-    let vk = prepare_verifying_key(&vk2);
-    Ok(vk)
+pub fn p2c_vk() -> Result<Arc<PreparedVerifyingKey<Bls12>>, Error> {
+    default_params()?.vk(ParamKind::P2c)
 }
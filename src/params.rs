@@ -0,0 +1,348 @@
+//A parameter set: the Pedersen generators plus the proving parameters of
+//each circuit, with their prepared verifying keys built once and shared by
+//every prover and verifier using the set.
+
+use bellman::groth16::*;
+use bellman::Circuit;
+use pairing::bls12_381::{Bls12, Fr};
+use rand::thread_rng;
+
+use std::collections::HashMap;
+use std::fs::{metadata, File};
+use std::io::BufReader;
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+use base::*;
+use error::Error;
+use header::{check_params, header_path, FileHeader};
+
+enum Source {
+    //provers stream the parameters from disk, so only the file is reopened per proof
+    Dir(PathBuf),
+    Loaded(HashMap<ParamKind, Parameters<Bls12>>),
+}
+
+type Keys = (Arc<VerifyingKey<Bls12>>, Arc<PreparedVerifyingKey<Bls12>>);
+
+//length and modification times of a parameter file and of its header
+type Fingerprint = (u64, SystemTime, SystemTime);
+
+fn fingerprint(path: &Path) -> Result<Fingerprint, Error> {
+    let file = metadata(path)?;
+    let header = metadata(header_path(path))?;
+    Ok((file.len(), file.modified()?, header.modified()?))
+}
+
+pub struct ParamSet {
+    source: Source,
+    generators: Vec<(Vec<Fr>, Vec<Fr>)>,
+    //prepared on first use, the files of unused circuits may be absent
+    vks: Mutex<HashMap<ParamKind, Keys>>,
+    //files of a Dir source as they were when last checked against their header
+    checked: Mutex<HashMap<ParamKind, Fingerprint>>,
+}
+
+impl ParamSet {
+    //directory laid out by gen_params
+    pub fn from_dir<P: AsRef<Path>>(dir: P) -> Result<ParamSet, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        let generator_path = dir.join(GENERATORS);
+        existing_param_path(generator_path.clone())?;
+        let generators = read_ph_generator(
+            &mut BufReader::new(File::open(&generator_path)?),
+            &generator_path,
+        )?;
+        Ok(ParamSet {
+            source: Source::Dir(dir),
+            generators,
+            vks: Mutex::new(HashMap::new()),
+            checked: Mutex::new(HashMap::new()),
+        })
+    }
+
+    //contents of the generators file, and of the parameter files of the circuits
+    //needed each with the contents of its .header file
+    pub fn from_bytes(
+        generators: &[u8],
+        params: &[(ParamKind, &[u8], &[u8])],
+    ) -> Result<ParamSet, Error> {
+        let generators = read_ph_generator(&mut &generators[..], Path::new(GENERATORS))?;
+        let mut loaded = HashMap::new();
+        for &(kind, bytes, header) in params.iter() {
+            let path = PathBuf::from(kind.file_name());
+            let header = FileHeader::read(&mut &header[..], &header_path(&path))?;
+            header.expect_circuit(Some(kind), &path)?;
+            header.check(&mut &bytes[..], &path)?;
+            loaded.insert(kind, Parameters::<Bls12>::read(&mut &bytes[..], true)?);
+        }
+        Ok(ParamSet {
+            source: Source::Loaded(loaded),
+            generators,
+            vks: Mutex::new(HashMap::new()),
+            checked: Mutex::new(HashMap::new()),
+        })
+    }
+
+    pub fn dir(&self) -> Option<&Path> {
+        match self.source {
+            Source::Dir(ref dir) => Some(dir),
+            Source::Loaded(_) => None,
+        }
+    }
+
+    pub fn generators(&self) -> &[(Vec<Fr>, Vec<Fr>)] {
+        &self.generators
+    }
+
+    fn loaded<'a>(
+        params: &'a HashMap<ParamKind, Parameters<Bls12>>,
+        kind: ParamKind,
+    ) -> Result<&'a Parameters<Bls12>, Error> {
+        params
+            .get(&kind)
+            .ok_or_else(|| Error::MissingParamFile(PathBuf::from(kind.file_name())))
+    }
+
+    pub(crate) fn prove<C: Circuit<Bls12>>(
+        &self,
+        kind: ParamKind,
+        circuit: C,
+    ) -> Result<Proof<Bls12>, Error> {
+        let rng = &mut thread_rng();
+        let proof = match self.source {
+            Source::Dir(ref dir) => {
+                let path = self.checked_path(dir, kind)?;
+                create_random_proof::<Bls12, _, _, _>(circuit, ProverStream::new(&path)?, rng)?
+            }
+            Source::Loaded(ref params) => create_random_proof::<Bls12, _, _, _>(
+                circuit,
+                ParamSet::loaded(params, kind)?,
+                rng,
+            )?,
+        };
+        Ok(proof)
+    }
+
+    //path of the parameter file of kind in dir, checked against its header
+    //unless neither changed since the last check; the keys of a changed file
+    //are prepared again
+    fn checked_path(&self, dir: &Path, kind: ParamKind) -> Result<String, Error> {
+        let path = existing_param_path(dir.join(kind.file_name()))?;
+        let now = fingerprint(Path::new(&path))?;
+        if self.checked.lock().unwrap().get(&kind) == Some(&now) {
+            return Ok(path);
+        }
+        self.checked.lock().unwrap().remove(&kind);
+        self.vks.lock().unwrap().remove(&kind);
+        check_params(kind, Path::new(&path))?;
+        self.checked.lock().unwrap().insert(kind, now);
+        Ok(path)
+    }
+
+    //raw and prepared verifying key of the circuit
+    fn keys(&self, kind: ParamKind) -> Result<Keys, Error> {
+        if let Source::Dir(ref dir) = self.source {
+            self.checked_path(dir, kind)?;
+        }
+        if let Some(keys) = self.vks.lock().unwrap().get(&kind) {
+            return Ok(keys.clone());
+        }
+        //not holding the lock while preparing; a racing thread at worst prepares it twice
+        let vk = match self.source {
+            Source::Dir(ref dir) => {
+                let path = existing_param_path(dir.join(kind.file_name()))?;
+                ProverStream::new(&path)?.get_vk(kind.inputs())?
+            }
+            Source::Loaded(ref params) => ParamSet::loaded(params, kind)?.vk.clone(),
+        };
+        let pvk = Arc::new(prepare_verifying_key(&vk));
+        let keys = (Arc::new(vk), pvk);
+        self.vks.lock().unwrap().insert(kind, keys.clone());
+        Ok(keys)
+    }
+
+    pub fn vk(&self, kind: ParamKind) -> Result<Arc<PreparedVerifyingKey<Bls12>>, Error> {
+        Ok(self.keys(kind)?.1)
+    }
+
+    pub fn verifying_key(&self, kind: ParamKind) -> Result<Arc<VerifyingKey<Bls12>>, Error> {
+        Ok(self.keys(kind)?.0)
+    }
+}
+
+lazy_static! {
+    static ref DEFAULT: Mutex<Option<Arc<ParamSet>>> = Mutex::new(None);
+}
+
+//set of the directory given to set_param_path, for the functions without a ParamSet argument
+pub fn default_params() -> Result<Arc<ParamSet>, Error> {
+    let dir = PathBuf::from(PARAMPATH.lock().unwrap().clone());
+    let mut default = DEFAULT.lock().unwrap();
+    if let Some(ref params) = *default {
+        if params.dir() == Some(dir.as_path()) {
+            return Ok(params.clone());
+        }
+    }
+    let params = Arc::new(ParamSet::from_dir(dir)?);
+    *default = Some(params.clone());
+    Ok(params)
+}
+
+pub(crate) fn clear_default_params() {
+    *DEFAULT.lock().unwrap() = None;
+}
+
+//one file of a parameter directory as found by inspect
+#[derive(Debug)]
+pub struct ParamFileInfo {
+    //"generators" or the lowercase ParamKind
+    pub name: String,
+    pub path: PathBuf,
+    //None if the file is absent
+    pub bytes: Option<u64>,
+    pub header: Option<FileHeader>,
+    //why the file fails its header check, None if it passes
+    pub error: Option<String>,
+}
+
+fn check_file(path: &Path, kind: Option<ParamKind>) -> Result<(), Error> {
+    existing_param_path(path.to_path_buf())?;
+    match kind {
+        None => read_ph_generator(&mut BufReader::new(File::open(path)?), path).map(|_| ()),
+        Some(kind) => check_params(kind, path).map(|_| ()),
+    }
+}
+
+fn inspect_file(name: String, path: PathBuf, kind: Option<ParamKind>) -> ParamFileInfo {
+    let bytes = metadata(&path).ok().map(|m| m.len());
+    //a header that reads but fails its check is still shown
+    let header_file = match kind {
+        None => path.clone(),
+        Some(_) => header_path(&path),
+    };
+    let header = File::open(&header_file)
+        .ok()
+        .and_then(|mut file| FileHeader::read(&mut file, &header_file).ok());
+    let error = check_file(&path, kind).err().map(|e| e.to_string());
+    ParamFileInfo {
+        name,
+        path,
+        bytes,
+        header,
+        error,
+    }
+}
+
+//the generators and every parameter file of dir, each checked against its header
+pub fn inspect<P: AsRef<Path>>(dir: P) -> Vec<ParamFileInfo> {
+    let dir = dir.as_ref();
+    let mut files = vec![inspect_file(
+        GENERATORS.to_string(),
+        dir.join(GENERATORS),
+        None,
+    )];
+    for &kind in PARAM_KINDS.iter() {
+        files.push(inspect_file(
+            format!("{:?}", kind).to_lowercase(),
+            dir.join(kind.file_name()),
+            Some(kind),
+        ));
+    }
+    files
+}
+
+//a parameter directory holding only the generators, enough for the merkle tree
+#[cfg(test)]
+pub(crate) fn test_generators() {
+    use std::env;
+    use std::fs::create_dir_all;
+    use std::process;
+    use std::sync::{Once, ONCE_INIT};
+
+    static INIT: Once = ONCE_INIT;
+    INIT.call_once(|| {
+        let dir = env::temp_dir().join(format!("zktx-test-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+        set_param_path(dir.to_str().unwrap());
+        gen_ph_generator().unwrap();
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use header::HEADER_BYTES;
+    use std::io::Read;
+
+    fn generators() -> Vec<u8> {
+        test_generators();
+        let mut bytes = vec![];
+        File::open(generator_path())
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        bytes
+    }
+
+    fn header(kind: ParamKind, contents: &[u8]) -> Vec<u8> {
+        let mut bytes = vec![];
+        FileHeader::new(Some(kind), 1 << 10, contents)
+            .write(&mut bytes)
+            .unwrap();
+        bytes
+    }
+
+    fn rejects(params: &[(ParamKind, &[u8], &[u8])]) {
+        match ParamSet::from_bytes(&generators(), params) {
+            Err(Error::BadParamFile(..)) => {}
+            Err(e) => panic!("{}", e),
+            Ok(_) => panic!("accepted"),
+        }
+    }
+
+    #[test]
+    fn from_bytes_checks_headers() {
+        assert!(ParamSet::from_bytes(&generators(), &[]).is_ok());
+        let contents = b"parameters";
+        //another file's header, another circuit's, a truncated one
+        rejects(&[(ParamKind::C2c, contents, &header(ParamKind::C2c, b"other"))]);
+        rejects(&[(ParamKind::C2c, contents, &header(ParamKind::P2c, contents))]);
+        rejects(&[(
+            ParamKind::C2c,
+            contents,
+            &header(ParamKind::C2c, contents)[..HEADER_BYTES - 1],
+        )]);
+    }
+
+    #[test]
+    fn rechecks_changed_files() {
+        use std::env;
+        use std::fs::{create_dir_all, write};
+        use std::process;
+
+        let dir = env::temp_dir().join(format!("zktx-recheck-{}", process::id()));
+        create_dir_all(&dir).unwrap();
+        write(dir.join(GENERATORS), generators()).unwrap();
+        let params = ParamSet::from_dir(&dir).unwrap();
+        let kind = ParamKind::Range;
+        let path = dir.join(kind.file_name());
+
+        write(&path, b"parameters").unwrap();
+        write(header_path(&path), header(kind, b"parameters")).unwrap();
+        assert!(params.checked_path(&dir, kind).is_ok());
+        assert!(params.checked.lock().unwrap().contains_key(&kind));
+
+        //the file replaced after its first use, its header left behind
+        write(&path, b"other parameters").unwrap();
+        match params.checked_path(&dir, kind) {
+            Err(Error::BadParamFile(..)) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(!params.checked.lock().unwrap().contains_key(&kind));
+
+        write(header_path(&path), header(kind, b"other parameters")).unwrap();
+        assert!(params.checked_path(&dir, kind).is_ok());
+    }
+}
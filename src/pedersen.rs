@@ -1,10 +1,16 @@
 use super::pedersen_hash_root;
-use incrementalmerkletree::Hashable;
+use error::Error;
+use incrementalmerkletree::{Encodable, Hashable};
 use rand::{Rng, SeedableRng, StdRng};
+use std::io::{Read, Write};
+use store::{read_u64, write_u64};
 
 impl Hashable for PedersenDigest {
     fn combine(left: &Self, right: &Self) -> PedersenDigest {
-        PedersenDigest(pedersen_hash_root(left.0.clone(), right.0.clone()))
+        PedersenDigest(
+            pedersen_hash_root(left.0.clone(), right.0.clone())
+                .expect("generators are required to hash tree nodes"),
+        )
     }
 
     fn blank() -> PedersenDigest {
@@ -12,6 +18,23 @@ impl Hashable for PedersenDigest {
     }
 }
 
+impl Encodable for PedersenDigest {
+    fn write<W: Write>(&self, writer: &mut W) -> Result<(), Error> {
+        for num in self.0.iter() {
+            write_u64(writer, *num)?;
+        }
+        Ok(())
+    }
+
+    fn read<R: Read>(reader: &mut R) -> Result<PedersenDigest, Error> {
+        let mut digest = [0u64; 4];
+        for num in digest.iter_mut() {
+            *num = read_u64(reader)?;
+        }
+        Ok(PedersenDigest(digest))
+    }
+}
+
 #[derive(Copy, Clone, Eq, PartialEq, Debug)]
 pub struct PedersenDigest(pub [u64; 4]);
 
@@ -0,0 +1,201 @@
+//Worker pool proving independent transactions concurrently from one ParamSet.
+//
+//Each job runs a whole `*_info_with` call on one worker. Inside a proof the
+//multi-exponentiations and FFTs are spread by bellman over its own cpu pool,
+//so `threads` bounds the proofs in flight, not the cores each one uses.
+//The bellman version in use sizes that pool itself and has no setting for
+//it, so with several workers the proofs compete for the same cores; pick
+//`threads` small when proofs are large.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::sync::{Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use error::Error;
+use params::ParamSet;
+
+type Job = Box<dyn FnOnce(&ParamSet) + Send>;
+
+pub struct ProverPool {
+    params: Arc<ParamSet>,
+    jobs: Option<Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ProverPool {
+    //threads of 0 means one per available core
+    pub fn new(params: Arc<ParamSet>, threads: usize) -> Result<ProverPool, Error> {
+        let threads = if threads == 0 {
+            thread::available_parallelism()?.get()
+        } else {
+            threads
+        };
+        let (jobs, queue) = channel::<Job>();
+        let queue = Arc::new(Mutex::new(queue));
+        let mut workers = Vec::with_capacity(threads);
+        for i in 0..threads {
+            let queue = queue.clone();
+            let params = params.clone();
+            workers.push(
+                thread::Builder::new()
+                    .name(format!("zktx-prover-{}", i))
+                    .spawn(move || loop {
+                        let job = match queue.lock().unwrap().recv() {
+                            Ok(job) => job,
+                            Err(_) => return,
+                        };
+                        //a panicking job drops its result sender, the worker carries on
+                        let _ = panic::catch_unwind(AssertUnwindSafe(|| job(&params)));
+                    })?,
+            );
+        }
+        Ok(ProverPool {
+            params,
+            jobs: Some(jobs),
+            workers,
+        })
+    }
+
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    pub fn params(&self) -> &Arc<ParamSet> {
+        &self.params
+    }
+
+    //queues job, e.g. move |params| p2c_info_with(params, ..)
+    pub fn spawn<T, F>(&self, job: F) -> Pending<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&ParamSet) -> Result<T, Error> + Send + 'static,
+    {
+        let (result, pending) = channel();
+        let job: Job = Box::new(move |params: &ParamSet| {
+            let _ = result.send(job(params));
+        });
+        //workers only stop once jobs is dropped
+        self.jobs.as_ref().unwrap().send(job).unwrap();
+        Pending(pending)
+    }
+}
+
+impl Drop for ProverPool {
+    //finishes the queued jobs
+    fn drop(&mut self) {
+        self.jobs.take();
+        for worker in self.workers.drain(..) {
+            let _ = worker.join();
+        }
+    }
+}
+
+//result of a job queued on a ProverPool
+pub struct Pending<T>(Receiver<Result<T, Error>>);
+
+impl<T> Pending<T> {
+    pub fn wait(self) -> Result<T, Error> {
+        match self.0.recv() {
+            Ok(res) => res,
+            Err(_) => Err(Error::ProverPanicked),
+        }
+    }
+
+    //None while the job is queued or running
+    pub fn try_wait(&self) -> Option<Result<T, Error>> {
+        match self.0.try_recv() {
+            Ok(res) => Some(res),
+            Err(TryRecvError::Empty) => None,
+            Err(TryRecvError::Disconnected) => Some(Err(Error::ProverPanicked)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base::generator_path;
+    use params::test_generators;
+
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::Duration;
+
+    //a set with the generators only, enough for jobs not proving
+    fn pool(threads: usize) -> ProverPool {
+        test_generators();
+        let params = ParamSet::from_dir(generator_path().parent().unwrap()).unwrap();
+        ProverPool::new(Arc::new(params), threads).unwrap()
+    }
+
+    #[test]
+    fn returns_results() {
+        let pool = pool(3);
+        assert_eq!(pool.threads(), 3);
+        let generators = pool.params().generators().len();
+        let pending: Vec<_> = (0..10)
+            .map(|i| pool.spawn(move |params| Ok(params.generators().len() + i)))
+            .collect();
+        for (i, p) in pending.into_iter().enumerate() {
+            assert_eq!(p.wait().unwrap(), generators + i);
+        }
+
+        //errors come back as they are
+        match pool
+            .spawn(|_| -> Result<(), Error> { Err(Error::ProofInvalid) })
+            .wait()
+        {
+            Err(Error::ProofInvalid) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn panicking_job() {
+        let pool = pool(1);
+        let bad = pool.spawn(|_| -> Result<(), Error> { panic!("job panics") });
+        match bad.wait() {
+            Err(Error::ProverPanicked) => {}
+            other => panic!("{:?}", other),
+        }
+
+        //the worker carries on
+        let good = pool.spawn(|_| Ok(7));
+        assert_eq!(good.wait().unwrap(), 7);
+    }
+
+    #[test]
+    fn try_wait() {
+        let pool = pool(1);
+        let (go, wait) = channel::<()>();
+        let pending = pool.spawn(move |_| {
+            wait.recv().unwrap();
+            Ok(1)
+        });
+        assert!(pending.try_wait().is_none());
+        go.send(()).unwrap();
+        assert_eq!(pending.wait().unwrap(), 1);
+    }
+
+    #[test]
+    fn drop_finishes_queued_jobs() {
+        let done = Arc::new(AtomicUsize::new(0));
+        let pending: Vec<_> = {
+            let pool = pool(2);
+            (0..8)
+                .map(|_| {
+                    let done = done.clone();
+                    pool.spawn(move |_| {
+                        thread::sleep(Duration::from_millis(20));
+                        done.fetch_add(1, Ordering::SeqCst);
+                        Ok(())
+                    })
+                })
+                .collect()
+        };
+        assert_eq!(done.load(Ordering::SeqCst), 8);
+        for p in pending.into_iter() {
+            assert!(p.wait().is_ok());
+        }
+    }
+}
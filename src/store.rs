@@ -0,0 +1,599 @@
+use blake2_rfc::blake2b::blake2b;
+
+use std::fs::{create_dir_all, rename, File, OpenOptions};
+use std::io::prelude::*;
+use std::io::{Cursor, SeekFrom};
+use std::path::{Path, PathBuf};
+
+use convert::{u64to8, u8sto64};
+use error::Error;
+use pedersen::PedersenDigest;
+
+//a single change of PrivacyContract state
+#[derive(Clone, Debug, PartialEq)]
+pub enum StateOp {
+    //(address, asset, balance)
+    SetBalance(String, u64, String),
+    SetNonce(String, u64),
+    SetTransparent(String, u64, u128),
+    //address credited with the fees
+    SetFeeCollector(String),
+    AddNullifier(String),
+    //the coin is also appended to the tree
+    AddCoin(String),
+    //start a new checkpoint
+    Checkpoint,
+    //undo everything since the last checkpoint
+    Rewind,
+}
+
+//full PrivacyContract state, the tree being stored through its export hooks
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct ContractSnapshot {
+    pub balances: Vec<(String, u64, String)>,
+    pub nonces: Vec<(String, u64)>,
+    pub transparent: Vec<(String, u64, u128)>,
+    pub fee_collector: Option<String>,
+    pub coins: Vec<String>,
+    pub nullifiers: Vec<String>,
+    pub tree_left: Option<PedersenDigest>,
+    pub tree_right: Option<PedersenDigest>,
+    pub tree_parents: Vec<Option<PedersenDigest>>,
+    //recent roots, oldest first
+    pub roots: Vec<PedersenDigest>,
+}
+
+pub trait StateStore {
+    //durably record one batch of operations; a batch is replayed entirely or not at all
+    fn append(&mut self, ops: &[StateOp]) -> Result<(), Error>;
+
+    //replace everything recorded so far with a snapshot
+    fn snapshot(&mut self, snapshot: &ContractSnapshot) -> Result<(), Error>;
+
+    //the last snapshot and the batches recorded after it
+    fn load(&mut self) -> Result<(Option<ContractSnapshot>, Vec<Vec<StateOp>>), Error>;
+}
+
+#[derive(Clone, Default)]
+pub struct MemoryStore {
+    snapshot: Option<ContractSnapshot>,
+    log: Vec<Vec<StateOp>>,
+}
+
+impl MemoryStore {
+    pub fn new() -> Self {
+        MemoryStore::default()
+    }
+}
+
+impl StateStore for MemoryStore {
+    fn append(&mut self, ops: &[StateOp]) -> Result<(), Error> {
+        self.log.push(ops.to_vec());
+        Ok(())
+    }
+
+    fn snapshot(&mut self, snapshot: &ContractSnapshot) -> Result<(), Error> {
+        self.snapshot = Some(snapshot.clone());
+        self.log.clear();
+        Ok(())
+    }
+
+    fn load(&mut self) -> Result<(Option<ContractSnapshot>, Vec<Vec<StateOp>>), Error> {
+        Ok((self.snapshot.clone(), self.log.clone()))
+    }
+}
+
+//Append-only log of batches plus an occasional snapshot, both in one directory.
+//
+//log record:      len(u64) | seq(u64) | ops | blake2b(seq | ops)
+//snapshot file:   seq(u64) | snapshot | blake2b(seq | snapshot)
+//
+//A torn record at the end of the log is dropped on load, a damaged record
+//anywhere else fails the load with CorruptStore. The snapshot is
+//written to a temporary file and renamed into place; records whose seq is
+//below the snapshot's are already contained in it and skipped.
+pub struct FileStore {
+    dir: PathBuf,
+    log: File,
+    next_seq: u64,
+}
+
+const CHECKSUM: usize = 32;
+
+impl FileStore {
+    pub fn open<P: AsRef<Path>>(dir: P) -> Result<FileStore, Error> {
+        let dir = dir.as_ref().to_path_buf();
+        create_dir_all(&dir)?;
+        let log = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(dir.join("log"))?;
+        let mut store = FileStore {
+            dir,
+            log,
+            next_seq: 0,
+        };
+        //recover next_seq and drop a torn tail
+        store.load()?;
+        Ok(store)
+    }
+
+    fn snapshot_path(&self) -> PathBuf {
+        self.dir.join("snapshot")
+    }
+
+    fn read_snapshot(&self) -> Result<Option<(u64, ContractSnapshot)>, Error> {
+        let path = self.snapshot_path();
+        if !path.exists() {
+            return Ok(None);
+        }
+        let mut bytes = vec![];
+        File::open(path)?.read_to_end(&mut bytes)?;
+        let payload = match checked(&bytes) {
+            Some(payload) => payload,
+            None => return Err(Error::CorruptStore),
+        };
+        let mut reader = Cursor::new(payload);
+        let seq = read_u64(&mut reader)?;
+        Ok(Some((seq, read_snapshot(&mut reader)?)))
+    }
+}
+
+impl StateStore for FileStore {
+    fn append(&mut self, ops: &[StateOp]) -> Result<(), Error> {
+        let mut payload = vec![];
+        write_u64(&mut payload, self.next_seq)?;
+        write_u64(&mut payload, ops.len() as u64)?;
+        for op in ops.iter() {
+            write_op(&mut payload, op)?;
+        }
+        let mut record = vec![];
+        write_u64(&mut record, payload.len() as u64)?;
+        record.extend_from_slice(&payload);
+        record.extend_from_slice(blake2b(CHECKSUM, &[], &payload).as_bytes());
+
+        self.log.seek(SeekFrom::End(0))?;
+        self.log.write_all(&record)?;
+        self.log.sync_data()?;
+        self.next_seq += 1;
+        Ok(())
+    }
+
+    fn snapshot(&mut self, snapshot: &ContractSnapshot) -> Result<(), Error> {
+        let mut payload = vec![];
+        write_u64(&mut payload, self.next_seq)?;
+        write_snapshot(&mut payload, snapshot)?;
+
+        let tmp = self.dir.join("snapshot.tmp");
+        {
+            let mut file = File::create(&tmp)?;
+            file.write_all(&payload)?;
+            file.write_all(blake2b(CHECKSUM, &[], &payload).as_bytes())?;
+            file.sync_all()?;
+        }
+        rename(tmp, self.snapshot_path())?;
+
+        self.log.set_len(0)?;
+        self.log.sync_all()?;
+        Ok(())
+    }
+
+    fn load(&mut self) -> Result<(Option<ContractSnapshot>, Vec<Vec<StateOp>>), Error> {
+        let (snapshot_seq, snapshot) = match self.read_snapshot()? {
+            Some((seq, snapshot)) => (seq, Some(snapshot)),
+            None => (0, None),
+        };
+
+        let mut bytes = vec![];
+        self.log.seek(SeekFrom::Start(0))?;
+        self.log.read_to_end(&mut bytes)?;
+
+        let mut batches = vec![];
+        let mut offset = 0;
+        self.next_seq = snapshot_seq;
+        while bytes.len() - offset >= 8 {
+            let len = u8sto64(&bytes[offset..offset + 8]) as usize;
+            let end = match len
+                .checked_add(CHECKSUM)
+                .and_then(|len| (offset + 8).checked_add(len))
+            {
+                Some(end) if end <= bytes.len() => end,
+                _ => break,
+            };
+            let payload = match checked(&bytes[offset + 8..end]) {
+                Some(payload) => payload,
+                //only the last record can be torn, a bad one before others is corruption
+                None if end == bytes.len() => break,
+                None => return Err(Error::CorruptStore),
+            };
+            let mut reader = Cursor::new(payload);
+            let seq = read_u64(&mut reader)?;
+            let n = read_u64(&mut reader)?;
+            let mut ops = vec![];
+            for _ in 0..n {
+                ops.push(read_op(&mut reader)?);
+            }
+            if seq >= snapshot_seq {
+                batches.push(ops);
+                self.next_seq = seq + 1;
+            }
+            offset = end;
+        }
+        //drop a torn record left by a crash
+        if offset != bytes.len() {
+            self.log.set_len(offset as u64)?;
+            self.log.sync_all()?;
+        }
+
+        Ok((snapshot, batches))
+    }
+}
+
+//payload of payload|checksum if the checksum matches
+fn checked(bytes: &[u8]) -> Option<&[u8]> {
+    if bytes.len() < CHECKSUM {
+        return None;
+    }
+    let (payload, sum) = bytes.split_at(bytes.len() - CHECKSUM);
+    if blake2b(CHECKSUM, &[], payload).as_bytes() == sum {
+        Some(payload)
+    } else {
+        None
+    }
+}
+
+pub(crate) fn write_u64<W: Write>(writer: &mut W, num: u64) -> Result<(), Error> {
+    writer.write_all(&u64to8(num))?;
+    Ok(())
+}
+
+pub(crate) fn read_u64<R: Read>(reader: &mut R) -> Result<u64, Error> {
+    let mut num = [0u8; 8];
+    reader.read_exact(&mut num)?;
+    Ok(u8sto64(&num))
+}
+
+//low then high half
+fn write_u128<W: Write>(writer: &mut W, num: u128) -> Result<(), Error> {
+    write_u64(writer, num as u64)?;
+    write_u64(writer, (num >> 64) as u64)
+}
+
+fn read_u128<R: Read>(reader: &mut R) -> Result<u128, Error> {
+    let low = read_u64(reader)?;
+    let high = read_u64(reader)?;
+    Ok((high as u128) << 64 | low as u128)
+}
+
+fn write_str<W: Write>(writer: &mut W, st: &str) -> Result<(), Error> {
+    write_u64(writer, st.len() as u64)?;
+    writer.write_all(st.as_bytes())?;
+    Ok(())
+}
+
+fn read_str<R: Read>(reader: &mut R) -> Result<String, Error> {
+    let len = read_u64(reader)?;
+    let mut bytes = vec![];
+    reader.by_ref().take(len).read_to_end(&mut bytes)?;
+    if bytes.len() as u64 != len {
+        return Err(Error::CorruptStore);
+    }
+    String::from_utf8(bytes).map_err(|_| Error::CorruptStore)
+}
+
+fn write_digest<W: Write>(writer: &mut W, digest: &Option<PedersenDigest>) -> Result<(), Error> {
+    match *digest {
+        Some(ref digest) => {
+            writer.write_all(&[1])?;
+            for num in digest.0.iter() {
+                write_u64(writer, *num)?;
+            }
+        }
+        None => writer.write_all(&[0])?,
+    }
+    Ok(())
+}
+
+fn read_digest<R: Read>(reader: &mut R) -> Result<Option<PedersenDigest>, Error> {
+    let mut flag = [0u8; 1];
+    reader.read_exact(&mut flag)?;
+    match flag[0] {
+        0 => Ok(None),
+        1 => {
+            let mut digest = [0u64; 4];
+            for num in digest.iter_mut() {
+                *num = read_u64(reader)?;
+            }
+            Ok(Some(PedersenDigest(digest)))
+        }
+        _ => Err(Error::CorruptStore),
+    }
+}
+
+fn write_op<W: Write>(writer: &mut W, op: &StateOp) -> Result<(), Error> {
+    match *op {
+        StateOp::SetBalance(ref address, asset, ref balance) => {
+            writer.write_all(&[0])?;
+            write_str(writer, address)?;
+            write_u64(writer, asset)?;
+            write_str(writer, balance)?;
+        }
+        StateOp::SetNonce(ref address, nonce) => {
+            writer.write_all(&[1])?;
+            write_str(writer, address)?;
+            write_u64(writer, nonce)?;
+        }
+        StateOp::AddNullifier(ref nullifier) => {
+            writer.write_all(&[2])?;
+            write_str(writer, nullifier)?;
+        }
+        StateOp::AddCoin(ref coin) => {
+            writer.write_all(&[3])?;
+            write_str(writer, coin)?;
+        }
+        StateOp::Checkpoint => writer.write_all(&[4])?,
+        StateOp::Rewind => writer.write_all(&[5])?,
+        StateOp::SetTransparent(ref address, asset, balance) => {
+            writer.write_all(&[6])?;
+            write_str(writer, address)?;
+            write_u64(writer, asset)?;
+            write_u128(writer, balance)?;
+        }
+        StateOp::SetFeeCollector(ref address) => {
+            writer.write_all(&[7])?;
+            write_str(writer, address)?;
+        }
+    }
+    Ok(())
+}
+
+fn read_op<R: Read>(reader: &mut R) -> Result<StateOp, Error> {
+    let mut tag = [0u8; 1];
+    reader.read_exact(&mut tag)?;
+    match tag[0] {
+        0 => Ok(StateOp::SetBalance(
+            read_str(reader)?,
+            read_u64(reader)?,
+            read_str(reader)?,
+        )),
+        1 => Ok(StateOp::SetNonce(read_str(reader)?, read_u64(reader)?)),
+        2 => Ok(StateOp::AddNullifier(read_str(reader)?)),
+        3 => Ok(StateOp::AddCoin(read_str(reader)?)),
+        4 => Ok(StateOp::Checkpoint),
+        5 => Ok(StateOp::Rewind),
+        6 => Ok(StateOp::SetTransparent(
+            read_str(reader)?,
+            read_u64(reader)?,
+            read_u128(reader)?,
+        )),
+        7 => Ok(StateOp::SetFeeCollector(read_str(reader)?)),
+        _ => Err(Error::CorruptStore),
+    }
+}
+
+fn write_snapshot<W: Write>(writer: &mut W, snapshot: &ContractSnapshot) -> Result<(), Error> {
+    write_u64(writer, snapshot.balances.len() as u64)?;
+    for &(ref address, asset, ref balance) in snapshot.balances.iter() {
+        write_str(writer, address)?;
+        write_u64(writer, asset)?;
+        write_str(writer, balance)?;
+    }
+    write_u64(writer, snapshot.nonces.len() as u64)?;
+    for &(ref address, nonce) in snapshot.nonces.iter() {
+        write_str(writer, address)?;
+        write_u64(writer, nonce)?;
+    }
+    write_u64(writer, snapshot.transparent.len() as u64)?;
+    for &(ref address, asset, balance) in snapshot.transparent.iter() {
+        write_str(writer, address)?;
+        write_u64(writer, asset)?;
+        write_u128(writer, balance)?;
+    }
+    match snapshot.fee_collector {
+        Some(ref address) => {
+            writer.write_all(&[1])?;
+            write_str(writer, address)?;
+        }
+        None => writer.write_all(&[0])?,
+    }
+    write_u64(writer, snapshot.coins.len() as u64)?;
+    for coin in snapshot.coins.iter() {
+        write_str(writer, coin)?;
+    }
+    write_u64(writer, snapshot.nullifiers.len() as u64)?;
+    for nullifier in snapshot.nullifiers.iter() {
+        write_str(writer, nullifier)?;
+    }
+    write_digest(writer, &snapshot.tree_left)?;
+    write_digest(writer, &snapshot.tree_right)?;
+    write_u64(writer, snapshot.tree_parents.len() as u64)?;
+    for parent in snapshot.tree_parents.iter() {
+        write_digest(writer, parent)?;
+    }
+    write_u64(writer, snapshot.roots.len() as u64)?;
+    for root in snapshot.roots.iter() {
+        write_digest(writer, &Some(*root))?;
+    }
+    Ok(())
+}
+
+fn read_snapshot<R: Read>(reader: &mut R) -> Result<ContractSnapshot, Error> {
+    let mut snapshot = ContractSnapshot::default();
+    for _ in 0..read_u64(reader)? {
+        snapshot
+            .balances
+            .push((read_str(reader)?, read_u64(reader)?, read_str(reader)?));
+    }
+    for _ in 0..read_u64(reader)? {
+        snapshot.nonces.push((read_str(reader)?, read_u64(reader)?));
+    }
+    for _ in 0..read_u64(reader)? {
+        snapshot
+            .transparent
+            .push((read_str(reader)?, read_u64(reader)?, read_u128(reader)?));
+    }
+    let mut flag = [0u8; 1];
+    reader.read_exact(&mut flag)?;
+    snapshot.fee_collector = match flag[0] {
+        0 => None,
+        1 => Some(read_str(reader)?),
+        _ => return Err(Error::CorruptStore),
+    };
+    for _ in 0..read_u64(reader)? {
+        snapshot.coins.push(read_str(reader)?);
+    }
+    for _ in 0..read_u64(reader)? {
+        snapshot.nullifiers.push(read_str(reader)?);
+    }
+    snapshot.tree_left = read_digest(reader)?;
+    snapshot.tree_right = read_digest(reader)?;
+    for _ in 0..read_u64(reader)? {
+        snapshot.tree_parents.push(read_digest(reader)?);
+    }
+    for _ in 0..read_u64(reader)? {
+        match read_digest(reader)? {
+            Some(root) => snapshot.roots.push(root),
+            None => return Err(Error::CorruptStore),
+        }
+    }
+    Ok(snapshot)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::env;
+    use std::fs::remove_dir_all;
+    use std::process;
+
+    fn dir(name: &str) -> PathBuf {
+        let dir = env::temp_dir().join(format!("zktx-store-{}-{}", process::id(), name));
+        let _ = remove_dir_all(&dir);
+        dir
+    }
+
+    fn batch(n: u64) -> Vec<StateOp> {
+        vec![
+            StateOp::SetNonce(format!("address{}", n), n),
+            StateOp::AddNullifier(format!("nullifier{}", n)),
+            StateOp::SetTransparent(format!("address{}", n), n, (n as u128) << 64 | 1),
+        ]
+    }
+
+    fn snapshot() -> ContractSnapshot {
+        ContractSnapshot {
+            balances: vec![("address".to_string(), 1, "balance".to_string())],
+            nonces: vec![("address".to_string(), 2)],
+            transparent: vec![("address".to_string(), 1, 3)],
+            fee_collector: Some("collector".to_string()),
+            coins: vec!["coin".to_string()],
+            nullifiers: vec!["nullifier".to_string()],
+            tree_left: Some(PedersenDigest([1, 2, 3, 4])),
+            tree_right: None,
+            tree_parents: vec![None, Some(PedersenDigest([5, 6, 7, 8]))],
+            roots: vec![PedersenDigest([9, 10, 11, 12])],
+        }
+    }
+
+    fn log_len(dir: &Path) -> u64 {
+        dir.join("log").metadata().unwrap().len()
+    }
+
+    #[test]
+    fn memory_store() {
+        let mut store = MemoryStore::new();
+        store.append(&batch(0)).unwrap();
+        store.append(&batch(1)).unwrap();
+        assert_eq!(store.load().unwrap(), (None, vec![batch(0), batch(1)]));
+        store.snapshot(&snapshot()).unwrap();
+        store.append(&batch(2)).unwrap();
+        assert_eq!(store.load().unwrap(), (Some(snapshot()), vec![batch(2)]));
+    }
+
+    #[test]
+    fn file_store_append_load() {
+        let dir = dir("append");
+        {
+            let mut store = FileStore::open(&dir).unwrap();
+            assert_eq!(store.load().unwrap(), (None, vec![]));
+            store.append(&batch(0)).unwrap();
+            store.append(&[]).unwrap();
+            store.append(&batch(1)).unwrap();
+        }
+        let mut store = FileStore::open(&dir).unwrap();
+        assert_eq!(
+            store.load().unwrap(),
+            (None, vec![batch(0), vec![], batch(1)])
+        );
+        store.append(&batch(2)).unwrap();
+        assert_eq!(store.load().unwrap().1.len(), 4);
+    }
+
+    #[test]
+    fn file_store_snapshot() {
+        let dir = dir("snapshot");
+        {
+            let mut store = FileStore::open(&dir).unwrap();
+            store.append(&batch(0)).unwrap();
+            store.snapshot(&snapshot()).unwrap();
+            store.append(&batch(1)).unwrap();
+        }
+        let mut store = FileStore::open(&dir).unwrap();
+        assert_eq!(store.load().unwrap(), (Some(snapshot()), vec![batch(1)]));
+    }
+
+    #[test]
+    fn file_store_drops_torn_tail() {
+        let dir = dir("torn");
+        let len = {
+            let mut store = FileStore::open(&dir).unwrap();
+            store.append(&batch(0)).unwrap();
+            let len = log_len(&dir);
+            store.append(&batch(1)).unwrap();
+            len
+        };
+        let full = log_len(&dir);
+        OpenOptions::new()
+            .write(true)
+            .open(dir.join("log"))
+            .unwrap()
+            .set_len(full - 1)
+            .unwrap();
+
+        let mut store = FileStore::open(&dir).unwrap();
+        assert_eq!(store.load().unwrap(), (None, vec![batch(0)]));
+        assert_eq!(log_len(&dir), len);
+        //appends continue after the dropped record
+        store.append(&batch(2)).unwrap();
+        assert_eq!(store.load().unwrap(), (None, vec![batch(0), batch(2)]));
+    }
+
+    #[test]
+    fn file_store_rejects_corrupt_record() {
+        let dir = dir("corrupt");
+        {
+            let mut store = FileStore::open(&dir).unwrap();
+            store.append(&batch(0)).unwrap();
+            store.append(&batch(1)).unwrap();
+        }
+        let mut bytes = vec![];
+        File::open(dir.join("log"))
+            .unwrap()
+            .read_to_end(&mut bytes)
+            .unwrap();
+        //flip a byte of the first record's ops
+        bytes[8 + 16] ^= 1;
+        File::create(dir.join("log"))
+            .unwrap()
+            .write_all(&bytes)
+            .unwrap();
+
+        match FileStore::open(&dir) {
+            Err(Error::CorruptStore) => {}
+            other => panic!("{:?}", other.err()),
+        }
+        //nothing was truncated
+        assert_eq!(log_len(&dir), bytes.len() as u64);
+    }
+}
@@ -0,0 +1,312 @@
+use b2c;
+use c2b;
+use c2c;
+use c2p;
+use convert::u6442str;
+use error::Error;
+use incrementalmerkletree::MerklePath;
+use keys::PaymentAddress;
+use p2c;
+use pedersen::PedersenDigest;
+use types::*;
+
+fn path2str(path: &MerklePath<PedersenDigest>) -> (Vec<String>, Vec<bool>) {
+    let auth = path
+        .authentication_path
+        .iter()
+        .map(|p| u6442str(p.0))
+        .collect();
+    (auth, path.index.clone())
+}
+
+pub fn b2c_info(
+    rcm: [u64; 2],
+    va: [u64; 2],
+    asset: u64,
+    to: &PaymentAddress,
+    addr_sk: &SpendingKey,
+    enc_random: [u64; 4],
+    memo: &[u8],
+) -> Result<(Groth16Proof, Coin, EncryptedNote), Error> {
+    let (proof, coin, enc) = b2c::b2c_info(
+        rcm,
+        va,
+        asset,
+        to.addr.to_string(),
+        to.enc_key.to_string(),
+        addr_sk.to_string(),
+        enc_random,
+        memo.to_vec(),
+    )?;
+    Ok((proof.parse()?, coin.parse()?, enc.parse()?))
+}
+
+pub fn b2c_verify(
+    va: [u64; 2],
+    asset: u64,
+    coin: &Coin,
+    enc: &EncryptedNote,
+    addr: &Address,
+    proof: &Groth16Proof,
+) -> Result<bool, Error> {
+    b2c::b2c_verify(
+        va,
+        asset,
+        coin.to_string(),
+        enc.to_string(),
+        addr.to_string(),
+        proof.to_string(),
+    )
+}
+
+pub fn p2c_info(
+    rh: [u64; 4],
+    rcm: [u64; 2],
+    ba: [u64; 2],
+    va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
+    nonce: u64,
+    to: &PaymentAddress,
+    addr_sk: &SpendingKey,
+    enc_random: [u64; 4],
+    memo: &[u8],
+) -> Result<
+    (
+        Groth16Proof,
+        ValueCommitment,
+        Coin,
+        ValueCommitment,
+        EncryptedNote,
+    ),
+    Error,
+> {
+    let (proof, hb, coin, delt_ba, enc) = p2c::p2c_info(
+        rh,
+        rcm,
+        ba,
+        va,
+        asset,
+        fee,
+        max_fee,
+        nonce,
+        to.addr.to_string(),
+        to.enc_key.to_string(),
+        addr_sk.to_string(),
+        enc_random,
+        memo.to_vec(),
+    )?;
+    Ok((
+        proof.parse()?,
+        hb.parse()?,
+        coin.parse()?,
+        delt_ba.parse()?,
+        enc.parse()?,
+    ))
+}
+
+pub fn p2c_verify(
+    hb: &ValueCommitment,
+    coin: &Coin,
+    delt_ba: &ValueCommitment,
+    enc: &EncryptedNote,
+    addr: &Address,
+    asset: u64,
+    fee: [u64; 2],
+    nonce: u64,
+    proof: &Groth16Proof,
+) -> Result<bool, Error> {
+    p2c::p2c_verify(
+        hb.to_string(),
+        coin.to_string(),
+        delt_ba.to_string(),
+        enc.to_string(),
+        addr.to_string(),
+        asset,
+        fee,
+        nonce,
+        proof.to_string(),
+    )
+}
+
+pub fn c2p_info(
+    rcm: [u64; 2],
+    rcm_new: [u64; 2],
+    va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
+    addr_sk: &SpendingKey,
+    path: &MerklePath<PedersenDigest>,
+) -> Result<(Groth16Proof, Nullifier, MerkleRoot, ValueCommitment), Error> {
+    let (auth, loc) = path2str(path);
+    let (proof, nullifier, root, delt_ba) = c2p::c2p_info(
+        rcm,
+        rcm_new,
+        va,
+        asset,
+        fee,
+        max_fee,
+        addr_sk.to_string(),
+        auth,
+        loc,
+    )?;
+    Ok((
+        proof.parse()?,
+        nullifier.parse()?,
+        root.parse()?,
+        delt_ba.parse()?,
+    ))
+}
+
+pub fn c2p_verify(
+    nullifier: &Nullifier,
+    root: &MerkleRoot,
+    delt_ba: &ValueCommitment,
+    asset: u64,
+    fee: [u64; 2],
+    proof: &Groth16Proof,
+) -> Result<bool, Error> {
+    c2p::c2p_verify(
+        nullifier.to_string(),
+        root.to_string(),
+        delt_ba.to_string(),
+        asset,
+        fee,
+        proof.to_string(),
+    )
+}
+
+pub fn c2b_info(
+    rcm: [u64; 2],
+    va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    max_fee: [u64; 2],
+    to: &Address,
+    addr_sk: &SpendingKey,
+    path: &MerklePath<PedersenDigest>,
+) -> Result<(Groth16Proof, Nullifier, MerkleRoot), Error> {
+    let (auth, loc) = path2str(path);
+    let (proof, nullifier, root) = c2b::c2b_info(
+        rcm,
+        va,
+        asset,
+        fee,
+        max_fee,
+        to.to_string(),
+        addr_sk.to_string(),
+        auth,
+        loc,
+    )?;
+    Ok((proof.parse()?, nullifier.parse()?, root.parse()?))
+}
+
+pub fn c2b_verify(
+    va: [u64; 2],
+    asset: u64,
+    fee: [u64; 2],
+    to: &Address,
+    nullifier: &Nullifier,
+    root: &MerkleRoot,
+    proof: &Groth16Proof,
+) -> Result<bool, Error> {
+    c2b::c2b_verify(
+        va,
+        asset,
+        fee,
+        to.to_string(),
+        nullifier.to_string(),
+        root.to_string(),
+        proof.to_string(),
+    )
+}
+
+pub struct C2cSpend<'a> {
+    pub addr_sk: &'a SpendingKey,
+    pub rcm: [u64; 2],
+    pub va: [u64; 2],
+    //None for a zero value dummy input
+    pub path: Option<&'a MerklePath<PedersenDigest>>,
+}
+
+pub struct C2cOutput<'a> {
+    pub to: &'a PaymentAddress,
+    pub rcm: [u64; 2],
+    pub va: [u64; 2],
+    pub enc_random: [u64; 4],
+    pub memo: &'a [u8],
+}
+
+pub fn c2c_info(
+    asset: u64,
+    inputs: &[C2cSpend],
+    outputs: &[C2cOutput],
+) -> Result<
+    (
+        Groth16Proof,
+        Vec<Nullifier>,
+        MerkleRoot,
+        Vec<Coin>,
+        Vec<EncryptedNote>,
+    ),
+    Error,
+> {
+    let mut spends = Vec::with_capacity(inputs.len());
+    for input in inputs.iter() {
+        spends.push(match input.path {
+            Some(path) => {
+                let (auth, loc) = path2str(path);
+                c2c::C2cSpend {
+                    addr_sk: input.addr_sk.to_string(),
+                    rcm: input.rcm,
+                    va: input.va,
+                    path: auth,
+                    loc,
+                }
+            }
+            //a coin with value is always in the tree
+            None if input.va != [0, 0] => return Err(Error::UnknownNote),
+            None => c2c::C2cSpend::dummy(input.addr_sk.to_string(), input.rcm),
+        });
+    }
+    let outs = outputs
+        .iter()
+        .map(|output| c2c::C2cOutput {
+            rcm: output.rcm,
+            va: output.va,
+            addr: output.to.addr.to_string(),
+            enc_key: output.to.enc_key.to_string(),
+            enc_random: output.enc_random,
+            memo: output.memo.to_vec(),
+        })
+        .collect();
+    let (proof, nullifiers, root, coins, encs) = c2c::c2c_info(asset, spends, outs)?;
+    Ok((
+        proof.parse()?,
+        nullifiers
+            .iter()
+            .map(|n| n.parse())
+            .collect::<Result<_, _>>()?,
+        root.parse()?,
+        coins.iter().map(|c| c.parse()).collect::<Result<_, _>>()?,
+        encs.iter().map(|e| e.parse()).collect::<Result<_, _>>()?,
+    ))
+}
+
+pub fn c2c_verify(
+    nullifiers: &[Nullifier],
+    root: &MerkleRoot,
+    coins: &[Coin],
+    encs: &[EncryptedNote],
+    proof: &Groth16Proof,
+) -> Result<bool, Error> {
+    c2c::c2c_verify(
+        nullifiers.iter().map(|n| n.to_string()).collect(),
+        root.to_string(),
+        coins.iter().map(|c| c.to_string()).collect(),
+        encs.iter().map(|e| e.to_string()).collect(),
+        proof.to_string(),
+    )
+}
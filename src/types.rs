@@ -0,0 +1,388 @@
+use bellman::groth16::Proof;
+use pairing::bls12_381::{Bls12, Fq, FqRepr};
+use pairing::{CurveAffine, EncodedPoint, PrimeField};
+
+use std::fmt;
+use std::str::FromStr;
+
+use base::{address, point2fr, ADSK, NOTE_CIPHERTEXT};
+use convert::*;
+use error::Error;
+use pedersen::PedersenDigest;
+
+pub type ProofSerial = (
+    ([u64; 6], [u64; 6], bool),
+    (([u64; 6], [u64; 6]), ([u64; 6], [u64; 6]), bool),
+    ([u64; 6], [u64; 6], bool),
+);
+
+macro_rules! field_type {
+    ($name:ident) => {
+        #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+        pub struct $name([u64; 4]);
+
+        impl $name {
+            pub fn new(num: [u64; 4]) -> Result<$name, Error> {
+                u6442fr(num)?;
+                Ok($name(num))
+            }
+
+            pub fn to_u644(&self) -> [u64; 4] {
+                self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<$name, Error> {
+                $name::new(str2u644(s.to_string())?)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", u6442str(self.0))
+            }
+        }
+    };
+}
+
+macro_rules! point_type {
+    ($name:ident) => {
+        #[derive(Copy, Clone, Eq, PartialEq, Hash, Debug)]
+        pub struct $name(([u64; 4], [u64; 4]));
+
+        impl $name {
+            pub fn new(point: ([u64; 4], [u64; 4])) -> Result<$name, Error> {
+                point2fr(point)?;
+                Ok($name(point))
+            }
+
+            pub fn to_point(&self) -> ([u64; 4], [u64; 4]) {
+                self.0
+            }
+        }
+
+        impl FromStr for $name {
+            type Err = Error;
+
+            fn from_str(s: &str) -> Result<$name, Error> {
+                $name::new(str2point(s.to_string())?)
+            }
+        }
+
+        impl fmt::Display for $name {
+            fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                write!(f, "{}", point2str(self.0))
+            }
+        }
+    };
+}
+
+//PH(PH(rcm|va|pa)|asset) with pa = PH(addr.x|enc_key.y), as build_coin
+field_type!(Coin);
+//PH(coin|nk)
+field_type!(Nullifier);
+//root of the coin tree
+field_type!(MerkleRoot);
+
+//addr_sk*P1
+point_type!(Address);
+//va*G+r*P2, G the value generator of the asset
+point_type!(ValueCommitment);
+
+impl Coin {
+    pub fn digest(&self) -> PedersenDigest {
+        PedersenDigest(self.0)
+    }
+}
+
+impl MerkleRoot {
+    pub fn digest(&self) -> PedersenDigest {
+        PedersenDigest(self.0)
+    }
+}
+
+impl From<PedersenDigest> for MerkleRoot {
+    fn from(digest: PedersenDigest) -> MerkleRoot {
+        //pedersen hashes are x coordinates, hence always canonical
+        MerkleRoot(digest.0)
+    }
+}
+
+#[derive(Clone, Eq, PartialEq)]
+pub struct SpendingKey(Vec<bool>);
+
+impl SpendingKey {
+    pub fn new(bits: Vec<bool>) -> Result<SpendingKey, Error> {
+        if bits.len() != ADSK {
+            return Err(Error::WrongLength {
+                expected: ADSK,
+                found: bits.len(),
+            });
+        }
+        Ok(SpendingKey(bits))
+    }
+
+    pub fn to_bits(&self) -> Vec<bool> {
+        self.0.clone()
+    }
+
+    pub fn address(&self) -> Result<Address, Error> {
+        address(self.to_string())?.parse()
+    }
+}
+
+impl FromStr for SpendingKey {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<SpendingKey, Error> {
+        SpendingKey::new(str2sk(s.to_string())?)
+    }
+}
+
+impl fmt::Display for SpendingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let st = sk2str(self.0.clone()).map_err(|_| fmt::Error)?;
+        write!(f, "{}", st)
+    }
+}
+
+impl fmt::Debug for SpendingKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "SpendingKey(..)")
+    }
+}
+
+//(rP, e1 | e2 | memo ciphertext), see base::encrypt
+#[derive(Clone, Eq, PartialEq, Hash, Debug)]
+pub struct EncryptedNote(([u64; 4], [u64; 4]), Vec<u8>);
+
+impl EncryptedNote {
+    pub fn new(rp: ([u64; 4], [u64; 4]), ct: Vec<u8>) -> Result<EncryptedNote, Error> {
+        point2fr(rp)?;
+        if ct.len() != NOTE_CIPHERTEXT {
+            return Err(Error::WrongLength {
+                expected: NOTE_CIPHERTEXT,
+                found: ct.len(),
+            });
+        }
+        Ok(EncryptedNote(rp, ct))
+    }
+
+    pub fn rp(&self) -> ([u64; 4], [u64; 4]) {
+        self.0
+    }
+
+    pub fn ciphertext(&self) -> &[u8] {
+        &self.1
+    }
+}
+
+impl FromStr for EncryptedNote {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<EncryptedNote, Error> {
+        let (rp, ct) = str2enc(s.to_string())?;
+        EncryptedNote::new(rp, ct)
+    }
+}
+
+impl fmt::Display for EncryptedNote {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", enc2str(self.0, &self.1))
+    }
+}
+
+#[derive(Copy, Clone, Eq, PartialEq, Debug)]
+pub struct Groth16Proof(ProofSerial);
+
+impl Groth16Proof {
+    //the points must be on their curves and in the prime order subgroups,
+    //which verify_proof takes for granted
+    pub fn new(proof: ProofSerial) -> Result<Groth16Proof, Error> {
+        let coordinates = [
+            (proof.0).0,
+            (proof.0).1,
+            ((proof.1).0).0,
+            ((proof.1).0).1,
+            ((proof.1).1).0,
+            ((proof.1).1).1,
+            (proof.2).0,
+            (proof.2).1,
+        ];
+        if coordinates.iter().any(|&c| FqRepr(c) >= Fq::char()) {
+            return Err(Error::BadProof);
+        }
+        //an uncompressed encoding only decodes to a point of the group
+        let decoded = Proof::<Bls12>::from_serial(proof);
+        decoded
+            .a
+            .into_uncompressed()
+            .into_affine()
+            .map_err(|_| Error::BadProof)?;
+        decoded
+            .b
+            .into_uncompressed()
+            .into_affine()
+            .map_err(|_| Error::BadProof)?;
+        decoded
+            .c
+            .into_uncompressed()
+            .into_affine()
+            .map_err(|_| Error::BadProof)?;
+        Ok(Groth16Proof(proof))
+    }
+
+    pub fn to_serial(&self) -> ProofSerial {
+        self.0
+    }
+}
+
+impl FromStr for Groth16Proof {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Groth16Proof, Error> {
+        Groth16Proof::new(str2proof(s.to_string())?)
+    }
+}
+
+impl fmt::Display for Groth16Proof {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", proof2str(self.0))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    //the identity, the simplest point on the curve
+    const IDENTITY: ([u64; 4], [u64; 4]) = ([0; 4], [1, 0, 0, 0]);
+
+    #[test]
+    fn field_roundtrip() {
+        let coin = Coin::new([1, 2, 3, 4]).unwrap();
+        let s = coin.to_string();
+        assert_eq!(s.len(), 64);
+        assert_eq!(s.parse::<Coin>().unwrap(), coin);
+        assert_eq!(s.parse::<Nullifier>().unwrap().to_u644(), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn field_rejects_bad_input() {
+        match Coin::new([!0; 4]) {
+            Err(Error::NonCanonical) => {}
+            other => panic!("{:?}", other),
+        }
+        match Coin::from_str(&"ff".repeat(32)) {
+            Err(Error::NonCanonical) => {}
+            other => panic!("{:?}", other),
+        }
+        match MerkleRoot::from_str(&"00".repeat(31)) {
+            Err(Error::WrongLength {
+                expected: 32,
+                found: 31,
+            }) => {}
+            other => panic!("{:?}", other),
+        }
+        assert!(Coin::from_str(&"zz".repeat(32)).is_err());
+    }
+
+    #[test]
+    fn point_roundtrip() {
+        let address = Address::new(IDENTITY).unwrap();
+        let s = address.to_string();
+        assert_eq!(s.len(), 128);
+        assert_eq!(s.parse::<Address>().unwrap(), address);
+
+        let sk: SpendingKey = "01".repeat(32).parse().unwrap();
+        let address = sk.address().unwrap();
+        assert_eq!(address.to_string().parse::<Address>().unwrap(), address);
+    }
+
+    #[test]
+    fn point_rejects_bad_input() {
+        match Address::new(([0; 4], [2, 0, 0, 0])) {
+            Err(Error::NotOnCurve) => {}
+            other => panic!("{:?}", other),
+        }
+        match ValueCommitment::new(([!0; 4], [1, 0, 0, 0])) {
+            Err(Error::NonCanonical) => {}
+            other => panic!("{:?}", other),
+        }
+        match ValueCommitment::from_str(&"00".repeat(63)) {
+            Err(Error::WrongLength {
+                expected: 64,
+                found: 63,
+            }) => {}
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn spending_key_roundtrip() {
+        let s = "0123456789abcdef".repeat(4);
+        let sk: SpendingKey = s.parse().unwrap();
+        assert_eq!(sk.to_bits().len(), ADSK);
+        assert_eq!(sk.to_string(), s);
+        assert!("01".repeat(31).parse::<SpendingKey>().is_err());
+        assert!(SpendingKey::new(vec![true; ADSK - 1]).is_err());
+    }
+
+    fn generators() -> ProofSerial {
+        use pairing::bls12_381::{G1Affine, G2Affine};
+
+        Proof::<Bls12> {
+            a: G1Affine::one(),
+            b: G2Affine::one(),
+            c: G1Affine::one(),
+        }
+        .serial()
+    }
+
+    #[test]
+    fn proof_roundtrip() {
+        let proof = Groth16Proof::new(generators()).unwrap();
+        assert_eq!(proof.to_serial(), generators());
+        let s = proof.to_string();
+        assert_eq!(s.len(), 770);
+        assert_eq!(s.parse::<Groth16Proof>().unwrap().to_serial(), generators());
+    }
+
+    #[test]
+    fn proof_rejects_bad_points() {
+        let mut proof = generators();
+        //(0, 0) is off the curve y^2 = x^3 + 4
+        proof.0 = ([0; 6], [0; 6], false);
+        match Groth16Proof::new(proof) {
+            Err(Error::BadProof) => {}
+            other => panic!("{:?}", other),
+        }
+        //(0, 2) is on it but of order 3, outside the prime order subgroup
+        proof.0 = ([0; 6], [2, 0, 0, 0, 0, 0], false);
+        match Groth16Proof::new(proof) {
+            Err(Error::BadProof) => {}
+            other => panic!("{:?}", other),
+        }
+        let mut proof = generators();
+        (proof.2).1 = [!0; 6];
+        match Groth16Proof::new(proof) {
+            Err(Error::BadProof) => {}
+            other => panic!("{:?}", other),
+        }
+        let mut proof = generators();
+        ((proof.1).1).0 = [0; 6];
+        assert!(Groth16Proof::new(proof).is_err());
+        assert!(proof2str(proof).parse::<Groth16Proof>().is_err());
+    }
+
+    #[test]
+    fn encrypted_note_roundtrip() {
+        let enc = EncryptedNote::new(IDENTITY, vec![7; NOTE_CIPHERTEXT]).unwrap();
+        assert_eq!(enc.to_string().parse::<EncryptedNote>().unwrap(), enc);
+        assert!(EncryptedNote::new(IDENTITY, vec![7; NOTE_CIPHERTEXT - 1]).is_err());
+        assert!(EncryptedNote::new(([0; 4], [2, 0, 0, 0]), vec![7; NOTE_CIPHERTEXT]).is_err());
+    }
+}
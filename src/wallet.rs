@@ -0,0 +1,277 @@
+//Notes of one spending key, kept in step with the coin tree.
+//
+//Every coin appended to the tree is fed to `append` in order: it advances the
+//witnesses of the notes held so far and trial-decrypts the new one. Nullifiers
+//from c2p, c2b and c2c go to `spend`.
+
+use std::collections::HashMap;
+
+use base::TREEDEPTH;
+use convert::value2u128;
+use error::Error;
+use incrementalmerkletree::*;
+use keys::{FullViewingKey, IncomingViewingKey};
+use pedersen::PedersenDigest;
+use types::*;
+
+#[derive(Clone, Debug)]
+pub struct WalletNote {
+    pub coin: Coin,
+    pub va: [u64; 2],
+    pub rcm: [u64; 2],
+    pub asset: u64,
+    pub memo: Vec<u8>,
+    //leaf index in the coin tree
+    pub position: usize,
+    pub nullifier: Nullifier,
+    pub spent: bool,
+}
+
+pub struct Wallet {
+    sk: SpendingKey,
+    fvk: FullViewingKey,
+    ivk: IncomingViewingKey,
+    tree: IncrementalMerkleTree<PedersenDigest>,
+    notes: Vec<WalletNote>,
+    //of the unspent notes, by position
+    witnesses: HashMap<usize, IncrementalWitness<PedersenDigest>>,
+    //index into notes
+    nullifiers: HashMap<Nullifier, usize>,
+}
+
+impl Wallet {
+    pub fn new(sk: SpendingKey) -> Result<Wallet, Error> {
+        let fvk = sk.full_viewing_key()?;
+        let ivk = fvk.incoming_viewing_key()?;
+        Ok(Wallet {
+            sk,
+            fvk,
+            ivk,
+            tree: IncrementalMerkleTree::new(TREEDEPTH),
+            notes: vec![],
+            witnesses: HashMap::new(),
+            nullifiers: HashMap::new(),
+        })
+    }
+
+    pub fn spending_key(&self) -> &SpendingKey {
+        &self.sk
+    }
+
+    //coins seen so far
+    pub fn size(&self) -> usize {
+        self.tree.size()
+    }
+
+    //equals the contract's current root once the wallet has caught up
+    pub fn root(&self) -> MerkleRoot {
+        MerkleRoot::from(self.tree.root())
+    }
+
+    //the next coin of the tree; returns the note if it is ours
+    pub fn append(
+        &mut self,
+        coin: Coin,
+        enc: &EncryptedNote,
+    ) -> Result<Option<&WalletNote>, Error> {
+        let digest = coin.digest();
+        for witness in self.witnesses.values_mut() {
+            witness.append(digest);
+        }
+        self.tree.append(digest);
+
+        let (va, rcm, asset, memo) = match self.ivk.open(&coin, enc)? {
+            Some(note) => note,
+            None => return Ok(None),
+        };
+        let position = self.tree.size() - 1;
+        let nullifier = self.fvk.nullifier(&coin)?;
+        self.witnesses.insert(position, self.tree.clone().witness());
+        self.nullifiers.insert(nullifier, self.notes.len());
+        self.notes.push(WalletNote {
+            coin,
+            va,
+            rcm,
+            asset,
+            memo,
+            position,
+            nullifier,
+            spent: false,
+        });
+        Ok(self.notes.last())
+    }
+
+    //append the coins in tree order; returns how many were ours
+    pub fn scan(&mut self, coins: &[(Coin, EncryptedNote)]) -> Result<usize, Error> {
+        let mut found = 0;
+        for &(coin, ref enc) in coins.iter() {
+            if self.append(coin, enc)?.is_some() {
+                found += 1;
+            }
+        }
+        Ok(found)
+    }
+
+    //a nullifier seen on chain; returns whether it spent one of our notes
+    pub fn spend(&mut self, nullifier: &Nullifier) -> bool {
+        let index = match self.nullifiers.get(nullifier) {
+            Some(&index) => index,
+            None => return false,
+        };
+        let note = &mut self.notes[index];
+        if note.spent {
+            return false;
+        }
+        note.spent = true;
+        self.witnesses.remove(&note.position);
+        true
+    }
+
+    pub fn notes(&self) -> &[WalletNote] {
+        &self.notes
+    }
+
+    pub fn unspent_notes(&self) -> Vec<&WalletNote> {
+        self.notes.iter().filter(|note| !note.spent).collect()
+    }
+
+    //sum of the unspent notes of asset
+    pub fn balance(&self, asset: u64) -> Result<u128, Error> {
+        let mut balance: u128 = 0;
+        for note in self.notes.iter() {
+            if note.spent || note.asset != asset {
+                continue;
+            }
+            balance = match balance.checked_add(value2u128(note.va)) {
+                Some(balance) => balance,
+                None => return Err(Error::BalanceOutOfRange),
+            };
+        }
+        Ok(balance)
+    }
+
+    //path of an unspent note against the current root
+    pub fn witness_for(&self, note: &WalletNote) -> Option<MerklePath<PedersenDigest>> {
+        self.witnesses
+            .get(&note.position)
+            .map(|witness| witness.path())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use base::encrypt;
+    use build_coin;
+    use keys::PaymentAddress;
+    use params::test_generators;
+
+    fn wallet(n: u8) -> Wallet {
+        test_generators();
+        Wallet::new(format!("{:02x}", n).repeat(32).parse().unwrap()).unwrap()
+    }
+
+    fn payment_address(wallet: &Wallet) -> PaymentAddress {
+        wallet.ivk.payment_address().unwrap()
+    }
+
+    fn note(to: &PaymentAddress, va: u64, rcm: u64, asset: u64) -> (Coin, EncryptedNote) {
+        let (va, rcm) = ([va, 0], [rcm, 0]);
+        let coin = build_coin(to.addr.to_string(), to.enc_key.to_string(), va, rcm, asset).unwrap();
+        let enc = encrypt(
+            va,
+            rcm,
+            asset,
+            b"",
+            [rcm[0], 1, 2, 0],
+            to.enc_key.to_string(),
+        )
+        .unwrap();
+        (coin.parse().unwrap(), enc.parse().unwrap())
+    }
+
+    //root the path leads to from coin
+    fn path_root(coin: &Coin, path: &MerklePath<PedersenDigest>) -> MerkleRoot {
+        let mut node = coin.digest();
+        for (sib, &right) in path.authentication_path.iter().zip(path.index.iter()) {
+            node = if right {
+                PedersenDigest::combine(sib, &node)
+            } else {
+                PedersenDigest::combine(&node, sib)
+            };
+        }
+        MerkleRoot::from(node)
+    }
+
+    #[test]
+    fn scan_finds_own_notes() {
+        let mut wallet = wallet(1);
+        let me = payment_address(&wallet);
+        let other = payment_address(&self::wallet(2));
+        let coins = vec![
+            note(&me, 10, 1, 0),
+            note(&other, 20, 2, 0),
+            note(&me, 30, 3, 0),
+        ];
+        assert_eq!(wallet.scan(&coins).unwrap(), 2);
+        assert_eq!(wallet.size(), 3);
+        let positions: Vec<usize> = wallet.notes().iter().map(|n| n.position).collect();
+        assert_eq!(positions, vec![0, 2]);
+        assert_eq!(wallet.notes()[1].va, [30, 0]);
+        assert_eq!(wallet.balance(0).unwrap(), 40);
+        assert_eq!(wallet.balance(1).unwrap(), 0);
+    }
+
+    #[test]
+    fn witnesses_follow_the_root() {
+        let mut wallet = wallet(1);
+        let me = payment_address(&wallet);
+        let other = payment_address(&self::wallet(2));
+        wallet.scan(&[note(&me, 10, 1, 0)]).unwrap();
+        for i in 0..3 {
+            wallet.scan(&[note(&other, 20, i + 2, 0)]).unwrap();
+            for note in wallet.unspent_notes() {
+                let path = wallet.witness_for(note).unwrap();
+                assert_eq!(path_root(&note.coin, &path), wallet.root());
+            }
+        }
+    }
+
+    #[test]
+    fn spend_marks_notes() {
+        let mut wallet = wallet(1);
+        let me = payment_address(&wallet);
+        wallet
+            .scan(&[note(&me, 10, 1, 0), note(&me, 30, 3, 0)])
+            .unwrap();
+        let nullifier = wallet.notes()[0].nullifier;
+        let unknown = self::wallet(2)
+            .fvk
+            .nullifier(&wallet.notes()[0].coin)
+            .unwrap();
+
+        assert!(!wallet.spend(&unknown));
+        assert!(wallet.spend(&nullifier));
+        assert!(!wallet.spend(&nullifier));
+        assert!(wallet.notes()[0].spent);
+        assert_eq!(wallet.balance(0).unwrap(), 30);
+        assert_eq!(wallet.unspent_notes().len(), 1);
+        assert!(wallet.witness_for(&wallet.notes()[0]).is_none());
+    }
+
+    #[test]
+    fn assets_have_their_own_nullifiers() {
+        let mut wallet = wallet(1);
+        let me = payment_address(&wallet);
+        //same va and rcm in two assets
+        wallet
+            .scan(&[note(&me, 10, 1, 0), note(&me, 10, 1, 1)])
+            .unwrap();
+        assert!(wallet.notes()[0].nullifier != wallet.notes()[1].nullifier);
+        assert_eq!(wallet.balance(1).unwrap(), 10);
+        assert_eq!(wallet.unspent_notes().len(), 2);
+        let nullifier = wallet.notes()[1].nullifier;
+        assert!(wallet.spend(&nullifier));
+        assert!(!wallet.notes()[0].spent);
+    }
+}